@@ -0,0 +1,50 @@
+//! Decodes small streams with a new decoder each time, and with one decoder that is reset.
+
+#[macro_use]
+extern crate bencher;
+extern crate brotli;
+
+use bencher::{ black_box, Bencher };
+use brotli::bitreader::SliceBitReader;
+use brotli::decoder::Decoder;
+use std::fs::File;
+use std::io::Read;
+
+fn read_testdata( name: &str ) -> Vec<u8> {
+    let mut data = Vec::new();
+    File::open( format!( "tests/testdata/{}", name )).unwrap().read_to_end( &mut data ).unwrap();
+    data
+}
+
+fn decode_new( b: &mut Bencher, name: &str ) {
+    let compressed = read_testdata( &format!( "{}.compressed", name ));
+    let mut output = Vec::new();
+    b.iter( || {
+        output.clear();
+        let mut decoder = Decoder::new( SliceBitReader::new( &compressed ));
+        decoder.read_to_end( &mut output ).unwrap();
+        black_box( &output );
+    });
+}
+
+fn decode_reset( b: &mut Bencher, name: &str ) {
+    let compressed = read_testdata( &format!( "{}.compressed", name ));
+    let mut output = Vec::new();
+    let mut decoder = Decoder::new( SliceBitReader::new( &compressed ));
+    b.iter( || {
+        output.clear();
+        decoder.reset( SliceBitReader::new( &compressed ));
+        decoder.read_to_end( &mut output ).unwrap();
+        black_box( &output );
+    });
+}
+
+fn new_x( b: &mut Bencher ) { decode_new( b, "x" ) }
+fn new_quickfox( b: &mut Bencher ) { decode_new( b, "quickfox" ) }
+fn new_10x10y( b: &mut Bencher ) { decode_new( b, "10x10y" ) }
+fn reset_x( b: &mut Bencher ) { decode_reset( b, "x" ) }
+fn reset_quickfox( b: &mut Bencher ) { decode_reset( b, "quickfox" ) }
+fn reset_10x10y( b: &mut Bencher ) { decode_reset( b, "10x10y" ) }
+
+benchmark_group!( benches, new_x, new_quickfox, new_10x10y, reset_x, reset_quickfox, reset_10x10y );
+benchmark_main!( benches );
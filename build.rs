@@ -0,0 +1,40 @@
+//! With the `header` feature, generates the encoder's C header from the declarations in
+//! `src/ffi_encoder.rs`, as `encode.h` in `OUT_DIR`. `tests/header.rs` checks that
+//! `c/include/brotli/encode.h` is up to date with it.
+
+#[cfg(feature = "header")]
+extern crate cbindgen;
+
+#[cfg(feature = "header")]
+fn generate_encoder_header() {
+    println!( "cargo:rerun-if-changed=src/ffi_encoder.rs" );
+    let config = cbindgen::Config {
+        language: cbindgen::Language::C,
+        header: Some( "/* Generated from src/ffi_encoder.rs by build.rs with the header feature. Do not edit. */".to_string() ),
+        include_guard: Some( "BROTLI_ENC_ENCODE_H_".to_string() ),
+        sys_includes: vec!["brotli/types.h".to_string()],
+        no_includes: true,
+        cpp_compat: true,
+        usize_is_size_t: true,
+        style: cbindgen::Style::Both,
+        documentation_style: cbindgen::DocumentationStyle::Doxy,
+        // The functions take these as integers, so nothing refers to them
+        export: cbindgen::ExportConfig {
+            include: vec!["BrotliEncoderParameter".to_string(), "BrotliEncoderOperation".to_string()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    cbindgen::Builder::new()
+        .with_config( config )
+        .with_src( "src/ffi_encoder.rs" )
+        .generate()
+        .expect( "failed to generate the encoder header" )
+        .write_to_file( std::path::Path::new( &std::env::var( "OUT_DIR" ).unwrap() ).join( "encode.h" ));
+}
+
+fn main() {
+    println!( "cargo:rerun-if-changed=build.rs" );
+    #[cfg(feature = "header")]
+    generate_encoder_header();
+}
@@ -0,0 +1,263 @@
+/* Generated from src/ffi_encoder.rs by build.rs with the header feature. Do not edit. */
+
+#ifndef BROTLI_ENC_ENCODE_H_
+#define BROTLI_ENC_ENCODE_H_
+
+#include <brotli/types.h>
+
+/**
+ * Minimal value for ::BROTLI_PARAM_LGWIN parameter.
+ */
+#define BROTLI_MIN_WINDOW_BITS 10
+
+/**
+ * Maximal value for ::BROTLI_PARAM_LGWIN parameter.
+ */
+#define BROTLI_MAX_WINDOW_BITS 24
+
+/**
+ * Minimal value for ::BROTLI_PARAM_LGBLOCK parameter.
+ */
+#define BROTLI_MIN_INPUT_BLOCK_BITS 16
+
+/**
+ * Maximal value for ::BROTLI_PARAM_LGBLOCK parameter.
+ */
+#define BROTLI_MAX_INPUT_BLOCK_BITS 24
+
+/**
+ * Minimal value for ::BROTLI_PARAM_QUALITY parameter.
+ */
+#define BROTLI_MIN_QUALITY 0
+
+/**
+ * Maximal value for ::BROTLI_PARAM_QUALITY parameter.
+ */
+#define BROTLI_MAX_QUALITY 11
+
+/**
+ * Default value for ::BROTLI_PARAM_QUALITY parameter. Like in `EncoderOptions`, it's lower
+ * than the C library's 11.
+ */
+#define BROTLI_DEFAULT_QUALITY 9
+
+/**
+ * Default value for ::BROTLI_PARAM_LGWIN parameter.
+ */
+#define BROTLI_DEFAULT_WINDOW 22
+
+/**
+ * Options for ::BROTLI_PARAM_MODE parameter.
+ */
+typedef enum BrotliEncoderMode {
+  /**
+   * Default compression mode. The compressor does not know anything in advance about the
+   * properties of the input.
+   */
+  BROTLI_MODE_GENERIC = 0,
+  /**
+   * Compression mode for UTF-8 formatted text input.
+   */
+  BROTLI_MODE_TEXT = 1,
+  /**
+   * Compression mode used in WOFF 2.0.
+   */
+  BROTLI_MODE_FONT = 2,
+} BrotliEncoderMode;
+
+/**
+ * Options to be used with ::BrotliEncoderSetParameter.
+ */
+typedef enum BrotliEncoderParameter {
+  /**
+   * Tunes the encoder for the type of input, one of ::BrotliEncoderMode.
+   */
+  BROTLI_PARAM_MODE = 0,
+  /**
+   * The compression level, from ::BROTLI_MIN_QUALITY to ::BROTLI_MAX_QUALITY.
+   */
+  BROTLI_PARAM_QUALITY = 1,
+  /**
+   * Base 2 logarithm of the sliding window size, from ::BROTLI_MIN_WINDOW_BITS to
+   * ::BROTLI_MAX_WINDOW_BITS.
+   */
+  BROTLI_PARAM_LGWIN = 2,
+  /**
+   * Base 2 logarithm of the maximum input block size, from ::BROTLI_MIN_INPUT_BLOCK_BITS
+   * to ::BROTLI_MAX_INPUT_BLOCK_BITS, or 0 to pick it based on the window size.
+   */
+  BROTLI_PARAM_LGBLOCK = 3,
+  /**
+   * Any value is accepted: the encoder never models the context of literals.
+   */
+  BROTLI_PARAM_DISABLE_LITERAL_CONTEXT_MODELING = 4,
+  /**
+   * The expected size of the input, or 0 if it's unknown. While ::BROTLI_PARAM_LGBLOCK is
+   * 0, the input block isn't made larger than needed for this size.
+   */
+  BROTLI_PARAM_SIZE_HINT = 5,
+  /**
+   * Only 0 is accepted: large windows aren't supported.
+   */
+  BROTLI_PARAM_LARGE_WINDOW = 6,
+  /**
+   * The number of distance postfix bits, from 0 to 3. Combinations with ::BROTLI_PARAM_NDIRECT
+   * that the format can't express select 0 for both. Unless either is set, the mode picks them.
+   */
+  BROTLI_PARAM_NPOSTFIX = 7,
+  /**
+   * The number of direct distance codes, from 0 to 120, a multiple of
+   * <tt>1 << ::BROTLI_PARAM_NPOSTFIX</tt>.
+   */
+  BROTLI_PARAM_NDIRECT = 8,
+  /**
+   * Only 0 is accepted: the stream always starts at the beginning.
+   */
+  BROTLI_PARAM_STREAM_OFFSET = 9,
+} BrotliEncoderParameter;
+
+/**
+ * Operations that can be performed by the streaming encoder.
+ */
+typedef enum BrotliEncoderOperation {
+  /**
+   * Processes the input. The encoder may postpone producing output until it has processed
+   * enough input.
+   */
+  BROTLI_OPERATION_PROCESS = 0,
+  /**
+   * Produces output for all processed input, padded to a byte boundary, so that the
+   * decoder can decode everything passed in so far. Call repeatedly, without new input,
+   * until ::BrotliEncoderHasMoreOutput returns ::BROTLI_FALSE.
+   */
+  BROTLI_OPERATION_FLUSH = 1,
+  /**
+   * Finalizes the stream. Call repeatedly, without new input, until
+   * ::BrotliEncoderIsFinished returns ::BROTLI_TRUE.
+   */
+  BROTLI_OPERATION_FINISH = 2,
+  /**
+   * Emits the whole input, at most 16 MiB, as metadata, after flushing the input processed
+   * before. Call repeatedly, without new input, until ::BrotliEncoderHasMoreOutput returns
+   * ::BROTLI_FALSE.
+   */
+  BROTLI_OPERATION_EMIT_METADATA = 3,
+} BrotliEncoderOperation;
+
+/**
+ * Opaque structure that holds encoder state.
+ */
+typedef struct BrotliEncoderState BrotliEncoderState;
+
+/**
+ * Default value for ::BROTLI_PARAM_MODE parameter.
+ */
+#define BROTLI_DEFAULT_MODE BROTLI_MODE_GENERIC
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates an instance of ::BrotliEncoderState and initializes it. Returns @c 0 if only one
+ * of @p alloc_func and @p free_func is given, or the state can't be allocated.
+ *
+ * @warning Only the state itself is allocated with @p alloc_func. The buffers of the encoder,
+ * which grow with the window and input block sizes, come from the global allocator of the
+ * library, @c malloc unless it was built otherwise, regardless of the functions given.
+ */
+struct BrotliEncoderState *BrotliEncoderCreateInstance(brotli_alloc_func alloc_func,
+                                                       brotli_free_func free_func,
+                                                       void *opaque);
+
+/**
+ * Deinitializes and frees ::BrotliEncoderState instance.
+ *
+ * # Safety
+ *
+ * `state` must be null or come from `BrotliEncoderCreateInstance()`, and not be used again.
+ */
+void BrotliEncoderDestroyInstance(struct BrotliEncoderState *state);
+
+/**
+ * Sets the specified parameter, one of ::BrotliEncoderParameter, to the given encoder instance.
+ * Values out of range are clamped to the nearest valid value. Returns ::BROTLI_FALSE if
+ * @p state is @c 0, the parameter or value is unsupported, or compression has already started.
+ *
+ * # Safety
+ *
+ * `state` must be null or come from `BrotliEncoderCreateInstance()`.
+ */
+BROTLI_BOOL BrotliEncoderSetParameter(struct BrotliEncoderState *state, int param, uint32_t value);
+
+/**
+ * Calculates the output size bound for the given @p input_size. Returns 0 if the result
+ * doesn't fit in @c size_t.
+ */
+size_t BrotliEncoderMaxCompressedSize(size_t input_size);
+
+/**
+ * Compresses @p input_size bytes at @p input_buffer into @p encoded_buffer, which holds
+ * @p *encoded_size bytes, and sets @p *encoded_size to the size of the stream. @p mode is one
+ * of ::BrotliEncoderMode. Returns ::BROTLI_FALSE if an argument is invalid, or the stream
+ * doesn't fit. ::BrotliEncoderMaxCompressedSize tells a size that always fits.
+ *
+ * # Safety
+ *
+ * The buffers must hold as many bytes as claimed.
+ */
+BROTLI_BOOL BrotliEncoderCompress(int quality,
+                                  int lgwin,
+                                  int mode,
+                                  size_t input_size,
+                                  const uint8_t *input_buffer,
+                                  size_t *encoded_size,
+                                  uint8_t *encoded_buffer);
+
+/**
+ * Compresses input stream to output stream, performing @p op, one of ::BrotliEncoderOperation.
+ * Consumes up to @p *available_in bytes at @p *next_in, writes up to @p *available_out bytes at
+ * @p *next_out, and advances both past what was consumed and produced. @p *total_out, unless
+ * @p total_out is @c 0, is set to the number of bytes produced so far.
+ *
+ * Returns ::BROTLI_FALSE if the arguments are invalid, or if @p op is unknown or not allowed:
+ * a flush, metadata or the end of the stream has to be completed with the same operation and
+ * no new input before anything else is done.
+ *
+ * # Safety
+ *
+ * The pointers must be valid, and the buffers hold as many bytes as claimed. `next_in` and
+ * `next_out` may be null while nothing is available.
+ */
+BROTLI_BOOL BrotliEncoderCompressStream(struct BrotliEncoderState *state,
+                                        int op,
+                                        size_t *available_in,
+                                        const uint8_t **next_in,
+                                        size_t *available_out,
+                                        uint8_t **next_out,
+                                        size_t *total_out);
+
+/**
+ * Returns ::BROTLI_TRUE if the encoder has produced output that didn't fit in the output
+ * buffer yet.
+ *
+ * # Safety
+ *
+ * `state` must be null or come from `BrotliEncoderCreateInstance()`.
+ */
+BROTLI_BOOL BrotliEncoderHasMoreOutput(const struct BrotliEncoderState *state);
+
+/**
+ * Returns ::BROTLI_TRUE once the stream is finalized, and all of its output handed out.
+ *
+ * # Safety
+ *
+ * `state` must be null or come from `BrotliEncoderCreateInstance()`.
+ */
+BROTLI_BOOL BrotliEncoderIsFinished(const struct BrotliEncoderState *state);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* BROTLI_ENC_ENCODE_H_ */
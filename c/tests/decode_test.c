@@ -0,0 +1,330 @@
+/* Tests the C interface of the decoder against pairs of compressed and
+   expected files.
+
+   Usage: decode_test COMPRESSED EXPECTED [COMPRESSED EXPECTED ...]
+
+   Built and run by ffi/tests/capi.rs, which builds the library first:
+
+     cargo build -p brotli-ffi
+     cc -std=c99 -Ic/include c/tests/decode_test.c -Ltarget/debug -lbrotli \
+        -Wl,-rpath,target/debug -o decode_test
+*/
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include <brotli/decode.h>
+
+static int failures = 0;
+
+#define CHECK(name, condition)                                            \
+  do {                                                                    \
+    if (!(condition)) {                                                   \
+      fprintf(stderr, "%s: %s:%d: %s\n", name, __FILE__, __LINE__,        \
+              #condition);                                                \
+      failures++;                                                         \
+      return;                                                             \
+    }                                                                     \
+  } while (0)
+
+static void free_func(void* opaque, void* address) {
+  (void)opaque;
+  free(address);
+}
+
+/* Counts the allocations made through it, and fails those past `limit`. */
+typedef struct {
+  size_t allocs;
+  size_t frees;
+  size_t limit;
+} Counter;
+
+static void* counting_alloc(void* opaque, size_t size) {
+  Counter* counter = (Counter*)opaque;
+  if (counter->allocs == counter->limit) return NULL;
+  counter->allocs++;
+  return malloc(size);
+}
+
+static void counting_free(void* opaque, void* address) {
+  Counter* counter = (Counter*)opaque;
+  if (address) counter->frees++;
+  free(address);
+}
+
+static uint8_t* read_file(const char* path, size_t* size) {
+  FILE* file = fopen(path, "rb");
+  uint8_t* data = NULL;
+  size_t capacity = 0;
+  *size = 0;
+  if (!file) {
+    perror(path);
+    exit(2);
+  }
+  for (;;) {
+    if (*size == capacity) {
+      capacity = capacity * 2 + 4096;
+      data = (uint8_t*)realloc(data, capacity);
+    }
+    size_t count = fread(data + *size, 1, capacity - *size, file);
+    if (count == 0) break;
+    *size += count;
+  }
+  fclose(file);
+  return data;
+}
+
+/* Decodes with input arriving `in_chunk` bytes and output leaving `out_chunk`
+   bytes at a time, followed by `trailing` bytes that must be left unconsumed. */
+static void test_stream(const char* name, const uint8_t* compressed,
+                        size_t compressed_size, const uint8_t* expected,
+                        size_t expected_size, size_t in_chunk,
+                        size_t out_chunk, size_t trailing) {
+  size_t input_size = compressed_size + trailing;
+  uint8_t* input = (uint8_t*)malloc(input_size + 1);
+  uint8_t* output = (uint8_t*)malloc(expected_size + 1);
+  uint8_t* buffer = (uint8_t*)malloc(out_chunk);
+  BrotliDecoderState* state = BrotliDecoderCreateInstance(NULL, NULL, NULL);
+  size_t in_pos = 0;
+  size_t out_size = 0;
+  size_t available_in = 0;
+  const uint8_t* next_in = input;
+  size_t available_out = out_chunk;
+  uint8_t* next_out = buffer;
+  size_t total_out = 0;
+  BrotliDecoderResult result;
+
+  memcpy(input, compressed, compressed_size);
+  memset(input + compressed_size, 0xaa, trailing);
+  CHECK(name, state != NULL);
+  for (;;) {
+    result = BrotliDecoderDecompressStream(state, &available_in, &next_in,
+        &available_out, &next_out, &total_out);
+    size_t produced = (size_t)(next_out - buffer);
+    CHECK(name, out_size + produced <= expected_size);
+    memcpy(output + out_size, buffer, produced);
+    out_size += produced;
+    next_out = buffer;
+    available_out = out_chunk;
+    CHECK(name, total_out == out_size);
+
+    if (result == BROTLI_DECODER_RESULT_NEEDS_MORE_INPUT) {
+      CHECK(name, available_in == 0);
+      CHECK(name, in_pos < input_size);
+      next_in = input + in_pos;
+      available_in = input_size - in_pos < in_chunk ? input_size - in_pos
+                                                    : in_chunk;
+      in_pos += available_in;
+    } else if (result == BROTLI_DECODER_RESULT_SUCCESS) {
+      break;
+    } else {
+      CHECK(name, result == BROTLI_DECODER_RESULT_NEEDS_MORE_OUTPUT);
+    }
+  }
+
+  CHECK(name, BrotliDecoderGetErrorCode(state) == BROTLI_DECODER_SUCCESS);
+  CHECK(name, BrotliDecoderIsFinished(state));
+  CHECK(name, !BrotliDecoderHasMoreOutput(state));
+  CHECK(name, out_size == expected_size);
+  CHECK(name, memcmp(output, expected, expected_size) == 0);
+  /* Whatever follows the stream is left to the caller */
+  CHECK(name, (size_t)(next_in - input) + available_in == in_pos);
+  CHECK(name, input_size - ((size_t)(next_in - input)) == trailing);
+
+  BrotliDecoderDestroyInstance(state);
+  free(buffer);
+  free(output);
+  free(input);
+}
+
+static void test_one_shot(const char* name, const uint8_t* compressed,
+                          size_t compressed_size, const uint8_t* expected,
+                          size_t expected_size) {
+  uint8_t* output = (uint8_t*)malloc(expected_size + 1);
+  size_t size = expected_size;
+  CHECK(name, BrotliDecoderDecompress(compressed_size, compressed, &size,
+      output) == BROTLI_DECODER_RESULT_SUCCESS);
+  CHECK(name, size == expected_size);
+  CHECK(name, memcmp(output, expected, expected_size) == 0);
+
+  /* Output that doesn't fit is an error */
+  if (expected_size > 0) {
+    size = expected_size - 1;
+    CHECK(name, BrotliDecoderDecompress(compressed_size, compressed, &size,
+        output) == BROTLI_DECODER_RESULT_ERROR);
+  }
+
+  /* So is a truncated stream */
+  size = expected_size;
+  CHECK(name, BrotliDecoderDecompress(compressed_size - 1, compressed, &size,
+      output) == BROTLI_DECODER_RESULT_ERROR);
+  free(output);
+}
+
+static void test_errors(const char* name, const uint8_t* compressed,
+                        size_t compressed_size) {
+  uint8_t output[256];
+  BrotliDecoderState* state;
+  size_t available_in = compressed_size;
+  const uint8_t* next_in = compressed;
+  size_t available_out = sizeof(output);
+  uint8_t* next_out = NULL;
+  BrotliDecoderResult result;
+  size_t step = compressed_size / 64 + 1;
+  size_t i;
+
+  CHECK(name, BrotliDecoderCreateInstance(NULL, free_func, NULL) == NULL);
+
+  /* Output space without an output buffer */
+  state = BrotliDecoderCreateInstance(NULL, NULL, NULL);
+  CHECK(name, BrotliDecoderGetErrorCode(state) == BROTLI_DECODER_NO_ERROR);
+  result = BrotliDecoderDecompressStream(state, &available_in, &next_in,
+      &available_out, &next_out, NULL);
+  CHECK(name, result == BROTLI_DECODER_RESULT_ERROR);
+  CHECK(name, BrotliDecoderGetErrorCode(state) ==
+      BROTLI_DECODER_ERROR_INVALID_ARGUMENTS);
+  CHECK(name, strcmp(BrotliDecoderErrorString(BrotliDecoderGetErrorCode(
+      state)), "_ERROR_INVALID_ARGUMENTS") == 0);
+  BrotliDecoderDestroyInstance(state);
+
+  /* Corrupt input is caught, and the error sticks */
+  for (i = 0; i < compressed_size; i += step) {
+    uint8_t* corrupt = (uint8_t*)malloc(compressed_size);
+    memcpy(corrupt, compressed, compressed_size);
+    corrupt[i] ^= 0x5a;
+    state = BrotliDecoderCreateInstance(NULL, NULL, NULL);
+    available_in = compressed_size;
+    next_in = corrupt;
+    do {
+      available_out = sizeof(output);
+      next_out = output;
+      result = BrotliDecoderDecompressStream(state, &available_in, &next_in,
+          &available_out, &next_out, NULL);
+    } while (result == BROTLI_DECODER_RESULT_NEEDS_MORE_OUTPUT);
+    if (result == BROTLI_DECODER_RESULT_ERROR) {
+      BrotliDecoderErrorCode code = BrotliDecoderGetErrorCode(state);
+      CHECK(name, code < 0 && code >= BROTLI_LAST_ERROR_CODE);
+      CHECK(name, strcmp(BrotliDecoderErrorString(code), "INVALID") != 0);
+      result = BrotliDecoderDecompressStream(state, &available_in, &next_in,
+          &available_out, &next_out, NULL);
+      CHECK(name, result == BROTLI_DECODER_RESULT_ERROR);
+    }
+    BrotliDecoderDestroyInstance(state);
+    free(corrupt);
+  }
+}
+
+/* Decodes with a custom allocator, which has to be used for everything. */
+static void test_alloc(const char* name, const uint8_t* compressed,
+                       size_t compressed_size, size_t expected_size) {
+  Counter counter = {0, 0, (size_t)-1};
+  uint8_t* output = (uint8_t*)malloc(expected_size + 1);
+  BrotliDecoderState* state;
+  size_t available_in = compressed_size;
+  const uint8_t* next_in = compressed;
+  size_t available_out = expected_size + 1;
+  uint8_t* next_out = output;
+  BrotliDecoderResult result;
+
+  state = BrotliDecoderCreateInstance(counting_alloc, counting_free, &counter);
+  CHECK(name, state != NULL);
+  CHECK(name, BrotliDecoderDecompressStream(state, &available_in, &next_in,
+      &available_out, &next_out, NULL) == BROTLI_DECODER_RESULT_SUCCESS);
+  CHECK(name, (size_t)(next_out - output) == expected_size);
+  BrotliDecoderDestroyInstance(state);
+  CHECK(name, counter.allocs > 0 && counter.allocs == counter.frees);
+
+  /* Without memory for the state, there is no decoder */
+  counter.allocs = counter.frees = counter.limit = 0;
+  CHECK(name, BrotliDecoderCreateInstance(counting_alloc, counting_free,
+      &counter) == NULL);
+
+  /* The ring buffer is allocated first */
+  counter.limit = 1;
+  state = BrotliDecoderCreateInstance(counting_alloc, counting_free, &counter);
+  CHECK(name, state != NULL);
+  available_in = compressed_size;
+  next_in = compressed;
+  available_out = expected_size + 1;
+  next_out = output;
+  result = BrotliDecoderDecompressStream(state, &available_in, &next_in,
+      &available_out, &next_out, NULL);
+  if (result == BROTLI_DECODER_RESULT_ERROR) {
+    CHECK(name, BrotliDecoderGetErrorCode(state) ==
+        BROTLI_DECODER_ERROR_ALLOC_RING_BUFFER_1);
+  }
+  BrotliDecoderDestroyInstance(state);
+  CHECK(name, counter.allocs == counter.frees);
+  free(output);
+}
+
+/* Streams with a known error report its code. */
+static void test_error_codes(void) {
+  /* A metadata meta-block with its reserved bit set */
+  static const uint8_t reserved[] = {0x1c};
+  /* A meta-block length of 5 nibbles, the last of them zero */
+  static const uint8_t nibble[] = {0x04, 0x00, 0x00, 0x00};
+  uint8_t output[16];
+  BrotliDecoderState* state;
+  size_t available_in = sizeof(reserved);
+  const uint8_t* next_in = reserved;
+  size_t available_out = sizeof(output);
+  uint8_t* next_out = output;
+
+  state = BrotliDecoderCreateInstance(NULL, NULL, NULL);
+  CHECK("reserved", BrotliDecoderDecompressStream(state, &available_in,
+      &next_in, &available_out, &next_out, NULL) ==
+      BROTLI_DECODER_RESULT_ERROR);
+  CHECK("reserved", BrotliDecoderGetErrorCode(state) ==
+      BROTLI_DECODER_ERROR_FORMAT_RESERVED);
+  BrotliDecoderDestroyInstance(state);
+
+  state = BrotliDecoderCreateInstance(NULL, NULL, NULL);
+  available_in = sizeof(nibble);
+  next_in = nibble;
+  CHECK("nibble", BrotliDecoderDecompressStream(state, &available_in,
+      &next_in, &available_out, &next_out, NULL) ==
+      BROTLI_DECODER_RESULT_ERROR);
+  CHECK("nibble", BrotliDecoderGetErrorCode(state) ==
+      BROTLI_DECODER_ERROR_FORMAT_EXUBERANT_NIBBLE);
+  BrotliDecoderDestroyInstance(state);
+}
+
+int main(int argc, char** argv) {
+  int i;
+  if (argc < 3 || argc % 2 != 1) {
+    fprintf(stderr, "usage: decode_test COMPRESSED EXPECTED ...\n");
+    return 2;
+  }
+
+  for (i = 1; i < argc; i += 2) {
+    size_t compressed_size, expected_size;
+    uint8_t* compressed = read_file(argv[i], &compressed_size);
+    uint8_t* expected = read_file(argv[i + 1], &expected_size);
+    const char* name = argv[i];
+
+    test_one_shot(name, compressed, compressed_size, expected, expected_size);
+    test_stream(name, compressed, compressed_size, expected, expected_size,
+                1 << 20, 1 << 20, 0);
+    test_stream(name, compressed, compressed_size, expected, expected_size,
+                1 << 20, 1 << 20, 100);
+    test_stream(name, compressed, compressed_size, expected, expected_size,
+                1, 1, 3);
+    test_stream(name, compressed, compressed_size, expected, expected_size,
+                13, 4099, 1);
+    test_errors(name, compressed, compressed_size);
+    test_alloc(name, compressed, compressed_size, expected_size);
+
+    free(expected);
+    free(compressed);
+  }
+  test_error_codes();
+
+  if (failures > 0) {
+    fprintf(stderr, "%d failures\n", failures);
+    return 1;
+  }
+  printf("%d files decoded\n", (argc - 1) / 2);
+  return 0;
+}
@@ -0,0 +1,325 @@
+/* Tests the C interface of the encoder by compressing files and decompressing
+   them again with the C interface of the decoder.
+
+   Usage: encode_test FILE [FILE ...]
+
+   Built and run by ffi/tests/capi.rs, which builds the library first:
+
+     cargo build -p brotli-ffi
+     cc -std=c99 -Ic/include c/tests/encode_test.c -Ltarget/debug -lbrotli \
+        -Wl,-rpath,target/debug -o encode_test
+*/
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include <brotli/decode.h>
+#include <brotli/encode.h>
+
+static int failures = 0;
+
+#define CHECK(name, condition)                                            \
+  do {                                                                    \
+    if (!(condition)) {                                                   \
+      fprintf(stderr, "%s: %s:%d: %s\n", name, __FILE__, __LINE__,        \
+              #condition);                                                \
+      failures++;                                                         \
+      return;                                                             \
+    }                                                                     \
+  } while (0)
+
+static const uint8_t metadata[] = "metadata the decoder skips";
+
+static void free_func(void* opaque, void* address) {
+  (void)opaque;
+  free(address);
+}
+
+/* Counts the allocations made through it, and fails those past `limit`. */
+typedef struct {
+  size_t allocs;
+  size_t frees;
+  size_t limit;
+} Counter;
+
+static void* counting_alloc(void* opaque, size_t size) {
+  Counter* counter = (Counter*)opaque;
+  if (counter->allocs == counter->limit) return NULL;
+  counter->allocs++;
+  return malloc(size);
+}
+
+static void counting_free(void* opaque, void* address) {
+  Counter* counter = (Counter*)opaque;
+  if (address) counter->frees++;
+  free(address);
+}
+
+static uint8_t* read_file(const char* path, size_t* size) {
+  FILE* file = fopen(path, "rb");
+  uint8_t* data = NULL;
+  size_t capacity = 0;
+  *size = 0;
+  if (!file) {
+    perror(path);
+    exit(2);
+  }
+  for (;;) {
+    if (*size == capacity) {
+      capacity = capacity * 2 + 4096;
+      data = (uint8_t*)realloc(data, capacity);
+    }
+    size_t count = fread(data + *size, 1, capacity - *size, file);
+    if (count == 0) break;
+    *size += count;
+  }
+  fclose(file);
+  return data;
+}
+
+static void test_one_shot(const char* name, const uint8_t* data, size_t size,
+                          int quality, int lgwin, BrotliEncoderMode mode) {
+  size_t max_size = BrotliEncoderMaxCompressedSize(size);
+  size_t encoded_size = max_size;
+  uint8_t* encoded = (uint8_t*)malloc(max_size);
+  uint8_t* decoded = (uint8_t*)malloc(size + 1);
+  size_t decoded_size = size + 1;
+
+  CHECK(name, max_size >= size);
+  CHECK(name, BrotliEncoderCompress(quality, lgwin, mode, size, data,
+      &encoded_size, encoded));
+  CHECK(name, encoded_size <= max_size);
+  CHECK(name, BrotliDecoderDecompress(encoded_size, encoded, &decoded_size,
+      decoded) == BROTLI_DECODER_RESULT_SUCCESS);
+  CHECK(name, decoded_size == size);
+  CHECK(name, memcmp(decoded, data, size) == 0);
+
+  /* A stream that doesn't fit is an error */
+  encoded_size -= 1;
+  CHECK(name, encoded_size == 0 || !BrotliEncoderCompress(quality, lgwin,
+      mode, size, data, &encoded_size, encoded));
+
+  free(decoded);
+  free(encoded);
+}
+
+/* Compresses with the streaming interface, handing out `out_chunk` bytes at a
+   time, and decodes each chunk as soon as it is produced. */
+typedef struct {
+  const char* name;
+  BrotliEncoderState* encoder;
+  BrotliDecoderState* decoder;
+  size_t out_chunk;
+  uint8_t* decoded;
+  size_t decoded_size;
+  size_t capacity;
+  size_t total_out;
+} Stream;
+
+/* Performs `op` until it is complete, passing the input in `in_chunk` bytes
+   at a time. */
+static void run(Stream* s, BrotliEncoderOperation op, const uint8_t* input,
+                size_t input_size, size_t in_chunk) {
+  const char* name = s->name;
+  uint8_t* buffer = (uint8_t*)malloc(s->out_chunk);
+  size_t in_pos = 0;
+  size_t available_in = 0;
+  const uint8_t* next_in = input;
+  if (op == BROTLI_OPERATION_EMIT_METADATA) in_chunk = input_size;
+
+  for (;;) {
+    size_t available_out = s->out_chunk;
+    uint8_t* next_out = buffer;
+    size_t total_out = 0;
+    if (available_in == 0 && in_pos < input_size) {
+      next_in = input + in_pos;
+      available_in = input_size - in_pos < in_chunk ? input_size - in_pos
+                                                     : in_chunk;
+      in_pos += available_in;
+    }
+
+    CHECK(name, BrotliEncoderCompressStream(s->encoder, op, &available_in,
+        &next_in, &available_out, &next_out, &total_out));
+    size_t produced = s->out_chunk - available_out;
+    CHECK(name, (size_t)(next_out - buffer) == produced);
+    s->total_out += produced;
+    CHECK(name, total_out == s->total_out);
+
+    /* Everything produced so far can be decoded */
+    const uint8_t* next_encoded = buffer;
+    size_t available_decoded = s->capacity - s->decoded_size;
+    uint8_t* next_decoded = s->decoded + s->decoded_size;
+    BrotliDecoderResult result = BrotliDecoderDecompressStream(s->decoder,
+        &produced, &next_encoded, &available_decoded, &next_decoded, NULL);
+    CHECK(name, produced == 0);
+    CHECK(name, result == BROTLI_DECODER_RESULT_NEEDS_MORE_INPUT ||
+                result == BROTLI_DECODER_RESULT_SUCCESS);
+    s->decoded_size = (size_t)(next_decoded - s->decoded);
+
+    if (available_in > 0 || in_pos < input_size ||
+        BrotliEncoderHasMoreOutput(s->encoder)) {
+      continue;
+    }
+    if (op != BROTLI_OPERATION_FINISH || BrotliEncoderIsFinished(s->encoder)) {
+      break;
+    }
+  }
+  free(buffer);
+}
+
+static void test_stream(const char* name, const uint8_t* data, size_t size,
+                        int quality, size_t in_chunk, size_t out_chunk,
+                        uint32_t npostfix, uint32_t ndirect) {
+  Counter counter = {0, 0, (size_t)-1};
+  Stream s;
+  size_t half = size / 2;
+  size_t available_in = 1;
+  const uint8_t* next_in = data;
+  size_t available_out = 0;
+  uint8_t* next_out = NULL;
+
+  s.name = name;
+  s.encoder = BrotliEncoderCreateInstance(counting_alloc, counting_free,
+      &counter);
+  s.decoder = BrotliDecoderCreateInstance(NULL, NULL, NULL);
+  s.out_chunk = out_chunk;
+  s.capacity = size + 1;
+  s.decoded = (uint8_t*)malloc(s.capacity);
+  s.decoded_size = 0;
+  s.total_out = 0;
+  CHECK(name, s.encoder != NULL && s.decoder != NULL);
+  CHECK(name, BrotliEncoderSetParameter(s.encoder, BROTLI_PARAM_QUALITY,
+      (uint32_t)quality));
+  CHECK(name, BrotliEncoderSetParameter(s.encoder, BROTLI_PARAM_LGWIN, 18));
+  CHECK(name, BrotliEncoderSetParameter(s.encoder, BROTLI_PARAM_SIZE_HINT,
+      (uint32_t)size));
+  CHECK(name, BrotliEncoderSetParameter(s.encoder,
+      BROTLI_PARAM_DISABLE_LITERAL_CONTEXT_MODELING, BROTLI_TRUE));
+  CHECK(name, BrotliEncoderSetParameter(s.encoder, BROTLI_PARAM_NPOSTFIX,
+      npostfix));
+  CHECK(name, BrotliEncoderSetParameter(s.encoder, BROTLI_PARAM_NDIRECT,
+      ndirect));
+  CHECK(name, !BrotliEncoderSetParameter(s.encoder, BROTLI_PARAM_LARGE_WINDOW,
+      BROTLI_TRUE));
+
+  /* A flush makes the first half decodable */
+  run(&s, BROTLI_OPERATION_PROCESS, data, half, in_chunk);
+  run(&s, BROTLI_OPERATION_FLUSH, NULL, 0, in_chunk);
+  CHECK(name, s.decoded_size == half);
+  CHECK(name, memcmp(s.decoded, data, half) == 0);
+  CHECK(name, !BrotliEncoderSetParameter(s.encoder, BROTLI_PARAM_QUALITY, 1));
+
+  run(&s, BROTLI_OPERATION_EMIT_METADATA, metadata, sizeof(metadata),
+      in_chunk);
+  CHECK(name, s.decoded_size == half);
+  run(&s, BROTLI_OPERATION_PROCESS, data + half, size - half, in_chunk);
+  run(&s, BROTLI_OPERATION_FINISH, NULL, 0, in_chunk);
+
+  CHECK(name, BrotliEncoderIsFinished(s.encoder));
+  CHECK(name, BrotliDecoderIsFinished(s.decoder));
+  CHECK(name, s.decoded_size == size);
+  CHECK(name, memcmp(s.decoded, data, size) == 0);
+
+  /* Nothing can follow the end of the stream */
+  CHECK(name, !BrotliEncoderCompressStream(s.encoder, BROTLI_OPERATION_PROCESS,
+      &available_in, &next_in, &available_out, &next_out, NULL));
+  CHECK(name, !BrotliEncoderCompressStream(s.encoder,
+      BROTLI_OPERATION_EMIT_METADATA, &available_in, &next_in, &available_out,
+      &next_out, NULL));
+
+  BrotliEncoderDestroyInstance(s.encoder);
+  BrotliDecoderDestroyInstance(s.decoder);
+  free(s.decoded);
+
+  /* The state came from the allocation functions */
+  CHECK(name, counter.allocs == 1 && counter.frees == 1);
+}
+
+static void test_errors(void) {
+  const char* name = "errors";
+  BrotliEncoderState* state;
+  size_t available_in = 1;
+  const uint8_t* next_in = metadata;
+  size_t available_out = 1;
+  uint8_t* next_out = NULL;
+  uint8_t output[1];
+  size_t size = sizeof(output);
+  Counter counter = {0, 0, 0};
+
+  CHECK(name, BrotliEncoderCreateInstance(NULL, free_func, NULL) == NULL);
+  CHECK(name, BrotliEncoderCreateInstance(counting_alloc, counting_free,
+      &counter) == NULL);
+  CHECK(name, BrotliEncoderMaxCompressedSize(0) == 2);
+  CHECK(name, BrotliEncoderMaxCompressedSize(~(size_t)0) == 0);
+  CHECK(name, !BrotliEncoderCompress(BROTLI_DEFAULT_QUALITY,
+      BROTLI_DEFAULT_WINDOW, BROTLI_DEFAULT_MODE, 1, NULL, &size, output));
+  CHECK(name, !BrotliEncoderCompress(BROTLI_DEFAULT_QUALITY,
+      BROTLI_DEFAULT_WINDOW, 3, 0, NULL, &size, output));
+
+  /* Without a state, nothing is done */
+  CHECK(name, !BrotliEncoderSetParameter(NULL, BROTLI_PARAM_QUALITY, 1));
+  CHECK(name, !BrotliEncoderHasMoreOutput(NULL));
+  CHECK(name, !BrotliEncoderIsFinished(NULL));
+
+  state = BrotliEncoderCreateInstance(NULL, NULL, NULL);
+  CHECK(name, !BrotliEncoderSetParameter(state, BROTLI_PARAM_MODE, 3));
+  CHECK(name, !BrotliEncoderSetParameter(state, 10, 0));
+  CHECK(name, !BrotliEncoderSetParameter(state, -1, 0));
+  CHECK(name, BrotliEncoderSetParameter(state, BROTLI_PARAM_MODE,
+      BROTLI_MODE_TEXT));
+
+  /* Unknown operations are rejected */
+  CHECK(name, !BrotliEncoderCompressStream(state, 4, &available_in, &next_in,
+      &available_out, &next_out, NULL));
+
+  /* Output space without an output buffer */
+  CHECK(name, !BrotliEncoderCompressStream(state, BROTLI_OPERATION_PROCESS,
+      &available_in, &next_in, &available_out, &next_out, NULL));
+
+  /* A flush has to complete before new input is taken */
+  next_out = output;
+  CHECK(name, BrotliEncoderCompressStream(state, BROTLI_OPERATION_FLUSH,
+      &available_in, &next_in, &available_out, &next_out, NULL));
+  CHECK(name, available_in == 0 && available_out == 0);
+  CHECK(name, BrotliEncoderHasMoreOutput(state));
+  available_in = 1;
+  CHECK(name, !BrotliEncoderCompressStream(state, BROTLI_OPERATION_PROCESS,
+      &available_in, &next_in, &available_out, &next_out, NULL));
+  CHECK(name, !BrotliEncoderCompressStream(state,
+      BROTLI_OPERATION_EMIT_METADATA, &available_in, &next_in, &available_out,
+      &next_out, NULL));
+  BrotliEncoderDestroyInstance(state);
+}
+
+int main(int argc, char** argv) {
+  int i;
+  if (argc < 2) {
+    fprintf(stderr, "usage: encode_test FILE ...\n");
+    return 2;
+  }
+
+  test_errors();
+  for (i = 1; i < argc; i++) {
+    size_t size;
+    uint8_t* data = read_file(argv[i], &size);
+    const char* name = argv[i];
+
+    test_one_shot(name, data, size, 0, 22, BROTLI_MODE_GENERIC);
+    test_one_shot(name, data, size, 6, 16, BROTLI_MODE_TEXT);
+    test_one_shot(name, data, size, 3, 10, BROTLI_MODE_FONT);
+    test_stream(name, data, size, 1, 1 << 20, 1 << 20, 0, 0);
+    test_stream(name, data, size, 1, 1, 1, 2, 8);
+    test_stream(name, data, size, 5, 4099, 13, 3, 120);
+    test_stream(name, data, size, 5, 4099, 4099, 1, 13);
+
+    free(data);
+  }
+
+  if (failures > 0) {
+    fprintf(stderr, "%d failures\n", failures);
+    return 1;
+  }
+  printf("%d files round-tripped\n", argc - 1);
+  return 0;
+}
@@ -0,0 +1,15 @@
+//! # C library
+//!
+//! Builds the C interface of the `brotli` crate as `libbrotli`, a shared and a static library
+//! that C programs written against Google's `brotli/decode.h` and `brotli/encode.h` can link
+//! with instead of the C library. The headers are in `c/include`.
+//!
+//! ```text
+//! cargo build --release -p brotli-ffi
+//! ```
+
+extern crate brotli;
+
+pub use brotli::ffi::*;
+#[cfg(feature = "encoder")]
+pub use brotli::ffi_encoder::*;
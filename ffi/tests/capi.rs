@@ -0,0 +1,88 @@
+//! Builds the library of this package, and the C test harnesses in `c/tests` with the system C
+//! compiler. Links them together, and runs them on the test data.
+
+#![cfg(unix)]
+
+use std::env;
+use std::path::{ Path, PathBuf };
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// The files of `tests/testdata` the harnesses run on, each with a `.compressed` version.
+const FIXTURES: [&str; 16] = ["10x10y", "64x", "asyoulik.txt", "backward65536", "compressed_file",
+                              "compressed_repeated", "empty", "mapsdatazrh", "monkey", "quickfox",
+                              "quickfox_repeated", "random_org_10k.bin", "ukkonooa", "x", "xyzzy", "zeros"];
+
+/// The directory of the `brotli` crate, which holds the headers, the harnesses and the test data.
+fn crate_dir() -> &'static Path {
+    Path::new( env!( "CARGO_MANIFEST_DIR" )).parent().unwrap()
+}
+
+/// Builds the library, once, and returns the directory holding it. Cargo only builds the
+/// libraries Rust code can link with for tests, so it is built with `cargo build`, in a target
+/// directory of its own, as the one of the tests stays locked while they run.
+fn library_dir() -> &'static Path {
+    static LIBRARY_DIR: OnceLock<PathBuf> = OnceLock::new();
+    LIBRARY_DIR.get_or_init( || {
+        let target_dir = Path::new( env!( "CARGO_TARGET_TMPDIR" )).join( "capi" );
+        let mut command = Command::new( env::var( "CARGO" ).unwrap_or_else( |_| "cargo".to_string() ));
+        command.args( ["build", "--package", env!( "CARGO_PKG_NAME" ), "--no-default-features"] );
+        if cfg!( feature = "encoder" ) {
+            command.args( ["--features", "encoder"] );
+        }
+        let status = command.arg( "--target-dir" ).arg( &target_dir ).status().expect( "failed to run cargo" );
+        assert!( status.success(), "the library failed to build" );
+        target_dir.join( "debug" )
+    } )
+}
+
+/// Compiles `c/tests/{name}.c`, and returns the path of the executable.
+fn build_harness( name: &str ) -> PathBuf {
+    let library_dir = library_dir();
+    let executable = Path::new( env!( "CARGO_TARGET_TMPDIR" )).join( name );
+    let status = Command::new( env::var( "CC" ).unwrap_or_else( |_| "cc".to_string() ))
+        .current_dir( crate_dir() )
+        .args( ["-std=c99", "-Wall", "-Wextra", "-Werror", "-Ic/include"] )
+        .arg( format!( "c/tests/{}.c", name ))
+        .arg( format!( "-L{}", library_dir.display() ))
+        .arg( "-lbrotli" )
+        .arg( format!( "-Wl,-rpath,{}", library_dir.display() ))
+        .arg( "-o" )
+        .arg( &executable )
+        .status()
+        .expect( "failed to run the C compiler" );
+    assert!( status.success(), "{}.c failed to build", name );
+    executable
+}
+
+/// Builds the harness `name`, and returns a command running it in the crate directory. Cargo puts
+/// its own target directory on the library path of tests, ahead of the run path of the harness,
+/// so the path is pointed at the library just built, rather than one left by `cargo build`.
+fn harness( name: &str ) -> Command {
+    let mut command = Command::new( build_harness( name ));
+    command.current_dir( crate_dir() )
+           .env( "LD_LIBRARY_PATH", library_dir() )
+           .env( "DYLD_LIBRARY_PATH", library_dir() );
+    command
+}
+
+#[test]
+fn test_c_decoder() {
+    let mut command = harness( "decode_test" );
+    for name in FIXTURES.iter() {
+        command.arg( format!( "tests/testdata/{}.compressed", name )).arg( format!( "tests/testdata/{}", name ));
+    }
+    let status = command.status().unwrap();
+    assert!( status.success() );
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_c_encoder() {
+    let mut command = harness( "encode_test" );
+    for name in FIXTURES.iter() {
+        command.arg( format!( "tests/testdata/{}", name ));
+    }
+    let status = command.status().unwrap();
+    assert!( status.success() );
+}
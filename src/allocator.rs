@@ -0,0 +1,235 @@
+//! # Allocators
+//!
+//! The decoder takes its large buffers from a `BrotliAlloc`: the ring buffer holding the sliding
+//! window, the context maps, and the tables of the prefix codes. `HeapAlloc` takes them from the
+//! global allocator. An arena or a fixed pool can be used instead by implementing the trait, and
+//! handing it to `Decoder::with_allocator()`. `StaticAlloc` keeps them in fixed arrays inside
+//! the decoder.
+//!
+//! The decoder allocates the ring buffer once, before the first meta-block with data. The context
+//! maps and the prefix code tables are allocated as the meta-block headers ask for them, and
+//! replaced with larger ones when a later header needs more room. A buffer that is replaced is
+//! handed back to the allocator first; the ones still in use are dropped along with the decoder.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::ops::{ Deref, DerefMut };
+pub use decoder::{ context_maps_size, trees_size, MAX_CONTEXT_MAPS_SIZE, MAX_TREES_SIZE };
+pub use huffman::{ Code, HUFFMAN_MAX_TABLE_SIZE };
+use { BrotliError, Result };
+
+/// Allocates the buffers of the decoder.
+///
+/// Buffers are allocated in place, so that a buffer type can hold its memory inline. The `Default`
+/// value of a buffer type must be empty; it stands for a buffer not allocated yet. The contents of
+/// a new buffer don't matter, the decoder initializes everything it reads.
+pub trait BrotliAlloc {
+    /// A buffer of bytes.
+    type Bytes: Default + DerefMut<Target = [u8]>;
+
+    /// A buffer of prefix code table entries.
+    type Codes: Default + DerefMut<Target = [Code]>;
+
+    /// The base 2 logarithm of the largest window the decoder may allocate a ring buffer for.
+    /// Streams with a larger window are rejected with `BrotliError::WindowTooLarge`.
+    const MAX_WINDOW_BITS: u32 = 24;
+
+    /// The largest number of block types of a category, and of prefix codes in a context map, the
+    /// decoder may allocate buffers for. Meta-blocks with more are rejected with
+    /// `BrotliError::TooManyTypes`.
+    const MAX_NUM_TYPES: usize = 256;
+
+    /// Makes the empty `buf` a buffer of `len` bytes. Fails with `BrotliError::OutOfMemory` if they
+    /// can't be had.
+    fn alloc_u8( &mut self, buf: &mut Self::Bytes, len: usize ) -> Result<()>;
+
+    /// Makes the empty `buf` a buffer of `len` table entries. Fails with `BrotliError::OutOfMemory`
+    /// if they can't be had.
+    fn alloc_codes( &mut self, buf: &mut Self::Codes, len: usize ) -> Result<()>;
+
+    /// Takes back the memory of a buffer the decoder no longer uses, leaving it empty. The memory
+    /// is dropped by default.
+    fn free_u8( &mut self, buf: &mut Self::Bytes ) {
+        *buf = Self::Bytes::default();
+    }
+
+    /// Takes back the memory of a buffer of table entries the decoder no longer uses, leaving it
+    /// empty. The memory is dropped by default.
+    fn free_codes( &mut self, buf: &mut Self::Codes ) {
+        *buf = Self::Codes::default();
+    }
+}
+
+/// Allocates from the global allocator. A failed allocation is reported instead of aborting.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct HeapAlloc;
+
+impl HeapAlloc {
+
+    fn alloc<T: Copy + Default>( len: usize ) -> Result<Box<[T]>> {
+        let mut buf = Vec::new();
+        buf.try_reserve_exact( len ).map_err( |_| BrotliError::OutOfMemory )?;
+        buf.resize( len, T::default() );
+        Ok( buf.into_boxed_slice() )
+    }
+}
+
+impl BrotliAlloc for HeapAlloc {
+    type Bytes = Box<[u8]>;
+    type Codes = Box<[Code]>;
+
+    fn alloc_u8( &mut self, buf: &mut Box<[u8]>, len: usize ) -> Result<()> {
+        *buf = HeapAlloc::alloc( len )?;
+        Ok( () )
+    }
+
+    fn alloc_codes( &mut self, buf: &mut Box<[Code]>, len: usize ) -> Result<()> {
+        *buf = HeapAlloc::alloc( len )?;
+        Ok( () )
+    }
+}
+
+/// A buffer of up to `N` elements, held inline.
+pub struct FixedBuffer<T, const N: usize> {
+    data: [T; N],
+    len: usize
+}
+
+impl<T: Copy + Default, const N: usize> Default for FixedBuffer<T, N> {
+    fn default() -> Self {
+        FixedBuffer { data: [T::default(); N], len: 0 }
+    }
+}
+
+impl<T, const N: usize> FixedBuffer<T, N> {
+
+    /// Makes the buffer hold `len` elements, if they fit.
+    fn set_len( &mut self, len: usize ) -> Result<()> {
+        if len > N {
+            return Err( BrotliError::OutOfMemory )
+        }
+        self.len = len;
+        Ok( () )
+    }
+}
+
+impl<T, const N: usize> Deref for FixedBuffer<T, N> {
+    type Target = [T];
+
+    fn deref( &self ) -> &[T] {
+        &self.data[..self.len]
+    }
+}
+
+impl<T, const N: usize> DerefMut for FixedBuffer<T, N> {
+    fn deref_mut( &mut self ) -> &mut [T] {
+        &mut self.data[..self.len]
+    }
+}
+
+/// Keeps the buffers of the decoder in fixed arrays inside it, for streams with windows of up to
+/// `W`, and meta-blocks with up to `T` block types and prefix codes of each category, never
+/// touching the heap. Other streams are rejected with `BrotliError::WindowTooLarge` or
+/// `BrotliError::TooManyTypes`.
+///
+/// The ring buffer and the context maps each take the larger of the window size and
+/// `context_maps_size()` bytes, and the prefix codes take `trees_size()` table entries of 4 bytes,
+/// about 10.4 KiB per type. Together with the rest of the decoder, a `Decoder` holding a
+/// `SliceBitReader` takes, on 64-bit targets:
+///
+/// | Window     | `Types16` | `Types64` | `Types256` |
+/// |------------|-----------|-----------|------------|
+/// | `Window10` |   184 808 |   696 296 |  2 742 248 |
+/// | `Window11` |   186 728 |   696 296 |  2 742 248 |
+/// | `Window12` |   190 824 |   696 296 |  2 742 248 |
+/// | `Window13` |   199 016 |   703 976 |  2 742 248 |
+/// | `Window14` |   215 400 |   720 360 |  2 742 248 |
+/// | `Window15` |   248 168 |   753 128 |  2 772 968 |
+/// | `Window16` |   313 704 |   818 664 |  2 838 504 |
+///
+/// The decoder is built on the stack, which has to have room for it. To keep it off the heap, its
+/// input has to be in memory for a `SliceBitReader`, and no metadata callback can be set.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct StaticAlloc<W, T = Types256> {
+    window: PhantomData<W>,
+    types: PhantomData<T>
+}
+
+/// The larger of two sizes.
+const fn max_size( a: usize, b: usize ) -> usize {
+    if a > b { a } else { b }
+}
+
+macro_rules! static_types {
+    ( $name:ident, $num_types:expr ) => {
+        #[doc = concat!( "Up to ", stringify!( $num_types ), " block types and prefix codes of each category, for `StaticAlloc`." )]
+        #[derive(Debug, Copy, Clone, Default)]
+        pub struct $name;
+    }
+}
+
+static_types!( Types16, 16 );
+static_types!( Types64, 64 );
+static_types!( Types256, 256 );
+
+macro_rules! static_alloc {
+    ( $window:ident, $bits:expr, $types:ident, $num_types:expr ) => {
+        impl BrotliAlloc for StaticAlloc<$window, $types> {
+            type Bytes = FixedBuffer<u8, { max_size( 1 << $bits, context_maps_size( $num_types )) }>;
+            type Codes = FixedBuffer<Code, { trees_size( $num_types ) }>;
+            const MAX_WINDOW_BITS: u32 = $bits;
+            const MAX_NUM_TYPES: usize = $num_types;
+
+            fn alloc_u8( &mut self, buf: &mut Self::Bytes, len: usize ) -> Result<()> {
+                buf.set_len( len )
+            }
+
+            fn alloc_codes( &mut self, buf: &mut Self::Codes, len: usize ) -> Result<()> {
+                buf.set_len( len )
+            }
+
+            fn free_u8( &mut self, buf: &mut Self::Bytes ) {
+                buf.len = 0;
+            }
+
+            fn free_codes( &mut self, buf: &mut Self::Codes ) {
+                buf.len = 0;
+            }
+        }
+    }
+}
+
+macro_rules! static_window {
+    ( $name:ident, $bits:expr ) => {
+        #[doc = concat!( "A window of 2^", stringify!( $bits ), " bytes, for `StaticAlloc`." )]
+        #[derive(Debug, Copy, Clone, Default)]
+        pub struct $name;
+
+        static_alloc!( $name, $bits, Types16, 16 );
+        static_alloc!( $name, $bits, Types64, 64 );
+        static_alloc!( $name, $bits, Types256, 256 );
+    }
+}
+
+static_window!( Window10, 10 );
+static_window!( Window11, 11 );
+static_window!( Window12, 12 );
+static_window!( Window13, 13 );
+static_window!( Window14, 14 );
+static_window!( Window15, 15 );
+static_window!( Window16, 16 );
+
+#[test]
+fn test_heap_alloc() {
+    let mut allocator = HeapAlloc;
+    let mut bytes = Box::default();
+    allocator.alloc_u8( &mut bytes, 100 ).unwrap();
+    assert_eq!( bytes.len(), 100 );
+    allocator.free_u8( &mut bytes );
+    assert!( bytes.is_empty() );
+    let mut codes = Box::default();
+    allocator.alloc_codes( &mut codes, HUFFMAN_MAX_TABLE_SIZE ).unwrap();
+    assert_eq!( codes.len(), HUFFMAN_MAX_TABLE_SIZE );
+    assert!( matches!( allocator.alloc_u8( &mut bytes, usize::MAX ), Err( BrotliError::OutOfMemory )));
+}
@@ -0,0 +1,1119 @@
+//! # Stream assembler
+//!
+//! Builds a Brotli stream from a text listing of its contents, in the format written by the
+//! disassembler: one instruction per line, optionally preceded by its bit offset, which is
+//! ignored. Every field is written exactly as given, so the assembler can produce streams that
+//! no encoder would, including invalid ones, to test decoders with.
+//!
+//! The listing of a stream written by this crate's encoder assembles back to the same stream.
+//! Other encoders make choices that a listing doesn't record, such as how the code lengths of a
+//! complex prefix code are run length coded. Their streams assemble to streams that decode to
+//! the same output, but aren't necessarily bit-identical.
+//!
+//! ## Syntax
+//!
+//! `#` starts a comment. Strings are quoted, with `\\`, `\"`, `\'`, `\n`, `\r`, `\t` and `\xNN`
+//! escapes, lists are written as `[1, 2, 3]`, and numbers may be given in hex with `0x` or in
+//! binary with `0b`. The instructions are:
+//!
+//! * `window_bits N`
+//! * `meta_block compressed|uncompressed length=N last=B [nibbles=K]`
+//! * `meta_block metadata length=N last=B [skip_bytes=K]`
+//! * `meta_block empty last=1`
+//! * `data "..."`, the contents of an uncompressed meta-block, and `metadata "..."`, the payload
+//!   of a metadata meta-block. Both pad the stream to a byte boundary first.
+//! * `block_types CATEGORY N [length=L]`. With two or more block types, the block type and
+//!   block length codes follow as `prefix_code CATEGORY_block_type 0` and
+//!   `prefix_code CATEGORY_block_length 0`.
+//! * `distance_parameters postfix_bits=P direct_codes=D context_modes=[...]`
+//! * `context_map CATEGORY trees=N [MAP] [rle=R] [imtf=B]`, where `R` is the largest run length
+//!   prefix used for runs of zeros. With two or more trees, the code of the context map follows
+//!   as `prefix_code CATEGORY_context_map 0`.
+//! * `prefix_code KIND INDEX [simple|complex] SYMBOL:LENGTH ...`. A code is stored as a simple
+//!   prefix code if its code lengths allow it, unless `complex` is given.
+//! * `block_switch CATEGORY type=T length=L [code=S]`. Block switches are written where the
+//!   decoder reads them: when the current block of their category ends.
+//! * `command [CODE] insert=N "LITERALS" copy=C [DISTANCE]`, where `DISTANCE` is one of
+//!   `last_distance [D] [code=K]`, `distance D [code=K]` or
+//!   `dictionary [D] [word=W transform=T] [length=L] [code=K]`. The command code and the
+//!   distance code are derived from the lengths and the distance unless they are given.
+//!
+//! The bits can also be written directly:
+//!
+//! * `bits N VALUE` writes a field of `N` bits.
+//! * `align [VALUE]` pads the stream to a byte boundary with the bits of `VALUE`.
+//! * `bytes "..."` writes 8-bit fields, without padding first.
+
+use bitwriter::BitBuffer;
+use context::context_id;
+use dictionary;
+use disasm::{ Category, CodeKind };
+use encoder::encode_window_bits;
+use entropy_encode::PrefixCode;
+use prefix::{ CMD_LUT, PREFIX_CODE_RANGE, NUM_DISTANCE_SHORT_CODES, DISTANCE_SHORT_CODE_INDEX,
+              DISTANCE_SHORT_CODE_OFFSET, block_length_code, command_code, distance_code };
+use transforms::TRANSFORMS;
+use std::cmp::{ min, max };
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{ self, Display, Formatter };
+use std::result;
+
+const NUM_LITERAL_SYMBOLS: usize = 256;
+const NUM_COMMAND_SYMBOLS: usize = 704;
+const NUM_BLOCK_LENGTH_SYMBOLS: usize = 26;
+
+const LITERAL_CONTEXT_BITS: usize = 6;
+const DISTANCE_CONTEXT_BITS: usize = 2;
+
+const LITERAL: usize = 0;
+const COMMAND: usize = 1;
+const DISTANCE: usize = 2;
+
+/// The number of blocks of a category with a single block type, which never ends.
+const INFINITE_BLOCK_COUNT: usize = 1 << 28;
+
+const MAX_META_BLOCK_LENGTH: usize = 1 << 24;
+const MAX_TRANSFORMED_WORD_LENGTH: usize = 64;
+
+/// An error in the assembler source, and the line it was found on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String
+}
+
+impl Display for AsmError {
+    fn fmt( &self, f: &mut Formatter ) -> fmt::Result {
+        write!( f, "line {}: {}", self.line, self.message )
+    }
+}
+
+impl Error for AsmError {}
+
+type AsmResult<T> = result::Result<T, String>;
+
+/// Assembles the listing in `source` into a Brotli stream.
+pub fn assemble( source: &str ) -> result::Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::new();
+    let mut line = 0;
+    for (index, text) in source.lines().enumerate() {
+        line = index + 1;
+        let result = parse_line( text ).and_then( |fields| {
+            if fields.values.is_empty() { Ok( () ) } else { assembler.instruction( &fields ) }
+        });
+        result.map_err( |message| AsmError { line: line, message: message } )?;
+    }
+
+    assembler.finish().map_err( |message| AsmError { line: line, message: message } )
+}
+
+/// A field of an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Word( String ),
+    Str( Vec<u8> ),
+    List( Vec<u64> )
+}
+
+/// The fields of a line: the instruction name and other positional values, and `key=value`
+/// pairs.
+struct Fields {
+    values: Vec<Value>,
+    pairs: Vec<(String, Value)>
+}
+
+fn parse_number( text: &str ) -> AsmResult<u64> {
+    let result = if let Some( hex ) = text.strip_prefix( "0x" ) {
+        u64::from_str_radix( hex, 16 )
+    } else if let Some( binary ) = text.strip_prefix( "0b" ) {
+        u64::from_str_radix( binary, 2 )
+    } else {
+        text.parse()
+    };
+    result.map_err( |_| format!( "invalid number '{}'", text ))
+}
+
+fn is_number( value: &Value ) -> bool {
+    match *value {
+        Value::Word( ref word ) => parse_number( word ).is_ok(),
+        _ => false
+    }
+}
+
+/// Parses a quoted string starting at `bytes[*i]`.
+fn parse_string( bytes: &[u8], i: &mut usize ) -> AsmResult<Vec<u8>> {
+    let mut string = Vec::new();
+    *i += 1;
+    loop {
+        let byte = *bytes.get( *i ).ok_or( "unterminated string" )?;
+        *i += 1;
+        match byte {
+            b'"' => return Ok( string ),
+            b'\\' => {
+                let escape = *bytes.get( *i ).ok_or( "unterminated string" )?;
+                *i += 1;
+                string.push( match escape {
+                    b'n' => b'\n',
+                    b'r' => b'\r',
+                    b't' => b'\t',
+                    b'\\' | b'"' | b'\'' => escape,
+                    b'x' => {
+                        let hex = bytes.get( *i .. *i + 2 ).and_then( |hex| ::std::str::from_utf8( hex ).ok() );
+                        let byte = hex.and_then( |hex| u8::from_str_radix( hex, 16 ).ok() ).ok_or( "invalid \\x escape" )?;
+                        *i += 2;
+                        byte
+                    }
+                    _ => return Err( format!( "invalid escape '\\{}'", escape as char ))
+                });
+            }
+            _ => string.push( byte )
+        }
+    }
+}
+
+/// Splits a line into fields, skipping a leading bit offset and comments.
+fn parse_line( text: &str ) -> AsmResult<Fields> {
+    let bytes = text.as_bytes();
+    let mut fields = Fields { values: Vec::new(), pairs: Vec::new() };
+    let mut i = 0;
+    while i < bytes.len() && bytes[i] != b'#' {
+        if bytes[i].is_ascii_whitespace() {
+            i += 1;
+            continue
+        }
+
+        let start = i;
+        while i < bytes.len() && !bytes[i].is_ascii_whitespace() && !b"=\"[#".contains( &bytes[i] ) {
+            i += 1;
+        }
+        let key = if i < bytes.len() && bytes[i] == b'=' {
+            i += 1;
+            Some( text[start .. i - 1].to_string() )
+        } else {
+            i = start;
+            None
+        };
+
+        let value = match bytes.get( i ) {
+            Some( &b'"' ) => Value::Str( parse_string( bytes, &mut i )? ),
+            Some( &b'[' ) => {
+                let end = text[i..].find( ']' ).ok_or( "unterminated list" )? + i;
+                let list = text[i + 1 .. end].split( |c: char| c == ',' || c.is_whitespace() )
+                                             .filter( |item| !item.is_empty() )
+                                             .map( parse_number )
+                                             .collect::<AsmResult<Vec<u64>>>()?;
+                i = end + 1;
+                Value::List( list )
+            }
+            _ => {
+                let start = i;
+                while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'#' {
+                    i += 1;
+                }
+                Value::Word( text[start .. i].to_string() )
+            }
+        };
+
+        match key {
+            Some( key ) => fields.pairs.push( (key, value) ),
+            None => fields.values.push( value )
+        }
+    }
+
+    // The listing of the disassembler starts with the bit offset
+    if fields.values.first().is_some_and( is_number ) {
+        fields.values.remove( 0 );
+    }
+    Ok( fields )
+}
+
+impl Fields {
+
+    /// Fails if the line has a `key=value` pair that isn't one of `keys`.
+    fn expect_keys( &self, keys: &[&str] ) -> AsmResult<()> {
+        match self.pairs.iter().find( |&(key, _)| !keys.contains( &key.as_str() )) {
+            Some( (key, _) ) => Err( format!( "unexpected field '{}'", key )),
+            None => Ok( () )
+        }
+    }
+
+    fn get( &self, key: &str ) -> Option<&Value> {
+        self.pairs.iter().find( |&(k, _)| k == key ).map( |(_, value)| value )
+    }
+
+    fn number( &self, key: &str ) -> AsmResult<Option<u64>> {
+        match self.get( key ) {
+            Some( Value::Word( word ) ) => parse_number( word ).map( Some ),
+            Some( _ ) => Err( format!( "'{}' must be a number", key )),
+            None => Ok( None )
+        }
+    }
+
+    fn required( &self, key: &str ) -> AsmResult<u64> {
+        self.number( key )?.ok_or_else( || format!( "missing field '{}'", key ))
+    }
+
+    fn flag( &self, key: &str ) -> AsmResult<bool> {
+        match self.number( key )? {
+            None | Some( 0 ) => Ok( false ),
+            Some( 1 ) => Ok( true ),
+            Some( _ ) => Err( format!( "'{}' must be 0 or 1", key ))
+        }
+    }
+
+    fn word( &self, index: usize ) -> AsmResult<&str> {
+        match self.values.get( index ) {
+            Some( Value::Word( word ) ) => Ok( word ),
+            _ => Err( "missing operand".to_string() )
+        }
+    }
+
+    fn number_at( &self, index: usize ) -> AsmResult<u64> {
+        parse_number( self.word( index )? )
+    }
+
+    /// Fails if there are more than `count` positional values, including the instruction name.
+    fn expect_values( &self, count: usize ) -> AsmResult<()> {
+        if self.values.len() > count {
+            return Err( format!( "unexpected operand {:?}", self.values[count] ))
+        }
+        Ok( () )
+    }
+}
+
+fn parse_category( name: &str ) -> AsmResult<usize> {
+    match name {
+        "literal" => Ok( LITERAL ),
+        "command" => Ok( COMMAND ),
+        "distance" => Ok( DISTANCE ),
+        _ => Err( format!( "unknown category '{}'", name ))
+    }
+}
+
+fn parse_code_kind( name: &str ) -> AsmResult<CodeKind> {
+    let category = |name: &str| parse_category( name ).map( Category::from_index );
+    if let Some( name ) = name.strip_suffix( "_block_type" ) {
+        Ok( CodeKind::BlockType( category( name )? ))
+    } else if let Some( name ) = name.strip_suffix( "_block_length" ) {
+        Ok( CodeKind::BlockLength( category( name )? ))
+    } else if let Some( name ) = name.strip_suffix( "_context_map" ) {
+        Ok( CodeKind::ContextMap( category( name )? ))
+    } else {
+        Ok( CodeKind::Symbols( category( name )? ))
+    }
+}
+
+/// The largest block length that the block length code can represent.
+fn max_block_length() -> usize {
+    PREFIX_CODE_RANGE[25].offset as usize + ( 1 << PREFIX_CODE_RANGE[25].nbits ) - 1
+}
+
+/// Checks that `value` fits in `bits` bits.
+fn check_fits( value: u64, bits: u32, what: &str ) -> AsmResult<()> {
+    if bits < 64 && value >> bits != 0 {
+        return Err( format!( "{} {} doesn't fit in {} bits", what, value, bits ))
+    }
+    Ok( () )
+}
+
+fn write_var_len_uint8( value: usize, bw: &mut BitBuffer ) {
+    if value == 0 {
+        bw.put_bits( 1, 0 );
+    } else {
+        let n = value.ilog2();
+        bw.put_bits( 1, 1 );
+        bw.put_bits( 3, n as u64 );
+        bw.put_bits( n, ( value - ( 1 << n )) as u64 );
+    }
+}
+
+/// Writes `symbol` with `code`, the prefix code `kind` `index`.
+fn write_symbol( bw: &mut BitBuffer, code: Option<&PrefixCode>, kind: CodeKind, index: usize, symbol: usize ) -> AsmResult<()> {
+    match code {
+        Some( code ) if code.contains( symbol ) => {
+            code.write_symbol( symbol, bw );
+            Ok( () )
+        }
+        Some( _ ) => Err( format!( "symbol {} isn't in prefix_code {} {}", symbol, kind, index )),
+        None => Err( format!( "missing prefix_code {} {}", kind, index ))
+    }
+}
+
+fn write_block_length( bw: &mut BitBuffer, code: Option<&PrefixCode>, category: usize, length: usize ) -> AsmResult<()> {
+    let (symbol, nbits, extra) = block_length_code( length );
+    write_symbol( bw, code, CodeKind::BlockLength( Category::from_index( category )), 0, symbol )?;
+    bw.put_bits( nbits, extra as u64 );
+    Ok( () )
+}
+
+/// The forward move-to-front transform, the inverse of the one applied by the decoder.
+fn move_to_front( values: &[u8] ) -> Vec<u8> {
+    let mut mtf: Vec<u8> = ( 0 ..= 255 ).collect();
+    values.iter().map( |&value| {
+        let index = mtf.iter().position( |&v| v == value ).unwrap();
+        mtf.remove( index );
+        mtf.insert( 0, value );
+        index as u8
+    }).collect()
+}
+
+/// Codes the values of a context map: `(symbol, extra bits, extra value)`. Runs of zeros use
+/// the run length codes up to `max_run_length_prefix`.
+fn context_map_symbols( values: &[u8], max_run_length_prefix: usize ) -> Vec<(usize, u32, u64)> {
+    let mut symbols = Vec::new();
+    let mut i = 0;
+    while i < values.len() {
+        if values[i] != 0 {
+            symbols.push( (values[i] as usize + max_run_length_prefix, 0, 0) );
+            i += 1;
+            continue
+        }
+
+        let mut run = values[i..].iter().take_while( |&&v| v == 0 ).count();
+        i += run;
+        while run > 0 {
+            if max_run_length_prefix == 0 || run == 1 {
+                symbols.push( (0, 0, 0) );
+                run -= 1;
+            } else {
+                let code = min( run.ilog2() as usize, max_run_length_prefix );
+                let length = min( run, ( 2 << code ) - 1 );
+                symbols.push( (code, code as u32, ( length - ( 1 << code )) as u64) );
+                run -= length;
+            }
+        }
+    }
+    symbols
+}
+
+/// A listed block switch that hasn't been written yet.
+struct BlockSwitch {
+    block_type: usize,
+    length: usize,
+    code: Option<usize>
+}
+
+/// The block types of a category in the current meta-block.
+#[derive(Default)]
+struct Blocks {
+    num_types: usize,
+
+    /// The current and the previous block type.
+    types: [usize; 2],
+
+    /// The number of symbols left in the current block.
+    remaining: usize,
+    type_code: Option<PrefixCode>,
+    length_code: Option<PrefixCode>,
+    switches: VecDeque<BlockSwitch>
+}
+
+/// Output of a meta-block header that waits for the prefix code that follows it.
+enum Pending {
+    /// The length of the first block, coded with the block length code of the category.
+    FirstBlockLength { category: usize, length: usize },
+
+    /// The coded values of a context map, and whether the IMTF bit is set.
+    ContextMap { category: usize, alphabet_size: usize, symbols: Vec<(usize, u32, u64)>, imtf: bool }
+}
+
+struct Assembler {
+    bw: BitBuffer,
+    window_bits: u32,
+
+    /// The output the stream decodes to, which literal contexts and dictionary references
+    /// depend on.
+    output: Vec<u8>,
+    distances: [usize; 4],
+
+    blocks: [Blocks; 3],
+    postfix_bits: u32,
+    num_direct_codes: usize,
+    context_modes: Vec<u8>,
+    literal_context_map: Vec<u8>,
+    distance_context_map: Vec<u8>,
+    num_trees: [usize; 3],
+    codes: [Vec<Option<PrefixCode>>; 3],
+    pending: Option<Pending>
+}
+
+impl Assembler {
+
+    fn new() -> Assembler {
+        let mut assembler = Assembler {
+            bw: BitBuffer::new(),
+            window_bits: 16,
+            output: Vec::new(),
+            distances: [4, 11, 15, 16],
+            blocks: Default::default(),
+            postfix_bits: 0,
+            num_direct_codes: 0,
+            context_modes: Vec::new(),
+            literal_context_map: Vec::new(),
+            distance_context_map: Vec::new(),
+            num_trees: [1; 3],
+            codes: Default::default(),
+            pending: None
+        };
+        assembler.reset_meta_block();
+        assembler
+    }
+
+    /// Sets up the defaults of a compressed meta-block: a single block type, prefix code and
+    /// context for each category.
+    fn reset_meta_block( &mut self ) {
+        for blocks in &mut self.blocks {
+            *blocks = Blocks { num_types: 1, types: [0, 1], remaining: INFINITE_BLOCK_COUNT, .. Default::default() };
+        }
+        self.postfix_bits = 0;
+        self.num_direct_codes = 0;
+        self.context_modes = vec![0];
+        self.literal_context_map = vec![0; 1 << LITERAL_CONTEXT_BITS];
+        self.distance_context_map = vec![0; 1 << DISTANCE_CONTEXT_BITS];
+        self.num_trees = [1; 3];
+        self.codes = Default::default();
+    }
+
+    fn num_distance_symbols( &self ) -> usize {
+        NUM_DISTANCE_SHORT_CODES + self.num_direct_codes + ( 48 << self.postfix_bits )
+    }
+
+    /// Fails if a block switch was listed, but its block never ended.
+    fn check_block_switches( &self ) -> AsmResult<()> {
+        for (category, blocks) in self.blocks.iter().enumerate() {
+            if !blocks.switches.is_empty() {
+                return Err( format!( "unused block_switch {}", Category::from_index( category )))
+            }
+        }
+        Ok( () )
+    }
+
+    fn instruction( &mut self, fields: &Fields ) -> AsmResult<()> {
+        let name = fields.word( 0 )?;
+        if name != "prefix_code" {
+            if let Some( kind ) = self.expected_code() {
+                return Err( format!( "expected prefix_code {} 0", kind ))
+            }
+        }
+
+        match name {
+            "window_bits" => self.window_bits( fields ),
+            "meta_block" => self.meta_block( fields ),
+            "data" | "metadata" => {
+                fields.expect_keys( &[] )?;
+                fields.expect_values( 2 )?;
+                let bytes = match fields.values.get( 1 ) {
+                    Some( Value::Str( bytes ) ) => bytes,
+                    _ => return Err( "missing string".to_string() )
+                };
+                self.bw.pad_to_byte();
+                self.bw.put_bytes( bytes );
+                if name == "data" {
+                    self.output.extend_from_slice( bytes );
+                }
+                Ok( () )
+            }
+            "block_types" => self.block_types( fields ),
+            "distance_parameters" => self.distance_parameters( fields ),
+            "context_map" => self.context_map( fields ),
+            "prefix_code" => self.prefix_code( fields ),
+            "block_switch" => self.block_switch( fields ),
+            "command" => self.command( fields ),
+            "bits" => {
+                fields.expect_keys( &[] )?;
+                fields.expect_values( 3 )?;
+                let (bits, value) = (fields.number_at( 1 )?, fields.number_at( 2 )?);
+                if bits > 56 {
+                    return Err( "at most 56 bits can be written at a time".to_string() )
+                }
+                check_fits( value, bits as u32, "value" )?;
+                self.bw.put_bits( bits as u32, value );
+                Ok( () )
+            }
+            "align" => {
+                fields.expect_keys( &[] )?;
+                fields.expect_values( 2 )?;
+                let value = if fields.values.len() > 1 { fields.number_at( 1 )? } else { 0 };
+                let bits = ( 8 - self.bw.bit_len() as u32 % 8 ) % 8;
+                check_fits( value, bits, "padding" )?;
+                self.bw.put_bits( bits, value );
+                Ok( () )
+            }
+            "bytes" => {
+                fields.expect_keys( &[] )?;
+                fields.expect_values( 2 )?;
+                match fields.values.get( 1 ) {
+                    Some( Value::Str( bytes ) ) => bytes.iter().for_each( |&byte| self.bw.put_bits( 8, byte as u64 )),
+                    _ => return Err( "missing string".to_string() )
+                }
+                Ok( () )
+            }
+            _ => Err( format!( "unknown instruction '{}'", name ))
+        }
+    }
+
+    fn finish( self ) -> AsmResult<Vec<u8>> {
+        if let Some( kind ) = self.expected_code() {
+            return Err( format!( "expected prefix_code {} 0", kind ))
+        }
+        self.check_block_switches()?;
+        Ok( self.bw.into_bytes() )
+    }
+
+    /// The kind of prefix code that the pending output waits for.
+    fn expected_code( &self ) -> Option<CodeKind> {
+        match self.pending {
+            Some( Pending::FirstBlockLength { category, .. } ) => {
+                let category_ = Category::from_index( category );
+                Some( if self.blocks[category].type_code.is_none() { CodeKind::BlockType( category_ ) } else { CodeKind::BlockLength( category_ ) })
+            }
+            Some( Pending::ContextMap { category, .. } ) => Some( CodeKind::ContextMap( Category::from_index( category ))),
+            None => None
+        }
+    }
+
+    fn window_bits( &mut self, fields: &Fields ) -> AsmResult<()> {
+        fields.expect_keys( &[] )?;
+        fields.expect_values( 2 )?;
+        let bits = fields.number_at( 1 )?;
+        if !( 10 ..= 24 ).contains( &bits ) {
+            return Err( format!( "invalid window size {}", bits ))
+        }
+        encode_window_bits( bits as u32, &mut self.bw );
+        self.window_bits = bits as u32;
+        Ok( () )
+    }
+
+    fn meta_block( &mut self, fields: &Fields ) -> AsmResult<()> {
+        self.check_block_switches()?;
+        fields.expect_values( 2 )?;
+        let kind = fields.word( 1 )?;
+        let is_last = fields.flag( "last" )?;
+
+        // ISLAST, and ISLASTEMPTY if it is set
+        self.bw.put_bits( 1, is_last as u64 );
+        if kind == "empty" {
+            fields.expect_keys( &["length", "last"] )?;
+            if !is_last || fields.number( "length" )?.unwrap_or( 0 ) != 0 {
+                return Err( "an empty meta-block must be last, and have length 0".to_string() )
+            }
+            self.bw.put_bits( 1, 1 );
+            return Ok( () )
+        }
+        if is_last {
+            self.bw.put_bits( 1, 0 );
+        }
+
+        let length = fields.required( "length" )? as usize;
+        if length > MAX_META_BLOCK_LENGTH {
+            return Err( format!( "meta-block length {} is too long", length ))
+        }
+
+        match kind {
+            "metadata" => {
+                fields.expect_keys( &["length", "last", "skip_bytes"] )?;
+                let min_skip_bytes = if length == 0 { 0 } else { ( ( length - 1 ) | 1 ).ilog2() as u64 / 8 + 1 };
+                let skip_bytes = fields.number( "skip_bytes" )?.unwrap_or( min_skip_bytes );
+                if skip_bytes > 3 || ( skip_bytes == 0 ) != ( length == 0 ) {
+                    return Err( format!( "invalid skip_bytes {}", skip_bytes ))
+                }
+
+                // MNIBBLES = 0, reserved bit, MSKIPBYTES and MLEN - 1
+                self.bw.put_bits( 2, 3 );
+                self.bw.put_bits( 1, 0 );
+                self.bw.put_bits( 2, skip_bytes );
+                if skip_bytes > 0 {
+                    check_fits( length as u64 - 1, skip_bytes as u32 * 8, "length - 1" )?;
+                    self.bw.put_bits( skip_bytes as u32 * 8, length as u64 - 1 );
+                }
+            }
+            "compressed" | "uncompressed" => {
+                fields.expect_keys( &["length", "last", "nibbles"] )?;
+                let is_uncompressed = kind == "uncompressed";
+                if length == 0 || ( is_last && is_uncompressed ) {
+                    return Err( format!( "invalid {} meta-block", kind ))
+                }
+
+                let min_nibbles = max( 4, ( ( length - 1 ) | 1 ).ilog2() as u64 / 4 + 1 );
+                let nibbles = fields.number( "nibbles" )?.unwrap_or( min_nibbles );
+                if !( 4 ..= 6 ).contains( &nibbles ) {
+                    return Err( format!( "invalid nibbles {}", nibbles ))
+                }
+                check_fits( length as u64 - 1, nibbles as u32 * 4, "length - 1" )?;
+
+                self.bw.put_bits( 2, nibbles - 4 );
+                self.bw.put_bits( nibbles as u32 * 4, length as u64 - 1 );
+                if !is_last {
+                    self.bw.put_bits( 1, is_uncompressed as u64 );
+                }
+                if !is_uncompressed {
+                    self.reset_meta_block();
+                }
+            }
+            _ => return Err( format!( "unknown meta-block kind '{}'", kind ))
+        }
+        Ok( () )
+    }
+
+    fn block_types( &mut self, fields: &Fields ) -> AsmResult<()> {
+        fields.expect_keys( &["length"] )?;
+        fields.expect_values( 3 )?;
+        let category = parse_category( fields.word( 1 )? )?;
+        let num_types = fields.number_at( 2 )? as usize;
+        if !( 1 ..= 256 ).contains( &num_types ) {
+            return Err( format!( "invalid number of block types {}", num_types ))
+        }
+
+        write_var_len_uint8( num_types - 1, &mut self.bw );
+        self.blocks[category] = Blocks { num_types: num_types, types: [0, 1], remaining: INFINITE_BLOCK_COUNT, .. Default::default() };
+        if category == COMMAND {
+            self.num_trees[COMMAND] = num_types;
+        }
+
+        let length = fields.number( "length" )?;
+        match length {
+            Some( length ) if num_types >= 2 => {
+                let length = length as usize;
+                if length == 0 || length > max_block_length() {
+                    return Err( format!( "invalid block length {}", length ))
+                }
+                self.pending = Some( Pending::FirstBlockLength { category: category, length: length } );
+            }
+            None if num_types == 1 => {}
+            _ => return Err( "the first block length must be given if and only if there are 2 or more block types".to_string() )
+        }
+        Ok( () )
+    }
+
+    fn distance_parameters( &mut self, fields: &Fields ) -> AsmResult<()> {
+        fields.expect_keys( &["postfix_bits", "direct_codes", "context_modes"] )?;
+        fields.expect_values( 1 )?;
+        let postfix_bits = fields.required( "postfix_bits" )? as u32;
+        let direct_codes = fields.required( "direct_codes" )? as usize;
+        if postfix_bits > 3 || direct_codes & (( 1 << postfix_bits ) - 1 ) != 0 || direct_codes >> postfix_bits > 15 {
+            return Err( format!( "invalid distance parameters postfix_bits={} direct_codes={}", postfix_bits, direct_codes ))
+        }
+
+        let context_modes = match fields.get( "context_modes" ) {
+            Some( Value::List( modes ) ) => modes,
+            _ => return Err( "missing field 'context_modes'".to_string() )
+        };
+        if context_modes.len() != self.blocks[LITERAL].num_types || context_modes.iter().any( |&mode| mode > 3 ) {
+            return Err( "there must be a context mode from 0 to 3 for each literal block type".to_string() )
+        }
+
+        self.bw.put_bits( 2, postfix_bits as u64 );
+        self.bw.put_bits( 4, ( direct_codes >> postfix_bits ) as u64 );
+        for &mode in context_modes {
+            self.bw.put_bits( 2, mode );
+        }
+        self.postfix_bits = postfix_bits;
+        self.num_direct_codes = direct_codes;
+        self.context_modes = context_modes.iter().map( |&mode| mode as u8 ).collect();
+        Ok( () )
+    }
+
+    fn context_map( &mut self, fields: &Fields ) -> AsmResult<()> {
+        fields.expect_keys( &["trees", "rle", "imtf"] )?;
+        fields.expect_values( 3 )?;
+        let category = parse_category( fields.word( 1 )? )?;
+        let context_bits = match category {
+            LITERAL => LITERAL_CONTEXT_BITS,
+            DISTANCE => DISTANCE_CONTEXT_BITS,
+            _ => return Err( "only literals and distances have a context map".to_string() )
+        };
+        let size = self.blocks[category].num_types << context_bits;
+
+        let num_trees = fields.required( "trees" )? as usize;
+        if !( 1 ..= 256 ).contains( &num_trees ) {
+            return Err( format!( "invalid number of trees {}", num_trees ))
+        }
+
+        let map: Vec<u8> = match fields.values.get( 2 ) {
+            Some( Value::List( map ) ) => {
+                if map.len() != size || map.iter().any( |&tree| tree as usize >= num_trees ) {
+                    return Err( format!( "the context map must have {} entries less than {}", size, num_trees ))
+                }
+                map.iter().map( |&tree| tree as u8 ).collect()
+            }
+            None => vec![0; size],
+            Some( _ ) => return Err( "the context map must be a list".to_string() )
+        };
+
+        write_var_len_uint8( num_trees - 1, &mut self.bw );
+        if num_trees >= 2 {
+            let max_run_length_prefix = fields.number( "rle" )?.unwrap_or( 0 ) as usize;
+            if max_run_length_prefix > 16 {
+                return Err( format!( "invalid rle {}", max_run_length_prefix ))
+            }
+            self.bw.put_bits( 1, ( max_run_length_prefix > 0 ) as u64 );
+            if max_run_length_prefix > 0 {
+                self.bw.put_bits( 4, max_run_length_prefix as u64 - 1 );
+            }
+
+            let imtf = fields.flag( "imtf" )?;
+            let values = if imtf { move_to_front( &map ) } else { map.clone() };
+            self.pending = Some( Pending::ContextMap {
+                category: category,
+                alphabet_size: num_trees + max_run_length_prefix,
+                symbols: context_map_symbols( &values, max_run_length_prefix ),
+                imtf: imtf
+            });
+        } else if fields.get( "rle" ).is_some() || fields.get( "imtf" ).is_some() {
+            return Err( "a context map with a single tree isn't coded".to_string() )
+        }
+
+        if category == LITERAL {
+            self.literal_context_map = map;
+        } else {
+            self.distance_context_map = map;
+        }
+        self.num_trees[category] = num_trees;
+        Ok( () )
+    }
+
+    fn prefix_code( &mut self, fields: &Fields ) -> AsmResult<()> {
+        fields.expect_keys( &[] )?;
+        let kind = parse_code_kind( fields.word( 1 )? )?;
+        let index = fields.number_at( 2 )? as usize;
+
+        let expected = self.expected_code();
+        let alphabet_size = match kind {
+            CodeKind::Symbols( category ) => {
+                if index >= self.num_trees[category as usize] {
+                    return Err( format!( "there are only {} {} prefix codes", self.num_trees[category as usize], category ))
+                }
+                match category {
+                    Category::Literal => NUM_LITERAL_SYMBOLS,
+                    Category::Command => NUM_COMMAND_SYMBOLS,
+                    Category::Distance => self.num_distance_symbols()
+                }
+            }
+            _ if Some( kind ) != expected || index != 0 => return Err( format!( "unexpected prefix_code {} {}", kind, index )),
+            CodeKind::BlockType( category ) => self.blocks[category as usize].num_types + 2,
+            CodeKind::BlockLength( _ ) => NUM_BLOCK_LENGTH_SYMBOLS,
+            CodeKind::ContextMap( _ ) => match self.pending {
+                Some( Pending::ContextMap { alphabet_size, .. } ) => alphabet_size,
+                _ => unreachable!()
+            }
+        };
+        if let Some( expected ) = expected {
+            if kind != expected {
+                return Err( format!( "expected prefix_code {} 0", expected ))
+            }
+        }
+
+        let mut form = None;
+        let mut lengths = vec![0u8; alphabet_size];
+        for value in &fields.values[3..] {
+            let word = match *value {
+                Value::Word( ref word ) => word,
+                _ => return Err( format!( "unexpected operand {:?}", value ))
+            };
+            if word == "simple" || word == "complex" {
+                form = Some( word.as_str() );
+                continue
+            }
+
+            let mut parts = word.splitn( 2, ':' );
+            let symbol = parse_number( parts.next().unwrap() )? as usize;
+            let length = parse_number( parts.next().ok_or_else( || format!( "expected SYMBOL:LENGTH, not '{}'", word ))? )?;
+            if symbol >= alphabet_size || lengths[symbol] != 0 || !( 1 ..= 15 ).contains( &length ) {
+                return Err( format!( "invalid code length '{}'", word ))
+            }
+            lengths[symbol] = length as u8;
+        }
+        if lengths.iter().all( |&length| length == 0 ) {
+            return Err( "the prefix code has no symbols".to_string() )
+        }
+
+        let code = PrefixCode::from_lengths( &lengths );
+        match form {
+            Some( "simple" ) if !code.is_simple() => return Err( "the code lengths don't form a simple prefix code".to_string() ),
+            Some( "complex" ) => code.store_complex( alphabet_size, &mut self.bw ),
+            _ => code.store( alphabet_size, &mut self.bw )
+        }
+
+        match kind {
+            CodeKind::Symbols( category ) => {
+                let codes = &mut self.codes[category as usize];
+                if codes.len() <= index {
+                    codes.resize_with( index + 1, || None );
+                }
+                codes[index] = Some( code );
+            }
+            CodeKind::BlockType( category ) => self.blocks[category as usize].type_code = Some( code ),
+            CodeKind::BlockLength( _ ) => {
+                if let Some( Pending::FirstBlockLength { category, length } ) = self.pending.take() {
+                    write_block_length( &mut self.bw, Some( &code ), category, length )?;
+                    self.blocks[category].remaining = length;
+                    self.blocks[category].length_code = Some( code );
+                }
+            }
+            CodeKind::ContextMap( _ ) => {
+                if let Some( Pending::ContextMap { symbols, imtf, .. } ) = self.pending.take() {
+                    for (symbol, nbits, extra) in symbols {
+                        write_symbol( &mut self.bw, Some( &code ), kind, 0, symbol )?;
+                        self.bw.put_bits( nbits, extra );
+                    }
+                    self.bw.put_bits( 1, imtf as u64 );
+                }
+            }
+        }
+        Ok( () )
+    }
+
+    fn block_switch( &mut self, fields: &Fields ) -> AsmResult<()> {
+        fields.expect_keys( &["type", "length", "code"] )?;
+        fields.expect_values( 2 )?;
+        let category = parse_category( fields.word( 1 )? )?;
+        let block_type = fields.required( "type" )? as usize;
+        let length = fields.required( "length" )? as usize;
+        let code = fields.number( "code" )?.map( |code| code as usize );
+
+        let blocks = &mut self.blocks[category];
+        if block_type >= blocks.num_types || length == 0 || length > max_block_length() {
+            return Err( format!( "invalid block switch to type {} of length {}", block_type, length ))
+        }
+        if code.is_some_and( |code| code >= blocks.num_types + 2 ) {
+            return Err( format!( "invalid block type code {}", code.unwrap() ))
+        }
+        blocks.switches.push_back( BlockSwitch { block_type: block_type, length: length, code: code } );
+        Ok( () )
+    }
+
+    /// Writes the next listed block switch of `category`, when its current block has ended.
+    fn switch_block_type( &mut self, category: usize ) -> AsmResult<()> {
+        let blocks = &mut self.blocks[category];
+        let switch = blocks.switches.pop_front().ok_or_else( || format!( "the {} block ends, but there is no block_switch", Category::from_index( category )))?;
+
+        // The type following the current one, and the previous one, have short codes
+        let n = blocks.num_types;
+        let symbol = switch.code.unwrap_or( if switch.block_type == ( blocks.types[0] + 1 ) % n {
+            1
+        } else if switch.block_type == blocks.types[1] {
+            0
+        } else {
+            switch.block_type + 2
+        });
+
+        let mut block_type = match symbol {
+            0 => blocks.types[1],
+            1 => blocks.types[0] + 1,
+            _ => symbol - 2
+        };
+        if block_type >= n {
+            block_type -= n;
+        }
+        if block_type != switch.block_type {
+            return Err( format!( "block type code {} selects {} block type {}, not {}", symbol, Category::from_index( category ), block_type, switch.block_type ))
+        }
+
+        write_symbol( &mut self.bw, blocks.type_code.as_ref(), CodeKind::BlockType( Category::from_index( category )), 0, symbol )?;
+        write_block_length( &mut self.bw, blocks.length_code.as_ref(), category, switch.length )?;
+        blocks.types = [block_type, blocks.types[0]];
+        blocks.remaining = switch.length;
+        Ok( () )
+    }
+
+    /// Counts a symbol of `category`, and returns its block type.
+    fn next_block_symbol( &mut self, category: usize ) -> AsmResult<usize> {
+        if self.blocks[category].remaining == 0 {
+            self.switch_block_type( category )?;
+        }
+        self.blocks[category].remaining -= 1;
+        Ok( self.blocks[category].types[0] )
+    }
+
+    /// The distance that short code `code` refers to.
+    fn short_code_distance( &self, code: usize ) -> AsmResult<usize> {
+        let distance = self.distances[DISTANCE_SHORT_CODE_INDEX[code]] as isize + DISTANCE_SHORT_CODE_OFFSET[code];
+        if distance <= 0 {
+            return Err( format!( "distance code {} refers to distance {}", code, distance ))
+        }
+        Ok( distance as usize )
+    }
+
+    /// Finds the code of a distance: `(code, extra bits, extra value, distance)`. If `short` is
+    /// set and no code is given, the distance must be one of the short codes.
+    fn distance_code( &self, distance: Option<usize>, code: Option<usize>, short: bool ) -> AsmResult<(usize, u32, u32, usize)> {
+        match code {
+            Some( code ) if code < NUM_DISTANCE_SHORT_CODES => {
+                let actual = self.short_code_distance( code )?;
+                match distance {
+                    Some( distance ) if distance != actual => Err( format!( "distance code {} refers to distance {}, not {}", code, actual, distance )),
+                    _ => Ok( (code, 0, 0, actual) )
+                }
+            }
+            None if short => {
+                let distance = distance.ok_or( "missing distance" )?;
+                ( 0 .. NUM_DISTANCE_SHORT_CODES ).find( |&code| self.short_code_distance( code ) == Ok( distance ))
+                                                 .map( |code| (code, 0, 0, distance) )
+                                                 .ok_or_else( || format!( "distance {} isn't one of the last distances", distance ))
+            }
+            _ => {
+                let distance = distance.ok_or( "missing distance" )?;
+                if distance == 0 {
+                    return Err( "invalid distance 0".to_string() )
+                }
+                let (actual, nbits, extra) = distance_code( distance, self.postfix_bits, self.num_direct_codes );
+                if actual >= self.num_distance_symbols() {
+                    return Err( format!( "distance {} is too long for the distance parameters", distance ))
+                }
+                if code.is_some_and( |code| code != actual ) {
+                    return Err( format!( "distance {} has code {}, not {}", distance, actual, code.unwrap() ))
+                }
+                Ok( (actual, nbits, extra, distance) )
+            }
+        }
+    }
+
+    fn command( &mut self, fields: &Fields ) -> AsmResult<()> {
+        fields.expect_keys( &["insert", "copy", "code", "word", "transform", "length"] )?;
+
+        // Operands: [CODE] "LITERALS" [last_distance|distance|dictionary [D]]
+        let mut operands = fields.values[1..].iter().peekable();
+        let command = match operands.peek() {
+            Some( &value ) if is_number( value ) => { operands.next(); Some( parse_number( fields.word( 1 )? )? as usize ) }
+            _ => None
+        };
+        let literals = match operands.next() {
+            Some( Value::Str( literals ) ) => literals.clone(),
+            _ => return Err( "missing literals".to_string() )
+        };
+        let kind = match operands.next() {
+            Some( Value::Word( word ) ) => Some( word.clone() ),
+            Some( value ) => return Err( format!( "unexpected operand {:?}", value )),
+            None => None
+        };
+        let distance = match operands.next() {
+            Some( Value::Word( word ) ) => Some( parse_number( word )? as usize ),
+            Some( value ) => return Err( format!( "unexpected operand {:?}", value )),
+            None => None
+        };
+        if let Some( value ) = operands.next() {
+            return Err( format!( "unexpected operand {:?}", value ))
+        }
+
+        let insert_length = literals.len();
+        if fields.number( "insert" )?.is_some_and( |insert| insert as usize != insert_length ) {
+            return Err( format!( "there are {} literals", insert_length ))
+        }
+        let copy_length = fields.required( "copy" )? as usize;
+        let code = fields.number( "code" )?.map( |code| code as usize );
+
+        // Dictionary references are beyond the window, which includes the inserted literals
+        let total_out = self.output.len() + insert_length;
+        let max_distance = min(( 1 << self.window_bits ) - 16, total_out );
+        let distance = match kind.as_deref() {
+            None => None,
+            Some( "last_distance" ) => Some( self.distance_code( distance, code, true )? ),
+            Some( "distance" ) => Some( self.distance_code( distance, code, false )? ),
+            Some( "dictionary" ) => {
+                if !( dictionary::MIN_WORD_LENGTH ..= dictionary::MAX_WORD_LENGTH ).contains( &copy_length ) {
+                    return Err( format!( "invalid dictionary word length {}", copy_length ))
+                }
+                let index_bits = dictionary::NDBITS[copy_length];
+                let word_id = match (fields.number( "word" )?, fields.number( "transform" )?) {
+                    (Some( word ), Some( transform )) => Some( ( transform as usize ) << index_bits | word as usize ),
+                    (None, None) => None,
+                    _ => return Err( "both 'word' and 'transform' must be given".to_string() )
+                };
+                let distance = match (distance, word_id) {
+                    (Some( distance ), Some( word_id )) if distance != max_distance + 1 + word_id =>
+                        return Err( format!( "the word is at distance {}, not {}", max_distance + 1 + word_id, distance )),
+                    (Some( distance ), _) if distance <= max_distance => return Err( format!( "distance {} is within the window", distance )),
+                    (Some( distance ), _) => distance,
+                    (None, Some( word_id )) => max_distance + 1 + word_id,
+                    (None, None) => return Err( "missing distance".to_string() )
+                };
+                Some( self.distance_code( Some( distance ), code, false )? )
+            }
+            Some( kind ) => return Err( format!( "unknown distance kind '{}'", kind ))
+        };
+
+        let command = match command {
+            Some( command ) if command < NUM_COMMAND_SYMBOLS => command,
+            Some( command ) => return Err( format!( "invalid command code {}", command )),
+            None if copy_length < 2 => return Err( format!( "invalid copy length {}", copy_length )),
+            None => command_code( insert_length, copy_length, distance.is_none_or( |d| d.0 == 0 )) as usize
+        };
+        let lut = CMD_LUT[command];
+        let insert_extra = insert_length.wrapping_sub( lut.insert_len_offset as usize );
+        let copy_extra = copy_length.wrapping_sub( lut.copy_len_offset as usize );
+        if insert_extra >> lut.insert_len_extra_bits != 0 || copy_extra >> lut.copy_len_extra_bits != 0 {
+            return Err( format!( "command code {} can't have insert={} copy={}", command, insert_length, copy_length ))
+        }
+        if !lut.distance_code && distance.is_some_and( |d| d.0 != 0 ) {
+            return Err( format!( "command code {} implies the last distance", command ))
+        }
+
+        // Command
+        let block_type = self.next_block_symbol( COMMAND )?;
+        write_symbol( &mut self.bw, self.codes[COMMAND].get( block_type ).and_then( Option::as_ref ), CodeKind::Symbols( Category::Command ), block_type, command )?;
+        self.bw.put_bits( lut.insert_len_extra_bits as u32, insert_extra as u64 );
+        self.bw.put_bits( lut.copy_len_extra_bits as u32, copy_extra as u64 );
+
+        // Literals
+        for &literal in &literals {
+            let block_type = self.next_block_symbol( LITERAL )?;
+            let p1 = self.output.last().cloned().unwrap_or( 0 );
+            let p2 = self.output.len().checked_sub( 2 ).map_or( 0, |i| self.output[i] );
+            let mode = *self.context_modes.get( block_type ).ok_or( "missing literal context mode" )?;
+            let context = context_id( p1, p2, mode ) as usize;
+            let tree = self.literal_context_map[( block_type << LITERAL_CONTEXT_BITS ) + context] as usize;
+            write_symbol( &mut self.bw, self.codes[LITERAL].get( tree ).and_then( Option::as_ref ), CodeKind::Symbols( Category::Literal ), tree, literal as usize )?;
+            self.output.push( literal );
+        }
+
+        // Distance
+        let (code, distance) = match distance {
+            Some( (code, nbits, extra, distance) ) => {
+                if lut.distance_code {
+                    let block_type = self.next_block_symbol( DISTANCE )?;
+                    let tree = self.distance_context_map[( block_type << DISTANCE_CONTEXT_BITS ) + lut.context as usize] as usize;
+                    write_symbol( &mut self.bw, self.codes[DISTANCE].get( tree ).and_then( Option::as_ref ), CodeKind::Symbols( Category::Distance ), tree, code )?;
+                    self.bw.put_bits( nbits, extra as u64 );
+                }
+                (code, distance)
+            }
+            None => return Ok( () )
+        };
+
+        // Update the output, as far as it is valid
+        if distance > max_distance {
+            let word_id = distance - max_distance - 1;
+            if ( dictionary::MIN_WORD_LENGTH ..= dictionary::MAX_WORD_LENGTH ).contains( &copy_length ) {
+                let index_bits = dictionary::NDBITS[copy_length];
+                if let (Some( word ), Some( transform )) = (dictionary::word( copy_length, word_id & (( 1 << index_bits ) - 1 )), TRANSFORMS.get( word_id >> index_bits )) {
+                    let mut buffer = [0u8; MAX_TRANSFORMED_WORD_LENGTH];
+                    let length = transform.transform( word, &mut buffer );
+                    self.output.extend_from_slice( &buffer[..length] );
+                }
+            }
+        } else {
+            if code != 0 {
+                self.distances = [distance, self.distances[0], self.distances[1], self.distances[2]];
+            }
+            for _ in 0 .. copy_length {
+                let byte = self.output[self.output.len() - distance];
+                self.output.push( byte );
+            }
+        }
+        Ok( () )
+    }
+}
+
+#[test]
+fn test_parse_line()
+{
+    let fields = parse_line( r#"      1234  command 8 insert=2 "a\"\x00" copy=2 map=[1, 0x2,3] # comment "x""# ).unwrap();
+    assert_eq!( fields.values, vec![Value::Word( "command".to_string() ), Value::Word( "8".to_string() ), Value::Str( b"a\"\0".to_vec() )] );
+    assert_eq!( fields.pairs, vec![("insert".to_string(), Value::Word( "2".to_string() )),
+                                   ("copy".to_string(), Value::Word( "2".to_string() )),
+                                   ("map".to_string(), Value::List( vec![1, 2, 3] ))] );
+    assert!( parse_line( "# only a comment" ).unwrap().values.is_empty() );
+    assert!( parse_line( r#"data "abc"# ).is_err() );
+    assert!( parse_line( r#"data "\q""# ).is_err() );
+}
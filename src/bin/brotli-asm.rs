@@ -0,0 +1,39 @@
+//! Assembles a Brotli stream from a listing in the format written by `brotli-dump`, and writes
+//! it to the standard output. Reads the file given as the only argument, or the standard input.
+//!
+//! Usage: `brotli-asm [FILE]`
+
+extern crate brotli;
+
+use brotli::asm::assemble;
+use std::env;
+use std::fs::File;
+use std::io::{ self, ErrorKind, Read, Write };
+use std::process;
+
+fn run<R: Read>( mut reader: R ) -> io::Result<()> {
+    let mut source = String::new();
+    reader.read_to_string( &mut source )?;
+    let stream = assemble( &source ).map_err( |e| io::Error::new( ErrorKind::InvalidData, e ))?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    out.write_all( &stream )?;
+    out.flush()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip( 1 ).collect();
+    let result = match args.len() {
+        0 => run( io::stdin() ),
+        1 => File::open( &args[0] ).and_then( run ),
+        _ => {
+            eprintln!( "usage: brotli-asm [FILE]" );
+            process::exit( 2 );
+        }
+    };
+
+    if let Err( e ) = result {
+        eprintln!( "brotli-asm: {}", e );
+        process::exit( 1 );
+    }
+}
@@ -0,0 +1,43 @@
+//! Lists the contents of a Brotli stream, one instruction per line, each with the bit offset of
+//! its compressed data. Reads the file given as the only argument, or the standard input.
+//!
+//! Usage: `brotli-dump [FILE]`
+
+extern crate brotli;
+
+use brotli::disasm::Disassembler;
+use std::env;
+use std::fs::File;
+use std::io::{ self, BufWriter, ErrorKind, Read, Write };
+use std::process;
+
+fn dump<R: Read>( reader: R ) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = BufWriter::new( stdout.lock() );
+    for entry in Disassembler::new( reader ) {
+        writeln!( out, "{}", entry? )?;
+    }
+    out.flush()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip( 1 ).collect();
+    let result = match args.len() {
+        0 => dump( io::stdin() ),
+        1 => File::open( &args[0] ).and_then( dump ),
+        _ => {
+            eprintln!( "usage: brotli-dump [FILE]" );
+            process::exit( 2 );
+        }
+    };
+
+    match result {
+        // The output was cut short, e.g. by `head`
+        Err( ref e ) if e.kind() == ErrorKind::BrokenPipe => {}
+        Err( e ) => {
+            eprintln!( "brotli-dump: {}", e );
+            process::exit( 1 );
+        }
+        Ok( () ) => {}
+    }
+}
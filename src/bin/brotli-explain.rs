@@ -0,0 +1,47 @@
+//! Shows how each byte of the output of a Brotli stream was coded. Writes an HTML page with the
+//! decompressed content, each literal shaded by its cost in bits, to HTML (by default FILE.html),
+//! and prints a summary listing the costliest runs of literals.
+//!
+//! Usage: `brotli-explain FILE [HTML]`
+
+extern crate brotli;
+
+use brotli::explain::explain;
+use std::env;
+use std::fs::File;
+use std::io::{ self, BufWriter, Write };
+use std::process;
+
+/// The number of regions listed in the summary.
+const NUM_REGIONS: usize = 10;
+
+fn run( path: &str, html_path: &str ) -> io::Result<()> {
+    let explanation = explain( File::open( path )? )?;
+
+    let mut html = BufWriter::new( File::create( html_path )? );
+    explanation.write_html( &mut html, path )?;
+    html.flush()?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    explanation.write_summary( &mut out, NUM_REGIONS )?;
+    writeln!( out )?;
+    writeln!( out, "wrote {}", html_path )
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip( 1 ).collect();
+    let html_path = match args.len() {
+        1 => format!( "{}.html", args[0] ),
+        2 => args[1].clone(),
+        _ => {
+            eprintln!( "usage: brotli-explain FILE [HTML]" );
+            process::exit( 2 );
+        }
+    };
+
+    if let Err( e ) = run( &args[0], &html_path ) {
+        eprintln!( "brotli-explain: {}", e );
+        process::exit( 1 );
+    }
+}
@@ -0,0 +1,46 @@
+//! Works with seekable Brotli streams. `list` prints the frame table of the stream in FILE, one
+//! frame per line, with the position and size of its compressed data and of its output.
+//!
+//! Usage: `brotli-seekable list FILE`
+
+extern crate brotli;
+
+use brotli::seekable::SeekTable;
+use std::env;
+use std::fs::File;
+use std::io::{ self, BufWriter, ErrorKind, Write };
+use std::process;
+
+fn list( mut file: File ) -> io::Result<()> {
+    let table = SeekTable::read_from( &mut file )?;
+    let stdout = io::stdout();
+    let mut out = BufWriter::new( stdout.lock() );
+    writeln!( out, "window: 2^{} bytes, {} frames, {} bytes of output", table.window_bits(), table.frames().len(), table.output_size() )?;
+    writeln!( out, "{:>8} {:>14} {:>12} {:>14} {:>12}", "frame", "offset", "compressed", "output", "size" )?;
+    for (index, frame) in table.frames().iter().enumerate() {
+        writeln!( out, "{:>8} {:>14} {:>12} {:>14} {:>12}", index, frame.compressed_offset, frame.compressed_size,
+                  frame.output_offset, frame.output_size )?;
+    }
+    out.flush()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip( 1 ).collect();
+    let result = match args.iter().map( String::as_str ).collect::<Vec<_>>()[..] {
+        ["list", path] => File::open( path ).and_then( list ),
+        _ => {
+            eprintln!( "usage: brotli-seekable list FILE" );
+            process::exit( 2 );
+        }
+    };
+
+    match result {
+        // The output was cut short, e.g. by `head`
+        Err( ref e ) if e.kind() == ErrorKind::BrokenPipe => {}
+        Err( e ) => {
+            eprintln!( "brotli-seekable: {}", e );
+            process::exit( 1 );
+        }
+        Ok( () ) => {}
+    }
+}
@@ -1,49 +1,52 @@
-﻿use std::io::{ Read, Write, ErrorKind };
+﻿use alloc::vec::Vec;
+use core::cmp::min;
+#[cfg(feature = "std")]
+use std::io::{ Read, ErrorKind };
 use Result;
 use BrotliError;
 
-// TODO: Move into BitReader trait (associated constants)
-const MAX_NUM_BIT_READ : u32 = 25;
-const READ_SIZE: usize = 1024;
-const IMPLICIT_ZEROES: usize = 128;
-const IBUF_SIZE: usize = (READ_SIZE + IMPLICIT_ZEROES);
-const IBUF_MASK: usize =  (READ_SIZE - 1);
-
-// TODO: This reader might have cleaner code if the input can be read from EITHER
-// the bit buffer OR the byte buffer. That is, the bit offset can never be more than
-// 7 bits.
-// Also, I'm not a fan of the way implicit zeroes is implemented. Might be more elegant
-// to use one of the Read implementations that chains the base reader with 0 repeated.
-
-/// Data source for the Brotli decoder. The default implementation wraps `std::io::Read`,
-/// or a custom implementation can be provided.
+/// The number of bytes requested from the underlying reader at a time.
+const READ_SIZE: usize = 4096;
+
+/// Data source for the Brotli decoder. The default implementation reads from a `Source`, or a
+/// custom implementation can be provided.
+///
+/// Bits are read from an accumulator, which is filled from a buffer of input bytes. The
+/// decoder reads the stream in small units (a prefix code, a command, a literal), saving the
+/// reader state before each one. If a unit runs out of input half-way, the decoder restores
+/// the saved state, fetches more input and tries again.
 pub trait BitReader {
 
     /// Ensures that accumulator is not empty. May consume one byte of input.
-    /// Returns `false` if data is required but there is no input available.
-    #[must_use()]
+    /// Returns `InsufficientData` if data is required but there is no input available.
     fn warmup( &mut self ) -> Result<()>;
 
-    /// returns the number of unread bytes remaining in the buffer.
-    #[must_use()]
+    /// returns the number of unread bytes remaining in the buffer, including whole bytes
+    /// in the accumulator.
+    #[must_use]
     fn get_remaining_bytes( &self ) -> usize;
 
-    /// Checks if there is at least num bytes left in the input ringbuffer (excluding
-    /// the bits remaining in the buffer). The maximum value for num is `IMPLICIT_ZEROES` bytes.
-    #[must_use()]
+    /// Checks if there is at least num bytes left in the input buffer (excluding
+    /// the bits remaining in the accumulator).
+    #[must_use]
     fn check_input_amount( &self, bytes: usize ) -> bool;
 
-    /// Like `get_bits()`, but does not mask the result, it is only guaranteed that it has minimum of 24 bits.
-    #[must_use()]
+    /// Fills the accumulator from the input buffer, and returns the number of bits in it.
+    #[must_use]
+    fn get_available_bits( &mut self ) -> u32;
+
+    /// Like `get_bits()`, but does not mask the result. Bits beyond the end of the available
+    /// input are zero, so check `get_available_bits()` when reading near the end of the input.
+    #[must_use]
     fn get_bits_unmasked( &mut self ) -> u32;
 
     /// Returns the specified number of bits without advancing bit pos.
     #[inline(always)]
-    #[must_use()]
+    #[must_use]
     fn get_bits( &mut self, bits: u32 ) -> u32
     {
         debug_assert!( bits <= 32 );
-        self.get_bits_unmasked() & !((0xffffffff) << bits )
+        self.get_bits_unmasked() & !((0xffffffffu64) << bits ) as u32
     }
 
     /// Advances the bit position by `bits`.
@@ -53,152 +56,435 @@ pub trait BitReader {
     /// Precondition: accumulator MUST contain the required number of bits.
     /// #panic
     /// If there are insufficient number of bits remaining in the input, this function will panic in debug builds.
-    #[must_use()]
+    #[must_use]
     fn take_bits( &mut self, bits: u32 ) -> u32;
 
-    /// Returns the specified number of bits without advancing the bit position.
+    /// Reads the specified number of bits and advances the bit position.
     /// #panic
     /// If there are insufficient number of bits remaining in the input, this function will panic in debug builds.
-    #[must_use()]
+    #[must_use]
     fn read_bits( &mut self, bits: u32 ) -> u32;
-    
+
     /// Same as `read_bits`, but returns `None` if there is insufficient input available.
-    #[must_use()]
+    #[must_use]
     fn safe_read_bits( &mut self, bits: u32 ) -> Option<u32>;
 
+    /// Same as `safe_read_bits`, but fails with `InsufficientData`, so that the decoder can
+    /// restore the saved state and try again once more input is available.
+    #[inline(always)]
+    fn try_read_bits( &mut self, bits: u32 ) -> Result<u32>
+    {
+        self.safe_read_bits( bits ).ok_or( BrotliError::InsufficientData )
+    }
+
     /// Advances the bit reader position to the next byte boundary and verifies that any skipped bits are set to zero.
-    #[must_use()]
+    #[must_use]
     fn jump_to_byte_boundary( &mut self ) -> bool;
 
     /// Peeks a byte at specified offset. Precondition: bit reader is parked to a byte boundry.
-    #[must_use()]
+    #[must_use]
     fn peek_byte( &self, offset: usize ) -> Option<u8>;
 
-    /// Copies remaining input bytes stored in the bit reader to the output, which may not be 
-    /// larger than get_remaining_bytes(). The bit reader must be warmed up again after this.
-    fn copy_bytes( &mut self, mut dst: &mut [u8] );
+    /// Copies remaining input bytes stored in the bit reader to the output, which may not be
+    /// larger than get_remaining_bytes(). Precondition: bit reader is parked to a byte boundry.
+    fn copy_bytes( &mut self, dst: &mut [u8] );
+
+    /// Remembers the current position. Input from this position onwards is retained until the
+    /// next call, so that the position can be restored with `restore_state()`.
+    fn save_state( &mut self );
+
+    /// Returns to the position remembered by the last call to `save_state()`.
+    fn restore_state( &mut self );
+
+    /// Reads more input into the buffer. Returns `false` if the end of the input was reached
+    /// and no more bytes were added.
+    fn fetch_input( &mut self ) -> Result<bool>;
+
+    /// The number of bits read from the start of the input.
+    #[must_use]
+    fn bit_position( &self ) -> u64;
+}
+
+/// Where a `StreamBitReader` reads its input from. Implemented for every `std::io::Read`.
+pub trait Source {
 
-    /// Checks that bit reader hasn't read after the end of input.
-    /// Returns false if bit reader has used implicit zeroes after the end of input.
-    #[must_use()]
-    fn is_reader_okay( &self ) -> bool;
+    /// Reads input into `buf`, and returns the number of bytes read, which is 0 at the end of
+    /// the input.
+    fn read_input( &mut self, buf: &mut [u8] ) -> Result<usize>;
 }
 
-/// Wraps a `Read` stream to implement the `BitReader` trait for the Brotli decoder.
+#[cfg(feature = "std")]
+impl<R: Read> Source for R {
+    fn read_input( &mut self, buf: &mut [u8] ) -> Result<usize> {
+        loop {
+            match self.read( buf ) {
+                Ok( bytes_read ) => return Ok( bytes_read ),
+                Err( ref e ) if e.kind() == ErrorKind::Interrupted => {}
+                Err( e ) => return Err( BrotliError::from( e ))
+            }
+        }
+    }
+}
+
+/// The source of a `StreamBitReader` that is handed all of its input with `push()`. Reading
+/// from it finds the end of the input.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NoSource;
+
+impl Source for NoSource {
+    fn read_input( &mut self, _buf: &mut [u8] ) -> Result<usize> {
+        Ok( 0 )
+    }
+}
+
+/// Position of a `StreamBitReader`, as remembered by `save_state()`.
+#[derive(Debug, Copy, Clone, Default)]
+struct State {
+    prefetch_bits: u64,
+    available_bits: u32,
+    buf_position: usize
+}
+
+/// Wraps a `Source` to implement the `BitReader` trait for the Brotli decoder.
 pub struct StreamBitReader<R> {
     pub reader: R,
-    
-    // Pre-fetched bits from 'reader'
-    // TODO: add conditional compilation to support 32-bit
+
+    // Pre-fetched bits from 'buf', least significant bit first.
     prefetch_bits: u64,
 
-    // bit position in 'prefetchBits'
-    bit_position: u32,
+    // number of valid bits in 'prefetch_bits'
+    available_bits: u32,
 
     // the byte we're reading from
     buf_position: usize,
 
-    // the number of bytes remaining
-    available_bytes: usize,
-
     end_of_stream: bool,
 
-    // Input byte buffer, consist of a ringbuffer and a "slack" region where 
-    // bytes from the start of the ringbuffer are copied.
-    buf: [u8; IBUF_SIZE]
+    saved: State,
+
+    // number of bytes discarded from the start of 'buf'
+    discarded: u64,
+
+    // Input bytes. Bytes before the saved position are discarded when more input is read.
+    buf: Vec<u8>
 }
 
-impl<R: Read + Sized> StreamBitReader<R> {
+impl<R> StreamBitReader<R> {
 
     // *********** These methods are not implementations of the trait *********** //
     pub fn new(reader: R) -> StreamBitReader<R> {
         StreamBitReader {
             reader: reader,
             prefetch_bits: 0,
-            bit_position: 64, // TODO: add conditional compilation to support 32-bit
+            available_bits: 0,
             buf_position: 0,
-            available_bytes: 0,
             end_of_stream: false,
-            buf: [0; IBUF_SIZE]
+            saved: State::default(),
+            discarded: 0,
+            buf: Vec::new()
+        }
+    }
+
+    /// Returns the wrapped reader.
+    pub fn into_inner( self ) -> R {
+        self.reader
+    }
+
+    /// Appends `input` to the buffered input, for a decoder that is handed its input instead of
+    /// reading it. Call it when the decoder needs more input.
+    pub fn push( &mut self, input: &[u8] ) {
+        self.discard_used_input();
+        self.buf.extend_from_slice( input );
+    }
+
+    /// Drops the buffered input, so that reading starts over at the current position of the
+    /// source, as after `new()`.
+    pub fn clear( &mut self ) {
+        self.prefetch_bits = 0;
+        self.available_bits = 0;
+        self.buf_position = 0;
+        self.end_of_stream = false;
+        self.saved = State::default();
+        self.discarded = 0;
+        self.buf.clear();
+    }
+
+    /// Discards the bytes that can no longer be read again.
+    fn discard_used_input( &mut self ) {
+        let discard = self.saved.buf_position;
+        if discard > 0 {
+            self.buf.drain( .. discard );
+            self.buf_position -= discard;
+            self.saved.buf_position = 0;
+            self.discarded += discard as u64;
+        }
+    }
+
+    /// Gives back up to `max` whole bytes of input that were read from the source, but not
+    /// used yet, last first. Returns how many were given back. They have to be read again, and
+    /// the saved state is reset to the current position.
+    pub fn unread( &mut self, max: usize ) -> usize {
+        let from_buf = min( max, self.buf.len() - self.buf_position );
+        self.buf.truncate( self.buf.len() - from_buf );
+
+        // Whole bytes in the bit window are the last ones taken from the buffer
+        let from_window = min( max - from_buf, min(( self.available_bits >> 3 ) as usize, self.buf_position ));
+        if from_window > 0 {
+            self.available_bits -= from_window as u32 * 8;
+            self.prefetch_bits &= ( 1u64 << self.available_bits ) - 1;
+            self.buf_position -= from_window;
+            self.buf.truncate( self.buf_position );
         }
+
+        self.saved = self.position();
+        from_buf + from_window
     }
 
-    fn read_input( &mut self, finish: bool ) -> Result<()>
+    #[inline(always)]
+    fn position( &self ) -> State {
+        State { prefetch_bits: self.prefetch_bits, available_bits: self.available_bits, buf_position: self.buf_position }
+    }
+
+    // Tops up the accumulator from the byte buffer, 8 bits at a time.
+    #[inline(always)]
+    fn fill_bit_window( &mut self )
     {
-        if self.end_of_stream {
+        if self.available_bits <= 32 && self.buf_position + 4 <= self.buf.len()
+        {
+            let b = &self.buf[self.buf_position .. self.buf_position + 4];
+            self.prefetch_bits |=
+                ( ( b[0] as u64 ) |
+                  ( b[1] as u64 ) << 8 |
+                  ( b[2] as u64 ) << 16 |
+                  ( b[3] as u64 ) << 24 ) << self.available_bits;
+            self.available_bits += 32;
+            self.buf_position += 4;
+        }
+
+        while self.available_bits <= 56 && self.buf_position < self.buf.len()
+        {
+            self.prefetch_bits |= ( self.buf[self.buf_position] as u64 ) << self.available_bits;
+            self.available_bits += 8;
+            self.buf_position += 1;
+        }
+    }
+}
+
+impl<R: Source> BitReader for StreamBitReader<R> {
+
+    fn warmup( &mut self ) -> Result<()>
+    {
+        self.fill_bit_window();
+        if self.available_bits == 0 {
             Err( BrotliError::InsufficientData )
         } else {
-            // Shift buffer contents to the front
-            if self.buf_position != 0 
-            {
-                // TODO: Replace with overlapping memcpy
-                for i in 0 .. self.available_bytes
-                {
-                    self.buf[i] = self.buf[i+self.buf_position];
-                }
-                self.buf_position = 0;
+            Ok(())
+        }
+    }
+
+    #[inline(always)]
+    fn get_remaining_bytes( &self ) -> usize
+    {
+        self.buf.len() - self.buf_position + ( self.available_bits >> 3 ) as usize
+    }
+
+    #[inline(always)]
+    fn check_input_amount( &self, bytes: usize ) -> bool
+    {
+        self.buf.len() - self.buf_position >= bytes
+    }
+
+    #[inline(always)]
+    fn get_available_bits( &mut self ) -> u32
+    {
+        self.fill_bit_window();
+        self.available_bits
+    }
+
+    #[inline(always)]
+    fn get_bits_unmasked( &mut self ) -> u32
+    {
+        self.fill_bit_window();
+        self.prefetch_bits as u32
+    }
+
+    #[inline(always)]
+    fn drop_bits( &mut self, bits: u32 )
+    {
+        debug_assert!( bits <= self.available_bits );
+        self.prefetch_bits >>= bits;
+        self.available_bits -= bits;
+    }
+
+    #[inline(always)]
+    fn take_bits( &mut self, bits: u32 ) -> u32
+    {
+        debug_assert!( bits <= 32 && bits <= self.available_bits );
+        let result = self.prefetch_bits & !((0xffffffffffffffffu64) << bits );
+        self.drop_bits( bits );
+
+        result as u32
+    }
+
+    #[inline(always)]
+    fn read_bits( &mut self, bits: u32 ) -> u32
+    {
+        self.fill_bit_window();
+        self.take_bits( bits )
+    }
+
+    #[inline(always)]
+    fn safe_read_bits( &mut self, bits: u32 ) -> Option<u32>
+    {
+        if self.available_bits < bits {
+            self.fill_bit_window();
+            if self.available_bits < bits {
+                return None
             }
+        }
+
+        Some( self.take_bits( bits ))
+    }
+
+    #[inline(always)]
+    fn jump_to_byte_boundary(&mut self) -> bool {
+        let pad_bits_count = self.available_bits & 0x7;
+        pad_bits_count == 0 || self.take_bits( pad_bits_count ) == 0
+    }
+
+    #[inline(always)]
+    fn peek_byte( &self, offset: usize ) -> Option<u8> {
+        if self.available_bits & 7 != 0 {
+            return None
+        }
+
+        let bytes_left = ( self.available_bits >> 3 ) as usize;
+        if offset < bytes_left {
+            return Some( ( self.prefetch_bits >> ( offset << 3 )) as u8 );
+        }
+
+        self.buf.get( self.buf_position + offset - bytes_left ).cloned()
+    }
+
+    fn copy_bytes( &mut self, dst: &mut [u8] )
+    {
+        debug_assert!( self.available_bits & 7 == 0 && dst.len() <= self.get_remaining_bytes() );
+        let mut dst_offs = 0;
+        while self.available_bits >= 8 && dst.len() > dst_offs {
+            dst[dst_offs] = self.take_bits( 8 ) as u8;
+            dst_offs += 1;
+        }
+
+        let count = dst.len() - dst_offs;
+        dst[dst_offs..].copy_from_slice( &self.buf[self.buf_position .. self.buf_position + count] );
+        self.buf_position += count;
+    }
+
+    #[inline(always)]
+    fn save_state( &mut self )
+    {
+        self.saved = self.position();
+    }
+
+    #[inline(always)]
+    fn restore_state( &mut self )
+    {
+        self.prefetch_bits = self.saved.prefetch_bits;
+        self.available_bits = self.saved.available_bits;
+        self.buf_position = self.saved.buf_position;
+    }
 
-            let bytes_read = try!( self.reader.read( & mut self.buf[0 .. READ_SIZE - self.available_bytes] ));
-            self.available_bytes += bytes_read; 
-            if bytes_read < READ_SIZE
-            {
-                if !finish
-                {
-                    return Err( BrotliError::InsufficientData );
-                }
-
-                self.end_of_stream = true;
-                let start = self.buf_position+self.available_bytes;
-
-                // TODO: Use memset or zeromemory, but this is probably optimised away anyway.
-                for byte in &mut self.buf[start .. start + IMPLICIT_ZEROES]
-                {
-                    *byte = 0;
-                }
-                self.available_bytes += IMPLICIT_ZEROES;
+    fn fetch_input( &mut self ) -> Result<bool>
+    {
+        if self.end_of_stream {
+            return Ok( false )
+        }
+
+        self.discard_used_input();
+        let start = self.buf.len();
+        self.buf.resize( start + READ_SIZE, 0 );
+        match self.reader.read_input( &mut self.buf[start..] ) {
+            Ok( bytes_read ) => {
+                self.buf.truncate( start + bytes_read );
+                self.end_of_stream = bytes_read == 0;
+                Ok( bytes_read > 0 )
             }
-            Ok(())
+            Err( e ) => {
+                self.buf.truncate( start );
+                Err( e )
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn bit_position( &self ) -> u64
+    {
+        ( self.discarded + self.buf_position as u64 ) * 8 - self.available_bits as u64
+    }
+}
+
+/// Implements the `BitReader` trait over input that is in memory as a whole, such as a stream
+/// in flash. Unlike `StreamBitReader`, it reads the input where it is, without buffering it.
+pub struct SliceBitReader<'a> {
+    input: &'a [u8],
+
+    // Pre-fetched bits from 'input', least significant bit first.
+    prefetch_bits: u64,
+
+    // number of valid bits in 'prefetch_bits'
+    available_bits: u32,
+
+    // the byte we're reading from
+    buf_position: usize,
+
+    saved: State
+}
+
+impl<'a> SliceBitReader<'a> {
+
+    pub fn new( input: &'a [u8] ) -> SliceBitReader<'a> {
+        SliceBitReader {
+            input: input,
+            prefetch_bits: 0,
+            available_bits: 0,
+            buf_position: 0,
+            saved: State::default()
         }
     }
 
-    // Guarantees that there are at least 24 bits in accumulator.
+    // Tops up the accumulator from the input, 8 bits at a time.
     #[inline(always)]
     fn fill_bit_window( &mut self )
     {
-        if self.bit_position >= 32
+        if self.available_bits <= 32 && self.buf_position + 4 <= self.input.len()
         {
-            self.prefetch_bits >>= 32;
-            self.bit_position -= 32;
-            // TODO: optimise using mem::transmute
-            self.prefetch_bits |= 
-                ( self.buf[self.buf_position]   as u64 ) << 56 |
-                ( self.buf[self.buf_position+1] as u64 ) << 48 |
-                ( self.buf[self.buf_position+2] as u64 ) << 40 |
-                ( self.buf[self.buf_position+3] as u64 ) << 32;
-            self.available_bytes -= 4;
+            let b = &self.input[self.buf_position .. self.buf_position + 4];
+            self.prefetch_bits |=
+                ( ( b[0] as u64 ) |
+                  ( b[1] as u64 ) << 8 |
+                  ( b[2] as u64 ) << 16 |
+                  ( b[3] as u64 ) << 24 ) << self.available_bits;
+            self.available_bits += 32;
             self.buf_position += 4;
         }
-    }  
+
+        while self.available_bits <= 56 && self.buf_position < self.input.len()
+        {
+            self.prefetch_bits |= ( self.input[self.buf_position] as u64 ) << self.available_bits;
+            self.available_bits += 8;
+            self.buf_position += 1;
+        }
+    }
 }
 
-impl<R: Read + Sized> BitReader for StreamBitReader<R> {
-    
+impl<'a> BitReader for SliceBitReader<'a> {
+
     fn warmup( &mut self ) -> Result<()>
     {
-        // SAFETY: additional bit_position check, more paranoid comparisons
-        if self.bit_position >= 64 && self.available_bytes <= 0 || self.bit_position < 8
-        {
+        self.fill_bit_window();
+        if self.available_bits == 0 {
             Err( BrotliError::InsufficientData )
-        }
-        else
-        {
-            self.bit_position -= 8;
-            self.prefetch_bits = self.buf[self.buf_position] as u64;
-            self.prefetch_bits <<= self.bit_position;
-            self.buf_position += 1;
-            self.available_bytes -= 1;
-
+        } else {
             Ok(())
         }
     }
@@ -206,138 +492,124 @@ impl<R: Read + Sized> BitReader for StreamBitReader<R> {
     #[inline(always)]
     fn get_remaining_bytes( &self ) -> usize
     {
-        // TODO: add conditional compilation to support 32-bit
-        let total = self.available_bytes + 64 - ( self.bit_position >> 3 ) as usize;
-        if !self.end_of_stream { 
-            total 
-        } else if total <= IMPLICIT_ZEROES {
-            0
-        } else {
-            total - IMPLICIT_ZEROES
-        }
+        self.input.len() - self.buf_position + ( self.available_bits >> 3 ) as usize
     }
 
     #[inline(always)]
     fn check_input_amount( &self, bytes: usize ) -> bool
     {
-        // SAFETY: Additional check (should be compiled out if inlined vs a constant)
-        bytes <= IMPLICIT_ZEROES && self.available_bytes >= bytes
+        self.input.len() - self.buf_position >= bytes
     }
 
     #[inline(always)]
-    fn get_bits_unmasked( &mut self ) -> u32
+    fn get_available_bits( &mut self ) -> u32
     {
         self.fill_bit_window();
-        
-        ( self.prefetch_bits >> self.bit_position ) as u32
+        self.available_bits
     }
 
     #[inline(always)]
-    fn get_bits( &mut self, bits: u32 ) -> u32
+    fn get_bits_unmasked( &mut self ) -> u32
     {
-        debug_assert!( bits <= 32 );
-        self.get_bits_unmasked() & !((0xffffffff) << bits )
+        self.fill_bit_window();
+        self.prefetch_bits as u32
     }
 
     #[inline(always)]
     fn drop_bits( &mut self, bits: u32 )
     {
-        debug_assert!( self.bit_position + bits <= 64 );
-        self.bit_position += bits;
+        debug_assert!( bits <= self.available_bits );
+        self.prefetch_bits >>= bits;
+        self.available_bits -= bits;
     }
 
     #[inline(always)]
-    fn take_bits( &mut self, bits: u32 ) -> u32 
-    { 
-        debug_assert!( self.bit_position + bits <= 64 );
-        let result = ( self.prefetch_bits >> self.bit_position ) & !((0xffffffff) << bits );
-        self.bit_position += bits;
-        
+    fn take_bits( &mut self, bits: u32 ) -> u32
+    {
+        debug_assert!( bits <= 32 && bits <= self.available_bits );
+        let result = self.prefetch_bits & !((0xffffffffffffffffu64) << bits );
+        self.drop_bits( bits );
+
         result as u32
     }
 
     #[inline(always)]
-    fn read_bits( &mut self, bits: u32 ) -> u32 
+    fn read_bits( &mut self, bits: u32 ) -> u32
     {
         self.fill_bit_window();
         self.take_bits( bits )
     }
-    
+
     #[inline(always)]
-    fn safe_read_bits( &mut self, bits: u32 ) -> Option<u32> 
+    fn safe_read_bits( &mut self, bits: u32 ) -> Option<u32>
     {
-        loop 
-        {
-            if self.bit_position + bits <= 64
-            {
-                break;
-            }
-            else if self.available_bytes == 0
-            {
-                return Option::None
-            }
-            else
-            {
-                self.prefetch_bits >>= 8;
-                self.prefetch_bits |= ( self.buf[self.buf_position] as u64 ) << 56;
-                self.bit_position -= 8;
-                self.available_bytes -= 1;
-                self.buf_position += 1;
+        if self.available_bits < bits {
+            self.fill_bit_window();
+            if self.available_bits < bits {
+                return None
             }
         }
-        
-        Option::Some( self.take_bits( bits ))
+
+        Some( self.take_bits( bits ))
     }
 
     #[inline(always)]
     fn jump_to_byte_boundary(&mut self) -> bool {
-        let pad_bits_count = ( 64 - self.bit_position ) & 0x7;
-        if pad_bits_count != 0 {
-            self.take_bits( pad_bits_count ) == 0
-        } else {
-            true
-        }
+        let pad_bits_count = self.available_bits & 0x7;
+        pad_bits_count == 0 || self.take_bits( pad_bits_count ) == 0
     }
 
     #[inline(always)]
     fn peek_byte( &self, offset: usize ) -> Option<u8> {
-        if self.bit_position & 7 != 0 {
-            return Option::None
+        if self.available_bits & 7 != 0 {
+            return None
         }
 
-        let bytes_left = ( 8 - (self.bit_position >> 3)) as usize;
-
+        let bytes_left = ( self.available_bits >> 3 ) as usize;
         if offset < bytes_left {
-            return Option::Some( ((self.prefetch_bits >> (self.bit_position as usize + (offset << 3))) & 0xFF ) as u8 );
-        }
-        
-        let offset_sub = offset - bytes_left;
-        if offset_sub < self.available_bytes {
-            Option::Some( self.buf[self.buf_position+offset_sub] )
-        } else {
-            Option::None
+            return Some( ( self.prefetch_bits >> ( offset << 3 )) as u8 );
         }
+
+        self.input.get( self.buf_position + offset - bytes_left ).cloned()
     }
 
-    fn copy_bytes( &mut self, dst: &mut [u8] ) 
+    fn copy_bytes( &mut self, dst: &mut [u8] )
     {
+        debug_assert!( self.available_bits & 7 == 0 && dst.len() <= self.get_remaining_bytes() );
         let mut dst_offs = 0;
-        while self.bit_position + 8 <= 64 && dst.len() > dst_offs {
-            dst[dst_offs] = ( self.prefetch_bits >> self.bit_position ) as u8;
-            self.bit_position += 8;
+        while self.available_bits >= 8 && dst.len() > dst_offs {
+            dst[dst_offs] = self.take_bits( 8 ) as u8;
             dst_offs += 1;
         }
 
-        let src = &self.buf[self.buf_position .. self.buf_position + dst.len() - dst_offs];
-        // UNSTABLE: bytes::copy_memory( src, dst );
-        (&mut dst[dst_offs..]).write( src ).unwrap();
-        self.available_bytes -= dst.len();
-        self.buf_position += dst.len();
+        let count = dst.len() - dst_offs;
+        dst[dst_offs..].copy_from_slice( &self.input[self.buf_position .. self.buf_position + count] );
+        self.buf_position += count;
     }
 
     #[inline(always)]
-    fn is_reader_okay( &self) -> bool {
-        let remaining_bytes = self.available_bytes + 8 - ((self.bit_position >> 3) as usize);
-        !self.end_of_stream || (remaining_bytes >= IMPLICIT_ZEROES)
+    fn save_state( &mut self )
+    {
+        self.saved = State { prefetch_bits: self.prefetch_bits, available_bits: self.available_bits, buf_position: self.buf_position };
+    }
+
+    #[inline(always)]
+    fn restore_state( &mut self )
+    {
+        self.prefetch_bits = self.saved.prefetch_bits;
+        self.available_bits = self.saved.available_bits;
+        self.buf_position = self.saved.buf_position;
+    }
+
+    /// All of the input is there from the start.
+    fn fetch_input( &mut self ) -> Result<bool>
+    {
+        Ok( false )
+    }
+
+    #[inline(always)]
+    fn bit_position( &self ) -> u64
+    {
+        self.buf_position as u64 * 8 - self.available_bits as u64
     }
 }
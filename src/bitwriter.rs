@@ -0,0 +1,359 @@
+use std::io::Write;
+use Result;
+
+/// Data sink for Brotli streams, the counterpart of `BitReader`. Bits are written least
+/// significant bit first, in the order `BitReader` reads them back.
+pub trait BitWriter {
+
+    /// Appends the lowest `bits` bits of `value`. At most 56 bits can be written at a time, and
+    /// the other bits of `value` must be zero.
+    fn write_bits( &mut self, bits: u32, value: u64 ) -> Result<()>;
+
+    /// Pads the output with zero bits up to the next byte boundary.
+    fn align_to_byte( &mut self ) -> Result<()>;
+
+    /// Appends whole bytes. Precondition: the output is aligned to a byte boundary.
+    fn write_bytes( &mut self, bytes: &[u8] ) -> Result<()>;
+
+    /// The number of bits written so far.
+    #[must_use]
+    fn bit_position( &self ) -> u64;
+
+    /// Returns `true` if the output ends on a byte boundary.
+    #[must_use]
+    fn is_aligned( &self ) -> bool
+    {
+        self.bit_position() & 7 == 0
+    }
+}
+
+/// Writes into a byte vector. This is the `Vec<u8>` implementation of `BitWriter`, and the
+/// output buffer of the Brotli encoder: complete bytes are appended to a buffer that the caller
+/// drains. The bits of an incomplete last byte are kept next to the vector, which only ever
+/// holds complete bytes.
+///
+/// `put_bits()`, `pad_to_byte()` and `put_bytes()` are the `BitWriter` methods for code that
+/// knows it writes into memory, where they can't fail.
+pub struct BitBuffer {
+    // Complete output bytes
+    bytes: Vec<u8>,
+
+    // Pending bits, least significant bit first
+    acc: u64,
+
+    // number of valid bits in 'acc'
+    nbits: u32,
+
+    // number of bytes drained with 'consume'
+    consumed: u64
+}
+
+impl BitBuffer {
+
+    pub fn new() -> BitBuffer {
+        BitBuffer { bytes: Vec::new(), acc: 0, nbits: 0, consumed: 0 }
+    }
+
+    /// Appends the lowest `bits` bits of `value`.
+    #[inline(always)]
+    pub fn put_bits( &mut self, bits: u32, value: u64 )
+    {
+        debug_assert!( bits <= 56 && ( bits == 56 || value >> bits == 0 ));
+        self.acc |= value << self.nbits;
+        self.nbits += bits;
+        while self.nbits >= 8 {
+            self.bytes.push( self.acc as u8 );
+            self.acc >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    /// Pads the output with zero bits up to the next byte boundary.
+    pub fn pad_to_byte( &mut self )
+    {
+        if self.nbits > 0 {
+            self.bytes.push( self.acc as u8 );
+            self.acc = 0;
+            self.nbits = 0;
+        }
+    }
+
+    /// Appends whole bytes. Precondition: the output is aligned to a byte boundary.
+    pub fn put_bytes( &mut self, bytes: &[u8] )
+    {
+        debug_assert!( self.nbits == 0 );
+        self.bytes.extend_from_slice( bytes );
+    }
+
+    /// The number of bits written since the buffer was last drained.
+    pub fn bit_len( &self ) -> usize
+    {
+        self.bytes.len() * 8 + self.nbits as usize
+    }
+
+    /// Returns `true` if the output ends on a byte boundary.
+    pub fn is_aligned( &self ) -> bool
+    {
+        self.nbits == 0
+    }
+
+    /// The complete bytes written so far.
+    pub fn bytes( &self ) -> &[u8]
+    {
+        &self.bytes
+    }
+
+    /// Discards the first `count` complete bytes, once they have been handed out.
+    pub fn consume( &mut self, count: usize )
+    {
+        self.bytes.drain( .. count );
+        self.consumed += count as u64;
+    }
+
+    /// Discards everything written after the given bit position, which must be at or after
+    /// the start of the pending complete bytes.
+    pub fn truncate( &mut self, bit_len: usize )
+    {
+        debug_assert!( bit_len <= self.bit_len() );
+        let nbits = ( bit_len & 7 ) as u32;
+        if bit_len / 8 < self.bytes.len() {
+            self.acc = self.bytes[bit_len / 8] as u64;
+        }
+        self.acc &= ( 1 << nbits ) - 1;
+        self.nbits = nbits;
+        self.bytes.truncate( bit_len / 8 );
+    }
+
+    /// Pads the output to a byte boundary, and returns the bytes not yet drained.
+    pub fn into_bytes( mut self ) -> Vec<u8>
+    {
+        self.pad_to_byte();
+        self.bytes
+    }
+}
+
+impl Default for BitBuffer {
+    fn default() -> BitBuffer {
+        BitBuffer::new()
+    }
+}
+
+impl From<Vec<u8>> for BitBuffer {
+    /// Appends to the bytes already in `bytes`, which `into_bytes()` returns.
+    fn from( bytes: Vec<u8> ) -> BitBuffer {
+        BitBuffer { bytes: bytes, acc: 0, nbits: 0, consumed: 0 }
+    }
+}
+
+impl BitWriter for BitBuffer {
+
+    #[inline(always)]
+    fn write_bits( &mut self, bits: u32, value: u64 ) -> Result<()>
+    {
+        self.put_bits( bits, value );
+        Ok( () )
+    }
+
+    fn align_to_byte( &mut self ) -> Result<()>
+    {
+        self.pad_to_byte();
+        Ok( () )
+    }
+
+    fn write_bytes( &mut self, bytes: &[u8] ) -> Result<()>
+    {
+        self.put_bytes( bytes );
+        Ok( () )
+    }
+
+    fn bit_position( &self ) -> u64
+    {
+        self.consumed * 8 + self.bit_len() as u64
+    }
+}
+
+/// Wraps a `Write` stream to implement the `BitWriter` trait. Bits are collected in a 64-bit
+/// accumulator, and written out a few bytes at a time, so the stream should be buffered.
+pub struct StreamBitWriter<W> {
+    pub writer: W,
+
+    // Pending bits, least significant bit first
+    acc: u64,
+
+    // number of valid bits in 'acc'
+    nbits: u32,
+
+    // number of bytes written to 'writer'
+    bytes_written: u64
+}
+
+impl<W: Write> StreamBitWriter<W> {
+
+    // *********** These methods are not implementations of the trait *********** //
+    pub fn new( writer: W ) -> StreamBitWriter<W> {
+        StreamBitWriter { writer: writer, acc: 0, nbits: 0, bytes_written: 0 }
+    }
+
+    /// Writes the complete bytes of the accumulator, and flushes the wrapped writer. Bits of an
+    /// incomplete byte stay pending.
+    pub fn flush( &mut self ) -> Result<()> {
+        self.write_accumulator()?;
+        self.writer.flush()?;
+        Ok( () )
+    }
+
+    /// Pads the output to a byte boundary, writes the pending bytes and returns the wrapped
+    /// writer.
+    pub fn into_inner( mut self ) -> Result<W> {
+        BitWriter::align_to_byte( &mut self )?;
+        self.flush()?;
+        Ok( self.writer )
+    }
+
+    // Writes the complete bytes of the accumulator to the wrapped writer.
+    fn write_accumulator( &mut self ) -> Result<()>
+    {
+        let count = self.nbits >> 3;
+        if count > 0 {
+            self.writer.write_all( &self.acc.to_le_bytes()[.. count as usize] )?;
+            self.acc = self.acc.checked_shr( count * 8 ).unwrap_or( 0 );
+            self.nbits -= count * 8;
+            self.bytes_written += count as u64;
+        }
+        Ok( () )
+    }
+}
+
+impl<W: Write> BitWriter for StreamBitWriter<W> {
+
+    #[inline(always)]
+    fn write_bits( &mut self, bits: u32, value: u64 ) -> Result<()>
+    {
+        debug_assert!( bits <= 56 && ( bits == 56 || value >> bits == 0 ));
+        if self.nbits + bits >= 64 {
+            self.write_accumulator()?;
+        }
+        self.acc |= value << self.nbits;
+        self.nbits += bits;
+        Ok( () )
+    }
+
+    fn align_to_byte( &mut self ) -> Result<()>
+    {
+        self.nbits = ( self.nbits + 7 ) & !7;
+        Ok( () )
+    }
+
+    fn write_bytes( &mut self, bytes: &[u8] ) -> Result<()>
+    {
+        debug_assert!( self.nbits & 7 == 0 );
+        self.write_accumulator()?;
+        self.writer.write_all( bytes )?;
+        self.bytes_written += bytes.len() as u64;
+        Ok( () )
+    }
+
+    fn bit_position( &self ) -> u64
+    {
+        self.bytes_written * 8 + self.nbits as u64
+    }
+}
+
+/// Writes a random sequence of bit fields, padding and byte strings with `bw`, and checks that
+/// `StreamBitReader` reads them back.
+#[cfg(test)]
+fn check_read_back<BW: BitWriter, F: FnOnce( BW ) -> Vec<u8>>( mut bw: BW, into_bytes: F, seed: u32 )
+{
+    use bitreader::{ BitReader, StreamBitReader };
+
+    enum Op { Bits( u32, u64 ), Align, Bytes( Vec<u8> ) }
+
+    let mut state = seed;
+    let mut random = |limit: u32| {
+        state = state.wrapping_mul( 1103515245 ).wrapping_add( 12345 );
+        ( state >> 8 ) % limit
+    };
+
+    let mut ops = Vec::new();
+    let mut position = 0;
+    for _ in 0 .. 2000 {
+        let op = match random( 16 ) {
+            0 => Op::Align,
+            1 => Op::Bytes( ( 0 .. random( 20 )).map( |_| random( 256 ) as u8 ).collect() ),
+            _ => {
+                let bits = random( 57 );
+                let value = ( ( random( 1 << 24 ) as u64 ) << 40 | ( random( 1 << 24 ) as u64 ) << 16 | random( 1 << 16 ) as u64 )
+                            & ( ( 1u64 << bits ) - 1 );
+                Op::Bits( bits, value )
+            }
+        };
+
+        match op {
+            Op::Bits( bits, value ) => {
+                bw.write_bits( bits, value ).unwrap();
+                position += bits as u64;
+            }
+            Op::Align | Op::Bytes( _ ) => {
+                bw.align_to_byte().unwrap();
+                position = ( position + 7 ) & !7;
+                assert!( bw.is_aligned() );
+            }
+        }
+        if let Op::Bytes( ref bytes ) = op {
+            bw.write_bytes( bytes ).unwrap();
+            position += bytes.len() as u64 * 8;
+        }
+        assert_eq!( bw.bit_position(), position );
+        ops.push( op );
+    }
+
+    let bytes = into_bytes( bw );
+    assert_eq!( bytes.len() as u64, position.div_ceil( 8 ));
+
+    let mut br = StreamBitReader::new( &bytes[..] );
+    while br.fetch_input().unwrap() {}
+    for op in &ops {
+        match *op {
+            Op::Bits( bits, value ) => {
+                let low = br.safe_read_bits( std::cmp::min( bits, 32 )).unwrap() as u64;
+                let high = br.safe_read_bits( bits.saturating_sub( 32 )).unwrap() as u64;
+                assert_eq!( low | high << 32, value );
+            }
+            Op::Align => assert!( br.jump_to_byte_boundary() ),
+            Op::Bytes( ref expected ) => {
+                assert!( br.jump_to_byte_boundary() );
+                let mut bytes = vec![0; expected.len()];
+                br.copy_bytes( &mut bytes );
+                assert_eq!( &bytes, expected );
+            }
+        }
+    }
+    assert!( br.jump_to_byte_boundary() );
+    assert_eq!( br.get_remaining_bytes(), 0 );
+}
+
+#[test]
+fn test_bit_buffer_read_back()
+{
+    for seed in 0 .. 20 {
+        check_read_back( BitBuffer::new(), |bw| bw.into_bytes(), seed );
+    }
+}
+
+#[test]
+fn test_bit_buffer_from_vec()
+{
+    let mut bw = BitBuffer::from( vec![0xab, 0xcd] );
+    assert_eq!( bw.bit_position(), 16 );
+    BitWriter::write_bits( &mut bw, 4, 0x5 ).unwrap();
+    BitWriter::write_bits( &mut bw, 8, 0xf6 ).unwrap();
+    assert_eq!( bw.into_bytes(), [0xab, 0xcd, 0x65, 0x0f] );
+}
+
+#[test]
+fn test_stream_bit_writer_read_back()
+{
+    for seed in 0 .. 20 {
+        check_read_back( StreamBitWriter::new( Vec::new() ), |bw| bw.into_inner().unwrap(), seed );
+    }
+}
@@ -0,0 +1,244 @@
+//! # Checkpoints
+//!
+//! A `Checkpoint` holds everything a decoder needs to continue decoding a stream from where it
+//! was taken: the position in the input down to the bit, the state of the current meta-block
+//! with its prefix codes and context maps, the last distances, and the ring buffer with the
+//! sliding window. Decoding an interrupted stream can then go on from the last checkpoint,
+//! instead of from the start.
+//!
+//! A checkpoint is kept as bytes, in the format below. Numbers are little-endian.
+//!
+//! | Bytes | Field                                                  |
+//! |-------|--------------------------------------------------------|
+//! | 4     | `BRCK`                                                 |
+//! | 1     | Version, 1                                             |
+//! | 8     | Position in the input, in bits                         |
+//! | 8     | Position in the output the resumed decoder starts at   |
+//! | ...   | The state of the decoder                               |
+//!
+//! The state of the decoder is only checked for being well formed. A checkpoint that wasn't
+//! taken from a decoder can make it fail, or decode the rest of the stream wrongly.
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use huffman::Code;
+use { BrotliError, Result };
+
+const MAGIC: &[u8; 4] = b"BRCK";
+const VERSION: u8 = 1;
+
+/// The size of the header, which the state of the decoder follows.
+pub const HEADER_SIZE: usize = 4 + 1 + 8 + 8;
+
+/// Where the window size of the stream is kept, in 4 bytes. The state of the decoder starts with
+/// the step it is at, in 17 bytes, followed by the window size.
+pub const WINDOW_BITS_OFFSET: usize = HEADER_SIZE + 17;
+
+/// The state of a decoder, taken with `Decoder::checkpoint()` and resumed with
+/// `Decoder::resume()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    bytes: Vec<u8>
+}
+
+impl Checkpoint {
+
+    /// Takes a checkpoint serialized with `as_bytes()` or `into_bytes()`. Fails with
+    /// `BrotliError::InvalidCheckpoint` if it doesn't start with the header of one.
+    pub fn from_bytes( bytes: Vec<u8> ) -> Result<Checkpoint> {
+        if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC || bytes[4] != VERSION {
+            return Err( BrotliError::InvalidCheckpoint )
+        }
+        Ok( Checkpoint { bytes: bytes } )
+    }
+
+    /// The checkpoint, serialized.
+    pub fn as_bytes( &self ) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The checkpoint, serialized.
+    pub fn into_bytes( self ) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// The position in the compressed stream where decoding goes on, in bits.
+    pub fn bit_offset( &self ) -> u64 {
+        read_u64( &self.bytes[5..] )
+    }
+
+    /// The byte of the compressed stream the input of the resumed decoder has to start at. The
+    /// bits of it before `bit_offset()` are skipped.
+    pub fn input_offset( &self ) -> u64 {
+        self.bit_offset() >> 3
+    }
+
+    /// The position in the decompressed output of the first byte the resumed decoder returns.
+    /// Output the decoder had not handed out yet when the checkpoint was taken is returned again.
+    pub fn output_offset( &self ) -> u64 {
+        read_u64( &self.bytes[13..] )
+    }
+
+    /// The state of the decoder, after the header.
+    pub( crate ) fn state( &self ) -> CheckpointReader<'_> {
+        CheckpointReader { bytes: &self.bytes[HEADER_SIZE..] }
+    }
+}
+
+fn read_u64( bytes: &[u8] ) -> u64 {
+    let mut value = [0; 8];
+    value.copy_from_slice( &bytes[..8] );
+    u64::from_le_bytes( value )
+}
+
+/// Serializes the state of a decoder into a `Checkpoint`.
+pub( crate ) struct CheckpointWriter {
+    bytes: Vec<u8>
+}
+
+impl CheckpointWriter {
+
+    pub( crate ) fn new( bit_offset: u64, output_offset: u64 ) -> CheckpointWriter {
+        let mut writer = CheckpointWriter { bytes: Vec::new() };
+        writer.bytes.extend_from_slice( MAGIC );
+        writer.bytes.push( VERSION );
+        writer.u64( bit_offset );
+        writer.u64( output_offset );
+        writer
+    }
+
+    pub( crate ) fn finish( self ) -> Checkpoint {
+        Checkpoint { bytes: self.bytes }
+    }
+
+    /// The number of bytes written, header included.
+    pub( crate ) fn position( &self ) -> usize {
+        self.bytes.len()
+    }
+
+    pub( crate ) fn u8( &mut self, value: u8 ) {
+        self.bytes.push( value );
+    }
+
+    pub( crate ) fn bool( &mut self, value: bool ) {
+        self.u8( value as u8 );
+    }
+
+    pub( crate ) fn u32( &mut self, value: u32 ) {
+        self.bytes.extend_from_slice( &value.to_le_bytes() );
+    }
+
+    pub( crate ) fn u64( &mut self, value: u64 ) {
+        self.bytes.extend_from_slice( &value.to_le_bytes() );
+    }
+
+    pub( crate ) fn usize( &mut self, value: usize ) {
+        self.u64( value as u64 );
+    }
+
+    /// Writes the length of `bytes`, followed by them.
+    pub( crate ) fn bytes( &mut self, bytes: &[u8] ) {
+        self.usize( bytes.len() );
+        self.bytes.extend_from_slice( bytes );
+    }
+
+    /// Writes the number of `codes`, followed by them.
+    pub( crate ) fn codes( &mut self, codes: &[Code] ) {
+        self.usize( codes.len() );
+        for code in codes {
+            self.bytes.extend_from_slice( &code.to_bytes() );
+        }
+    }
+}
+
+/// Reads back the state written by a `CheckpointWriter`. Fails with
+/// `BrotliError::InvalidCheckpoint` if it ends early, or a value is out of range.
+pub( crate ) struct CheckpointReader<'a> {
+    bytes: &'a [u8]
+}
+
+impl<'a> CheckpointReader<'a> {
+
+    fn take( &mut self, len: usize ) -> Result<&'a [u8]> {
+        if len > self.bytes.len() {
+            return Err( BrotliError::InvalidCheckpoint )
+        }
+        let (taken, rest) = self.bytes.split_at( len );
+        self.bytes = rest;
+        Ok( taken )
+    }
+
+    /// Fails unless all of the state has been read.
+    pub( crate ) fn finish( self ) -> Result<()> {
+        if !self.bytes.is_empty() {
+            return Err( BrotliError::InvalidCheckpoint )
+        }
+        Ok( () )
+    }
+
+    pub( crate ) fn u8( &mut self ) -> Result<u8> {
+        Ok( self.take( 1 )?[0] )
+    }
+
+    pub( crate ) fn bool( &mut self ) -> Result<bool> {
+        match self.u8()? {
+            0 => Ok( false ),
+            1 => Ok( true ),
+            _ => Err( BrotliError::InvalidCheckpoint )
+        }
+    }
+
+    pub( crate ) fn u32( &mut self ) -> Result<u32> {
+        let mut value = [0; 4];
+        value.copy_from_slice( self.take( 4 )? );
+        Ok( u32::from_le_bytes( value ))
+    }
+
+    pub( crate ) fn u64( &mut self ) -> Result<u64> {
+        Ok( read_u64( self.take( 8 )? ))
+    }
+
+    pub( crate ) fn usize( &mut self ) -> Result<usize> {
+        usize::try_from( self.u64()? ).map_err( |_| BrotliError::InvalidCheckpoint )
+    }
+
+    /// Reads a `usize` that is at most `max`.
+    pub( crate ) fn usize_max( &mut self, max: usize ) -> Result<usize> {
+        let value = self.usize()?;
+        if value > max {
+            return Err( BrotliError::InvalidCheckpoint )
+        }
+        Ok( value )
+    }
+
+    /// Reads bytes written with `CheckpointWriter::bytes()`.
+    pub( crate ) fn bytes( &mut self ) -> Result<&'a [u8]> {
+        let len = self.usize()?;
+        self.take( len )
+    }
+
+    /// Reads bytes written with `CheckpointWriter::bytes()` into `buf`, which they have to fill.
+    pub( crate ) fn bytes_into( &mut self, buf: &mut [u8] ) -> Result<()> {
+        let bytes = self.bytes()?;
+        if bytes.len() != buf.len() {
+            return Err( BrotliError::InvalidCheckpoint )
+        }
+        buf.copy_from_slice( bytes );
+        Ok( () )
+    }
+
+    /// Reads the number of codes written with `CheckpointWriter::codes()`, which is at most `max`.
+    /// The codes follow, and are read with `codes_into()`.
+    pub( crate ) fn codes_len( &mut self, max: usize ) -> Result<usize> {
+        self.usize_max( max )
+    }
+
+    /// Reads codes into `buf`, which they have to fill.
+    pub( crate ) fn codes_into( &mut self, buf: &mut [Code] ) -> Result<()> {
+        let bytes = self.take( buf.len() * 3 )?;
+        for (code, bytes) in buf.iter_mut().zip( bytes.chunks( 3 )) {
+            *code = Code::from_bytes( [bytes[0], bytes[1], bytes[2]] );
+        }
+        Ok( () )
+    }
+}
@@ -29,9 +29,9 @@
 /// `p1` is the most recent byte, `p2` is the second most recent byte.
 ///
 /// Implementation based on: https://github.com/madler/brotli/blob/master/yeast.c by Mark Adler
-fn context_id( p1: u8, p2: u8, mode: u8 ) -> u8
+pub fn context_id( p1: u8, p2: u8, mode: u8 ) -> u8
 {
-    const LUT0: [u8;256] = [
+    static LUT0: [u8;256] = [
         0,  0,  0,  0,  0,  0,  0,  0,  0,  4,  4,  0,  0,  4,  0,  0, 0,  0,
         0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0, 8, 12, 16, 12,
         12, 20, 12, 16, 24, 28, 12, 12, 32, 12, 36, 12, 44, 44, 44, 44, 44, 44,
@@ -47,7 +47,7 @@ fn context_id( p1: u8, p2: u8, mode: u8 ) -> u8
         3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3 
     ];
 
-    const LUT1: [u8;256] = [
+    static LUT1: [u8;256] = [
         0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
         0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
         2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2,
@@ -61,7 +61,7 @@ fn context_id( p1: u8, p2: u8, mode: u8 ) -> u8
         2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2 
     ];
 
-    const LUT2: [u8;256] = [
+    static LUT2: [u8;256] = [
         0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2,
         2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
         2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 3,
@@ -1,22 +1,615 @@
+use allocator::{ BrotliAlloc, HeapAlloc };
 use bitreader::BitReader;
-use std::io::{Read,Result};
+use checkpoint::{ Checkpoint, CheckpointReader, CheckpointWriter, WINDOW_BITS_OFFSET };
+#[cfg(feature = "std")]
+use bitreader::{ NoSource, StreamBitReader };
+use huffman::{ max_table_size, Code, Table, HUFFMAN_MAX_CODE_LENGTHS_SIZE, HUFFMAN_MAX_TABLE_SIZE };
+use prefix::{ PREFIX_CODE_RANGE, CMD_LUT, NUM_DISTANCE_SHORT_CODES, DISTANCE_SHORT_CODE_INDEX, DISTANCE_SHORT_CODE_OFFSET };
+use context::context_id;
+use dictionary;
+use disasm::{ Category, CodeKind, Distance, Entry, Instruction, Listing, MetaBlockKind };
+use observer::{ CommandObserver, MetaBlockHeader, NoObserver };
+use transforms::TRANSFORMS;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cmp::{ min, max, Ordering };
+use core::mem;
+#[cfg(feature = "std")]
+use index::Index;
+#[cfg(feature = "std")]
+use std::io::{ self, BufRead, Read, Seek, SeekFrom, Write };
+use { BrotliError, Result };
 
-static CODE_LENGTH_CODE_ORDER: [u8; 18] = [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15 ];
+const NUM_LITERAL_SYMBOLS: usize = 256;
+const NUM_COMMAND_SYMBOLS: usize = 704;
+const NUM_BLOCK_LENGTH_SYMBOLS: usize = 26;
+const MAX_NUM_DISTANCE_SYMBOLS: usize = NUM_DISTANCE_SHORT_CODES + ( 15 << 3 ) + ( 48 << 3 );
 
-struct Decoder<BR> {
-    br: BR
+const LITERAL_CONTEXT_BITS: usize = 6;
+const DISTANCE_CONTEXT_BITS: usize = 2;
+
+/// Block categories, in the order they appear in the meta-block header.
+const LITERAL: usize = 0;
+const COMMAND: usize = 1;
+const DISTANCE: usize = 2;
+
+/// Block count used when a category has a single block type, which is never reached.
+const INFINITE_BLOCK_COUNT: usize = 1 << 28;
+
+/// The ring buffer of the last meta-block is sized to fit the output, but no smaller than this.
+const MIN_RING_BUFFER_SIZE: usize = 64;
+
+/// Large enough for the longest transformed dictionary word.
+const MAX_TRANSFORMED_WORD_LENGTH: usize = 64;
+
+/// The largest number of block types, and of trees in a context map.
+const MAX_NUM_TYPES: usize = 256;
+
+/// The tables of the block type and block length codes of each category.
+const BLOCK_TYPE_TABLE_SIZE: usize = max_table_size( MAX_NUM_TYPES + 2 );
+const BLOCK_LENGTH_TABLE_SIZE: usize = max_table_size( NUM_BLOCK_LENGTH_SYMBOLS );
+const BLOCK_TREES_SIZE: usize = 3 * ( BLOCK_TYPE_TABLE_SIZE + BLOCK_LENGTH_TABLE_SIZE );
+
+/// The largest buffer the decoder allocates for the context maps of a meta-block with up to
+/// `num_types` block types of each category.
+pub const fn context_maps_size( num_types: usize ) -> usize {
+    ( num_types << LITERAL_CONTEXT_BITS ) + ( num_types << DISTANCE_CONTEXT_BITS )
 }
 
-impl<BR: BitReader> Read for Decoder<BR> {
-    fn read(&mut self, _buf: &mut [u8]) -> Result<usize> {
-        unimplemented!();
+/// The largest buffer the decoder allocates for the prefix code tables of a meta-block with up to
+/// `num_types` prefix codes of each category, in table entries.
+pub const fn trees_size( num_types: usize ) -> usize {
+    num_types * ( max_table_size( NUM_LITERAL_SYMBOLS ) +
+                  max_table_size( NUM_COMMAND_SYMBOLS ) +
+                  max_table_size( MAX_NUM_DISTANCE_SYMBOLS ))
+}
+
+/// The largest buffer the decoder allocates for the context maps of a meta-block.
+pub const MAX_CONTEXT_MAPS_SIZE: usize = context_maps_size( MAX_NUM_TYPES );
+
+/// The largest buffer the decoder allocates for the prefix code tables of a meta-block, in table
+/// entries.
+pub const MAX_TREES_SIZE: usize = trees_size( MAX_NUM_TYPES );
+
+/// Receives the payload of a metadata meta-block.
+type MetadataCallback = dyn FnMut( &[u8] ) + Send;
+
+/// Records what decoding failed on in `failure`, and returns the error for it.
+fn invalid( failure: &mut Option<Failure>, kind: Failure ) -> BrotliError {
+    *failure = Some( kind );
+    BrotliError::InvalidEncoding
+}
+
+/// The result of running the decoder until it can't make any more progress.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// The end of the stream was reached. Any remaining output can still be read.
+    Done,
+
+    /// The decoder needs more input to continue.
+    NeedsMoreInput,
+
+    /// The ring buffer is full, and output must be read before decoding can continue.
+    NeedsMoreOutput
+}
+
+/// What the decoder failed on, as told apart by the error codes of the C library. Returned by
+/// `Decoder::failure()` once decoding has failed with `BrotliError::InvalidEncoding`,
+/// `BrotliError::OutOfMemory` or `BrotliError::WindowTooLarge`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Failure {
+    /// The length of a meta-block has a leading zero nibble.
+    ExuberantNibble,
+
+    /// A reserved bit is set.
+    Reserved,
+
+    /// The length of a metadata meta-block has a leading zero byte.
+    ExuberantMetaNibble,
+
+    /// A prefix code, or a block switch or context map coded with it, is invalid.
+    PrefixCode,
+
+    /// A run of zeros in a context map goes past its end.
+    ContextMapRepeat,
+
+    /// The inserted literals go past the end of the meta-block.
+    InsertLength,
+
+    /// A copy goes past the end of the meta-block.
+    CopyLength,
+
+    /// A static dictionary reference has an invalid transform.
+    Transform,
+
+    /// A static dictionary reference has an invalid length.
+    Dictionary,
+
+    /// The window size is invalid, or larger than the allocator supports.
+    WindowBits,
+
+    /// The padding of a meta-block header to a byte boundary isn't zero.
+    MetaBlockPadding,
+
+    /// The padding at the end of the stream isn't zero.
+    StreamPadding,
+
+    /// A distance refers to before the start of the output.
+    Distance,
+
+    /// The ring buffer couldn't be allocated.
+    RingBufferAlloc,
+
+    /// The context maps couldn't be allocated.
+    ContextMapAlloc,
+
+    /// The prefix code tables couldn't be allocated.
+    TreesAlloc
+}
+
+/// Which part of the stream the decoder is going to read next. Each state reads one unit of
+/// input, and only updates the decoder once the whole unit has been read.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    StreamHeader,
+    MetaBlockHeader,
+    Uncompressed,
+    Metadata,
+    BlockTypes(usize),
+    DistanceParameters,
+    LiteralContextMap,
+    DistanceContextMap,
+    Trees(usize, usize),
+    CommandBegin,
+    Literals,
+    Distance,
+    Copy,
+    MetaBlockDone,
+    Done,
+    Failed
+}
+
+impl State {
+
+    /// The state as a tag and its arguments, for a `Checkpoint`.
+    fn to_parts( self ) -> (u8, usize, usize) {
+        match self {
+            State::StreamHeader => (0, 0, 0),
+            State::MetaBlockHeader => (1, 0, 0),
+            State::Uncompressed => (2, 0, 0),
+            State::Metadata => (3, 0, 0),
+            State::BlockTypes( category ) => (4, category, 0),
+            State::DistanceParameters => (5, 0, 0),
+            State::LiteralContextMap => (6, 0, 0),
+            State::DistanceContextMap => (7, 0, 0),
+            State::Trees( category, index ) => (8, category, index),
+            State::CommandBegin => (9, 0, 0),
+            State::Literals => (10, 0, 0),
+            State::Distance => (11, 0, 0),
+            State::Copy => (12, 0, 0),
+            State::MetaBlockDone => (13, 0, 0),
+            State::Done => (14, 0, 0),
+            State::Failed => (15, 0, 0)
+        }
+    }
+
+    /// The state of the parts returned by `to_parts()`.
+    fn from_parts( tag: u8, category: usize, index: usize ) -> Result<State> {
+        let state = match tag {
+            0 => State::StreamHeader,
+            1 => State::MetaBlockHeader,
+            2 => State::Uncompressed,
+            3 => State::Metadata,
+            4 => State::BlockTypes( category ),
+            5 => State::DistanceParameters,
+            6 => State::LiteralContextMap,
+            7 => State::DistanceContextMap,
+            8 => State::Trees( category, index ),
+            9 => State::CommandBegin,
+            10 => State::Literals,
+            11 => State::Distance,
+            12 => State::Copy,
+            13 => State::MetaBlockDone,
+            14 => State::Done,
+            15 => State::Failed,
+            _ => return Err( BrotliError::InvalidCheckpoint )
+        };
+        if category > DISTANCE || index >= MAX_NUM_TYPES || state.to_parts() != (tag, category, index) {
+            return Err( BrotliError::InvalidCheckpoint )
+        }
+        Ok( state )
+    }
+}
+
+/// Block switching state of one block category. Its prefix codes are in `block_trees`, at
+/// `block_tree_offset()`.
+#[derive(Clone, Default)]
+struct BlockTypes {
+    num_types: usize,
+
+    /// The current and the previous block type.
+    types: [usize; 2],
+
+    /// The number of symbols left in the current block.
+    remaining: usize
+}
+
+/// How a context map with more than one tree was coded.
+struct ContextMapCode {
+    /// The bit offset of the prefix code of the map.
+    offset: u64,
+    max_run_length_prefix: usize,
+    imtf: bool
+}
+
+/// Streaming Brotli decoder.
+///
+/// The decoded output is stored in a ring buffer the size of the sliding window, and handed
+/// out through the `Read` and `BufRead` implementations. The decoded commands can be followed with a
+/// `CommandObserver`. The ring buffer and the other large buffers come from a `BrotliAlloc`.
+pub struct Decoder<BR, O: CommandObserver = NoObserver, A: BrotliAlloc = HeapAlloc> {
+    br: BR,
+    state: State,
+
+    window_bits: u32,
+    is_last: bool,
+    meta_block_remaining: usize,
+
+    // Ring buffer holding the sliding window and any output not yet read
+    ring: A::Bytes,
+    ring_pos: usize,
+    pending: usize,
+    total_out: usize,
+
+    // Compressed meta-block header
+    blocks: [BlockTypes; 3],
+    distance_postfix_bits: u32,
+    num_direct_distance_codes: usize,
+    context_modes: [u8; MAX_NUM_TYPES],
+    num_literal_trees: usize,
+    num_distance_trees: usize,
+
+    // The literal context map, followed by the distance context map
+    context_maps: A::Bytes,
+
+    // The block type and block length codes of each category
+    block_trees: [Code; BLOCK_TREES_SIZE],
+
+    // The trees of each category, one after another
+    trees: A::Codes,
+    tree_offsets: [usize; 3],
+    tree_sizes: [usize; 3],
+
+    // Current command
+    insert_length: usize,
+    insert_remaining: usize,
+    copy_length: usize,
+    explicit_distance: bool,
+    distance_context: usize,
+    copy_distance: usize,
+    copy_remaining: usize,
+    word: [u8; MAX_TRANSFORMED_WORD_LENGTH],
+    word_length: usize,
+    word_pos: usize,
+
+    // The last four distances, most recent first
+    distances: [usize; 4],
+
+    // Scratch space for prefix code lengths
+    code_lengths: [u8; HUFFMAN_MAX_CODE_LENGTHS_SIZE],
+
+    // Receives the payloads of metadata meta-blocks, which are collected in 'metadata'
+    metadata_callback: Option<Box<MetadataCallback>>,
+    metadata: Vec<u8>,
+
+    // The instruction being read, if the observer lists them
+    listing: Listing,
+
+    // A resumed decoder starts reading in the middle of the stream, at this bit offset, and
+    // skips the bits of the first byte that were read before the checkpoint
+    source_bit_offset: u64,
+    skip_bits: u32,
+
+    // What decoding failed on, once it has
+    failure: Option<Failure>,
+
+    observer: O,
+    allocator: A
+}
+
+#[cfg(feature = "std")]
+impl<BR: BitReader, O: CommandObserver, A: BrotliAlloc> Read for Decoder<BR, O, A> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let bytes = self.write_output( buf );
+            if bytes > 0 || buf.is_empty() {
+                return Ok( bytes )
+            }
+
+            match self.decode()? {
+                Status::Done => if self.pending == 0 {
+                    return Ok( 0 )
+                },
+                Status::NeedsMoreOutput => {}
+                // Decoded output is handed out before waiting for more input
+                Status::NeedsMoreInput => if self.pending == 0 && !self.br.fetch_input()? {
+                    return Err( io::Error::from( BrotliError::InsufficientData ))
+                }
+            }
+        }
+    }
+}
+
+/// Hands out the decoded output where it is in the ring buffer, without copying it.
+#[cfg(feature = "std")]
+impl<BR: BitReader, O: CommandObserver, A: BrotliAlloc> BufRead for Decoder<BR, O, A> {
+    /// Returns the decoded output up to the end of the ring buffer, decoding more if there is
+    /// none. The rest of the output, from the start of the ring buffer, comes next.
+    fn fill_buf( &mut self ) -> io::Result<&[u8]> {
+        while self.pending == 0 {
+            match self.decode()? {
+                Status::Done => if self.pending == 0 {
+                    return Ok( &[] )
+                },
+                Status::NeedsMoreOutput => {}
+                Status::NeedsMoreInput => if !self.br.fetch_input()? {
+                    return Err( io::Error::from( BrotliError::InsufficientData ))
+                }
+            }
+        }
+
+        let start = self.output_start();
+        let count = min( self.pending, self.ring.len() - start );
+        Ok( &self.ring[start .. start + count] )
+    }
+
+    fn consume( &mut self, amount: usize ) {
+        self.pending -= min( amount, self.pending );
+    }
+}
+
+/// Decompresses the Brotli stream written to it, and writes the output to the wrapped writer.
+///
+/// The stream can be written in pieces split anywhere. Each piece is decoded as far as it goes,
+/// and the rest of it is kept until the next one. `close()` checks that the stream ended, and
+/// returns the wrapped writer.
+#[cfg(feature = "std")]
+pub struct DecompressorWriter<W: Write> {
+    decoder: Decoder<StreamBitReader<NoSource>>,
+    writer: W
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> DecompressorWriter<W> {
+
+    pub fn new( writer: W ) -> DecompressorWriter<W> {
+        DecompressorWriter { decoder: Decoder::new( StreamBitReader::new( NoSource )), writer: writer }
+    }
+
+    /// Returns a reference to the wrapped writer.
+    pub fn get_ref( &self ) -> &W {
+        &self.writer
+    }
+
+    /// Returns a mutable reference to the wrapped writer.
+    pub fn get_mut( &mut self ) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Writes out the remaining output, and returns the wrapped writer if the stream ended
+    /// cleanly. Fails with `ErrorKind::UnexpectedEof` if it was truncated, and with
+    /// `ErrorKind::InvalidData` if it was invalid or followed by more data.
+    pub fn close( mut self ) -> io::Result<W> {
+        self.decode_input()?;
+        if !self.decoder.is_finished() {
+            return Err( io::Error::from( BrotliError::InsufficientData ))
+        }
+        self.check_end()?;
+        self.writer.flush()?;
+        Ok( self.writer )
+    }
+
+    /// Decodes the input written so far, writing out the output whenever the ring buffer fills up.
+    fn decode_input( &mut self ) -> io::Result<()> {
+        loop {
+            self.write_output()?;
+            if self.decoder.decode()? != Status::NeedsMoreOutput {
+                return self.write_output()
+            }
+        }
+    }
+
+    /// Writes the decoded output to the wrapped writer, straight from the ring buffer.
+    fn write_output( &mut self ) -> io::Result<()> {
+        let decoder = &mut self.decoder;
+        while decoder.pending > 0 {
+            let start = decoder.output_start();
+            let count = min( decoder.pending, decoder.ring.len() - start );
+            self.writer.write_all( &decoder.ring[start .. start + count] )?;
+            decoder.pending -= count;
+        }
+        Ok( () )
+    }
+
+    /// Fails if there is data after the end of the stream.
+    fn check_end( &self ) -> io::Result<()> {
+        if self.decoder.is_finished() && self.decoder.br.get_remaining_bytes() > 0 {
+            return Err( io::Error::from( BrotliError::InvalidEncoding ))
+        }
+        Ok( () )
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> Write for DecompressorWriter<W> {
+    fn write( &mut self, buf: &[u8] ) -> io::Result<usize> {
+        // Output of earlier input is written first, so that an error doesn't lose any input
+        self.decode_input()?;
+        if self.decoder.is_finished() && !buf.is_empty() {
+            return Err( io::Error::from( BrotliError::InvalidEncoding ))
+        }
+
+        // Once the input is taken, errors of the wrapped writer are left to the next call
+        self.decoder.br.push( buf );
+        if let Err( e ) = self.decode_input() {
+            if self.decoder.state == State::Failed {
+                return Err( e )
+            }
+        }
+        self.check_end()?;
+        Ok( buf.len() )
+    }
+
+    fn flush( &mut self ) -> io::Result<()> {
+        self.decode_input()?;
+        self.writer.flush()
     }
 }
 
 impl <BR: BitReader> Decoder<BR> {
 
-    pub fn new(_source: BR) -> Decoder<BR> {
-        unimplemented!();
+    pub fn new(source: BR) -> Decoder<BR> {
+        Decoder::with_observer( source, NoObserver )
+    }
+
+    /// Creates a decoder that goes on from `checkpoint`, reading the rest of the stream from
+    /// `source`. The source has to start at `checkpoint.input_offset()` in the stream.
+    pub fn resume( checkpoint: &Checkpoint, source: BR ) -> Result<Decoder<BR>> {
+        let mut decoder = Decoder::new( source );
+        decoder.load_checkpoint( checkpoint )?;
+        Ok( decoder )
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read + Seek, O: CommandObserver, A: BrotliAlloc> Decoder<StreamBitReader<R>, O, A> {
+
+    /// Moves to `offset` in the output, where the next read starts. Decoding goes on from the
+    /// point of `index` nearest before `offset`, or from the current position if that is nearer,
+    /// and the output before `offset` is dropped. The source has to hold the stream `index` was
+    /// built from, at its start. Fails with `BrotliError::InsufficientData` if the stream ends
+    /// before `offset`.
+    pub fn seek_to( &mut self, index: &Index, offset: u64 ) -> Result<()> {
+        let position = self.output_position();
+        let point = index.point_before( offset );
+        if self.state == State::Failed || offset < position || point.output_offset() > position {
+            self.br.reader.seek( SeekFrom::Start( point.input_offset() ))?;
+            self.br.clear();
+            self.reset_state();
+            self.load_checkpoint( point )?;
+        }
+
+        self.skip_output( offset - self.output_position() )
+    }
+
+    /// Moves to a frame of a seekable stream, which is at `input_offset` in the source, and
+    /// starts at `output_offset` in the output. A frame starts with a meta-block header, and
+    /// doesn't refer to any output before it, except for the first one, which starts the stream.
+    #[cfg(feature = "encoder")]
+    pub( crate ) fn seek_to_frame( &mut self, input_offset: u64, output_offset: u64, window_bits: u32 ) -> Result<()> {
+        self.br.reader.seek( SeekFrom::Start( input_offset ))?;
+        self.br.clear();
+        self.reset_state();
+        if output_offset > 0 {
+            self.state = State::MetaBlockHeader;
+            self.window_bits = window_bits;
+            self.total_out = output_offset as usize;
+
+            // The ring buffer is allocated again unless it holds a whole window, and the context
+            // of the first literals is taken from its end, as at the start of a stream
+            if self.ring.len() < 1 << window_bits {
+                self.allocator.free_u8( &mut self.ring );
+            } else {
+                let size = self.ring.len();
+                self.ring[size - 2 ..].copy_from_slice( &[0, 0] );
+            }
+        }
+        Ok( () )
+    }
+}
+
+impl <BR: BitReader, O: CommandObserver> Decoder<BR, O> {
+
+    /// Creates a decoder that reports the commands it decodes to `observer`.
+    pub fn with_observer( source: BR, observer: O ) -> Decoder<BR, O> {
+        Decoder::with_allocator( source, observer, HeapAlloc )
+    }
+}
+
+impl <BR: BitReader, O: CommandObserver, A: BrotliAlloc> Decoder<BR, O, A> {
+
+    /// Creates a decoder that reports the commands it decodes to `observer`, and takes its
+    /// buffers from `allocator`.
+    pub fn with_allocator( source: BR, observer: O, allocator: A ) -> Decoder<BR, O, A> {
+        Decoder {
+            br: source,
+            state: State::StreamHeader,
+            window_bits: 0,
+            is_last: false,
+            meta_block_remaining: 0,
+            ring: A::Bytes::default(),
+            ring_pos: 0,
+            pending: 0,
+            total_out: 0,
+            blocks: Default::default(),
+            distance_postfix_bits: 0,
+            num_direct_distance_codes: 0,
+            context_modes: [0; MAX_NUM_TYPES],
+            num_literal_trees: 0,
+            num_distance_trees: 0,
+            context_maps: A::Bytes::default(),
+            block_trees: [Code::default(); BLOCK_TREES_SIZE],
+            trees: A::Codes::default(),
+            tree_offsets: [0; 3],
+            tree_sizes: [0; 3],
+            insert_length: 0,
+            insert_remaining: 0,
+            copy_length: 0,
+            explicit_distance: false,
+            distance_context: 0,
+            copy_distance: 0,
+            copy_remaining: 0,
+            word: [0; MAX_TRANSFORMED_WORD_LENGTH],
+            word_length: 0,
+            word_pos: 0,
+            distances: [4, 11, 15, 16],
+            code_lengths: [0; HUFFMAN_MAX_CODE_LENGTHS_SIZE],
+            metadata_callback: None,
+            metadata: Vec::new(),
+            listing: Listing::default(),
+            source_bit_offset: 0,
+            skip_bits: 0,
+            failure: None,
+            observer: observer,
+            allocator: allocator
+        }
+    }
+
+    /// Returns the observer.
+    pub fn observer( &self ) -> &O {
+        &self.observer
+    }
+
+    /// Returns the observer, mutably.
+    pub fn observer_mut( &mut self ) -> &mut O {
+        &mut self.observer
+    }
+
+    /// Returns the allocator.
+    pub fn allocator( &self ) -> &A {
+        &self.allocator
+    }
+
+    /// Returns the allocator, mutably.
+    pub fn allocator_mut( &mut self ) -> &mut A {
+        &mut self.allocator
+    }
+
+    /// Hands the payload of each metadata meta-block to `callback`, instead of skipping it. The
+    /// empty metadata meta-blocks that pad the stream at sync points are handed over as well.
+    /// A payload is only handed over once all of it has been read.
+    pub fn set_metadata_callback<F: FnMut( &[u8] ) + Send + 'static>( &mut self, callback: F ) {
+        self.metadata_callback = Some( Box::new( callback ));
     }
 
     /* @@@ TODO
@@ -24,17 +617,1173 @@ impl <BR: BitReader> Decoder<BR> {
         unimplemented!();
     } */
 
-    fn decode_window_bits(br: &mut BR) -> u32 {
-        if br.read_bits(1) == 0 { 
-            16
+    /// Returns a reference to the data source.
+    pub fn get_ref( &self ) -> &BR {
+        &self.br
+    }
+
+    /// Returns a mutable reference to the data source. Input read from it, but not decoded yet,
+    /// is buffered in it.
+    pub fn get_mut( &mut self ) -> &mut BR {
+        &mut self.br
+    }
+
+    /// Returns the data source.
+    pub fn into_inner( self ) -> BR {
+        self.br
+    }
+
+    /// Makes the decoder start over on a new stream read from `source`, and returns the old
+    /// source. The ring buffer, the context maps and the prefix code tables are kept, and reused
+    /// as long as they are large enough, so decoding many small streams with one decoder only
+    /// allocates for the largest window. The observer and the metadata callback are kept as well.
+    pub fn reset( &mut self, source: BR ) -> BR {
+        self.reset_state();
+        mem::replace( &mut self.br, source )
+    }
+
+    /// Makes the decoder start over, keeping its buffers and its source.
+    fn reset_state( &mut self ) {
+        self.state = State::StreamHeader;
+        self.window_bits = 0;
+        self.is_last = false;
+        self.meta_block_remaining = 0;
+        self.ring_pos = 0;
+        self.pending = 0;
+        self.total_out = 0;
+        self.blocks = Default::default();
+        self.distance_postfix_bits = 0;
+        self.num_direct_distance_codes = 0;
+        self.num_literal_trees = 0;
+        self.num_distance_trees = 0;
+        self.tree_offsets = [0; 3];
+        self.tree_sizes = [0; 3];
+        self.insert_length = 0;
+        self.insert_remaining = 0;
+        self.copy_length = 0;
+        self.explicit_distance = false;
+        self.distance_context = 0;
+        self.copy_distance = 0;
+        self.copy_remaining = 0;
+        self.word_length = 0;
+        self.word_pos = 0;
+        self.distances = [4, 11, 15, 16];
+        self.metadata.clear();
+        self.listing.data.clear();
+        self.source_bit_offset = 0;
+        self.skip_bits = 0;
+        self.failure = None;
+    }
+
+    /// Takes the state of the decoder, to go on decoding later with `resume()` or `restore()`.
+    /// It can be taken between any two calls of `decode()`. The output not read yet is part of
+    /// it, and is returned again by the resumed decoder.
+    pub fn checkpoint( &self ) -> Checkpoint {
+        let bit_offset = self.source_bit_offset + self.skip_bits as u64 + self.br.bit_position();
+        let mut writer = CheckpointWriter::new( bit_offset, self.output_position() );
+        let (tag, category, index) = self.state.to_parts();
+        writer.u8( tag );
+        writer.usize( category );
+        writer.usize( index );
+        debug_assert_eq!( writer.position(), WINDOW_BITS_OFFSET );
+        writer.u32( self.window_bits );
+        writer.bool( self.is_last );
+        writer.usize( self.meta_block_remaining );
+
+        writer.bytes( &self.ring );
+        writer.usize( self.ring_pos );
+        writer.usize( self.pending );
+        writer.usize( self.total_out );
+
+        for block in &self.blocks {
+            writer.usize( block.num_types );
+            writer.usize( block.types[0] );
+            writer.usize( block.types[1] );
+            writer.usize( block.remaining );
+        }
+        writer.u32( self.distance_postfix_bits );
+        writer.usize( self.num_direct_distance_codes );
+        writer.bytes( &self.context_modes );
+        writer.usize( self.num_literal_trees );
+        writer.usize( self.num_distance_trees );
+        writer.bytes( &self.context_maps );
+        writer.codes( &self.block_trees );
+
+        // The trees of the current meta-block, which come before any that are left over
+        let trees_size = self.tree_offsets[DISTANCE] + self.num_distance_trees * self.tree_sizes[DISTANCE];
+        writer.codes( &self.trees[.. min( trees_size, self.trees.len() )] );
+        for category in 0..3 {
+            writer.usize( self.tree_offsets[category] );
+            writer.usize( self.tree_sizes[category] );
+        }
+
+        writer.usize( self.insert_length );
+        writer.usize( self.insert_remaining );
+        writer.usize( self.copy_length );
+        writer.bool( self.explicit_distance );
+        writer.usize( self.distance_context );
+        writer.usize( self.copy_distance );
+        writer.usize( self.copy_remaining );
+        writer.bytes( &self.word );
+        writer.usize( self.word_length );
+        writer.usize( self.word_pos );
+        for &distance in &self.distances {
+            writer.usize( distance );
+        }
+        writer.bytes( &self.code_lengths );
+        writer.bytes( &self.metadata );
+        writer.finish()
+    }
+
+    /// Makes the decoder go on from `checkpoint`, reading the rest of the stream from `source`,
+    /// like `resume()`. Its buffers are reused, as with `reset()`. Fails with
+    /// `BrotliError::InvalidCheckpoint` if the checkpoint is not well formed.
+    pub fn restore( &mut self, checkpoint: &Checkpoint, source: BR ) -> Result<()> {
+        self.reset( source );
+        self.load_checkpoint( checkpoint )
+    }
+
+    /// Decodes and drops `count` bytes of output. Fails with `BrotliError::InsufficientData` if
+    /// the stream ends first.
+    #[cfg(feature = "std")]
+    pub( crate ) fn skip_output( &mut self, mut count: u64 ) -> Result<()> {
+        while count > 0 {
+            if self.pending == 0 {
+                match self.decode()? {
+                    Status::NeedsMoreInput if !self.br.fetch_input()? => return Err( BrotliError::InsufficientData ),
+                    Status::Done if self.pending == 0 => return Err( BrotliError::InsufficientData ),
+                    _ => {}
+                }
+            }
+            let skipped = min( self.pending as u64, count );
+            self.pending -= skipped as usize;
+            count -= skipped;
+        }
+        Ok( () )
+    }
+
+    /// Loads the state of `checkpoint` into a decoder that was just created or reset.
+    fn load_checkpoint( &mut self, checkpoint: &Checkpoint ) -> Result<()> {
+        if let Err( e ) = self.read_checkpoint( checkpoint.state() ) {
+            self.state = State::Failed;
+            return Err( e )
+        }
+        self.source_bit_offset = checkpoint.input_offset() << 3;
+        self.skip_bits = ( checkpoint.bit_offset() & 7 ) as u32;
+        Ok( () )
+    }
+
+    fn read_checkpoint( &mut self, mut reader: CheckpointReader ) -> Result<()> {
+        let tag = reader.u8()?;
+        let category = reader.usize()?;
+        let index = reader.usize()?;
+        self.state = State::from_parts( tag, category, index )?;
+        self.window_bits = reader.u32()?;
+        if self.window_bits > 24 || ( self.state != State::StreamHeader && self.window_bits < 10 ) {
+            return Err( BrotliError::InvalidCheckpoint )
+        }
+        if self.window_bits > A::MAX_WINDOW_BITS {
+            return Err( BrotliError::WindowTooLarge { window_bits: self.window_bits, max_window_bits: A::MAX_WINDOW_BITS } )
+        }
+        self.is_last = reader.bool()?;
+        self.meta_block_remaining = reader.usize_max( 1 << 24 )?;
+
+        let ring = reader.bytes()?;
+        if !ring.is_empty() {
+            if !ring.len().is_power_of_two() || !( MIN_RING_BUFFER_SIZE ..= max( 1 << self.window_bits, MIN_RING_BUFFER_SIZE )).contains( &ring.len() ) {
+                return Err( BrotliError::InvalidCheckpoint )
+            }
+            if self.ring.len() != ring.len() {
+                self.allocator.free_u8( &mut self.ring );
+                self.allocator.alloc_u8( &mut self.ring, ring.len() )?;
+            }
+            self.ring.copy_from_slice( ring );
+        }
+        self.ring_pos = reader.usize_max( max( ring.len(), 1 ) - 1 )?;
+        self.pending = reader.usize_max( ring.len() )?;
+        self.total_out = reader.usize()?;
+        if ( self.total_out > 0 && ring.is_empty() ) || self.pending > self.total_out {
+            return Err( BrotliError::InvalidCheckpoint )
+        }
+
+        for block in &mut self.blocks {
+            block.num_types = reader.usize_max( MAX_NUM_TYPES )?;
+            block.types = [reader.usize_max( MAX_NUM_TYPES - 1 )?, reader.usize_max( MAX_NUM_TYPES - 1 )?];
+            block.remaining = reader.usize()?;
+        }
+        self.distance_postfix_bits = reader.u32()?;
+        self.num_direct_distance_codes = reader.usize_max( 15 << 3 )?;
+        if self.distance_postfix_bits > 3 {
+            return Err( BrotliError::InvalidCheckpoint )
+        }
+        reader.bytes_into( &mut self.context_modes )?;
+        if self.context_modes.iter().any( |&mode| mode > 3 ) {
+            return Err( BrotliError::InvalidCheckpoint )
+        }
+        self.num_literal_trees = reader.usize_max( MAX_NUM_TYPES )?;
+        self.num_distance_trees = reader.usize_max( MAX_NUM_TYPES )?;
+        let num_types = self.blocks.iter().map( |block| block.num_types ).chain( [self.num_literal_trees, self.num_distance_trees] ).max().unwrap();
+        if num_types > A::MAX_NUM_TYPES {
+            return Err( BrotliError::TooManyTypes { num_types: num_types, max_num_types: A::MAX_NUM_TYPES } )
+        }
+
+        let context_maps = reader.bytes()?;
+        if context_maps.len() > MAX_CONTEXT_MAPS_SIZE {
+            return Err( BrotliError::InvalidCheckpoint )
+        }
+        if self.context_maps.len() < context_maps.len() {
+            self.allocator.free_u8( &mut self.context_maps );
+            self.allocator.alloc_u8( &mut self.context_maps, context_maps.len() )?;
+        }
+        self.context_maps[.. context_maps.len()].copy_from_slice( context_maps );
+
+        if reader.codes_len( BLOCK_TREES_SIZE )? != BLOCK_TREES_SIZE {
+            return Err( BrotliError::InvalidCheckpoint )
+        }
+        reader.codes_into( &mut self.block_trees )?;
+        let trees_size = reader.codes_len( MAX_TREES_SIZE )?;
+        if self.trees.len() < trees_size {
+            self.allocator.free_codes( &mut self.trees );
+            self.allocator.alloc_codes( &mut self.trees, trees_size )?;
+        }
+        reader.codes_into( &mut self.trees[.. trees_size] )?;
+        for category in 0..3 {
+            self.tree_offsets[category] = reader.usize_max( MAX_TREES_SIZE )?;
+            self.tree_sizes[category] = reader.usize_max( HUFFMAN_MAX_TABLE_SIZE )?;
+        }
+
+        self.insert_length = reader.usize()?;
+        self.insert_remaining = reader.usize()?;
+        self.copy_length = reader.usize()?;
+        self.explicit_distance = reader.bool()?;
+        self.distance_context = reader.usize_max( 3 )?;
+        self.copy_distance = reader.usize()?;
+        self.copy_remaining = reader.usize()?;
+        reader.bytes_into( &mut self.word )?;
+        self.word_length = reader.usize_max( MAX_TRANSFORMED_WORD_LENGTH )?;
+        self.word_pos = reader.usize_max( self.word_length )?;
+        for distance in &mut self.distances {
+            *distance = reader.usize()?;
+        }
+        reader.bytes_into( &mut self.code_lengths )?;
+        self.metadata.clear();
+        self.metadata.extend_from_slice( reader.bytes()? );
+        reader.finish()?;
+
+        if !self.is_consistent( ring.len(), context_maps.len(), trees_size ) {
+            return Err( BrotliError::InvalidCheckpoint )
+        }
+        Ok( () )
+    }
+
+    /// Returns `true` if the decoder can go on from the state read from a checkpoint, which has
+    /// a ring buffer, context maps and trees of the given sizes: every index the state holds
+    /// stays inside what it indexes, and the prefix codes read so far decode to their alphabets.
+    fn is_consistent( &self, ring_len: usize, context_maps_len: usize, trees_len: usize ) -> bool {
+        let max_distance = max( 1 << self.window_bits, 16 );
+        if self.distances.iter().any( |&distance| distance > max_distance ) {
+            return false
+        }
+        if self.state == State::Copy && ( self.copy_distance > ring_len || self.copy_remaining > self.copy_length ||
+                                          ( self.copy_remaining > 0 && self.copy_distance == 0 )) {
+            return false
+        }
+
+        // How much of the header of a compressed meta-block has been read
+        let progress = match self.state {
+            State::BlockTypes( category ) => category,
+            State::DistanceParameters => 3,
+            State::LiteralContextMap => 4,
+            State::DistanceContextMap => 5,
+            State::Trees( .. ) => 6,
+            State::CommandBegin | State::Literals | State::Distance | State::Copy => 7,
+            State::Uncompressed => return ring_len > 0,
+            _ => return true
+        };
+        if ring_len == 0 {
+            return false
+        }
+
+        for category in 0 .. min( progress, 3 ) {
+            let blocks = &self.blocks[category];
+            if blocks.num_types == 0 || blocks.types[0] >= blocks.num_types || blocks.types[1] > blocks.num_types {
+                return false
+            }
+            let offset = block_tree_offset( category );
+            if blocks.num_types == 1 {
+                if blocks.remaining == 0 {
+                    return false
+                }
+            } else if !table( &self.block_trees, offset, BLOCK_TYPE_TABLE_SIZE ).is_valid( blocks.num_types + 2 ) ||
+                      !table( &self.block_trees, offset + BLOCK_TYPE_TABLE_SIZE, BLOCK_LENGTH_TABLE_SIZE ).is_valid( NUM_BLOCK_LENGTH_SYMBOLS ) {
+                return false
+            }
+        }
+
+        let literal_map_size = self.literal_context_map_size();
+        let maps_size = literal_map_size + self.distance_context_map_size();
+        if progress > 4 && ( self.num_literal_trees == 0 || context_maps_len < literal_map_size ||
+                             self.context_maps[..literal_map_size].iter().any( |&tree| tree as usize >= self.num_literal_trees )) {
+            return false
+        }
+        if progress > 5 && ( self.num_distance_trees == 0 || context_maps_len < maps_size ||
+                             self.context_maps[literal_map_size..maps_size].iter().any( |&tree| tree as usize >= self.num_distance_trees )) {
+            return false
+        }
+        if progress < 6 {
+            return true
+        }
+
+        // The trees read so far: all of them, unless they are still being read
+        let counts = [self.num_literal_trees, self.blocks[COMMAND].num_types, self.num_distance_trees];
+        let (current_category, current_index) = match self.state {
+            State::Trees( category, index ) => (category, index),
+            _ => (3, 0)
+        };
+        if current_category < 3 && current_index >= counts[current_category] {
+            return false
+        }
+        for (category, &count) in counts.iter().enumerate() {
+            let alphabet_size = self.alphabet_size( category );
+            let size = self.tree_sizes[category];
+            let offset = self.tree_offsets[category];
+            if size != max_table_size( alphabet_size ) || offset + count * size > trees_len {
+                return false
+            }
+            let read = match category.cmp( &current_category ) {
+                Ordering::Less => count,
+                Ordering::Equal => current_index,
+                Ordering::Greater => 0
+            };
+            if ( 0..read ).any( |index| !table( &self.trees, offset + index * size, size ).is_valid( alphabet_size )) {
+                return false
+            }
+        }
+        true
+    }
+
+    /// Returns `true` once the end of the stream has been decoded.
+    pub fn is_finished( &self ) -> bool {
+        self.state == State::Done
+    }
+
+    /// What decoding failed on, once it has failed on the stream or on allocating memory.
+    pub fn failure( &self ) -> Option<Failure> {
+        self.failure
+    }
+
+    /// The base 2 logarithm of the window size, once the stream header has been read.
+    pub fn window_bits( &self ) -> u32 {
+        self.window_bits
+    }
+
+    /// The position in the output of the next byte read from the decoder.
+    pub fn output_position( &self ) -> u64 {
+        ( self.total_out - self.pending ) as u64
+    }
+
+    /// The number of decoded bytes waiting to be read.
+    pub fn pending_output( &self ) -> usize {
+        self.pending
+    }
+
+    /// Hands an instruction to the observer, if it lists them. Instructions are only listed once
+    /// all their input has been read, so that they aren't listed again if the decoder has to
+    /// restore its state and wait for more input.
+    #[inline(always)]
+    fn list( &mut self, bit_offset: u64, instruction: Instruction ) {
+        if self.observer.lists_instructions() {
+            self.observer.instruction( Entry { bit_offset: bit_offset, instruction: instruction } );
+        }
+    }
+
+    /// Lists a prefix code, whose code lengths are in `code_lengths`.
+    fn list_prefix_code( &mut self, bit_offset: u64, kind: CodeKind, index: usize, alphabet_size: usize ) {
+        if self.observer.lists_instructions() {
+            let code_lengths = self.code_lengths[..alphabet_size].to_vec();
+            self.list( bit_offset, Instruction::PrefixCode { kind: kind, index: index, code_lengths: code_lengths } );
+        }
+    }
+
+    /// Lists the current command, with the literals collected since it was read.
+    fn list_command( &mut self, distance: Option<Distance> ) {
+        if self.observer.lists_instructions() {
+            let instruction = Instruction::Command {
+                code: self.listing.command_code,
+                literals: mem::take( &mut self.listing.data ),
+                copy_length: self.copy_length,
+                distance: distance
+            };
+            self.observer.instruction( Entry { bit_offset: self.listing.command_offset, instruction: instruction } );
+        }
+    }
+
+    pub( crate ) fn decode_window_bits(br: &mut BR) -> Result<u32> {
+        if br.try_read_bits(1)? == 0 {
+            Ok( 16 )
         } else {
-            let n = br.read_bits(3);
+            let n = br.try_read_bits(3)?;
             if n != 0 {
-                n + 17
+                Ok( n + 17 )
+            } else {
+                let n = br.try_read_bits(3)?;
+                match n {
+                    0 => Ok( 17 ),
+                    // Value 9 is reserved for future use.
+                    1 => Err( BrotliError::InvalidEncoding ),
+                    n => Ok( n + 8 )
+                }
+            }
+        }
+    }
+
+    /// Decodes a value between 0 and 255, used for the number of block types and trees.
+    fn decode_var_len_uint8(br: &mut BR) -> Result<usize> {
+        if br.try_read_bits(1)? == 0 {
+            Ok( 0 )
+        } else {
+            let n = br.try_read_bits(3)?;
+            if n == 0 {
+                Ok( 1 )
+            } else {
+                Ok( br.try_read_bits(n)? as usize + (1 << n) )
+            }
+        }
+    }
+
+    /// Decodes a block count using the block length prefix code.
+    fn decode_block_length(tree: &Table<&[Code]>, br: &mut BR) -> Result<usize> {
+        let range = PREFIX_CODE_RANGE[tree.safe_read_huffman_symbol(br)? as usize];
+        Ok( range.offset as usize + br.try_read_bits(range.nbits as u32)? as usize )
+    }
+
+    /// Copies decoded bytes to `buf`, and returns the number of bytes copied. Together with
+    /// `decode()`, this hands out the output without the `Read` implementation.
+    pub fn write_output( &mut self, buf: &mut [u8] ) -> usize {
+        let mut written = 0;
+        while written < buf.len() && self.pending > 0 {
+            let start = self.output_start();
+            let count = min( min( buf.len() - written, self.pending ), self.ring.len() - start );
+            buf[written .. written + count].copy_from_slice( &self.ring[start .. start + count] );
+            written += count;
+            self.pending -= count;
+        }
+
+        written
+    }
+
+    /// The position in the ring buffer of the first decoded byte that hasn't been handed out.
+    fn output_start( &self ) -> usize {
+        ( self.ring_pos + self.ring.len() - self.pending ) & ( self.ring.len() - 1 )
+    }
+
+    /// Runs the decoder until it needs more input, the ring buffer is full or the stream ends.
+    pub fn decode( &mut self ) -> Result<Status> {
+        if self.skip_bits > 0 {
+            self.br.save_state();
+            if self.br.try_read_bits( self.skip_bits ).is_err() {
+                self.br.restore_state();
+                return Ok( Status::NeedsMoreInput )
+            }
+            self.skip_bits = 0;
+        }
+
+        loop {
+            self.br.save_state();
+            match self.decode_unit() {
+                Ok( None ) => {}
+                Ok( Some( status )) => return Ok( status ),
+                Err( BrotliError::InsufficientData ) => {
+                    self.br.restore_state();
+                    return Ok( Status::NeedsMoreInput )
+                }
+                Err( e ) => {
+                    if self.failure.is_none() {
+                        self.failure = self.failure_in_state( &e );
+                    }
+                    self.state = State::Failed;
+                    return Err( e )
+                }
+            }
+        }
+    }
+
+    /// What `error` in the current state failed on, for the errors that don't say themselves.
+    fn failure_in_state( &self, error: &BrotliError ) -> Option<Failure> {
+        match *error {
+            BrotliError::OutOfMemory => match self.state {
+                State::MetaBlockHeader => Some( Failure::RingBufferAlloc ),
+                State::LiteralContextMap => Some( Failure::ContextMapAlloc ),
+                _ => Some( Failure::TreesAlloc )
+            },
+            BrotliError::WindowTooLarge { .. } => Some( Failure::WindowBits ),
+            BrotliError::InvalidEncoding => match self.state {
+                State::StreamHeader => Some( Failure::WindowBits ),
+                State::BlockTypes( _ ) | State::LiteralContextMap | State::DistanceContextMap | State::Trees( .. ) => Some( Failure::PrefixCode ),
+                State::MetaBlockDone => Some( Failure::StreamPadding ),
+                _ => None
+            },
+            _ => None
+        }
+    }
+
+    /// Reads the next unit of input. Returns a status if decoding can't continue.
+    fn decode_unit( &mut self ) -> Result<Option<Status>> {
+        let bit_offset = self.br.bit_position();
+        match self.state {
+            State::StreamHeader => {
+                self.window_bits = Self::decode_window_bits( &mut self.br )?;
+                if self.window_bits > A::MAX_WINDOW_BITS {
+                    return Err( BrotliError::WindowTooLarge { window_bits: self.window_bits, max_window_bits: A::MAX_WINDOW_BITS } )
+                }
+                self.list( bit_offset, Instruction::WindowBits( self.window_bits ));
+                self.state = State::MetaBlockHeader;
+            }
+            State::MetaBlockHeader => self.decode_meta_block_header( bit_offset )?,
+            State::Uncompressed => {
+                while self.meta_block_remaining > 0 {
+                    if self.pending == self.ring.len() {
+                        return Ok( Some( Status::NeedsMoreOutput ))
+                    }
+
+                    let count = min( min( self.meta_block_remaining, self.br.get_remaining_bytes() ),
+                                     min( self.ring.len() - self.pending, self.ring.len() - self.ring_pos ));
+                    if count == 0 {
+                        return Err( BrotliError::InsufficientData )
+                    }
+
+                    self.br.copy_bytes( &mut self.ring[self.ring_pos .. self.ring_pos + count] );
+                    if self.observer.lists_instructions() {
+                        self.listing.data.extend_from_slice( &self.ring[self.ring_pos .. self.ring_pos + count] );
+                    }
+                    self.ring_pos = ( self.ring_pos + count ) & ( self.ring.len() - 1 );
+                    self.pending += count;
+                    self.total_out += count;
+                    self.meta_block_remaining -= count;
+                    self.br.save_state();
+                }
+                if self.observer.lists_instructions() {
+                    let instruction = Instruction::Uncompressed( mem::take( &mut self.listing.data ));
+                    self.list( self.listing.data_offset, instruction );
+                }
+                self.state = State::MetaBlockDone;
+            }
+            State::Metadata => {
+                let mut skipped = [0u8; 256];
+                while self.meta_block_remaining > 0 {
+                    let count = min( min( self.meta_block_remaining, self.br.get_remaining_bytes() ), skipped.len() );
+                    if count == 0 {
+                        return Err( BrotliError::InsufficientData )
+                    }
+
+                    self.br.copy_bytes( &mut skipped[..count] );
+                    if self.metadata_callback.is_some() {
+                        self.metadata.extend_from_slice( &skipped[..count] );
+                    }
+                    if self.observer.lists_instructions() {
+                        self.listing.data.extend_from_slice( &skipped[..count] );
+                    }
+                    self.meta_block_remaining -= count;
+                    self.br.save_state();
+                }
+                if let Some( ref mut callback ) = self.metadata_callback {
+                    callback( &self.metadata );
+                    self.metadata.clear();
+                }
+                if self.observer.lists_instructions() {
+                    let instruction = Instruction::Metadata( mem::take( &mut self.listing.data ));
+                    self.list( self.listing.data_offset, instruction );
+                }
+                self.state = State::MetaBlockDone;
+            }
+            State::BlockTypes( category ) => {
+                let num_types = Self::decode_var_len_uint8( &mut self.br )? + 1;
+                if num_types > A::MAX_NUM_TYPES {
+                    return Err( BrotliError::TooManyTypes { num_types: num_types, max_num_types: A::MAX_NUM_TYPES } )
+                }
+                let mut remaining = INFINITE_BLOCK_COUNT;
+                let mut codes = Vec::new();
+                {
+                    let blocks = &mut self.blocks[category];
+                    if num_types >= 2 {
+                        let type_tree_offset = self.br.bit_position();
+                        let offset = block_tree_offset( category );
+                        table_mut( &mut self.block_trees, offset, BLOCK_TYPE_TABLE_SIZE ).read( num_types + 2, &mut self.code_lengths, &mut self.br )?;
+                        if self.observer.lists_instructions() {
+                            codes.push( (type_tree_offset, CodeKind::BlockType( Category::from_index( category )), self.code_lengths[..num_types + 2].to_vec()) );
+                        }
+
+                        let length_tree_offset = self.br.bit_position();
+                        table_mut( &mut self.block_trees, offset + BLOCK_TYPE_TABLE_SIZE, BLOCK_LENGTH_TABLE_SIZE ).read( NUM_BLOCK_LENGTH_SYMBOLS, &mut self.code_lengths, &mut self.br )?;
+                        if self.observer.lists_instructions() {
+                            codes.push( (length_tree_offset, CodeKind::BlockLength( Category::from_index( category )), self.code_lengths[..NUM_BLOCK_LENGTH_SYMBOLS].to_vec()) );
+                        }
+                        remaining = Self::decode_block_length( &table( &self.block_trees, offset + BLOCK_TYPE_TABLE_SIZE, BLOCK_LENGTH_TABLE_SIZE ), &mut self.br )?;
+                    }
+
+                    blocks.num_types = num_types;
+                    blocks.types = [0, 1];
+                    blocks.remaining = remaining;
+                }
+
+                let first_block_length = if num_types >= 2 { Some( remaining ) } else { None };
+                self.list( bit_offset, Instruction::BlockTypes { category: Category::from_index( category ), num_types: num_types, first_block_length: first_block_length } );
+                for (offset, kind, code_lengths) in codes {
+                    self.list( offset, Instruction::PrefixCode { kind: kind, index: 0, code_lengths: code_lengths } );
+                }
+                self.state = if category < DISTANCE { State::BlockTypes( category + 1 ) } else { State::DistanceParameters };
+            }
+            State::DistanceParameters => {
+                let postfix_bits = self.br.try_read_bits( 2 )?;
+                let direct_codes = ( self.br.try_read_bits( 4 )? << postfix_bits ) as usize;
+                let num_types = self.blocks[LITERAL].num_types;
+                for i in 0 .. num_types {
+                    self.context_modes[i] = self.br.try_read_bits( 2 )? as u8;
+                }
+
+                self.distance_postfix_bits = postfix_bits;
+                self.num_direct_distance_codes = direct_codes;
+                if self.observer.lists_instructions() {
+                    let context_modes = self.context_modes[..num_types].to_vec();
+                    self.list( bit_offset, Instruction::DistanceParameters { postfix_bits: postfix_bits, num_direct_codes: direct_codes, context_modes: context_modes } );
+                }
+                self.state = State::LiteralContextMap;
+            }
+            State::LiteralContextMap => {
+                let num_trees = Self::decode_var_len_uint8( &mut self.br )? + 1;
+                if num_trees > A::MAX_NUM_TYPES {
+                    return Err( BrotliError::TooManyTypes { num_types: num_trees, max_num_types: A::MAX_NUM_TYPES } )
+                }
+                let size = self.literal_context_map_size();
+                let maps_size = size + self.distance_context_map_size();
+                if self.context_maps.len() < maps_size {
+                    self.allocator.free_u8( &mut self.context_maps );
+                    self.allocator.alloc_u8( &mut self.context_maps, maps_size )?;
+                }
+                let code = Self::decode_context_map( &mut self.context_maps[..size], num_trees, &mut self.code_lengths, &mut self.br, &mut self.failure )?;
+                self.num_literal_trees = num_trees;
+                self.list_context_map( bit_offset, LITERAL, num_trees, code );
+                self.state = State::DistanceContextMap;
+            }
+            State::DistanceContextMap => {
+                let num_trees = Self::decode_var_len_uint8( &mut self.br )? + 1;
+                if num_trees > A::MAX_NUM_TYPES {
+                    return Err( BrotliError::TooManyTypes { num_types: num_trees, max_num_types: A::MAX_NUM_TYPES } )
+                }
+                let start = self.literal_context_map_size();
+                let size = self.distance_context_map_size();
+                let code = Self::decode_context_map( &mut self.context_maps[start .. start + size], num_trees, &mut self.code_lengths, &mut self.br, &mut self.failure )?;
+                self.num_distance_trees = num_trees;
+                self.list_context_map( bit_offset, DISTANCE, num_trees, code );
+
+                let counts = [self.num_literal_trees, self.blocks[COMMAND].num_types, self.num_distance_trees];
+                let mut offset = 0;
+                for (category, &count) in counts.iter().enumerate() {
+                    self.tree_offsets[category] = offset;
+                    self.tree_sizes[category] = max_table_size( self.alphabet_size( category ));
+                    offset += count * self.tree_sizes[category];
+                }
+                if self.trees.len() < offset {
+                    self.allocator.free_codes( &mut self.trees );
+                    self.allocator.alloc_codes( &mut self.trees, offset )?;
+                }
+                self.state = State::Trees( LITERAL, 0 );
+            }
+            State::Trees( category, index ) => {
+                let alphabet_size = self.alphabet_size( category );
+                let count = match category {
+                    LITERAL => self.num_literal_trees,
+                    COMMAND => self.blocks[COMMAND].num_types,
+                    _ => self.num_distance_trees
+                };
+
+                let size = self.tree_sizes[category];
+                table_mut( &mut self.trees, self.tree_offsets[category] + index * size, size ).read( alphabet_size, &mut self.code_lengths, &mut self.br )?;
+                self.list_prefix_code( bit_offset, CodeKind::Symbols( Category::from_index( category )), index, alphabet_size );
+                self.state = if index + 1 < count {
+                    State::Trees( category, index + 1 )
+                } else if category < DISTANCE {
+                    State::Trees( category + 1, 0 )
+                } else {
+                    State::CommandBegin
+                };
+            }
+            State::CommandBegin => {
+                if self.blocks[COMMAND].remaining == 0 {
+                    self.switch_block_type( COMMAND )?;
+                    return Ok( None )
+                }
+
+                let block_type = self.blocks[COMMAND].types[0];
+                let size = self.tree_sizes[COMMAND];
+                let symbol = table( &self.trees, self.tree_offsets[COMMAND] + block_type * size, size ).safe_read_huffman_symbol( &mut self.br )?;
+                let cmd = CMD_LUT[symbol as usize];
+                let insert_length = cmd.insert_len_offset as usize + self.br.try_read_bits( cmd.insert_len_extra_bits as u32 )? as usize;
+                let copy_length = cmd.copy_len_offset as usize + self.br.try_read_bits( cmd.copy_len_extra_bits as u32 )? as usize;
+                if insert_length > self.meta_block_remaining {
+                    return Err( invalid( &mut self.failure, Failure::InsertLength ))
+                }
+
+                self.blocks[COMMAND].remaining -= 1;
+                self.insert_length = insert_length;
+                self.insert_remaining = insert_length;
+                self.copy_length = copy_length;
+                self.explicit_distance = cmd.distance_code;
+                self.distance_context = cmd.context as usize;
+                self.meta_block_remaining -= insert_length;
+                if self.observer.lists_instructions() {
+                    self.listing.command_offset = bit_offset;
+                    self.listing.command_code = symbol;
+                }
+                self.state = State::Literals;
+            }
+            State::Literals => {
+                while self.insert_remaining > 0 {
+                    if self.pending == self.ring.len() {
+                        return Ok( Some( Status::NeedsMoreOutput ))
+                    }
+
+                    if self.blocks[LITERAL].remaining == 0 {
+                        self.switch_block_type( LITERAL )?;
+                        self.br.save_state();
+                    }
+
+                    let mask = self.ring.len() - 1;
+                    let p1 = self.ring[( self.ring_pos + mask ) & mask];
+                    let p2 = self.ring[( self.ring_pos + mask - 1 ) & mask];
+                    let block_type = self.blocks[LITERAL].types[0];
+                    let context = context_id( p1, p2, self.context_modes[block_type] ) as usize;
+                    let tree = self.context_maps[( block_type << LITERAL_CONTEXT_BITS ) + context] as usize;
+                    let code_offset = self.br.bit_position();
+                    let size = self.tree_sizes[LITERAL];
+                    let literal = table( &self.trees, self.tree_offsets[LITERAL] + tree * size, size ).safe_read_huffman_symbol( &mut self.br )?;
+                    self.observer.literal_cost(( self.br.bit_position() - code_offset ) as u32 );
+
+                    self.blocks[LITERAL].remaining -= 1;
+                    self.insert_remaining -= 1;
+                    self.ring[self.ring_pos] = literal as u8;
+                    if self.observer.lists_instructions() {
+                        self.listing.data.push( literal as u8 );
+                    }
+                    self.ring_pos = ( self.ring_pos + 1 ) & mask;
+                    self.pending += 1;
+                    self.total_out += 1;
+                    self.br.save_state();
+                }
+
+                if self.insert_length > 0 {
+                    self.observer.literal( self.insert_length );
+                }
+                if self.meta_block_remaining == 0 {
+                    self.list_command( None );
+                    self.state = State::MetaBlockDone;
+                } else {
+                    self.state = State::Distance;
+                }
+            }
+            State::Distance => self.decode_distance()?,
+            State::Copy => {
+                let mask = self.ring.len() - 1;
+                if self.word_pos < self.word_length {
+                    while self.word_pos < self.word_length {
+                        if self.pending == self.ring.len() {
+                            return Ok( Some( Status::NeedsMoreOutput ))
+                        }
+
+                        self.ring[self.ring_pos] = self.word[self.word_pos];
+                        self.ring_pos = ( self.ring_pos + 1 ) & mask;
+                        self.pending += 1;
+                        self.total_out += 1;
+                        self.word_pos += 1;
+                    }
+                } else {
+                    while self.copy_remaining > 0 {
+                        if self.pending == self.ring.len() {
+                            return Ok( Some( Status::NeedsMoreOutput ))
+                        }
+
+                        // Copy in chunks that don't cross the end of the ring buffer
+                        let src = ( self.ring_pos + self.ring.len() - self.copy_distance ) & mask;
+                        let count = min( min( self.copy_remaining, self.ring.len() - self.pending ),
+                                         min( self.ring.len() - self.ring_pos, self.ring.len() - src ));
+                        if self.copy_distance >= count {
+                            self.ring.copy_within( src .. src + count, self.ring_pos );
+                        } else {
+                            for i in 0 .. count {
+                                self.ring[self.ring_pos + i] = self.ring[src + i];
+                            }
+                        }
+
+                        self.ring_pos = ( self.ring_pos + count ) & mask;
+                        self.pending += count;
+                        self.total_out += count;
+                        self.copy_remaining -= count;
+                    }
+                }
+
+                self.state = if self.meta_block_remaining == 0 { State::MetaBlockDone } else { State::CommandBegin };
+            }
+            State::MetaBlockDone => {
+                if self.is_last {
+                    if !self.br.jump_to_byte_boundary() {
+                        return Err( BrotliError::InvalidEncoding )
+                    }
+                    self.state = State::Done;
+                } else {
+                    self.state = State::MetaBlockHeader;
+                }
+            }
+            State::Done => return Ok( Some( Status::Done )),
+            State::Failed => return Err( BrotliError::InvalidEncoding )
+        }
+
+        Ok( None )
+    }
+
+    /// Reads the meta-block header up to the start of the data, or the prefix codes.
+    fn decode_meta_block_header( &mut self, bit_offset: u64 ) -> Result<()> {
+        let header = Self::read_meta_block_header( &mut self.br, &mut self.failure )?;
+        if ( self.total_out == 0 || self.ring.is_empty() ) && ( header.kind == MetaBlockKind::Compressed || header.kind == MetaBlockKind::Uncompressed ) {
+            // A ring buffer kept by reset() is reused if it is large enough
+            let size = Self::ring_buffer_size( self.window_bits, &header, self.total_out );
+            if self.ring.len() < size {
+                self.allocator.free_u8( &mut self.ring );
+                self.allocator.alloc_u8( &mut self.ring, size )?;
+            }
+
+            // The context of the first literals is taken from the end of the ring buffer
+            let size = self.ring.len();
+            self.ring[size - 2 ..].copy_from_slice( &[0, 0] );
+        }
+
+        self.list( bit_offset, Instruction::MetaBlockHeader { is_last: header.is_last, length: header.length, kind: header.kind } );
+        self.observer.meta_block( &header );
+        if header.kind != MetaBlockKind::Empty && self.observer.lists_instructions() {
+            self.listing.data_offset = self.br.bit_position();
+        }
+
+        self.is_last = header.is_last;
+        self.meta_block_remaining = header.length;
+        self.state = match header.kind {
+            MetaBlockKind::Empty => State::MetaBlockDone,
+            MetaBlockKind::Metadata => State::Metadata,
+            MetaBlockKind::Uncompressed => State::Uncompressed,
+            MetaBlockKind::Compressed => State::BlockTypes( LITERAL )
+        };
+        Ok( () )
+    }
+
+    /// Reads a meta-block header, up to the start of the data of an uncompressed or metadata
+    /// meta-block, or the prefix codes of a compressed one. An invalid header is described in
+    /// `failure`.
+    pub( crate ) fn read_meta_block_header( br: &mut BR, failure: &mut Option<Failure> ) -> Result<MetaBlockHeader> {
+        let is_last = br.try_read_bits( 1 )? == 1;
+        if is_last && br.try_read_bits( 1 )? == 1 {
+            return Ok( MetaBlockHeader { is_last: true, length: 0, kind: MetaBlockKind::Empty } )
+        }
+
+        let size_nibbles = br.try_read_bits( 2 )?;
+        if size_nibbles == 3 {
+            if br.try_read_bits( 1 )? != 0 {
+                return Err( invalid( failure, Failure::Reserved ))
+            }
+
+            let size_bytes = br.try_read_bits( 2 )?;
+            let mut length = 0;
+            for i in 0 .. size_bytes {
+                let byte = br.try_read_bits( 8 )? as usize;
+                if i + 1 == size_bytes && size_bytes > 1 && byte == 0 {
+                    return Err( invalid( failure, Failure::ExuberantMetaNibble ))
+                }
+                length |= byte << ( i * 8 );
+            }
+            if size_bytes > 0 {
+                length += 1;
+            }
+
+            if !br.jump_to_byte_boundary() {
+                return Err( invalid( failure, Failure::MetaBlockPadding ))
+            }
+            return Ok( MetaBlockHeader { is_last: is_last, length: length, kind: MetaBlockKind::Metadata } )
+        }
+
+        let size_nibbles = size_nibbles + 4;
+        let mut length = 0;
+        for i in 0 .. size_nibbles {
+            let nibble = br.try_read_bits( 4 )? as usize;
+            if i + 1 == size_nibbles && size_nibbles > 4 && nibble == 0 {
+                return Err( invalid( failure, Failure::ExuberantNibble ))
+            }
+            length |= nibble << ( i * 4 );
+        }
+        length += 1;
+
+        let is_uncompressed = !is_last && br.try_read_bits( 1 )? == 1;
+        if is_uncompressed && !br.jump_to_byte_boundary() {
+            return Err( invalid( failure, Failure::MetaBlockPadding ))
+        }
+
+        let kind = if is_uncompressed { MetaBlockKind::Uncompressed } else { MetaBlockKind::Compressed };
+        Ok( MetaBlockHeader { is_last: is_last, length: length, kind: kind } )
+    }
+
+    /// The size of the ring buffer, allocated before the first meta-block with data. A stream
+    /// that ends with that meta-block needs no more than its output.
+    pub( crate ) fn ring_buffer_size( window_bits: u32, header: &MetaBlockHeader, total_out: usize ) -> usize {
+        let window_size = 1 << window_bits;
+        let size = if header.is_last {
+            min( window_size, ( total_out + header.length ).next_power_of_two() )
+        } else {
+            window_size
+        };
+        max( size, MIN_RING_BUFFER_SIZE )
+    }
+
+    /// The number of symbols of the prefix codes of `category`, in the current meta-block.
+    fn alphabet_size( &self, category: usize ) -> usize {
+        match category {
+            LITERAL => NUM_LITERAL_SYMBOLS,
+            COMMAND => NUM_COMMAND_SYMBOLS,
+            _ => NUM_DISTANCE_SHORT_CODES + self.num_direct_distance_codes + ( 48 << self.distance_postfix_bits )
+        }
+    }
+
+    fn literal_context_map_size( &self ) -> usize {
+        self.blocks[LITERAL].num_types << LITERAL_CONTEXT_BITS
+    }
+
+    fn distance_context_map_size( &self ) -> usize {
+        self.blocks[DISTANCE].num_types << DISTANCE_CONTEXT_BITS
+    }
+
+    /// Reads a context map filling `map`, referring to `num_trees` prefix codes. Returns how
+    /// the map was coded, if it has a prefix code. Its code lengths are left in `code_lengths`.
+    /// A run of zeros past the end of the map is described in `failure`.
+    fn decode_context_map( map: &mut [u8], num_trees: usize, code_lengths: &mut [u8], br: &mut BR, failure: &mut Option<Failure> ) -> Result<Option<ContextMapCode>> {
+        let size = map.len();
+        for value in map.iter_mut() {
+            *value = 0;
+        }
+        if num_trees < 2 {
+            return Ok( None )
+        }
+
+        let max_run_length_prefix = if br.try_read_bits( 1 )? == 1 { br.try_read_bits( 4 )? as usize + 1 } else { 0 };
+        let code_offset = br.bit_position();
+        let mut table = Table::default();
+        table.read( num_trees + max_run_length_prefix, code_lengths, br )?;
+
+        let mut i = 0;
+        while i < size {
+            let code = table.safe_read_huffman_symbol( br )? as usize;
+            if code == 0 {
+                i += 1;
+            } else if code <= max_run_length_prefix {
+                let repeat = ( 1 << code ) + br.try_read_bits( code as u32 )? as usize;
+                if i + repeat > size {
+                    return Err( invalid( failure, Failure::ContextMapRepeat ))
+                }
+                i += repeat;
             } else {
-                let n = br.read_bits(3);
-                if n != 0 { n + 8 } else { 17 }
+                map[i] = ( code - max_run_length_prefix ) as u8;
+                i += 1;
+            }
+        }
+
+        let imtf = br.try_read_bits( 1 )? == 1;
+        if imtf {
+            Self::inverse_move_to_front( map );
+        }
+
+        Ok( Some( ContextMapCode { offset: code_offset, max_run_length_prefix: max_run_length_prefix, imtf: imtf } ))
+    }
+
+    /// Records a context map, and its prefix code as returned by `decode_context_map`.
+    fn list_context_map( &mut self, bit_offset: u64, category: usize, num_trees: usize, code: Option<ContextMapCode> ) {
+        if self.observer.lists_instructions() {
+            let start = if category == LITERAL { 0 } else { self.literal_context_map_size() };
+            let size = if category == LITERAL { self.literal_context_map_size() } else { self.distance_context_map_size() };
+            let map = self.context_maps[start .. start + size].to_vec();
+            let category = Category::from_index( category );
+            let (max_run_length_prefix, imtf) = code.as_ref().map_or( (0, false), |code| (code.max_run_length_prefix, code.imtf) );
+            self.list( bit_offset, Instruction::ContextMap {
+                category: category,
+                num_trees: num_trees,
+                map: map,
+                max_run_length_prefix: max_run_length_prefix,
+                imtf: imtf
+            });
+            if let Some( code ) = code {
+                self.list_prefix_code( code.offset, CodeKind::ContextMap( category ), 0, num_trees + code.max_run_length_prefix );
             }
         }
     }
+
+    fn inverse_move_to_front( values: &mut [u8] ) {
+        let mut mtf = [0u8; 256];
+        for (i, v) in mtf.iter_mut().enumerate() {
+            *v = i as u8;
+        }
+
+        for value in values.iter_mut() {
+            let index = *value as usize;
+            let v = mtf[index];
+            *value = v;
+            for i in ( 0 .. index ).rev() {
+                mtf[i + 1] = mtf[i];
+            }
+            mtf[0] = v;
+        }
+    }
+
+    /// Reads a block switch command for the given category.
+    fn switch_block_type( &mut self, category: usize ) -> Result<()> {
+        let bit_offset = self.br.bit_position();
+        let blocks = &mut self.blocks[category];
+        let offset = block_tree_offset( category );
+        let symbol = table( &self.block_trees, offset, BLOCK_TYPE_TABLE_SIZE ).safe_read_huffman_symbol( &mut self.br )? as usize;
+        let length = Self::decode_block_length( &table( &self.block_trees, offset + BLOCK_TYPE_TABLE_SIZE, BLOCK_LENGTH_TABLE_SIZE ), &mut self.br )?;
+
+        let mut block_type = match symbol {
+            0 => blocks.types[1],
+            1 => blocks.types[0] + 1,
+            _ => symbol - 2
+        };
+        if block_type >= blocks.num_types {
+            block_type -= blocks.num_types;
+        }
+
+        blocks.types = [block_type, blocks.types[0]];
+        blocks.remaining = length;
+        self.list( bit_offset, Instruction::BlockSwitch { category: Category::from_index( category ), block_type: block_type, length: length, code: symbol } );
+        self.observer.block_switch( Category::from_index( category ), block_type );
+        Ok( () )
+    }
+
+    /// Reads the distance of the current command, and sets up the copy.
+    fn decode_distance( &mut self ) -> Result<()> {
+        let code = if self.explicit_distance {
+            if self.blocks[DISTANCE].remaining == 0 {
+                self.switch_block_type( DISTANCE )?;
+                self.br.save_state();
+            }
+
+            let block_type = self.blocks[DISTANCE].types[0];
+            let tree = self.context_maps[self.literal_context_map_size() + ( block_type << DISTANCE_CONTEXT_BITS ) + self.distance_context] as usize;
+            let size = self.tree_sizes[DISTANCE];
+            table( &self.trees, self.tree_offsets[DISTANCE] + tree * size, size ).safe_read_huffman_symbol( &mut self.br )? as usize
+        } else {
+            0
+        };
+
+        let distance = if code < NUM_DISTANCE_SHORT_CODES {
+            let distance = self.distances[DISTANCE_SHORT_CODE_INDEX[code]] as isize + DISTANCE_SHORT_CODE_OFFSET[code];
+            if distance <= 0 {
+                return Err( invalid( &mut self.failure, Failure::Distance ))
+            }
+            distance as usize
+        } else if code < NUM_DISTANCE_SHORT_CODES + self.num_direct_distance_codes {
+            code - NUM_DISTANCE_SHORT_CODES + 1
+        } else {
+            let postfix_bits = self.distance_postfix_bits;
+            let code = code - NUM_DISTANCE_SHORT_CODES - self.num_direct_distance_codes;
+            let extra_bits = 1 + ( code >> ( postfix_bits + 1 ));
+            let high = ( code >> postfix_bits ) & 1;
+            let low = code & (( 1 << postfix_bits ) - 1 );
+            let offset = (( 2 + high ) << extra_bits ) - 4;
+            let extra = self.br.try_read_bits( extra_bits as u32 )? as usize;
+            (( offset + extra ) << postfix_bits ) + low + self.num_direct_distance_codes + 1
+        };
+
+        if self.explicit_distance {
+            self.blocks[DISTANCE].remaining -= 1;
+        }
+
+        let max_distance = min( ( 1 << self.window_bits ) - 16, self.total_out );
+        if distance > max_distance {
+            let word_id = distance - max_distance - 1;
+            self.decode_dictionary_word( word_id )?;
+            let index_bits = dictionary::NDBITS[self.copy_length];
+            self.observer.dictionary(
+                self.word_length,
+                dictionary::word_id( self.copy_length, word_id & (( 1 << index_bits ) - 1 )),
+                word_id >> index_bits );
+            if self.observer.lists_instructions() {
+                self.list_command( Some( Distance::Dictionary {
+                    distance: distance,
+                    word_index: word_id & (( 1 << index_bits ) - 1 ),
+                    transform: word_id >> index_bits,
+                    length: self.word_length
+                }));
+            }
+        } else {
+            if self.copy_length > self.meta_block_remaining {
+                return Err( invalid( &mut self.failure, Failure::CopyLength ))
+            }
+
+            if code != 0 {
+                self.distances = [distance, self.distances[0], self.distances[1], self.distances[2]];
+            }
+
+            self.copy_distance = distance;
+            self.copy_remaining = self.copy_length;
+            self.word_length = 0;
+            self.word_pos = 0;
+            self.meta_block_remaining -= self.copy_length;
+            self.observer.copy( self.copy_length, distance );
+            if self.observer.lists_instructions() {
+                let distance = if code < NUM_DISTANCE_SHORT_CODES {
+                    Distance::Last { code: code, distance: distance }
+                } else {
+                    Distance::Explicit { code: code, distance: distance }
+                };
+                self.list_command( Some( distance ));
+            }
+        }
+
+        self.state = State::Copy;
+        Ok( () )
+    }
+
+    /// Transforms the referenced static dictionary word into `word`.
+    fn decode_dictionary_word( &mut self, word_id: usize ) -> Result<()> {
+        let length = self.copy_length;
+        if !( dictionary::MIN_WORD_LENGTH ..= dictionary::MAX_WORD_LENGTH ).contains( &length ) {
+            return Err( invalid( &mut self.failure, Failure::Dictionary ))
+        }
+
+        let index_bits = dictionary::NDBITS[length];
+        let transform = word_id >> index_bits;
+        if transform >= TRANSFORMS.len() {
+            return Err( invalid( &mut self.failure, Failure::Transform ))
+        }
+
+        let word = dictionary::word( length, word_id & (( 1 << index_bits ) - 1 )).unwrap();
+        let word_length = TRANSFORMS[transform].transform( word, &mut self.word );
+        if word_length > self.meta_block_remaining {
+            return Err( invalid( &mut self.failure, Failure::CopyLength ))
+        }
+
+        self.word_length = word_length;
+        self.word_pos = 0;
+        self.copy_remaining = 0;
+        self.meta_block_remaining -= word_length;
+        Ok( () )
+    }
+}
+
+/// The offset of the block type code of `category` in `block_trees`, which is followed by its
+/// block length code.
+fn block_tree_offset( category: usize ) -> usize {
+    category * ( BLOCK_TYPE_TABLE_SIZE + BLOCK_LENGTH_TABLE_SIZE )
+}
+
+/// Returns the table of `size` entries at `offset` in `codes`.
+fn table( codes: &[Code], offset: usize, size: usize ) -> Table<&[Code]> {
+    Table::with_codes( &codes[offset .. offset + size] )
+}
+
+/// Returns the table of `size` entries at `offset` in `codes`, mutably.
+fn table_mut( codes: &mut [Code], offset: usize, size: usize ) -> Table<&mut [Code]> {
+    Table::with_codes( &mut codes[offset .. offset + size] )
 }
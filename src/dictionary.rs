@@ -1,7 +1,42 @@
+//! # Static dictionary
+//!
+//! The 122,784 byte dictionary shared by every Brotli encoder and decoder. Words are grouped
+//! by length (4 to 24 bytes), and each group holds `1 << NDBITS[length]` words.
 
-const NDBITS: [u8;25] = [0,  0,  0,  0, 10, 10, 11, 11, 10, 10, 10, 10, 10,  9,  9,  8,  7,  7,  8,  7, 7,  6,  6,  5,  5];
+/// Minimum length of a dictionary word.
+pub const MIN_WORD_LENGTH: usize = 4;
 
-const DICT: [u8;122784] = [
+/// Maximum length of a dictionary word.
+pub const MAX_WORD_LENGTH: usize = 24;
+
+/// The number of bits used to index the words of each length.
+pub static NDBITS: [u8;25] = [0,  0,  0,  0, 10, 10, 11, 11, 10, 10, 10, 10, 10,  9,  9,  8,  7,  7,  8,  7, 7,  6,  6,  5,  5];
+
+/// Offset into `DICT` of the first word of each length.
+pub static OFFSETS_BY_LENGTH: [u32;25] = [
+         0,      0,      0,      0,      0,   4096,   9216,  21504,  35840,  44032,
+     53248,  63488,  74752,  87040,  93696, 100864, 104704, 106752, 108928, 113536,
+    115968, 118528, 119872, 121280, 122016
+];
+
+/// Returns the `index`th dictionary word of the given length, or `None` if there is no such word.
+pub fn word( length: usize, index: usize ) -> Option<&'static [u8]> {
+    if !(MIN_WORD_LENGTH ..= MAX_WORD_LENGTH).contains( &length ) || index >= (1 << NDBITS[length]) {
+        return None
+    }
+
+    let offset = OFFSETS_BY_LENGTH[length] as usize + index * length;
+    Some( &DICT[offset .. offset + length] )
+}
+
+/// Returns the position among all dictionary words, shorter words first, of the `index`th word
+/// of the given length.
+pub fn word_id( length: usize, index: usize ) -> usize {
+    NDBITS[MIN_WORD_LENGTH .. length].iter().map( |&bits| 1 << bits ).sum::<usize>() + index
+}
+
+/// The dictionary words, concatenated.
+pub static DICT: [u8;122784] = [
 0x74, 0x69, 0x6d, 0x65, 0x64, 0x6f, 0x77, 0x6e, 0x6c, 0x69, 0x66, 0x65, 0x6c,
  0x65, 0x66, 0x74, 0x62, 0x61, 0x63, 0x6b, 0x63, 0x6f, 0x64, 0x65, 0x64, 0x61,
  0x74, 0x61, 0x73, 0x68, 0x6f, 0x77, 0x6f, 0x6e, 0x6c, 0x79, 0x73, 0x69, 0x74,
@@ -0,0 +1,299 @@
+//! # Stream disassembler
+//!
+//! Lists the contents of a Brotli stream: the window size, each meta-block header, every prefix
+//! code as its code lengths, the context maps, block switches and commands. Each entry has the
+//! offset in bits of the compressed data it was read from.
+//!
+//! The stream is decoded along the way, because literal contexts and dictionary references
+//! depend on the output. A command is listed once it is complete, after the literal and distance
+//! block switches that occurred while it was read.
+//!
+//! The text form of the listing, as printed by `brotli-dump`, can be turned back into a stream
+//! with the assembler in the `asm` module.
+
+#[cfg(feature = "std")]
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt::{ self, Display, Formatter };
+#[cfg(feature = "std")]
+use bitreader::StreamBitReader;
+#[cfg(feature = "std")]
+use decoder::Decoder;
+#[cfg(feature = "std")]
+use observer::CommandObserver;
+#[cfg(feature = "std")]
+use std::io::{ self, Read };
+
+/// The size of the buffer that decoded output is read into and discarded.
+#[cfg(feature = "std")]
+const OUTPUT_BUFFER_SIZE: usize = 65536;
+
+/// A category of symbols with its own block types, context map and prefix codes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Category {
+    Literal,
+    Command,
+    Distance
+}
+
+impl Category {
+    pub( crate ) fn from_index( index: usize ) -> Category {
+        [Category::Literal, Category::Command, Category::Distance][index]
+    }
+}
+
+/// What a prefix code is used for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CodeKind {
+    /// Literals, insert-and-copy commands or distances.
+    Symbols( Category ),
+    BlockType( Category ),
+    BlockLength( Category ),
+    ContextMap( Category )
+}
+
+/// The type of a meta-block.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MetaBlockKind {
+    Compressed,
+    Uncompressed,
+    Metadata,
+
+    /// The empty last meta-block, which only has the ISLAST and ISLASTEMPTY bits.
+    Empty
+}
+
+/// How the distance of a command was coded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Distance {
+    /// One of the short codes, which refer to the last distances.
+    Last { code: usize, distance: usize },
+
+    /// An explicitly coded distance.
+    Explicit { code: usize, distance: usize },
+
+    /// A distance beyond the window, which refers to a transformed static dictionary word.
+    Dictionary { distance: usize, word_index: usize, transform: usize, length: usize }
+}
+
+/// One element of a Brotli stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    WindowBits( u32 ),
+    MetaBlockHeader { is_last: bool, length: usize, kind: MetaBlockKind },
+
+    /// The contents of an uncompressed meta-block.
+    Uncompressed( Vec<u8> ),
+
+    /// The payload of a metadata meta-block.
+    Metadata( Vec<u8> ),
+
+    /// The number of block types of a category, and the length of the first block if there is
+    /// more than one.
+    BlockTypes { category: Category, num_types: usize, first_block_length: Option<usize> },
+    DistanceParameters { postfix_bits: u32, num_direct_codes: usize, context_modes: Vec<u8> },
+
+    /// A context map, with the largest run length code for runs of zeros, and whether it was
+    /// coded with the move-to-front transform.
+    ContextMap { category: Category, num_trees: usize, map: Vec<u8>, max_run_length_prefix: usize, imtf: bool },
+
+    /// A prefix code, given by the code length of each symbol of the alphabet.
+    PrefixCode { kind: CodeKind, index: usize, code_lengths: Vec<u8> },
+
+    /// A switch to another block type, coded with the given block type symbol.
+    BlockSwitch { category: Category, block_type: usize, length: usize, code: usize },
+
+    /// An insert-and-copy command. The last command of a meta-block may not have a distance.
+    Command { code: u16, literals: Vec<u8>, copy_length: usize, distance: Option<Distance> }
+}
+
+/// An instruction, and the bit offset of its compressed data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub bit_offset: u64,
+    pub instruction: Instruction
+}
+
+/// The state of the instruction the decoder is reading, if its observer lists instructions.
+#[derive(Default)]
+pub( crate ) struct Listing {
+    // The bit offset and code of the command being read
+    pub( crate ) command_offset: u64,
+    pub( crate ) command_code: u16,
+
+    // The literals of the command being read, or the contents of an uncompressed or metadata
+    // meta-block, which start at 'data_offset'
+    pub( crate ) data: Vec<u8>,
+    pub( crate ) data_offset: u64
+}
+
+/// The observer of the disassembler's decoder, which keeps the instructions it has read, but not
+/// handed out yet.
+#[cfg(feature = "std")]
+#[derive(Default)]
+struct Instructions( VecDeque<Entry> );
+
+#[cfg(feature = "std")]
+impl CommandObserver for Instructions {
+
+    fn lists_instructions( &self ) -> bool {
+        true
+    }
+
+    fn instruction( &mut self, entry: Entry ) {
+        self.0.push_back( entry );
+    }
+}
+
+/// Lists the contents of a Brotli stream read from `R`.
+#[cfg(feature = "std")]
+pub struct Disassembler<R: Read> {
+    decoder: Decoder<StreamBitReader<R>, Instructions>,
+    output: Vec<u8>,
+    finished: bool
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Disassembler<R> {
+
+    pub fn new( reader: R ) -> Disassembler<R> {
+        let decoder = Decoder::with_observer( StreamBitReader::new( reader ), Instructions::default() );
+        Disassembler { decoder: decoder, output: vec![0; OUTPUT_BUFFER_SIZE], finished: false }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Iterator for Disassembler<R> {
+    type Item = io::Result<Entry>;
+
+    fn next( &mut self ) -> Option<io::Result<Entry>> {
+        loop {
+            if let Some( entry ) = self.decoder.observer_mut().0.pop_front() {
+                return Some( Ok( entry ))
+            }
+
+            if self.finished {
+                return None
+            }
+
+            match self.decoder.read( &mut self.output ) {
+                Ok( 0 ) => self.finished = true,
+                Ok( _ ) => {}
+                Err( e ) => {
+                    self.finished = true;
+                    return Some( Err( e ))
+                }
+            }
+        }
+    }
+}
+
+/// Writes bytes as a quoted string, with the bytes that aren't printable ASCII escaped.
+fn write_bytes( f: &mut Formatter, bytes: &[u8] ) -> fmt::Result {
+    f.write_str( "\"" )?;
+    for &byte in bytes {
+        for c in ::core::ascii::escape_default( byte ) {
+            write!( f, "{}", c as char )?;
+        }
+    }
+    f.write_str( "\"" )
+}
+
+impl Display for Category {
+    fn fmt( &self, f: &mut Formatter ) -> fmt::Result {
+        f.write_str( match *self {
+            Category::Literal => "literal",
+            Category::Command => "command",
+            Category::Distance => "distance"
+        })
+    }
+}
+
+impl Display for CodeKind {
+    fn fmt( &self, f: &mut Formatter ) -> fmt::Result {
+        match *self {
+            CodeKind::Symbols( category ) => write!( f, "{}", category ),
+            CodeKind::BlockType( category ) => write!( f, "{}_block_type", category ),
+            CodeKind::BlockLength( category ) => write!( f, "{}_block_length", category ),
+            CodeKind::ContextMap( category ) => write!( f, "{}_context_map", category )
+        }
+    }
+}
+
+impl Display for Distance {
+    fn fmt( &self, f: &mut Formatter ) -> fmt::Result {
+        match *self {
+            Distance::Last { code, distance } => write!( f, "last_distance {} code={}", distance, code ),
+            Distance::Explicit { code, distance } => write!( f, "distance {} code={}", distance, code ),
+            Distance::Dictionary { distance, word_index, transform, length } =>
+                write!( f, "dictionary {} word={} transform={} length={}", distance, word_index, transform, length )
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt( &self, f: &mut Formatter ) -> fmt::Result {
+        match *self {
+            Instruction::WindowBits( bits ) => write!( f, "window_bits {}", bits ),
+            Instruction::MetaBlockHeader { is_last, length, kind } => {
+                let kind = match kind {
+                    MetaBlockKind::Compressed => "compressed",
+                    MetaBlockKind::Uncompressed => "uncompressed",
+                    MetaBlockKind::Metadata => "metadata",
+                    MetaBlockKind::Empty => "empty"
+                };
+                write!( f, "meta_block {} length={} last={}", kind, length, is_last as u8 )
+            }
+            Instruction::Uncompressed( ref data ) => {
+                f.write_str( "data " )?;
+                write_bytes( f, data )
+            }
+            Instruction::Metadata( ref data ) => {
+                f.write_str( "metadata " )?;
+                write_bytes( f, data )
+            }
+            Instruction::BlockTypes { category, num_types, first_block_length } => {
+                write!( f, "block_types {} {}", category, num_types )?;
+                match first_block_length {
+                    Some( length ) => write!( f, " length={}", length ),
+                    None => Ok( () )
+                }
+            }
+            Instruction::DistanceParameters { postfix_bits, num_direct_codes, ref context_modes } =>
+                write!( f, "distance_parameters postfix_bits={} direct_codes={} context_modes={:?}", postfix_bits, num_direct_codes, context_modes ),
+            Instruction::ContextMap { category, num_trees, ref map, max_run_length_prefix, imtf } => {
+                write!( f, "context_map {} trees={} {:?}", category, num_trees, map )?;
+                if num_trees > 1 {
+                    write!( f, " rle={} imtf={}", max_run_length_prefix, imtf as u8 )?;
+                }
+                Ok( () )
+            }
+            Instruction::PrefixCode { kind, index, ref code_lengths } => {
+                write!( f, "prefix_code {} {}", kind, index )?;
+                for (symbol, &length) in code_lengths.iter().enumerate() {
+                    if length > 0 {
+                        write!( f, " {}:{}", symbol, length )?;
+                    }
+                }
+                Ok( () )
+            }
+            Instruction::BlockSwitch { category, block_type, length, code } =>
+                write!( f, "block_switch {} type={} length={} code={}", category, block_type, length, code ),
+            Instruction::Command { code, ref literals, copy_length, distance } => {
+                write!( f, "command {} insert={} ", code, literals.len() )?;
+                write_bytes( f, literals )?;
+                write!( f, " copy={}", copy_length )?;
+                match distance {
+                    Some( distance ) => write!( f, " {}", distance ),
+                    None => Ok( () )
+                }
+            }
+        }
+    }
+}
+
+impl Display for Entry {
+    fn fmt( &self, f: &mut Formatter ) -> fmt::Result {
+        write!( f, "{:>10}  {}", self.bit_offset, self.instruction )
+    }
+}
@@ -0,0 +1,919 @@
+//! # Brotli encoder
+//!
+//! The input is compressed in blocks. Each block becomes one meta-block, which uses a single
+//! block type and a single prefix code for each of the literals, insert-and-copy commands and
+//! distances. Repeated data is found with a hash chain over the sliding window, and the static
+//! dictionary is searched at the higher quality levels. A block that doesn't compress is stored
+//! as an uncompressed meta-block instead.
+//!
+//! The stream always ends with an empty last meta-block, so that the data meta-blocks can be
+//! written out as soon as their input is complete.
+//!
+//! ## Flushing
+//!
+//! Flushing ends the current meta-block early, and then pads the stream to a byte boundary with
+//! an empty metadata meta-block. This is a sync point: the receiver can decode everything sent
+//! before it without any further input, and checks that the padding bits are zero. An uncompressed
+//! meta-block can't be used as padding instead, because it can't be empty. A flush that follows
+//! a sync point, or a meta-block that happens to end on a byte boundary, writes nothing.
+//!
+//! ## Metadata
+//!
+//! `CompressorWriter::write_metadata` embeds opaque data in the stream as metadata meta-blocks,
+//! which decoders skip unless they ask for them. A metadata meta-block holds at most 16 MiB,
+//! so longer payloads are split over several of them.
+//!
+//! ## Parallel compression
+//!
+//! `compress_parallel` splits the input into chunks of a fixed size, and compresses them on
+//! several threads. Each chunk ends with a sync point, so the compressed chunks can simply be
+//! concatenated. A chunk may refer back to the tail of the previous chunk, which its worker
+//! indexes before compressing the chunk itself. The last distances left by the previous chunk
+//! aren't known to the worker, so it only uses the short distance codes for distances of its
+//! own chunk. The chunk boundaries don't depend on the number of threads, and neither does the
+//! output.
+
+use bitwriter::BitBuffer;
+use entropy_encode::PrefixCode;
+use prefix::{ CMD_LUT, NUM_DISTANCE_SHORT_CODES, DISTANCE_SHORT_CODE_INDEX, DISTANCE_SHORT_CODE_OFFSET,
+              command_code, distance_code };
+use static_dict::StaticDictionaryIndex;
+use std::cmp::{ min, max };
+use std::io::{ self, Read, Write, ErrorKind };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::Mutex;
+use std::thread;
+
+const NUM_LITERAL_SYMBOLS: usize = 256;
+const NUM_COMMAND_SYMBOLS: usize = 704;
+
+/// Matches shorter than this aren't searched for.
+const MIN_MATCH_LENGTH: usize = 4;
+
+/// Scores of backward references, in 1/30 bits (see `backward_reference_score`).
+const LITERAL_BYTE_SCORE: usize = 135;
+const DISTANCE_BIT_PENALTY: usize = 30;
+const MIN_SCORE: usize = 100;
+const LAST_DISTANCE_BONUS: usize = 15;
+
+/// A lazy match replaces the current one if it scores at least this much higher.
+const LAZY_MATCH_MARGIN: usize = 175;
+
+/// The number of bytes requested from the source of a `CompressorReader` at a time.
+const READ_SIZE: usize = 4096;
+
+/// Estimated cost of a literal in bits, compared against the cost of a dictionary reference.
+const LITERAL_BITS: usize = 6;
+
+/// The copy length used by the final command of a meta-block, whose copy is never performed.
+const TAIL_COPY_LENGTH: usize = 4;
+
+/// The longest payload of a metadata meta-block.
+pub( crate ) const MAX_METADATA_LENGTH: usize = 1 << 24;
+
+/// Lowest quality level that searches the static dictionary and tries lazy matching.
+const DICTIONARY_QUALITY: u32 = 5;
+
+/// The type of input, which selects the parameters that suit it best.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// No assumptions are made about the input.
+    Generic,
+
+    /// UTF-8 formatted text.
+    Text,
+
+    /// WOFF 2.0 font data.
+    Font
+}
+
+/// Encoder settings. Values out of range are clamped to the nearest valid value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EncoderOptions {
+    /// Compression level from 0 (fastest) to 11 (densest).
+    pub quality: u32,
+
+    /// Base 2 logarithm of the sliding window size, from 10 to 24. The decoder has to keep this
+    /// much output in memory.
+    pub window_bits: u32,
+
+    /// Base 2 logarithm of the maximum input block size, from 16 to 24, or 0 to pick one based
+    /// on the window size.
+    pub block_bits: u32,
+
+    /// The type of input.
+    pub mode: Mode
+}
+
+impl Default for EncoderOptions {
+    fn default() -> EncoderOptions {
+        EncoderOptions { quality: 9, window_bits: 22, block_bits: 0, mode: Mode::Generic }
+    }
+}
+
+impl EncoderOptions {
+    pub( crate ) fn clamped( &self ) -> EncoderOptions {
+        let window_bits = self.window_bits.clamp( 10, 24 );
+        let block_bits = if self.block_bits == 0 { window_bits.clamp( 16, 18 ) } else { self.block_bits.clamp( 16, 24 ) };
+        EncoderOptions { quality: min( self.quality, 11 ), window_bits: window_bits, block_bits: block_bits, mode: self.mode }
+    }
+}
+
+/// What a chunk of `compress_parallel` may refer back to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChunkWindow {
+    /// Each chunk is compressed on its own, as if it started a new stream.
+    Independent,
+
+    /// A chunk may refer to the end of the previous chunk, up to the window size. This
+    /// compresses better, but each worker has to index the previous chunk's tail first.
+    PreviousChunk
+}
+
+/// Settings of `compress_parallel`. Values out of range are clamped to the nearest valid value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ParallelOptions {
+    /// The number of worker threads, or 0 to use one per available CPU.
+    pub threads: usize,
+
+    /// Base 2 logarithm of the chunk size, from 16 to 30.
+    pub chunk_bits: u32,
+
+    /// What a chunk may refer back to.
+    pub window: ChunkWindow
+}
+
+impl Default for ParallelOptions {
+    fn default() -> ParallelOptions {
+        ParallelOptions { threads: 0, chunk_bits: 22, window: ChunkWindow::PreviousChunk }
+    }
+}
+
+/// An insert-and-copy command of a meta-block.
+#[derive(Debug, Copy, Clone)]
+struct Command {
+    insert_length: usize,
+    copy_length: usize,
+    command_code: u16,
+
+    /// The distance symbol and its extra bits, if the distance is stored explicitly.
+    distance_code: Option<u16>,
+    distance_extra_bits: u32,
+    distance_extra: u32
+}
+
+/// A backward reference found by the match finder.
+#[derive(Debug, Copy, Clone)]
+struct Match {
+    /// The number of input bytes covered.
+    len: usize,
+
+    /// The copy length stored in the command, which is the word length for dictionary words.
+    copy_length: usize,
+    distance: usize,
+    is_dictionary: bool,
+    score: usize
+}
+
+#[inline(always)]
+fn floor_log2( value: usize ) -> usize {
+    ( 0usize.leading_zeros() - value.leading_zeros() - 1 ) as usize
+}
+
+/// Estimates how much a backward reference saves compared to inserting the bytes as literals.
+#[inline(always)]
+fn backward_reference_score( len: usize, distance: usize ) -> usize {
+    ( LITERAL_BYTE_SCORE * len ).saturating_sub( DISTANCE_BIT_PENALTY * floor_log2( distance ))
+}
+
+/// The number of equal bytes at the start of `a` and `b`.
+#[inline(always)]
+fn match_length( a: &[u8], b: &[u8] ) -> usize {
+    a.iter().zip( b ).take_while( |&(x, y)| x == y ).count()
+}
+
+/// Stores the window size as read by `Decoder::decode_window_bits`.
+pub( crate ) fn encode_window_bits( window_bits: u32, bw: &mut BitBuffer ) {
+    match window_bits {
+        16 => bw.put_bits( 1, 0 ),
+        17 => bw.put_bits( 7, 1 ),
+        18 ..= 24 => bw.put_bits( 4, ( ( window_bits - 17 ) << 1 | 1 ) as u64 ),
+        _ => bw.put_bits( 7, ( ( window_bits - 8 ) << 4 | 1 ) as u64 )
+    }
+}
+
+/// Hash chain over the positions of the sliding window.
+struct Hasher {
+    bits: u32,
+
+    /// The most recent position with each hash, plus one, or zero.
+    head: Vec<usize>,
+
+    /// The distance from each position to the previous one with the same hash, or zero.
+    /// Indexed by position modulo the window size. Empty if chains aren't followed.
+    chain: Vec<u32>,
+    window_mask: usize,
+    max_chain: usize
+}
+
+impl Hasher {
+    fn new( quality: u32, window_bits: u32 ) -> Hasher {
+        const MAX_CHAIN: [usize; 12] = [1, 1, 2, 4, 8, 16, 32, 64, 128, 256, 512, 1024];
+        let max_chain = MAX_CHAIN[quality as usize];
+        let bits = if quality < 4 { 15 } else { 17 };
+        Hasher {
+            bits: bits,
+            head: vec![0; 1 << bits],
+            chain: if max_chain > 1 { vec![0; 1 << window_bits] } else { Vec::new() },
+            window_mask: ( 1 << window_bits ) - 1,
+            max_chain: max_chain
+        }
+    }
+
+    #[inline(always)]
+    fn hash( &self, data: &[u8] ) -> usize {
+        let key = ( data[0] as u32 ) | ( data[1] as u32 ) << 8 | ( data[2] as u32 ) << 16 | ( data[3] as u32 ) << 24;
+        ( key.wrapping_mul( 0x1E35A7BD ) >> ( 32 - self.bits )) as usize
+    }
+
+    /// Adds the position `pos` of `data[0..4]`.
+    #[inline(always)]
+    fn insert( &mut self, data: &[u8], pos: usize ) {
+        let h = self.hash( data );
+        if !self.chain.is_empty() {
+            let previous = self.head[h];
+            self.chain[pos & self.window_mask] = if previous == 0 { 0 } else { min( pos + 1 - previous, u32::MAX as usize ) as u32 };
+        }
+        self.head[h] = pos + 1;
+    }
+}
+
+/// The compression engine behind `CompressorWriter`. Input is pushed in, and compressed
+/// output accumulates in `bw` until it is drained.
+pub( crate ) struct Encoder {
+    options: EncoderOptions,
+    bw: BitBuffer,
+    header_written: bool,
+
+    // Sliding window followed by the input not yet compressed
+    buffer: Vec<u8>,
+    // absolute position of buffer[0]
+    buffer_offset: usize,
+    // index of the first byte not yet compressed
+    processed: usize,
+    // index of the first byte not yet added to the hasher
+    hashed: usize,
+
+    hasher: Hasher,
+
+    // The last four distances, most recent first
+    distances: [usize; 4],
+
+    postfix_bits: u32,
+    num_direct_distance_codes: usize,
+    pub( crate ) finished: bool
+}
+
+impl Encoder {
+
+    pub( crate ) fn new( options: &EncoderOptions ) -> Encoder {
+        let options = options.clamped();
+        let (postfix_bits, num_direct_distance_codes) = if options.mode == Mode::Font { (1, 12) } else { (0, 0) };
+        Encoder {
+            options: options,
+            bw: BitBuffer::new(),
+            header_written: false,
+            buffer: Vec::new(),
+            buffer_offset: 0,
+            processed: 0,
+            hashed: 0,
+            hasher: Hasher::new( options.quality, options.window_bits ),
+            distances: [4, 11, 15, 16],
+            postfix_bits: postfix_bits,
+            num_direct_distance_codes: num_direct_distance_codes,
+            finished: false
+        }
+    }
+
+    /// Sets the distance postfix bits and the number of direct distance codes, which otherwise
+    /// follow from the mode. Combinations the format can't express select neither, like in the
+    /// C library. Takes effect from the next meta-block on.
+    pub( crate ) fn set_distance_parameters( &mut self, postfix_bits: u32, num_direct_distance_codes: usize ) {
+        if postfix_bits <= 3 && num_direct_distance_codes & (( 1 << postfix_bits ) - 1 ) == 0 && num_direct_distance_codes >> postfix_bits <= 15 {
+            self.postfix_bits = postfix_bits;
+            self.num_direct_distance_codes = num_direct_distance_codes;
+        } else {
+            self.postfix_bits = 0;
+            self.num_direct_distance_codes = 0;
+        }
+    }
+
+    /// Creates an encoder for the part of a stream that starts at `offset`, right after
+    /// `prefix`, which the encoder may refer back to. Unless the part starts the stream, the
+    /// stream header isn't written and the last distances are unknown.
+    fn with_prefix( options: &EncoderOptions, offset: usize, prefix: &[u8] ) -> Encoder {
+        let mut encoder = Encoder::new( options );
+        if offset > 0 {
+            encoder.header_written = true;
+            encoder.distances = [0; 4];
+        }
+        encoder.buffer.extend_from_slice( prefix );
+        encoder.buffer_offset = offset - prefix.len();
+        encoder.processed = prefix.len();
+        encoder
+    }
+
+    fn block_size( &self ) -> usize {
+        1 << self.options.block_bits
+    }
+
+    fn window_size( &self ) -> usize {
+        1 << self.options.window_bits
+    }
+
+    /// Buffers as much of `input` as fits in the current block, and returns the number of
+    /// bytes taken.
+    pub( crate ) fn push( &mut self, input: &[u8] ) -> usize {
+        let count = min( input.len(), self.input_space() );
+        self.buffer.extend_from_slice( &input[..count] );
+        count
+    }
+
+    /// The number of bytes the current block can still take.
+    fn input_space( &self ) -> usize {
+        self.block_size() - ( self.buffer.len() - self.processed )
+    }
+
+    /// Returns `true` if the current block can't take any more input.
+    pub( crate ) fn is_block_full( &self ) -> bool {
+        self.input_space() == 0
+    }
+
+    /// The compressed output that hasn't been drained yet.
+    pub( crate ) fn output( &self ) -> &[u8] {
+        self.bw.bytes()
+    }
+
+    pub( crate ) fn consume_output( &mut self, count: usize ) {
+        self.bw.consume( count );
+    }
+
+    fn write_stream_header( &mut self ) {
+        if !self.header_written {
+            encode_window_bits( self.options.window_bits, &mut self.bw );
+            self.header_written = true;
+        }
+    }
+
+    /// Compresses the buffered input into a meta-block.
+    pub( crate ) fn encode_block( &mut self ) {
+        let (start, end) = (self.processed, self.buffer.len());
+        if start == end {
+            return
+        }
+
+        self.write_stream_header();
+        let mark = self.bw.bit_len();
+        let distances = self.distances;
+        let (commands, literals) = self.create_commands( start, end );
+        self.store_compressed( end - start, &commands, &literals );
+
+        // Fall back to storing the data if it didn't compress
+        if self.bw.bit_len() - mark > ( end - start + 4 ) * 8 {
+            self.bw.truncate( mark );
+            self.distances = distances;
+            self.store_uncompressed( start, end );
+        }
+
+        self.processed = end;
+
+        // Drop the data that is out of the window
+        let keep = self.window_size();
+        if self.processed >= keep + self.block_size() {
+            let discard = self.processed - keep;
+            self.buffer.drain( .. discard );
+            self.buffer_offset += discard;
+            self.processed -= discard;
+            self.hashed -= discard;
+        }
+    }
+
+    /// Ends the current meta-block, and writes a sync point, so that everything written so far
+    /// can be decoded.
+    pub( crate ) fn flush( &mut self ) {
+        self.encode_block();
+        if !self.header_written || !self.bw.is_aligned() {
+            self.write_stream_header();
+            self.write_sync_point();
+        }
+    }
+
+    /// Pads the output to a byte boundary with an empty metadata meta-block.
+    fn write_sync_point( &mut self ) {
+        self.store_metadata( &[] );
+    }
+
+    /// Ends the current meta-block, and writes `data` in metadata meta-blocks.
+    pub( crate ) fn write_metadata( &mut self, data: &[u8] ) {
+        self.encode_block();
+        self.write_stream_header();
+        if data.is_empty() {
+            self.store_metadata( data );
+        }
+        for payload in data.chunks( MAX_METADATA_LENGTH ) {
+            self.store_metadata( payload );
+        }
+    }
+
+    fn store_metadata( &mut self, payload: &[u8] ) {
+        debug_assert!( payload.len() <= MAX_METADATA_LENGTH );
+        let skip_bytes = if payload.is_empty() { 0 } else { floor_log2( max( payload.len() - 1, 1 )) / 8 + 1 };
+
+        // ISLAST = 0, MNIBBLES = 0, reserved bit, MSKIPBYTES
+        self.bw.put_bits( 6, 0b000110 | ( skip_bytes as u64 ) << 4 );
+        if skip_bytes > 0 {
+            self.bw.put_bits( skip_bytes as u32 * 8, payload.len() as u64 - 1 );
+        }
+        self.bw.pad_to_byte();
+        self.bw.put_bytes( payload );
+    }
+
+    /// Compresses the remaining input and ends the stream.
+    pub( crate ) fn finish( &mut self ) {
+        if self.finished {
+            return
+        }
+
+        self.encode_block();
+        self.write_stream_header();
+
+        // ISLAST = 1, ISLASTEMPTY = 1
+        self.bw.put_bits( 2, 0b11 );
+        self.bw.pad_to_byte();
+        self.finished = true;
+    }
+
+    /// Writes the common start of a meta-block header, up to the ISUNCOMPRESSED bit.
+    fn store_meta_block_length( &mut self, length: usize, is_uncompressed: bool ) {
+        debug_assert!( length > 0 && length <= 1 << 24 );
+        let nibbles = max( 4, ( floor_log2( max( length - 1, 1 )) + 4 ) / 4 );
+        self.bw.put_bits( 1, 0 );
+        self.bw.put_bits( 2, nibbles as u64 - 4 );
+        self.bw.put_bits( nibbles as u32 * 4, length as u64 - 1 );
+        self.bw.put_bits( 1, is_uncompressed as u64 );
+    }
+
+    fn store_uncompressed( &mut self, start: usize, end: usize ) {
+        self.store_meta_block_length( end - start, true );
+        self.bw.pad_to_byte();
+        self.bw.put_bytes( &self.buffer[start .. end] );
+    }
+
+    fn store_compressed( &mut self, length: usize, commands: &[Command], literals: &[u8] ) {
+        let num_distance_symbols = NUM_DISTANCE_SHORT_CODES + self.num_direct_distance_codes + ( 48 << self.postfix_bits );
+        let mut literal_histogram = vec![0u32; NUM_LITERAL_SYMBOLS];
+        let mut command_histogram = vec![0u32; NUM_COMMAND_SYMBOLS];
+        let mut distance_histogram = vec![0u32; num_distance_symbols];
+        for &literal in literals {
+            literal_histogram[literal as usize] += 1;
+        }
+        for command in commands {
+            command_histogram[command.command_code as usize] += 1;
+            if let Some( code ) = command.distance_code {
+                distance_histogram[code as usize] += 1;
+            }
+        }
+
+        let literal_code = PrefixCode::build( &literal_histogram );
+        let command_code = PrefixCode::build( &command_histogram );
+        let distance_code = PrefixCode::build( &distance_histogram );
+
+        self.store_meta_block_length( length, false );
+
+        // One block type for each category
+        self.bw.put_bits( 3, 0 );
+
+        self.bw.put_bits( 2, self.postfix_bits as u64 );
+        self.bw.put_bits( 4, ( self.num_direct_distance_codes >> self.postfix_bits ) as u64 );
+
+        // Context mode of the literal block type: UTF8 or LSB6
+        self.bw.put_bits( 2, if self.options.mode == Mode::Text { 2 } else { 0 } );
+
+        // One prefix code each for literals and distances, so no context maps
+        self.bw.put_bits( 2, 0 );
+
+        literal_code.store( NUM_LITERAL_SYMBOLS, &mut self.bw );
+        command_code.store( NUM_COMMAND_SYMBOLS, &mut self.bw );
+        distance_code.store( num_distance_symbols, &mut self.bw );
+
+        let mut literals = literals.iter();
+        for command in commands {
+            let lut = &CMD_LUT[command.command_code as usize];
+            command_code.write_symbol( command.command_code as usize, &mut self.bw );
+            self.bw.put_bits( lut.insert_len_extra_bits as u32, ( command.insert_length - lut.insert_len_offset as usize ) as u64 );
+            self.bw.put_bits( lut.copy_len_extra_bits as u32, ( command.copy_length - lut.copy_len_offset as usize ) as u64 );
+            for &literal in literals.by_ref().take( command.insert_length ) {
+                literal_code.write_symbol( literal as usize, &mut self.bw );
+            }
+            if let Some( code ) = command.distance_code {
+                distance_code.write_symbol( code as usize, &mut self.bw );
+                self.bw.put_bits( command.distance_extra_bits, command.distance_extra as u64 );
+            }
+        }
+    }
+
+    /// Finds the distance code for `distance`: `(code, extra bits, extra value)`. A short code
+    /// that refers to the last distances is used if possible, unless `explicit` is set.
+    fn distance_code( &self, distance: usize, explicit: bool ) -> (usize, u32, u32) {
+        // Unknown last distances are zero
+        if !explicit {
+            for code in 0 .. NUM_DISTANCE_SHORT_CODES {
+                let last = self.distances[DISTANCE_SHORT_CODE_INDEX[code]];
+                if last > 0 && last as isize + DISTANCE_SHORT_CODE_OFFSET[code] == distance as isize {
+                    return (code, 0, 0)
+                }
+            }
+        }
+
+        distance_code( distance, self.postfix_bits, self.num_direct_distance_codes )
+    }
+
+    /// Adds the positions before `end` to the hasher.
+    fn update_hasher( &mut self, end: usize ) {
+        let end = min( end, self.buffer.len().saturating_sub( MIN_MATCH_LENGTH - 1 ));
+        while self.hashed < end {
+            let pos = self.hashed;
+            self.hasher.insert( &self.buffer[pos..], self.buffer_offset + pos );
+            self.hashed += 1;
+        }
+    }
+
+    /// Finds the best backward reference in the window for the data at `pos`, which ends at `end`.
+    fn find_match( &self, pos: usize, end: usize ) -> Option<Match> {
+        let data = &self.buffer[pos .. end];
+        if data.len() < MIN_MATCH_LENGTH {
+            return None
+        }
+
+        let abs_pos = self.buffer_offset + pos;
+        let max_distance = min( self.window_size() - 16, abs_pos );
+        let max_backward = min( max_distance, pos );
+        let mut best: Option<Match> = None;
+        let mut consider = |len: usize, distance: usize, score: usize| {
+            if len >= MIN_MATCH_LENGTH && score > MIN_SCORE && best.is_none_or( |b| score > b.score ) {
+                best = Some( Match { len: len, copy_length: len, distance: distance, is_dictionary: false, score: score } );
+            }
+        };
+
+        for (i, &distance) in self.distances.iter().enumerate() {
+            if distance > 0 && distance <= max_backward {
+                let len = match_length( &self.buffer[pos - distance .. end], data );
+                let bonus = if i == 0 { LAST_DISTANCE_BONUS } else { 0 };
+                consider( len, distance, LITERAL_BYTE_SCORE * len + bonus );
+            }
+        }
+
+        let mut candidate = self.hasher.head[self.hasher.hash( data )];
+        for _ in 0 .. self.hasher.max_chain {
+            if candidate == 0 || abs_pos + 1 - candidate > max_backward {
+                break;
+            }
+
+            let distance = abs_pos + 1 - candidate;
+            let len = match_length( &self.buffer[pos - distance .. end], data );
+            consider( len, distance, backward_reference_score( len, distance ));
+
+            match self.hasher.chain.get( ( candidate - 1 ) & self.hasher.window_mask ) {
+                Some( &delta ) if delta > 0 => candidate = candidate.saturating_sub( delta as usize ),
+                _ => break
+            }
+        }
+
+        if self.options.quality >= DICTIONARY_QUALITY && best.is_none_or( |b| b.len < 12 ) {
+            if let Some( m ) = StaticDictionaryIndex::get().find_match( &data[..min( data.len(), 128 )] ) {
+                if best.is_none_or( |b| m.len > b.len ) && m.cost( max_distance ) < m.len * LITERAL_BITS {
+                    let distance = m.distance( max_distance );
+                    best = Some( Match { len: m.len, copy_length: m.word_len, distance: distance, is_dictionary: true,
+                                         score: backward_reference_score( m.len, distance ) } );
+                }
+            }
+        }
+
+        best
+    }
+
+    /// Splits the input between `start` and `end` into commands and the literals they insert.
+    fn create_commands( &mut self, start: usize, end: usize ) -> (Vec<Command>, Vec<u8>) {
+        let mut commands = Vec::new();
+        let mut literals = Vec::new();
+        let mut insert_start = start;
+        let mut pos = start;
+        while pos + MIN_MATCH_LENGTH <= end {
+            self.update_hasher( pos );
+            let m = match self.find_match( pos, end ) {
+                Some( m ) => m,
+                None => {
+                    pos += 1;
+                    continue;
+                }
+            };
+
+            // Prefer a better match at the next position
+            if self.options.quality >= DICTIONARY_QUALITY && pos + 1 + MIN_MATCH_LENGTH <= end {
+                self.update_hasher( pos + 1 );
+                if let Some( next ) = self.find_match( pos + 1, end ) {
+                    if next.score > m.score + LAZY_MATCH_MARGIN {
+                        pos += 1;
+                        continue;
+                    }
+                }
+            }
+
+            let insert_length = pos - insert_start;
+            literals.extend_from_slice( &self.buffer[insert_start .. pos] );
+
+            let (code, extra_bits, extra) = self.distance_code( m.distance, m.is_dictionary );
+            let command_code = command_code( insert_length, m.copy_length, code == 0 );
+            commands.push( Command {
+                insert_length: insert_length,
+                copy_length: m.copy_length,
+                command_code: command_code,
+                distance_code: if command_code >= 128 { Some( code as u16 ) } else { None },
+                distance_extra_bits: extra_bits,
+                distance_extra: extra
+            });
+
+            // Dictionary references and the last distance don't enter the distance ring buffer
+            if code != 0 && !m.is_dictionary {
+                self.distances = [m.distance, self.distances[0], self.distances[1], self.distances[2]];
+            }
+
+            pos += m.len;
+            insert_start = pos;
+        }
+
+        if insert_start < end {
+            let insert_length = end - insert_start;
+            literals.extend_from_slice( &self.buffer[insert_start .. end] );
+            commands.push( Command {
+                insert_length: insert_length,
+                copy_length: TAIL_COPY_LENGTH,
+                command_code: command_code( insert_length, TAIL_COPY_LENGTH, true ),
+                distance_code: None,
+                distance_extra_bits: 0,
+                distance_extra: 0
+            });
+        }
+
+        self.update_hasher( end );
+        (commands, literals)
+    }
+}
+
+/// Compresses data written to it, and writes the Brotli stream to the wrapped writer.
+///
+/// Input is buffered until a block is complete, and each block is compressed into a meta-block.
+/// `flush()` compresses the buffered input, and pads the output so that the receiver can
+/// decode everything written so far. The stream must be ended with `finish()`, which returns
+/// the wrapped writer. If it isn't, the stream is ended when the `CompressorWriter` is dropped,
+/// and any errors are ignored.
+pub struct CompressorWriter<W: Write> {
+    encoder: Encoder,
+
+    // Only `None` once the stream is finished
+    writer: Option<W>
+}
+
+impl<W: Write> CompressorWriter<W> {
+
+    pub fn new( writer: W, options: &EncoderOptions ) -> CompressorWriter<W> {
+        CompressorWriter { encoder: Encoder::new( options ), writer: Some( writer ) }
+    }
+
+    /// Returns a reference to the wrapped writer.
+    pub fn get_ref( &self ) -> &W {
+        self.writer.as_ref().unwrap()
+    }
+
+    /// Returns a mutable reference to the wrapped writer. Writing to it corrupts the stream.
+    pub fn get_mut( &mut self ) -> &mut W {
+        self.writer.as_mut().unwrap()
+    }
+
+    /// Compresses the remaining input, ends the stream and returns the wrapped writer.
+    pub fn finish( mut self ) -> io::Result<W> {
+        self.finish_stream()?;
+        Ok( self.writer.take().unwrap() )
+    }
+
+    fn finish_stream( &mut self ) -> io::Result<()> {
+        self.write_output()?;
+        self.encoder.finish();
+        self.write_output()?;
+        self.get_mut().flush()
+    }
+
+    /// Compresses the buffered input, and then embeds `data` in the stream as metadata, which
+    /// the receiver can retrieve with `Decoder::set_metadata_callback`. Payloads longer than
+    /// 16 MiB are split over several metadata meta-blocks.
+    pub fn write_metadata( &mut self, data: &[u8] ) -> io::Result<()> {
+        self.write_output()?;
+        self.encoder.write_metadata( data );
+        self.write_output()
+    }
+
+    /// Writes the compressed output to the wrapped writer. What it accepts is consumed as it goes,
+    /// so that after an error only the rest of the output is written again.
+    fn write_output( &mut self ) -> io::Result<()> {
+        let writer = self.writer.as_mut().unwrap();
+        while !self.encoder.output().is_empty() {
+            match writer.write( self.encoder.output() ) {
+                Ok( 0 ) => return Err( io::Error::new( ErrorKind::WriteZero, "failed to write the compressed output" )),
+                Ok( count ) => self.encoder.consume_output( count ),
+                Err( ref e ) if e.kind() == ErrorKind::Interrupted => {}
+                Err( e ) => return Err( e )
+            }
+        }
+        Ok( () )
+    }
+}
+
+impl<W: Write> Write for CompressorWriter<W> {
+    fn write( &mut self, buf: &[u8] ) -> io::Result<usize> {
+        // Output of earlier blocks is written first, so that an error doesn't lose any input
+        self.write_output()?;
+        let count = self.encoder.push( buf );
+        if self.encoder.is_block_full() {
+            self.encoder.encode_block();
+        }
+        Ok( count )
+    }
+
+    fn flush( &mut self ) -> io::Result<()> {
+        self.encoder.flush();
+        self.write_output()?;
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for CompressorWriter<W> {
+    fn drop( &mut self ) {
+        if self.writer.is_some() {
+            let _ = self.finish_stream();
+        }
+    }
+}
+
+/// Compresses the data read from the wrapped reader, and hands out the Brotli stream.
+///
+/// A block of input is read from the source and compressed before its output is handed out, so
+/// at most one block of input and its compressed output are buffered, besides the sliding window.
+pub struct CompressorReader<R> {
+    reader: R,
+    encoder: Encoder,
+
+    // Scratch space for reading from the source
+    input: Vec<u8>
+}
+
+impl<R: Read> Read for CompressorReader<R> {
+    fn read( &mut self, buf: &mut [u8] ) -> io::Result<usize> {
+        loop {
+            let count = min( buf.len(), self.encoder.output().len() );
+            if count > 0 || buf.is_empty() {
+                buf[..count].copy_from_slice( &self.encoder.output()[..count] );
+                self.encoder.consume_output( count );
+                return Ok( count )
+            }
+
+            if self.encoder.finished {
+                return Ok( 0 )
+            }
+
+            // Input already read stays buffered if the source fails
+            if self.fill_input()? {
+                self.encoder.encode_block();
+            } else {
+                self.encoder.finish();
+            }
+        }
+    }
+}
+
+impl<R: Read> CompressorReader<R> {
+
+    pub fn new( reader: R, options: &EncoderOptions ) -> CompressorReader<R> {
+        CompressorReader { reader: reader, encoder: Encoder::new( options ), input: vec![0; READ_SIZE] }
+    }
+
+    /// Returns a reference to the wrapped reader.
+    pub fn get_ref( &self ) -> &R {
+        &self.reader
+    }
+
+    /// Returns a mutable reference to the wrapped reader. Reading from it skips input.
+    pub fn get_mut( &mut self ) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Returns the wrapped reader.
+    pub fn into_inner( self ) -> R {
+        self.reader
+    }
+
+    /// Reads from the source until the current block is full, and returns `false` if the end
+    /// of the input was reached first.
+    fn fill_input( &mut self ) -> io::Result<bool> {
+        while !self.encoder.is_block_full() {
+            let size = min( self.input.len(), self.encoder.input_space() );
+            match self.reader.read( &mut self.input[..size] ) {
+                Ok( 0 ) => return Ok( false ),
+                Ok( bytes_read ) => {
+                    self.encoder.push( &self.input[..bytes_read] );
+                }
+                Err( ref e ) if e.kind() == ErrorKind::Interrupted => {}
+                Err( e ) => return Err( e )
+            }
+        }
+
+        Ok( true )
+    }
+}
+
+/// Compresses one chunk of `compress_parallel` into byte-aligned, non-last meta-blocks.
+fn compress_chunk( input: &[u8], start: usize, end: usize, options: &EncoderOptions, window: ChunkWindow ) -> Vec<u8> {
+    let encoder = match window {
+        ChunkWindow::Independent => Encoder::with_prefix( options, start, &[] ),
+        ChunkWindow::PreviousChunk => {
+            let window_size = 1 << options.clamped().window_bits;
+            Encoder::with_prefix( options, start, &input[start.saturating_sub( window_size ) .. start] )
+        }
+    };
+    compress_part( encoder, &input[start .. end] )
+}
+
+/// Compresses `data`, which starts at `offset` in the output, into byte-aligned, non-last
+/// meta-blocks that don't refer to any output before it. At the start of the stream, the
+/// stream header comes first.
+pub( crate ) fn compress_independent( data: &[u8], offset: usize, options: &EncoderOptions ) -> Vec<u8> {
+    compress_part( Encoder::with_prefix( options, offset, &[] ), data )
+}
+
+/// Compresses `data` with `encoder`, and ends with a sync point.
+fn compress_part( mut encoder: Encoder, mut data: &[u8] ) -> Vec<u8> {
+    while !data.is_empty() {
+        let count = encoder.push( data );
+        data = &data[count..];
+        if encoder.is_block_full() {
+            encoder.encode_block();
+        }
+    }
+    encoder.flush();
+    encoder.bw.bytes().to_vec()
+}
+
+/// Ends a stream whose output so far ends on a byte boundary: `data` is stored in metadata
+/// meta-blocks, followed by the empty last meta-block.
+pub( crate ) fn metadata_end( data: &[u8] ) -> Vec<u8> {
+    let mut encoder = Encoder::new( &EncoderOptions::default() );
+    encoder.header_written = true;
+    encoder.write_metadata( data );
+    encoder.finish();
+    encoder.bw.bytes().to_vec()
+}
+
+/// Compresses `input` into a Brotli stream on several threads. See the module documentation.
+pub fn compress_parallel( input: &[u8], options: &EncoderOptions, parallel: &ParallelOptions ) -> Vec<u8> {
+    let chunk_size = 1usize << parallel.chunk_bits.clamp( 16, 30 );
+    let num_chunks = max( 1, input.len().div_ceil( chunk_size ));
+    let threads = match parallel.threads {
+        0 => thread::available_parallelism().map( |n| n.get() ).unwrap_or( 1 ),
+        threads => threads
+    };
+
+    // Workers take the next chunk until there are none left
+    let next_chunk = AtomicUsize::new( 0 );
+    let chunks: Vec<Mutex<Vec<u8>>> = ( 0 .. num_chunks ).map( |_| Mutex::new( Vec::new() )).collect();
+    thread::scope( |scope| {
+        for _ in 0 .. min( threads, num_chunks ) {
+            scope.spawn( || loop {
+                let index = next_chunk.fetch_add( 1, Ordering::Relaxed );
+                if index >= num_chunks {
+                    break;
+                }
+                let (start, end) = (index * chunk_size, min( input.len(), ( index + 1 ) * chunk_size ));
+                let output = compress_chunk( input, start, end, options, parallel.window );
+                *chunks[index].lock().unwrap() = output;
+            });
+        }
+    });
+
+    let mut output = Vec::new();
+    for chunk in chunks {
+        output.extend_from_slice( &chunk.into_inner().unwrap() );
+    }
+
+    // ISLAST = 1, ISLASTEMPTY = 1
+    output.push( 0b11 );
+    output
+}
@@ -0,0 +1,367 @@
+//! Construction and storage of the prefix codes used by the encoder.
+
+use bitwriter::BitBuffer;
+use huffman::{ HUFFMAN_MAX_CODE_LENGTH, CODE_LENGTH_CODES, CODE_LENGTH_CODE_ORDER,
+               REPEAT_PREVIOUS_CODE_LENGTH, REPEAT_ZERO_CODE_LENGTH };
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Maximum code length of the code length code.
+const MAX_CODE_LENGTH_CODE_LENGTH: u8 = 5;
+
+/// The code lengths are used until a previous non-zero length is seen.
+const INITIAL_REPEATED_CODE_LENGTH: u8 = 8;
+
+/// Static prefix code for the code length code lengths, indexed by length: `(bits, code)`.
+const CODE_LENGTH_PREFIX: [(u32, u64); 6] = [(2, 0), (4, 7), (3, 3), (2, 2), (2, 1), (4, 15)];
+
+/// A prefix code, as stored in the meta-block header.
+pub struct PrefixCode {
+    /// The code length of each symbol, zero for unused symbols and for the only symbol of a
+    /// single symbol code.
+    depths: Vec<u8>,
+
+    /// The code of each symbol, bit reversed so that it can be written least significant bit first.
+    bits: Vec<u16>,
+
+    /// Up to 4 used symbols, as needed for a simple prefix code.
+    symbols: Vec<usize>
+}
+
+/// Computes code lengths of at most `max_depth` bits for the symbols of `histogram`. A single
+/// used symbol gets a code length of 1.
+fn create_huffman_tree( histogram: &[u32], max_depth: u8, depths: &mut [u8] )
+{
+    for depth in depths.iter_mut() {
+        *depth = 0;
+    }
+
+    // Rare symbols are made more frequent until the tree is shallow enough
+    let mut count_limit = 1;
+    loop {
+        // Leaves are nodes 0 .. n, each merge adds a node that is the parent of two others
+        let mut parents: Vec<usize> = Vec::new();
+        let mut leaves: Vec<usize> = Vec::new();
+        let mut heap = BinaryHeap::new();
+        for (symbol, &count) in histogram.iter().enumerate() {
+            if count > 0 {
+                heap.push( Reverse( (::std::cmp::max( count, count_limit ) as u64, parents.len()) ));
+                parents.push( usize::MAX );
+                leaves.push( symbol );
+            }
+        }
+
+        match leaves.len() {
+            0 => return,
+            1 => {
+                depths[leaves[0]] = 1;
+                return
+            }
+            _ => {}
+        }
+
+        while heap.len() > 1 {
+            let Reverse( (weight1, node1) ) = heap.pop().unwrap();
+            let Reverse( (weight2, node2) ) = heap.pop().unwrap();
+            let parent = parents.len();
+            parents.push( usize::MAX );
+            parents[node1] = parent;
+            parents[node2] = parent;
+            heap.push( Reverse( (weight1 + weight2, parent) ));
+        }
+
+        // Parents always come after their children, so depths are resolved from the root down
+        let mut node_depths = vec![0u8; parents.len()];
+        for node in ( 0 .. parents.len() - 1 ).rev() {
+            node_depths[node] = node_depths[parents[node]] + 1;
+        }
+
+        if node_depths[..leaves.len()].iter().all( |&d| d <= max_depth ) {
+            for (node, &symbol) in leaves.iter().enumerate() {
+                depths[symbol] = node_depths[node];
+            }
+            return
+        }
+
+        count_limit *= 2;
+    }
+}
+
+/// Assigns canonical codes to the code lengths, bit reversed for writing. The lengths of an
+/// over-subscribed code get overlapping codes.
+fn convert_bit_depths_to_symbols( depths: &[u8], bits: &mut [u16] )
+{
+    let mut count = [0u16; HUFFMAN_MAX_CODE_LENGTH + 1];
+    for &depth in depths {
+        count[depth as usize] += 1;
+    }
+    count[0] = 0;
+
+    let mut next_code = [0u16; HUFFMAN_MAX_CODE_LENGTH + 1];
+    let mut code = 0u16;
+    for len in 1 .. HUFFMAN_MAX_CODE_LENGTH + 1 {
+        code = code.wrapping_add( count[len - 1] ) << 1;
+        next_code[len] = code;
+    }
+
+    for (symbol, &depth) in depths.iter().enumerate() {
+        if depth > 0 {
+            let code = next_code[depth as usize];
+            next_code[depth as usize] = code.wrapping_add( 1 );
+            bits[symbol] = code.reverse_bits() >> ( 16 - depth as u32 );
+        }
+    }
+}
+
+/// Appends the code length codes for a run of zero code lengths.
+fn write_zero_repetitions( mut repetitions: usize, tokens: &mut Vec<(u8, u8)> )
+{
+    if repetitions == 11 {
+        tokens.push( (0, 0) );
+        repetitions -= 1;
+    }
+
+    if repetitions < 3 {
+        for _ in 0 .. repetitions {
+            tokens.push( (0, 0) );
+        }
+    } else {
+        // Consecutive repeat codes multiply, so the extra bits are stored most significant first
+        let start = tokens.len();
+        repetitions -= 3;
+        loop {
+            tokens.push( (REPEAT_ZERO_CODE_LENGTH, ( repetitions & 7 ) as u8) );
+            repetitions >>= 3;
+            if repetitions == 0 {
+                break;
+            }
+            repetitions -= 1;
+        }
+        tokens[start..].reverse();
+    }
+}
+
+/// Appends the code length codes for a run of the same non-zero code length.
+fn write_repetitions( previous: u8, value: u8, mut repetitions: usize, tokens: &mut Vec<(u8, u8)> )
+{
+    if previous != value {
+        tokens.push( (value, 0) );
+        repetitions -= 1;
+    }
+
+    if repetitions == 7 {
+        tokens.push( (value, 0) );
+        repetitions -= 1;
+    }
+
+    if repetitions < 3 {
+        for _ in 0 .. repetitions {
+            tokens.push( (value, 0) );
+        }
+    } else {
+        let start = tokens.len();
+        repetitions -= 3;
+        loop {
+            tokens.push( (REPEAT_PREVIOUS_CODE_LENGTH, ( repetitions & 3 ) as u8) );
+            repetitions >>= 2;
+            if repetitions == 0 {
+                break;
+            }
+            repetitions -= 1;
+        }
+        tokens[start..].reverse();
+    }
+}
+
+impl PrefixCode {
+
+    /// Builds a prefix code of at most 15 bits for the symbols of `histogram`.
+    pub fn build( histogram: &[u32] ) -> PrefixCode {
+        let mut depths = vec![0u8; histogram.len()];
+        let mut bits = vec![0u16; histogram.len()];
+        create_huffman_tree( histogram, HUFFMAN_MAX_CODE_LENGTH as u8, &mut depths );
+
+        let mut symbols: Vec<usize> = ( 0 .. histogram.len() ).filter( |&s| depths[s] > 0 ).take( 5 ).collect();
+        match symbols.len() {
+            0 => symbols.push( 0 ),
+            1 => depths[symbols[0]] = 0,
+            _ => convert_bit_depths_to_symbols( &depths, &mut bits )
+        }
+
+        PrefixCode { depths: depths, bits: bits, symbols: symbols }
+    }
+
+    /// Makes a prefix code with the given code lengths, which don't need to form a complete code.
+    /// A single symbol with a code length of 1 makes a code whose symbol takes no bits.
+    pub fn from_lengths( lengths: &[u8] ) -> PrefixCode {
+        let mut depths = lengths.to_vec();
+        let mut bits = vec![0u16; lengths.len()];
+        let symbols: Vec<usize> = ( 0 .. lengths.len() ).filter( |&s| depths[s] > 0 ).take( 5 ).collect();
+        if symbols.len() == 1 && depths[symbols[0]] == 1 {
+            depths[symbols[0]] = 0;
+        } else {
+            convert_bit_depths_to_symbols( &depths, &mut bits );
+        }
+
+        PrefixCode { depths: depths, bits: bits, symbols: symbols }
+    }
+
+    /// Returns `true` if the code can be stored as a simple prefix code, which lists up to 4
+    /// symbols with fixed code lengths.
+    pub fn is_simple( &self ) -> bool {
+        let mut shape: Vec<u8> = self.symbols.iter().map( |&s| self.depths[s] ).collect();
+        shape.sort_unstable();
+        matches!( &shape[..], [0] | [1, 1] | [1, 2, 2] | [2, 2, 2, 2] | [1, 2, 3, 3] )
+    }
+
+    /// Returns `true` if `symbol` has a code.
+    pub fn contains( &self, symbol: usize ) -> bool {
+        self.depths.get( symbol ).is_some_and( |&d| d > 0 ) || self.symbols == [symbol]
+    }
+
+    /// Writes the code for `symbol`.
+    #[inline(always)]
+    pub fn write_symbol( &self, symbol: usize, bw: &mut BitBuffer ) {
+        debug_assert!( self.depths[symbol] > 0 || self.symbols == [symbol] );
+        bw.put_bits( self.depths[symbol] as u32, self.bits[symbol] as u64 );
+    }
+
+    /// Writes the description of the code, for an alphabet of `alphabet_size` symbols.
+    pub fn store( &self, alphabet_size: usize, bw: &mut BitBuffer ) {
+        if self.is_simple() {
+            self.store_simple( alphabet_size, bw );
+        } else {
+            self.store_complex( alphabet_size, bw );
+        }
+    }
+
+    /// Writes a code of up to 4 symbols by listing the symbols, shortest code first.
+    pub fn store_simple( &self, alphabet_size: usize, bw: &mut BitBuffer ) {
+        let alphabet_bits = 32 - ( alphabet_size as u32 - 1 ).leading_zeros();
+        let mut symbols = self.symbols.clone();
+        symbols.sort_by_key( |&s| self.depths[s] );
+
+        bw.put_bits( 2, 1 );
+        bw.put_bits( 2, symbols.len() as u64 - 1 );
+        for &symbol in &symbols {
+            bw.put_bits( alphabet_bits, symbol as u64 );
+        }
+
+        if symbols.len() == 4 {
+            bw.put_bits( 1, if self.depths[symbols[0]] == 1 { 1 } else { 0 } );
+        }
+    }
+
+    /// Writes the code lengths, run length encoded and compressed with the code length code.
+    pub fn store_complex( &self, alphabet_size: usize, bw: &mut BitBuffer ) {
+        let depths = &self.depths[..alphabet_size];
+
+        // Trailing zeros are implied by the code being complete
+        let length = depths.iter().rposition( |&d| d != 0 ).map_or( 0, |p| p + 1 );
+        let mut tokens = Vec::new();
+        let mut previous = INITIAL_REPEATED_CODE_LENGTH;
+        let mut i = 0;
+        while i < length {
+            let value = depths[i];
+            let repetitions = depths[i..length].iter().take_while( |&&d| d == value ).count();
+            if value == 0 {
+                write_zero_repetitions( repetitions, &mut tokens );
+            } else {
+                write_repetitions( previous, value, repetitions, &mut tokens );
+                previous = value;
+            }
+            i += repetitions;
+        }
+
+        let mut histogram = [0u32; CODE_LENGTH_CODES];
+        for &(code, _) in &tokens {
+            histogram[code as usize] += 1;
+        }
+
+        let mut code_depths = [0u8; CODE_LENGTH_CODES];
+        let mut code_bits = [0u16; CODE_LENGTH_CODES];
+        create_huffman_tree( &histogram, MAX_CODE_LENGTH_CODE_LENGTH, &mut code_depths );
+        convert_bit_depths_to_symbols( &code_depths, &mut code_bits );
+
+        // With a single code, all code lengths are read, and its symbols take no bits
+        let num_codes = histogram.iter().filter( |&&c| c > 0 ).count();
+        let mut codes_to_store = CODE_LENGTH_CODES;
+        if num_codes > 1 {
+            while codes_to_store > 0 && code_depths[CODE_LENGTH_CODE_ORDER[codes_to_store - 1] as usize] == 0 {
+                codes_to_store -= 1;
+            }
+        }
+
+        let mut skip = 0;
+        if code_depths[CODE_LENGTH_CODE_ORDER[0] as usize] == 0 && code_depths[CODE_LENGTH_CODE_ORDER[1] as usize] == 0 {
+            skip = 2;
+            if code_depths[CODE_LENGTH_CODE_ORDER[2] as usize] == 0 {
+                skip = 3;
+            }
+        }
+
+        bw.put_bits( 2, skip as u64 );
+        for &code in &CODE_LENGTH_CODE_ORDER[skip .. codes_to_store] {
+            let (nbits, value) = CODE_LENGTH_PREFIX[code_depths[code as usize] as usize];
+            bw.put_bits( nbits, value );
+        }
+
+        if num_codes == 1 {
+            code_depths = [0; CODE_LENGTH_CODES];
+        }
+
+        for &(code, extra) in &tokens {
+            bw.put_bits( code_depths[code as usize] as u32, code_bits[code as usize] as u64 );
+            match code {
+                REPEAT_PREVIOUS_CODE_LENGTH => bw.put_bits( 2, extra as u64 ),
+                REPEAT_ZERO_CODE_LENGTH => bw.put_bits( 3, extra as u64 ),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[test]
+fn test_store_prefix_code()
+{
+    use bitreader::{ BitReader, StreamBitReader };
+    use huffman::Table;
+
+    // Skewed and flat histograms, with few and many symbols and long runs of unused symbols
+    let mut histograms: Vec<Vec<u32>> = vec![vec![0; 256], vec![5; 256], vec![0; 704]];
+    histograms[0][65] = 1;
+    histograms[2][3] = 1;
+    histograms[2][700] = 1000;
+    for &(alphabet_size, seed) in &[(256, 1u32), (704, 7), (64, 3), (704, 11)] {
+        let mut value = seed;
+        histograms.push( ( 0 .. alphabet_size ).map( |i| {
+            value = value.wrapping_mul( 1103515245 ).wrapping_add( 12345 );
+            if i % 7 == 0 { 0 } else { ( value >> 16 ) % ( 1 << ( i % 17 )) }
+        }).collect() );
+    }
+    for count in 1 .. 6 {
+        let mut histogram = vec![0; 704];
+        for i in 0 .. count {
+            histogram[i * 100 + 3] = 1 << i;
+        }
+        histograms.push( histogram );
+    }
+
+    for histogram in &histograms {
+        let code = PrefixCode::build( histogram );
+        let mut bw = BitBuffer::new();
+        code.store( histogram.len(), &mut bw );
+        bw.pad_to_byte();
+
+        let mut table = Table::default();
+        let mut lengths = vec![0u8; 704];
+        let mut br = StreamBitReader::new( bw.bytes() );
+        br.fetch_input().unwrap();
+        table.read( histogram.len(), &mut lengths, &mut br ).unwrap();
+
+        if code.symbols.len() > 1 {
+            assert_eq!( &lengths[..histogram.len()], &code.depths[..] );
+        }
+        assert!( code.depths.iter().all( |&d| d as usize <= HUFFMAN_MAX_CODE_LENGTH ));
+    }
+}
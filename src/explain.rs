@@ -0,0 +1,229 @@
+//! # Compression report
+//!
+//! Explains how each byte of the output of a Brotli stream was coded: as a literal, costing the
+//! length of its prefix code word, as part of a copy of earlier output or of a transformed
+//! static dictionary word, or stored in an uncompressed meta-block. `brotli-explain` renders the
+//! report as an HTML page, with each literal shaded by its cost, and lists the runs of literals
+//! that cost the most.
+//!
+//! Only the bits spent on the bytes themselves are counted. The command, distance and block
+//! switch codes are not, so copies and dictionary words cost nothing here.
+
+use bitreader::StreamBitReader;
+use decoder::Decoder;
+use disasm::MetaBlockKind;
+use observer::{ CommandObserver, MetaBlockHeader };
+use transforms::TRANSFORMS;
+use std::io::{ self, Read, Write };
+
+/// The longest prefix code word.
+const MAX_BITS: u32 = 15;
+
+/// The number of bytes of a region shown in the summary.
+const PREVIEW_LENGTH: usize = 40;
+
+/// How a region of the output was coded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Coding {
+    /// A run of literals.
+    Literals,
+
+    /// A copy of the output `distance` bytes back.
+    Copy { distance: usize },
+
+    /// A static dictionary word, as numbered by `CommandObserver::dictionary()`.
+    Dictionary { word_id: usize, transform_id: usize },
+
+    /// The contents of an uncompressed meta-block.
+    Uncompressed
+}
+
+/// The bytes of the output coded by one command, or stored in one uncompressed meta-block.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub start: usize,
+    pub length: usize,
+    pub coding: Coding,
+
+    /// The bits spent on the bytes of the region.
+    pub bits: u64
+}
+
+/// The decompressed content of a stream, and how it was coded.
+#[derive(Debug, Default)]
+pub struct Explanation {
+    pub data: Vec<u8>,
+
+    /// The bits spent on each byte: the code word length of a literal, 8 for a stored byte and
+    /// 0 for the others.
+    pub bits: Vec<u8>,
+
+    /// The regions, in order. They cover all of `data`.
+    pub regions: Vec<Region>
+}
+
+impl CommandObserver for Explanation {
+    fn literal_cost( &mut self, bits: u32 ) {
+        self.bits.push( bits as u8 );
+    }
+
+    fn literal( &mut self, run: usize ) {
+        let bits = self.bits[self.bits.len() - run ..].iter().map( |&bits| bits as u64 ).sum();
+        self.add_region( run, Coding::Literals, bits );
+    }
+
+    fn copy( &mut self, length: usize, distance: usize ) {
+        self.bits.resize( self.bits.len() + length, 0 );
+        self.add_region( length, Coding::Copy { distance: distance }, 0 );
+    }
+
+    fn dictionary( &mut self, length: usize, word_id: usize, transform_id: usize ) {
+        self.bits.resize( self.bits.len() + length, 0 );
+        self.add_region( length, Coding::Dictionary { word_id: word_id, transform_id: transform_id }, 0 );
+    }
+
+    fn meta_block( &mut self, header: &MetaBlockHeader ) {
+        if header.kind == MetaBlockKind::Uncompressed {
+            self.bits.resize( self.bits.len() + header.length, 8 );
+            self.add_region( header.length, Coding::Uncompressed, header.length as u64 * 8 );
+        }
+    }
+}
+
+/// Decompresses the stream read from `reader`, and explains how its output was coded.
+pub fn explain<R: Read>( reader: R ) -> io::Result<Explanation> {
+    let mut explanation = Explanation::default();
+    let mut data = Vec::new();
+    Decoder::with_observer( StreamBitReader::new( reader ), &mut explanation ).read_to_end( &mut data )?;
+    explanation.data = data;
+    Ok( explanation )
+}
+
+/// Describes a word transform by its prefix, the transformation of the word, and its suffix.
+pub fn transform_name( transform_id: usize ) -> String {
+    let transform = &TRANSFORMS[transform_id];
+    format!( "{:?} {:?} {:?}",
+        String::from_utf8_lossy( transform.prefix ),
+        transform.transform,
+        String::from_utf8_lossy( transform.suffix ))
+}
+
+impl Explanation {
+
+    fn add_region( &mut self, length: usize, coding: Coding, bits: u64 ) {
+        let start = self.regions.last().map_or( 0, |region| region.start + region.length );
+        self.regions.push( Region { start: start, length: length, coding: coding, bits: bits } );
+    }
+
+    /// Returns the `count` runs of literals and uncompressed meta-blocks that cost the most
+    /// bits, costliest first.
+    pub fn costliest_regions( &self, count: usize ) -> Vec<&Region> {
+        let mut regions: Vec<&Region> = self.regions.iter().filter( |region| region.bits > 0 ).collect();
+        regions.sort_by( |a, b| b.bits.cmp( &a.bits ).then( a.start.cmp( &b.start )));
+        regions.truncate( count );
+        regions
+    }
+
+    /// Writes the number of bytes coded each way, and the `count` costliest regions.
+    pub fn write_summary<W: Write>( &self, mut out: W, count: usize ) -> io::Result<()> {
+        let total_bits: u64 = self.regions.iter().map( |region| region.bits ).sum();
+        writeln!( out, "{} bytes, {} bits spent on literals and stored bytes", self.data.len(), total_bits )?;
+        writeln!( out )?;
+        writeln!( out, "coding        regions      bytes   share        bits" )?;
+        let mut totals = [(0, 0, 0); 4];
+        for region in &self.regions {
+            let total = &mut totals[match region.coding {
+                Coding::Literals => 0,
+                Coding::Copy { .. } => 1,
+                Coding::Dictionary { .. } => 2,
+                Coding::Uncompressed => 3
+            }];
+            *total = (total.0 + 1, total.1 + region.length, total.2 + region.bits);
+        }
+        for (name, &(num_regions, bytes, bits)) in ["literals", "copies", "dictionary", "uncompressed"].iter().zip( &totals ) {
+            let share = if self.data.is_empty() { 0.0 } else { bytes as f64 * 100.0 / self.data.len() as f64 };
+            writeln!( out, "{:<12} {:>8} {:>10} {:>6.1}% {:>11}", name, num_regions, bytes, share, bits )?;
+        }
+
+        writeln!( out )?;
+        writeln!( out, "costliest regions:" )?;
+        writeln!( out, "    offset   length       bits  bits/byte  text" )?;
+        for region in self.costliest_regions( count ) {
+            let end = region.start + region.length.min( PREVIEW_LENGTH );
+            let ellipsis = if region.length > PREVIEW_LENGTH { "..." } else { "" };
+            writeln!( out, "{:>10} {:>8} {:>10} {:>10.2}  {:?}{}",
+                region.start, region.length, region.bits, region.bits as f64 / region.length as f64,
+                String::from_utf8_lossy( &self.data[region.start .. end] ), ellipsis )?;
+        }
+        Ok( () )
+    }
+
+    /// Writes a standalone HTML page showing the data, with literals shaded from green to red
+    /// by their cost, and the other regions marked by how they were coded. Hovering over a
+    /// literal or region tells its cost or origin.
+    pub fn write_html<W: Write>( &self, mut out: W, title: &str ) -> io::Result<()> {
+        writeln!( out, "<!DOCTYPE html>" )?;
+        writeln!( out, "<html><head><meta charset=\"utf-8\"><title>{}</title><style>", escape( title ))?;
+        writeln!( out, "body {{ font-family: sans-serif }}" )?;
+        writeln!( out, "pre {{ white-space: pre-wrap; word-break: break-all; line-height: 1.5 }}" )?;
+        for bits in 0 ..= MAX_BITS {
+            writeln!( out, ".l{} {{ background: hsl({}, 80%, 75%) }}", bits, 120 * ( MAX_BITS - bits ) / MAX_BITS )?;
+        }
+        writeln!( out, ".c {{ background: #c6dbff }}" )?;
+        writeln!( out, ".d {{ background: #e2c6ff }}" )?;
+        writeln!( out, ".u {{ background: #dddddd }}" )?;
+        writeln!( out, ".x {{ color: #888888 }}" )?;
+        writeln!( out, "</style></head><body>" )?;
+        writeln!( out, "<h1>{}</h1>", escape( title ))?;
+        write!( out, "<p>Literals by cost: " )?;
+        for bits in 0 ..= MAX_BITS {
+            write!( out, "<span class=\"l{}\">&nbsp;{}&nbsp;</span>", bits, bits )?;
+        }
+        writeln!( out, " bits. <span class=\"c\">Copies</span>, <span class=\"d\">dictionary words</span> \
+            and <span class=\"u\">uncompressed meta-blocks</span>. Bytes that aren't printable ASCII \
+            are shown as <span class=\"x\">&middot;</span>.</p>" )?;
+
+        write!( out, "<pre>" )?;
+        for region in &self.regions {
+            let bytes = &self.data[region.start .. region.start + region.length];
+            match region.coding {
+                Coding::Literals => for (&byte, &bits) in bytes.iter().zip( &self.bits[region.start ..] ) {
+                    write!( out, "<span class=\"l{}\" title=\"0x{:02x}: {} bits\">", bits.min( MAX_BITS as u8 ), byte, bits )?;
+                    write_byte( &mut out, byte )?;
+                    write!( out, "</span>" )?;
+                },
+                coding => {
+                    let (class, origin) = match coding {
+                        Coding::Copy { distance } => ("c", format!( "copy of {} bytes from {} back", region.length, distance )),
+                        Coding::Dictionary { word_id, transform_id } => ("d", format!( "dictionary word {}, transform {}: {}",
+                            word_id, transform_id, transform_name( transform_id ))),
+                        _ => ("u", format!( "{} bytes of an uncompressed meta-block", region.length ))
+                    };
+                    write!( out, "<span class=\"{}\" title=\"{}\">", class, escape( &origin ))?;
+                    for &byte in bytes {
+                        write_byte( &mut out, byte )?;
+                    }
+                    write!( out, "</span>" )?;
+                }
+            }
+        }
+        writeln!( out, "</pre>" )?;
+        writeln!( out, "</body></html>" )
+    }
+}
+
+/// Escapes text for HTML content and attribute values.
+fn escape( text: &str ) -> String {
+    text.replace( '&', "&amp;" ).replace( '<', "&lt;" ).replace( '>', "&gt;" ).replace( '"', "&quot;" )
+}
+
+/// Writes a byte of the data as HTML.
+fn write_byte<W: Write>( out: &mut W, byte: u8 ) -> io::Result<()> {
+    match byte {
+        b'&' => write!( out, "&amp;" ),
+        b'<' => write!( out, "&lt;" ),
+        b'>' => write!( out, "&gt;" ),
+        b'\n' | b'\r' | b'\t' | b' ' ..= b'~' => out.write_all( &[byte] ),
+        _ => write!( out, "<span class=\"x\">&middot;</span>" )
+    }
+}
@@ -0,0 +1,505 @@
+//! # C interface
+//!
+//! Exports the decoder functions of the C library's `brotli/decode.h`, so that C programs
+//! written against it can link with this crate instead. The header is `c/include/brotli/decode.h`.
+//! The encoder functions are in `ffi_encoder`.
+//!
+//! The crate builds as a Rust library only, so that it builds for targets without dynamic
+//! libraries too. The `brotli-ffi` package in `ffi` builds it as `libbrotli`, a shared and a
+//! static library for C:
+//!
+//! ```text
+//! cargo build --release -p brotli-ffi
+//! ```
+//!
+//! The allocation functions passed to `BrotliDecoderCreateInstance()` allocate the state and the
+//! buffers of the decoder, through `CAlloc`. Without them, the global allocator is used. Errors
+//! are reported with the code of the C library for what `Decoder::failure()` says went wrong.
+//! The decoder checks prefix codes as a whole, so any invalid one is reported as
+//! `BROTLI_DECODER_ERROR_FORMAT_HUFFMAN_SPACE`.
+
+#![allow(non_camel_case_types)]
+
+use allocator::{ BrotliAlloc, Code };
+use bitreader::{ BitReader, StreamBitReader };
+use decoder::{ Decoder, Failure, Status };
+use observer::NoObserver;
+use std::alloc::{ self, Layout };
+use std::cmp::min;
+use std::io::{ self, ErrorKind, Read };
+use std::mem;
+use std::ops::{ Deref, DerefMut };
+use std::os::raw::{ c_char, c_int, c_void };
+use std::ptr;
+use std::slice;
+use { BrotliError, Result };
+
+pub type BROTLI_BOOL = c_int;
+
+pub const BROTLI_TRUE: BROTLI_BOOL = 1;
+pub const BROTLI_FALSE: BROTLI_BOOL = 0;
+
+pub type brotli_alloc_func = Option<unsafe extern "C" fn( opaque: *mut c_void, size: usize ) -> *mut c_void>;
+pub type brotli_free_func = Option<unsafe extern "C" fn( opaque: *mut c_void, address: *mut c_void )>;
+
+/// The result of `BrotliDecoderDecompressStream()`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BrotliDecoderResult {
+    BROTLI_DECODER_RESULT_ERROR = 0,
+    BROTLI_DECODER_RESULT_SUCCESS = 1,
+    BROTLI_DECODER_RESULT_NEEDS_MORE_INPUT = 2,
+    BROTLI_DECODER_RESULT_NEEDS_MORE_OUTPUT = 3
+}
+
+/// The outcome of the last call of `BrotliDecoderDecompressStream()`, in more detail.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BrotliDecoderErrorCode {
+    BROTLI_DECODER_NO_ERROR = 0,
+    BROTLI_DECODER_SUCCESS = 1,
+    BROTLI_DECODER_NEEDS_MORE_INPUT = 2,
+    BROTLI_DECODER_NEEDS_MORE_OUTPUT = 3,
+    BROTLI_DECODER_ERROR_FORMAT_EXUBERANT_NIBBLE = -1,
+    BROTLI_DECODER_ERROR_FORMAT_RESERVED = -2,
+    BROTLI_DECODER_ERROR_FORMAT_EXUBERANT_META_NIBBLE = -3,
+    BROTLI_DECODER_ERROR_FORMAT_SIMPLE_HUFFMAN_ALPHABET = -4,
+    BROTLI_DECODER_ERROR_FORMAT_SIMPLE_HUFFMAN_SAME = -5,
+    BROTLI_DECODER_ERROR_FORMAT_CL_SPACE = -6,
+    BROTLI_DECODER_ERROR_FORMAT_HUFFMAN_SPACE = -7,
+    BROTLI_DECODER_ERROR_FORMAT_CONTEXT_MAP_REPEAT = -8,
+    BROTLI_DECODER_ERROR_FORMAT_BLOCK_LENGTH_1 = -9,
+    BROTLI_DECODER_ERROR_FORMAT_BLOCK_LENGTH_2 = -10,
+    BROTLI_DECODER_ERROR_FORMAT_TRANSFORM = -11,
+    BROTLI_DECODER_ERROR_FORMAT_DICTIONARY = -12,
+    BROTLI_DECODER_ERROR_FORMAT_WINDOW_BITS = -13,
+    BROTLI_DECODER_ERROR_FORMAT_PADDING_1 = -14,
+    BROTLI_DECODER_ERROR_FORMAT_PADDING_2 = -15,
+    BROTLI_DECODER_ERROR_FORMAT_DISTANCE = -16,
+    BROTLI_DECODER_ERROR_DICTIONARY_NOT_SET = -19,
+    BROTLI_DECODER_ERROR_INVALID_ARGUMENTS = -20,
+    BROTLI_DECODER_ERROR_ALLOC_CONTEXT_MODES = -21,
+    BROTLI_DECODER_ERROR_ALLOC_TREE_GROUPS = -22,
+    BROTLI_DECODER_ERROR_ALLOC_CONTEXT_MAP = -25,
+    BROTLI_DECODER_ERROR_ALLOC_RING_BUFFER_1 = -26,
+    BROTLI_DECODER_ERROR_ALLOC_RING_BUFFER_2 = -27,
+    BROTLI_DECODER_ERROR_ALLOC_BLOCK_TYPE_TREES = -30,
+    BROTLI_DECODER_ERROR_UNREACHABLE = -31
+}
+
+use self::BrotliDecoderErrorCode::*;
+use self::BrotliDecoderResult::*;
+
+/// The input of the current `BrotliDecoderDecompressStream()` call. The bit reader copies what
+/// it reads into its own buffer, so the pointer is only used during the call. Once the input
+/// runs out, reading it fails with `WouldBlock` until more is passed in.
+struct Input {
+    next: *const u8,
+    available: usize
+}
+
+impl Read for Input {
+    fn read( &mut self, buf: &mut [u8] ) -> io::Result<usize> {
+        if self.available == 0 {
+            return Err( io::Error::from( ErrorKind::WouldBlock ))
+        }
+
+        let count = min( buf.len(), self.available );
+        unsafe {
+            ptr::copy_nonoverlapping( self.next, buf.as_mut_ptr(), count );
+            self.next = self.next.add( count );
+        }
+        self.available -= count;
+        Ok( count )
+    }
+}
+
+/// Allocates with the functions passed to `BrotliDecoderCreateInstance()`, handing them `opaque`.
+/// Without them, allocates from the global allocator.
+#[derive(Debug, Copy, Clone)]
+pub struct CAlloc {
+    alloc_func: brotli_alloc_func,
+    free_func: brotli_free_func,
+    opaque: *mut c_void
+}
+
+impl Default for CAlloc {
+    fn default() -> CAlloc {
+        CAlloc { alloc_func: None, free_func: None, opaque: ptr::null_mut() }
+    }
+}
+
+impl CAlloc {
+
+    /// Allocates with `alloc_func` and `free_func`, or with the global allocator if neither is
+    /// given. Returns `None` if only one of them is.
+    pub( crate ) fn new( alloc_func: brotli_alloc_func, free_func: brotli_free_func, opaque: *mut c_void ) -> Option<CAlloc> {
+        if alloc_func.is_some() != free_func.is_some() {
+            return None
+        }
+        Some( CAlloc { alloc_func: alloc_func, free_func: free_func, opaque: opaque } )
+    }
+
+    /// Allocates room for `len` values of `T`. Returns null if it can't be had, or the allocation
+    /// function returns memory not aligned for `T`.
+    pub( crate ) unsafe fn alloc<T>( &self, len: usize ) -> *mut T {
+        let layout = match Layout::array::<T>( len ) {
+            Ok( layout ) => layout,
+            Err( _ ) => return ptr::null_mut()
+        };
+        match self.alloc_func {
+            Some( alloc_func ) => {
+                let data = alloc_func( self.opaque, layout.size() ) as *mut T;
+                if !( data as usize ).is_multiple_of( layout.align() ) {
+                    self.free( data, len );
+                    return ptr::null_mut()
+                }
+                data
+            },
+            None => alloc::alloc( layout ) as *mut T
+        }
+    }
+
+    /// Frees the `len` values of `T` at `data`, which came from `alloc()`.
+    pub( crate ) unsafe fn free<T>( &self, data: *mut T, len: usize ) {
+        match self.free_func {
+            Some( free_func ) => free_func( self.opaque, data as *mut c_void ),
+            None => alloc::dealloc( data as *mut u8, Layout::array::<T>( len ).unwrap() )
+        }
+    }
+
+    fn alloc_buffer<T: Copy + Default>( &self, len: usize ) -> Result<CBuffer<T>> {
+        if len == 0 || mem::size_of::<T>() == 0 {
+            return Ok( CBuffer::default() )
+        }
+        unsafe {
+            let data = self.alloc::<T>( len );
+            if data.is_null() {
+                return Err( BrotliError::OutOfMemory )
+            }
+            for i in 0..len {
+                data.add( i ).write( T::default() );
+            }
+            Ok( CBuffer { data: data, len: len, allocator: *self } )
+        }
+    }
+}
+
+impl BrotliAlloc for CAlloc {
+    type Bytes = CBuffer<u8>;
+    type Codes = CBuffer<Code>;
+
+    fn alloc_u8( &mut self, buf: &mut CBuffer<u8>, len: usize ) -> Result<()> {
+        *buf = self.alloc_buffer( len )?;
+        Ok( () )
+    }
+
+    fn alloc_codes( &mut self, buf: &mut CBuffer<Code>, len: usize ) -> Result<()> {
+        *buf = self.alloc_buffer( len )?;
+        Ok( () )
+    }
+}
+
+/// A buffer from `CAlloc`, freed when dropped.
+pub struct CBuffer<T> {
+    data: *mut T,
+    len: usize,
+    allocator: CAlloc
+}
+
+impl<T> Default for CBuffer<T> {
+    fn default() -> CBuffer<T> {
+        CBuffer { data: ptr::null_mut(), len: 0, allocator: CAlloc::default() }
+    }
+}
+
+impl<T> Deref for CBuffer<T> {
+    type Target = [T];
+
+    fn deref( &self ) -> &[T] {
+        if self.data.is_null() { &[] } else { unsafe { slice::from_raw_parts( self.data, self.len ) } }
+    }
+}
+
+impl<T> DerefMut for CBuffer<T> {
+    fn deref_mut( &mut self ) -> &mut [T] {
+        if self.data.is_null() { &mut [] } else { unsafe { slice::from_raw_parts_mut( self.data, self.len ) } }
+    }
+}
+
+impl<T> Drop for CBuffer<T> {
+    fn drop( &mut self ) {
+        if !self.data.is_null() {
+            unsafe { self.allocator.free( self.data, self.len ) }
+        }
+    }
+}
+
+/// The decoder behind a `BrotliDecoderState*`.
+pub struct BrotliDecoderState {
+    decoder: Decoder<StreamBitReader<Input>, NoObserver, CAlloc>,
+    allocator: CAlloc,
+    error_code: BrotliDecoderErrorCode,
+    total_out: usize
+}
+
+impl BrotliDecoderState {
+
+    fn new( allocator: CAlloc ) -> BrotliDecoderState {
+        BrotliDecoderState {
+            decoder: Decoder::with_allocator( StreamBitReader::new( Input { next: ptr::null(), available: 0 } ), NoObserver, allocator ),
+            allocator: allocator,
+            error_code: BROTLI_DECODER_NO_ERROR,
+            total_out: 0
+        }
+    }
+
+    /// Decodes the current input into `output` until either runs out, or the stream ends.
+    /// Returns the result and the number of bytes written.
+    fn decompress( &mut self, output: &mut [u8] ) -> (BrotliDecoderErrorCode, usize) {
+        let mut written = 0;
+        loop {
+            let error = if written < output.len() {
+                match self.decoder.read( &mut output[written..] ) {
+                    Ok( 0 ) => return (BROTLI_DECODER_SUCCESS, written),
+                    Ok( count ) => {
+                        written += count;
+                        continue
+                    }
+                    Err( e ) => BrotliError::from( e )
+                }
+            } else {
+                // There is no room for output, but the end of the stream may still be reached
+                match self.decoder.decode() {
+                    Ok( Status::Done ) if self.decoder.pending_output() == 0 => return (BROTLI_DECODER_SUCCESS, written),
+                    Ok( Status::NeedsMoreInput ) if self.decoder.pending_output() == 0 => match self.decoder.get_mut().fetch_input() {
+                        Ok( _ ) => continue,
+                        Err( e ) => e
+                    },
+                    Ok( _ ) => return (BROTLI_DECODER_NEEDS_MORE_OUTPUT, written),
+                    Err( e ) => e
+                }
+            };
+
+            return match error {
+                BrotliError::Io( ref e ) if e.kind() == ErrorKind::WouldBlock => (BROTLI_DECODER_NEEDS_MORE_INPUT, written),
+                _ => (error_code( self.decoder.failure() ), written)
+            }
+        }
+    }
+}
+
+/// The error code of the C library for what decoding failed on.
+fn error_code( failure: Option<Failure> ) -> BrotliDecoderErrorCode {
+    match failure {
+        Some( Failure::ExuberantNibble ) => BROTLI_DECODER_ERROR_FORMAT_EXUBERANT_NIBBLE,
+        Some( Failure::Reserved ) => BROTLI_DECODER_ERROR_FORMAT_RESERVED,
+        Some( Failure::ExuberantMetaNibble ) => BROTLI_DECODER_ERROR_FORMAT_EXUBERANT_META_NIBBLE,
+        Some( Failure::PrefixCode ) => BROTLI_DECODER_ERROR_FORMAT_HUFFMAN_SPACE,
+        Some( Failure::ContextMapRepeat ) => BROTLI_DECODER_ERROR_FORMAT_CONTEXT_MAP_REPEAT,
+        Some( Failure::InsertLength ) => BROTLI_DECODER_ERROR_FORMAT_BLOCK_LENGTH_1,
+        Some( Failure::CopyLength ) => BROTLI_DECODER_ERROR_FORMAT_BLOCK_LENGTH_2,
+        Some( Failure::Transform ) => BROTLI_DECODER_ERROR_FORMAT_TRANSFORM,
+        Some( Failure::Dictionary ) => BROTLI_DECODER_ERROR_FORMAT_DICTIONARY,
+        Some( Failure::WindowBits ) => BROTLI_DECODER_ERROR_FORMAT_WINDOW_BITS,
+        Some( Failure::MetaBlockPadding ) => BROTLI_DECODER_ERROR_FORMAT_PADDING_1,
+        Some( Failure::StreamPadding ) => BROTLI_DECODER_ERROR_FORMAT_PADDING_2,
+        Some( Failure::Distance ) => BROTLI_DECODER_ERROR_FORMAT_DISTANCE,
+        Some( Failure::RingBufferAlloc ) => BROTLI_DECODER_ERROR_ALLOC_RING_BUFFER_1,
+        Some( Failure::ContextMapAlloc ) => BROTLI_DECODER_ERROR_ALLOC_CONTEXT_MAP,
+        Some( Failure::TreesAlloc ) => BROTLI_DECODER_ERROR_ALLOC_TREE_GROUPS,
+        None => BROTLI_DECODER_ERROR_UNREACHABLE
+    }
+}
+
+/// Creates a decoder, allocating with `alloc_func` and `free_func` if they are given. Returns null
+/// if only one of them is, or the state can't be allocated.
+#[no_mangle]
+pub extern "C" fn BrotliDecoderCreateInstance( alloc_func: brotli_alloc_func, free_func: brotli_free_func, opaque: *mut c_void ) -> *mut BrotliDecoderState {
+    let allocator = match CAlloc::new( alloc_func, free_func, opaque ) {
+        Some( allocator ) => allocator,
+        None => return ptr::null_mut()
+    };
+    unsafe {
+        let state = allocator.alloc::<BrotliDecoderState>( 1 );
+        if !state.is_null() {
+            state.write( BrotliDecoderState::new( allocator ));
+        }
+        state
+    }
+}
+
+/// Frees a decoder.
+///
+/// # Safety
+///
+/// `state` must be null or come from `BrotliDecoderCreateInstance()`, and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliDecoderDestroyInstance( state: *mut BrotliDecoderState ) {
+    if !state.is_null() {
+        let allocator = ( *state ).allocator;
+        ptr::drop_in_place( state );
+        allocator.free( state, 1 );
+    }
+}
+
+/// Decodes `*available_in` bytes at `*next_in` into the `*available_out` bytes at `*next_out`,
+/// and advances both past what was consumed and produced. `*total_out`, unless `total_out` is
+/// null, is set to the number of bytes decoded so far.
+///
+/// Returns `NEEDS_MORE_INPUT` once all of the input is consumed, `NEEDS_MORE_OUTPUT` when the
+/// output is full, and `SUCCESS` at the end of the stream. Input following the stream is left
+/// unconsumed.
+///
+/// # Safety
+///
+/// The pointers must be valid, and the buffers hold as many bytes as claimed. `next_in` and
+/// `next_out` may be null while nothing is available.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliDecoderDecompressStream( state: *mut BrotliDecoderState, available_in: *mut usize, next_in: *mut *const u8,
+                                                        available_out: *mut usize, next_out: *mut *mut u8, total_out: *mut usize ) -> BrotliDecoderResult {
+    let state = match state.as_mut() {
+        Some( state ) => state,
+        None => return BROTLI_DECODER_RESULT_ERROR
+    };
+    if ( state.error_code as c_int ) < 0 {
+        return BROTLI_DECODER_RESULT_ERROR
+    }
+    if available_in.is_null() || available_out.is_null() ||
+       ( *available_in > 0 && ( next_in.is_null() || ( *next_in ).is_null() )) ||
+       ( *available_out > 0 && ( next_out.is_null() || ( *next_out ).is_null() )) {
+        state.error_code = BROTLI_DECODER_ERROR_INVALID_ARGUMENTS;
+        return BROTLI_DECODER_RESULT_ERROR
+    }
+
+    let offered = *available_in;
+    state.decoder.get_mut().reader = Input { next: if offered > 0 { *next_in } else { ptr::null() }, available: offered };
+    let output: &mut [u8] = if *available_out > 0 { slice::from_raw_parts_mut( *next_out, *available_out ) } else { &mut [] };
+    let (error_code, written) = state.decompress( output );
+
+    let br = state.decoder.get_mut();
+    let mut consumed = offered - br.reader.available;
+    match error_code {
+        // The bit reader may have read past the end of the stream
+        BROTLI_DECODER_SUCCESS => consumed -= min( br.get_remaining_bytes(), consumed ),
+        // Input the decoder hasn't used yet is passed in again with the next call
+        BROTLI_DECODER_NEEDS_MORE_OUTPUT => consumed -= br.unread( consumed ),
+        _ => {}
+    }
+    br.reader = Input { next: ptr::null(), available: 0 };
+
+    if consumed > 0 {
+        *next_in = ( *next_in ).add( consumed );
+        *available_in -= consumed;
+    }
+    if written > 0 {
+        *next_out = ( *next_out ).add( written );
+        *available_out -= written;
+    }
+    state.total_out += written;
+    if !total_out.is_null() {
+        *total_out = state.total_out;
+    }
+
+    state.error_code = error_code;
+    match error_code {
+        BROTLI_DECODER_SUCCESS => BROTLI_DECODER_RESULT_SUCCESS,
+        BROTLI_DECODER_NEEDS_MORE_INPUT => BROTLI_DECODER_RESULT_NEEDS_MORE_INPUT,
+        BROTLI_DECODER_NEEDS_MORE_OUTPUT => BROTLI_DECODER_RESULT_NEEDS_MORE_OUTPUT,
+        _ => BROTLI_DECODER_RESULT_ERROR
+    }
+}
+
+/// Decodes the whole stream in `encoded_buffer` into `decoded_buffer`, which holds
+/// `*decoded_size` bytes, and sets `*decoded_size` to the number of bytes decoded. Returns
+/// `ERROR` unless the stream is complete and fits.
+///
+/// # Safety
+///
+/// The buffers must hold as many bytes as claimed.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliDecoderDecompress( encoded_size: usize, encoded_buffer: *const u8, decoded_size: *mut usize, decoded_buffer: *mut u8 ) -> BrotliDecoderResult {
+    if decoded_size.is_null() {
+        return BROTLI_DECODER_RESULT_ERROR
+    }
+
+    let mut state = BrotliDecoderState::new( CAlloc::default() );
+    let mut available_in = encoded_size;
+    let mut next_in = encoded_buffer;
+    let mut available_out = *decoded_size;
+    let mut next_out = decoded_buffer;
+    let mut total_out = 0;
+    let result = BrotliDecoderDecompressStream( &mut state, &mut available_in, &mut next_in, &mut available_out, &mut next_out, &mut total_out );
+    *decoded_size = total_out;
+    if result == BROTLI_DECODER_RESULT_SUCCESS { result } else { BROTLI_DECODER_RESULT_ERROR }
+}
+
+/// Returns `BROTLI_TRUE` if decoded output is waiting for room in the output buffer.
+///
+/// # Safety
+///
+/// `state` must come from `BrotliDecoderCreateInstance()`.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliDecoderHasMoreOutput( state: *const BrotliDecoderState ) -> BROTLI_BOOL {
+    if ( *state ).decoder.pending_output() > 0 { BROTLI_TRUE } else { BROTLI_FALSE }
+}
+
+/// Returns `BROTLI_TRUE` once the stream has been decoded, and all of the output handed out.
+///
+/// # Safety
+///
+/// `state` must come from `BrotliDecoderCreateInstance()`.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliDecoderIsFinished( state: *const BrotliDecoderState ) -> BROTLI_BOOL {
+    let decoder = &( *state ).decoder;
+    if decoder.is_finished() && decoder.pending_output() == 0 { BROTLI_TRUE } else { BROTLI_FALSE }
+}
+
+/// Returns the outcome of the last call of `BrotliDecoderDecompressStream()`. Once an error
+/// occurred, it sticks.
+///
+/// # Safety
+///
+/// `state` must come from `BrotliDecoderCreateInstance()`.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliDecoderGetErrorCode( state: *const BrotliDecoderState ) -> BrotliDecoderErrorCode {
+    ( *state ).error_code
+}
+
+/// Returns the name of an error code, without the `BROTLI_DECODER` prefix.
+#[no_mangle]
+pub extern "C" fn BrotliDecoderErrorString( code: c_int ) -> *const c_char {
+    let name: &'static [u8] = match code {
+        0 => b"_NO_ERROR\0",
+        1 => b"_SUCCESS\0",
+        2 => b"_NEEDS_MORE_INPUT\0",
+        3 => b"_NEEDS_MORE_OUTPUT\0",
+        -1 => b"_ERROR_FORMAT_EXUBERANT_NIBBLE\0",
+        -2 => b"_ERROR_FORMAT_RESERVED\0",
+        -3 => b"_ERROR_FORMAT_EXUBERANT_META_NIBBLE\0",
+        -4 => b"_ERROR_FORMAT_SIMPLE_HUFFMAN_ALPHABET\0",
+        -5 => b"_ERROR_FORMAT_SIMPLE_HUFFMAN_SAME\0",
+        -6 => b"_ERROR_FORMAT_CL_SPACE\0",
+        -7 => b"_ERROR_FORMAT_HUFFMAN_SPACE\0",
+        -8 => b"_ERROR_FORMAT_CONTEXT_MAP_REPEAT\0",
+        -9 => b"_ERROR_FORMAT_BLOCK_LENGTH_1\0",
+        -10 => b"_ERROR_FORMAT_BLOCK_LENGTH_2\0",
+        -11 => b"_ERROR_FORMAT_TRANSFORM\0",
+        -12 => b"_ERROR_FORMAT_DICTIONARY\0",
+        -13 => b"_ERROR_FORMAT_WINDOW_BITS\0",
+        -14 => b"_ERROR_FORMAT_PADDING_1\0",
+        -15 => b"_ERROR_FORMAT_PADDING_2\0",
+        -16 => b"_ERROR_FORMAT_DISTANCE\0",
+        -19 => b"_ERROR_DICTIONARY_NOT_SET\0",
+        -20 => b"_ERROR_INVALID_ARGUMENTS\0",
+        -21 => b"_ERROR_ALLOC_CONTEXT_MODES\0",
+        -22 => b"_ERROR_ALLOC_TREE_GROUPS\0",
+        -25 => b"_ERROR_ALLOC_CONTEXT_MAP\0",
+        -26 => b"_ERROR_ALLOC_RING_BUFFER_1\0",
+        -27 => b"_ERROR_ALLOC_RING_BUFFER_2\0",
+        -30 => b"_ERROR_ALLOC_BLOCK_TYPE_TREES\0",
+        -31 => b"_ERROR_UNREACHABLE\0",
+        _ => b"INVALID\0"
+    };
+    name.as_ptr() as *const c_char
+}
@@ -0,0 +1,491 @@
+//! # C interface of the encoder
+//!
+//! Exports the encoder functions of the C library's `brotli/encode.h`, alongside the decoder
+//! functions of the `ffi` module. The header `c/include/brotli/encode.h` is generated from this
+//! file with the `header` feature, so the documentation of the items here ends up in the header.
+//!
+//! Unlike the decoder, the encoder only allocates its state with the custom allocation functions:
+//! its buffers come from the global allocator. The enumerations are taken as plain integers,
+//! since C may pass any value for them, and unknown values are rejected.
+
+#![allow(non_camel_case_types)]
+
+use encoder::{ Encoder, EncoderOptions, Mode, MAX_METADATA_LENGTH };
+use ffi::{ BROTLI_BOOL, BROTLI_TRUE, BROTLI_FALSE, CAlloc, brotli_alloc_func, brotli_free_func };
+use std::cmp::{ min, max };
+use std::os::raw::{ c_int, c_void };
+use std::ptr;
+use std::slice;
+
+/// Minimal value for ::BROTLI_PARAM_LGWIN parameter.
+pub const BROTLI_MIN_WINDOW_BITS: c_int = 10;
+
+/// Maximal value for ::BROTLI_PARAM_LGWIN parameter.
+pub const BROTLI_MAX_WINDOW_BITS: c_int = 24;
+
+/// Minimal value for ::BROTLI_PARAM_LGBLOCK parameter.
+pub const BROTLI_MIN_INPUT_BLOCK_BITS: c_int = 16;
+
+/// Maximal value for ::BROTLI_PARAM_LGBLOCK parameter.
+pub const BROTLI_MAX_INPUT_BLOCK_BITS: c_int = 24;
+
+/// Minimal value for ::BROTLI_PARAM_QUALITY parameter.
+pub const BROTLI_MIN_QUALITY: c_int = 0;
+
+/// Maximal value for ::BROTLI_PARAM_QUALITY parameter.
+pub const BROTLI_MAX_QUALITY: c_int = 11;
+
+/// Default value for ::BROTLI_PARAM_QUALITY parameter. Like in `EncoderOptions`, it's lower
+/// than the C library's 11.
+pub const BROTLI_DEFAULT_QUALITY: c_int = 9;
+
+/// Default value for ::BROTLI_PARAM_LGWIN parameter.
+pub const BROTLI_DEFAULT_WINDOW: c_int = 22;
+
+/// Default value for ::BROTLI_PARAM_MODE parameter.
+pub const BROTLI_DEFAULT_MODE: BrotliEncoderMode = BrotliEncoderMode::BROTLI_MODE_GENERIC;
+
+/// Options for ::BROTLI_PARAM_MODE parameter.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BrotliEncoderMode {
+    /// Default compression mode. The compressor does not know anything in advance about the
+    /// properties of the input.
+    BROTLI_MODE_GENERIC = 0,
+
+    /// Compression mode for UTF-8 formatted text input.
+    BROTLI_MODE_TEXT = 1,
+
+    /// Compression mode used in WOFF 2.0.
+    BROTLI_MODE_FONT = 2
+}
+
+/// Operations that can be performed by the streaming encoder.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BrotliEncoderOperation {
+    /// Processes the input. The encoder may postpone producing output until it has processed
+    /// enough input.
+    BROTLI_OPERATION_PROCESS = 0,
+
+    /// Produces output for all processed input, padded to a byte boundary, so that the
+    /// decoder can decode everything passed in so far. Call repeatedly, without new input,
+    /// until ::BrotliEncoderHasMoreOutput returns ::BROTLI_FALSE.
+    BROTLI_OPERATION_FLUSH = 1,
+
+    /// Finalizes the stream. Call repeatedly, without new input, until
+    /// ::BrotliEncoderIsFinished returns ::BROTLI_TRUE.
+    BROTLI_OPERATION_FINISH = 2,
+
+    /// Emits the whole input, at most 16 MiB, as metadata, after flushing the input processed
+    /// before. Call repeatedly, without new input, until ::BrotliEncoderHasMoreOutput returns
+    /// ::BROTLI_FALSE.
+    BROTLI_OPERATION_EMIT_METADATA = 3
+}
+
+/// Options to be used with ::BrotliEncoderSetParameter.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BrotliEncoderParameter {
+    /// Tunes the encoder for the type of input, one of ::BrotliEncoderMode.
+    BROTLI_PARAM_MODE = 0,
+
+    /// The compression level, from ::BROTLI_MIN_QUALITY to ::BROTLI_MAX_QUALITY.
+    BROTLI_PARAM_QUALITY = 1,
+
+    /// Base 2 logarithm of the sliding window size, from ::BROTLI_MIN_WINDOW_BITS to
+    /// ::BROTLI_MAX_WINDOW_BITS.
+    BROTLI_PARAM_LGWIN = 2,
+
+    /// Base 2 logarithm of the maximum input block size, from ::BROTLI_MIN_INPUT_BLOCK_BITS
+    /// to ::BROTLI_MAX_INPUT_BLOCK_BITS, or 0 to pick it based on the window size.
+    BROTLI_PARAM_LGBLOCK = 3,
+
+    /// Any value is accepted: the encoder never models the context of literals.
+    BROTLI_PARAM_DISABLE_LITERAL_CONTEXT_MODELING = 4,
+
+    /// The expected size of the input, or 0 if it's unknown. While ::BROTLI_PARAM_LGBLOCK is
+    /// 0, the input block isn't made larger than needed for this size.
+    BROTLI_PARAM_SIZE_HINT = 5,
+
+    /// Only 0 is accepted: large windows aren't supported.
+    BROTLI_PARAM_LARGE_WINDOW = 6,
+
+    /// The number of distance postfix bits, from 0 to 3. Combinations with ::BROTLI_PARAM_NDIRECT
+    /// that the format can't express select 0 for both. Unless either is set, the mode picks them.
+    BROTLI_PARAM_NPOSTFIX = 7,
+
+    /// The number of direct distance codes, from 0 to 120, a multiple of
+    /// <tt>1 << ::BROTLI_PARAM_NPOSTFIX</tt>.
+    BROTLI_PARAM_NDIRECT = 8,
+
+    /// Only 0 is accepted: the stream always starts at the beginning.
+    BROTLI_PARAM_STREAM_OFFSET = 9
+}
+
+use self::BrotliEncoderOperation::*;
+use self::BrotliEncoderParameter::*;
+
+/// Where the stream is, as far as the operations allowed next are concerned.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum StreamState {
+    Processing,
+
+    // The output of a flush or of metadata hasn't been handed out completely yet
+    Flushing,
+    Metadata,
+    Finished
+}
+
+/// Opaque structure that holds encoder state.
+pub struct BrotliEncoderState {
+    allocator: CAlloc,
+    options: EncoderOptions,
+    size_hint: u32,
+
+    // The distance parameters set, if any
+    postfix_bits: Option<u32>,
+    num_direct_distance_codes: Option<u32>,
+
+    // Created by the first call of `BrotliEncoderCompressStream()`, which fixes the options
+    encoder: Option<Encoder>,
+    stream_state: StreamState,
+    total_out: usize
+}
+
+impl BrotliEncoderState {
+
+    fn new( allocator: CAlloc ) -> BrotliEncoderState {
+        BrotliEncoderState {
+            allocator: allocator,
+            options: EncoderOptions::default(),
+            size_hint: 0,
+            postfix_bits: None,
+            num_direct_distance_codes: None,
+            encoder: None,
+            stream_state: StreamState::Processing,
+            total_out: 0
+        }
+    }
+
+    /// Performs `op` on `input`, writing into `output` until either runs out or the operation
+    /// is complete. Returns the number of bytes consumed and written, or `None` if `op` isn't
+    /// allowed in the current state.
+    fn compress( &mut self, op: BrotliEncoderOperation, input: &[u8], output: &mut [u8] ) -> Option<(usize, usize)> {
+        if self.encoder.is_none() {
+            self.encoder = Some( self.create_encoder() );
+        }
+        let encoder = self.encoder.as_mut().unwrap();
+        let mut consumed = 0;
+        let mut written = 0;
+        match (op, self.stream_state) {
+            (BROTLI_OPERATION_EMIT_METADATA, StreamState::Processing) => {
+                if input.len() > MAX_METADATA_LENGTH {
+                    return None
+                }
+                encoder.write_metadata( input );
+                consumed = input.len();
+                self.stream_state = StreamState::Metadata;
+            }
+            (BROTLI_OPERATION_EMIT_METADATA, StreamState::Metadata) if input.is_empty() => {}
+            (BROTLI_OPERATION_EMIT_METADATA, _) | (_, StreamState::Metadata) => return None,
+            (_, StreamState::Processing) => {}
+            // A flush or the end of the stream has to complete without new input
+            _ if !input.is_empty() => return None,
+            _ => {}
+        }
+
+        loop {
+            let count = min( output.len() - written, encoder.output().len() );
+            output[written .. written + count].copy_from_slice( &encoder.output()[..count] );
+            encoder.consume_output( count );
+            written += count;
+            if !encoder.output().is_empty() {
+                break
+            }
+
+            match self.stream_state {
+                StreamState::Flushing | StreamState::Metadata => {
+                    self.stream_state = StreamState::Processing;
+                    if op != BROTLI_OPERATION_FINISH {
+                        break
+                    }
+                }
+                StreamState::Finished => break,
+                StreamState::Processing => {}
+            }
+
+            if consumed < input.len() {
+                consumed += encoder.push( &input[consumed..] );
+                if encoder.is_block_full() {
+                    encoder.encode_block();
+                }
+            } else {
+                match op {
+                    BROTLI_OPERATION_FLUSH => {
+                        encoder.flush();
+                        self.stream_state = StreamState::Flushing;
+                    }
+                    BROTLI_OPERATION_FINISH => {
+                        encoder.finish();
+                        self.stream_state = StreamState::Finished;
+                    }
+                    _ => break
+                }
+            }
+        }
+
+        Some( (consumed, written) )
+    }
+
+    /// Creates the encoder with the parameters set.
+    fn create_encoder( &self ) -> Encoder {
+        let mut options = self.options;
+        if options.block_bits == 0 && self.size_hint > 0 {
+            let bits = u32::BITS - ( self.size_hint - 1 ).leading_zeros();
+            options.block_bits = min( max( bits, BROTLI_MIN_INPUT_BLOCK_BITS as u32 ), options.clamped().block_bits );
+        }
+
+        let mut encoder = Encoder::new( &options );
+        if self.postfix_bits.is_some() || self.num_direct_distance_codes.is_some() {
+            encoder.set_distance_parameters( self.postfix_bits.unwrap_or( 0 ), self.num_direct_distance_codes.unwrap_or( 0 ) as usize );
+        }
+        encoder
+    }
+
+    fn has_more_output( &self ) -> bool {
+        self.encoder.as_ref().is_some_and( |encoder| !encoder.output().is_empty() )
+    }
+}
+
+/// The ::BrotliEncoderMode with the value `mode`, as the encoder's mode.
+fn mode( mode: c_int ) -> Option<Mode> {
+    match mode {
+        0 => Some( Mode::Generic ),
+        1 => Some( Mode::Text ),
+        2 => Some( Mode::Font ),
+        _ => None
+    }
+}
+
+/// The ::BrotliEncoderOperation with the value `op`.
+fn operation( op: c_int ) -> Option<BrotliEncoderOperation> {
+    match op {
+        0 => Some( BROTLI_OPERATION_PROCESS ),
+        1 => Some( BROTLI_OPERATION_FLUSH ),
+        2 => Some( BROTLI_OPERATION_FINISH ),
+        3 => Some( BROTLI_OPERATION_EMIT_METADATA ),
+        _ => None
+    }
+}
+
+/// The ::BrotliEncoderParameter with the value `param`.
+fn parameter( param: c_int ) -> Option<BrotliEncoderParameter> {
+    match param {
+        0 => Some( BROTLI_PARAM_MODE ),
+        1 => Some( BROTLI_PARAM_QUALITY ),
+        2 => Some( BROTLI_PARAM_LGWIN ),
+        3 => Some( BROTLI_PARAM_LGBLOCK ),
+        4 => Some( BROTLI_PARAM_DISABLE_LITERAL_CONTEXT_MODELING ),
+        5 => Some( BROTLI_PARAM_SIZE_HINT ),
+        6 => Some( BROTLI_PARAM_LARGE_WINDOW ),
+        7 => Some( BROTLI_PARAM_NPOSTFIX ),
+        8 => Some( BROTLI_PARAM_NDIRECT ),
+        9 => Some( BROTLI_PARAM_STREAM_OFFSET ),
+        _ => None
+    }
+}
+
+/// Creates an instance of ::BrotliEncoderState and initializes it. Returns @c 0 if only one
+/// of @p alloc_func and @p free_func is given, or the state can't be allocated.
+///
+/// @warning Only the state itself is allocated with @p alloc_func. The buffers of the encoder,
+/// which grow with the window and input block sizes, come from the global allocator of the
+/// library, @c malloc unless it was built otherwise, regardless of the functions given.
+#[no_mangle]
+pub extern "C" fn BrotliEncoderCreateInstance( alloc_func: brotli_alloc_func, free_func: brotli_free_func, opaque: *mut c_void ) -> *mut BrotliEncoderState {
+    let allocator = match CAlloc::new( alloc_func, free_func, opaque ) {
+        Some( allocator ) => allocator,
+        None => return ptr::null_mut()
+    };
+    unsafe {
+        let state = allocator.alloc::<BrotliEncoderState>( 1 );
+        if !state.is_null() {
+            state.write( BrotliEncoderState::new( allocator ));
+        }
+        state
+    }
+}
+
+/// Deinitializes and frees ::BrotliEncoderState instance.
+///
+/// # Safety
+///
+/// `state` must be null or come from `BrotliEncoderCreateInstance()`, and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliEncoderDestroyInstance( state: *mut BrotliEncoderState ) {
+    if !state.is_null() {
+        let allocator = ( *state ).allocator;
+        ptr::drop_in_place( state );
+        allocator.free( state, 1 );
+    }
+}
+
+/// Sets the specified parameter, one of ::BrotliEncoderParameter, to the given encoder instance.
+/// Values out of range are clamped to the nearest valid value. Returns ::BROTLI_FALSE if
+/// @p state is @c 0, the parameter or value is unsupported, or compression has already started.
+///
+/// # Safety
+///
+/// `state` must be null or come from `BrotliEncoderCreateInstance()`.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliEncoderSetParameter( state: *mut BrotliEncoderState, param: c_int, value: u32 ) -> BROTLI_BOOL {
+    let state = match state.as_mut() {
+        Some( state ) if state.encoder.is_none() => state,
+        _ => return BROTLI_FALSE
+    };
+    let param = match parameter( param ) {
+        Some( param ) => param,
+        None => return BROTLI_FALSE
+    };
+
+    match param {
+        BROTLI_PARAM_MODE => state.options.mode = match mode( value as c_int ) {
+            Some( mode ) => mode,
+            None => return BROTLI_FALSE
+        },
+        BROTLI_PARAM_QUALITY => state.options.quality = value,
+        BROTLI_PARAM_LGWIN => state.options.window_bits = value,
+        BROTLI_PARAM_LGBLOCK => state.options.block_bits = value,
+        BROTLI_PARAM_DISABLE_LITERAL_CONTEXT_MODELING => {}
+        BROTLI_PARAM_SIZE_HINT => state.size_hint = value,
+        BROTLI_PARAM_NPOSTFIX => state.postfix_bits = Some( min( value, 3 )),
+        BROTLI_PARAM_NDIRECT => state.num_direct_distance_codes = Some( min( value, 15 << 3 )),
+        BROTLI_PARAM_LARGE_WINDOW | BROTLI_PARAM_STREAM_OFFSET => if value != 0 {
+            return BROTLI_FALSE
+        }
+    }
+    BROTLI_TRUE
+}
+
+/// Calculates the output size bound for the given @p input_size. Returns 0 if the result
+/// doesn't fit in @c size_t.
+#[no_mangle]
+pub extern "C" fn BrotliEncoderMaxCompressedSize( input_size: usize ) -> usize {
+    // Stream header, a stored meta-block header for every 16 KiB, and the last meta-block
+    let num_large_blocks = input_size >> 14;
+    let overhead = 2 + 4 * num_large_blocks + 3 + 1;
+    match input_size {
+        0 => 2,
+        _ => input_size.checked_add( overhead ).unwrap_or( 0 )
+    }
+}
+
+/// Compresses @p input_size bytes at @p input_buffer into @p encoded_buffer, which holds
+/// @p *encoded_size bytes, and sets @p *encoded_size to the size of the stream. @p mode is one
+/// of ::BrotliEncoderMode. Returns ::BROTLI_FALSE if an argument is invalid, or the stream
+/// doesn't fit. ::BrotliEncoderMaxCompressedSize tells a size that always fits.
+///
+/// # Safety
+///
+/// The buffers must hold as many bytes as claimed.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliEncoderCompress( quality: c_int, lgwin: c_int, mode: c_int, input_size: usize, input_buffer: *const u8,
+                                                encoded_size: *mut usize, encoded_buffer: *mut u8 ) -> BROTLI_BOOL {
+    if encoded_size.is_null() || *encoded_size == 0 || encoded_buffer.is_null() || ( input_size > 0 && input_buffer.is_null() ) {
+        return BROTLI_FALSE
+    }
+
+    let mut state = BrotliEncoderState::new( CAlloc::default() );
+    state.options.quality = max( quality, 0 ) as u32;
+    state.options.window_bits = max( lgwin, 0 ) as u32;
+    state.options.mode = match self::mode( mode ) {
+        Some( mode ) => mode,
+        None => return BROTLI_FALSE
+    };
+    let input = if input_size > 0 { slice::from_raw_parts( input_buffer, input_size ) } else { &[] };
+    let output = slice::from_raw_parts_mut( encoded_buffer, *encoded_size );
+    match state.compress( BROTLI_OPERATION_FINISH, input, output ) {
+        Some( (_, written) ) if state.stream_state == StreamState::Finished && !state.has_more_output() => {
+            *encoded_size = written;
+            BROTLI_TRUE
+        }
+        _ => BROTLI_FALSE
+    }
+}
+
+/// Compresses input stream to output stream, performing @p op, one of ::BrotliEncoderOperation.
+/// Consumes up to @p *available_in bytes at @p *next_in, writes up to @p *available_out bytes at
+/// @p *next_out, and advances both past what was consumed and produced. @p *total_out, unless
+/// @p total_out is @c 0, is set to the number of bytes produced so far.
+///
+/// Returns ::BROTLI_FALSE if the arguments are invalid, or if @p op is unknown or not allowed:
+/// a flush, metadata or the end of the stream has to be completed with the same operation and
+/// no new input before anything else is done.
+///
+/// # Safety
+///
+/// The pointers must be valid, and the buffers hold as many bytes as claimed. `next_in` and
+/// `next_out` may be null while nothing is available.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliEncoderCompressStream( state: *mut BrotliEncoderState, op: c_int, available_in: *mut usize,
+                                                      next_in: *mut *const u8, available_out: *mut usize, next_out: *mut *mut u8,
+                                                      total_out: *mut usize ) -> BROTLI_BOOL {
+    let (state, op) = match (state.as_mut(), operation( op )) {
+        (Some( state ), Some( op )) => (state, op),
+        _ => return BROTLI_FALSE
+    };
+    if available_in.is_null() || available_out.is_null() ||
+       ( *available_in > 0 && ( next_in.is_null() || ( *next_in ).is_null() )) ||
+       ( *available_out > 0 && ( next_out.is_null() || ( *next_out ).is_null() )) {
+        return BROTLI_FALSE
+    }
+
+    let input = if *available_in > 0 { slice::from_raw_parts( *next_in, *available_in ) } else { &[] };
+    let output: &mut [u8] = if *available_out > 0 { slice::from_raw_parts_mut( *next_out, *available_out ) } else { &mut [] };
+    let (consumed, written) = match state.compress( op, input, output ) {
+        Some( counts ) => counts,
+        None => return BROTLI_FALSE
+    };
+
+    if consumed > 0 {
+        *next_in = ( *next_in ).add( consumed );
+        *available_in -= consumed;
+    }
+    if written > 0 {
+        *next_out = ( *next_out ).add( written );
+        *available_out -= written;
+    }
+    state.total_out += written;
+    if !total_out.is_null() {
+        *total_out = state.total_out;
+    }
+    BROTLI_TRUE
+}
+
+/// Returns ::BROTLI_TRUE if the encoder has produced output that didn't fit in the output
+/// buffer yet.
+///
+/// # Safety
+///
+/// `state` must be null or come from `BrotliEncoderCreateInstance()`.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliEncoderHasMoreOutput( state: *const BrotliEncoderState ) -> BROTLI_BOOL {
+    match state.as_ref() {
+        Some( state ) if state.has_more_output() => BROTLI_TRUE,
+        _ => BROTLI_FALSE
+    }
+}
+
+/// Returns ::BROTLI_TRUE once the stream is finalized, and all of its output handed out.
+///
+/// # Safety
+///
+/// `state` must be null or come from `BrotliEncoderCreateInstance()`.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliEncoderIsFinished( state: *const BrotliEncoderState ) -> BROTLI_BOOL {
+    match state.as_ref() {
+        Some( state ) if state.stream_state == StreamState::Finished && !state.has_more_output() => BROTLI_TRUE,
+        _ => BROTLI_FALSE
+    }
+}
@@ -1,20 +1,43 @@
 use bitreader::BitReader;
-use std::ops::Index;
+use core::ops::Index;
 use BrotliError;
 use Result;
 
 /// Maximum number of bits used for a code
-const HUFFMAN_MAX_CODE_LENGTH: usize = 15;
+pub const HUFFMAN_MAX_CODE_LENGTH: usize = 15;
 
 /// For current format this constant equals to kNumInsertAndCopyCodes
-const HUFFMAN_MAX_CODE_LENGTHS_SIZE: usize = 704;
+pub const HUFFMAN_MAX_CODE_LENGTHS_SIZE: usize = 704;
 
 /// Maximum possible Huffman table size for an alphabet size of 704, max code length 15 and root table bits 8.
-const HUFFMAN_MAX_TABLE_SIZE: usize = 1080;
+pub const HUFFMAN_MAX_TABLE_SIZE: usize = 1080;
+
+/// Maximum possible Huffman table sizes for alphabets of up to 32, 64, .. 704 symbols.
+const MAX_TABLE_SIZES: [usize; 23] = [256, 402, 436, 468, 500, 534, 566, 598, 630, 662, 694, 726, 758, 790, 822,
+                                      854, 886, 920, 952, 984, 1016, 1048, 1080];
 
 /// Maximum number of bits used to represent a (15-bit) code.
 const HUFFMAN_MAX_CODE_LENGTH_CODE_LENGTH: u8 = 5;
 
+/// The number of symbols in the code length alphabet.
+pub const CODE_LENGTH_CODES: usize = 18;
+
+/// The order in which the code lengths of the code length alphabet are stored.
+pub static CODE_LENGTH_CODE_ORDER: [u8; CODE_LENGTH_CODES] = [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15 ];
+
+/// The code length used for repeating the previous non-zero code length.
+pub const REPEAT_PREVIOUS_CODE_LENGTH: u8 = 16;
+
+/// The code length used for repeating a zero code length.
+#[cfg(feature = "encoder")]
+pub const REPEAT_ZERO_CODE_LENGTH: u8 = 17;
+
+/// The code lengths are used until a previous non-zero length is seen.
+const INITIAL_REPEATED_CODE_LENGTH: u8 = 8;
+
+const HUFFMAN_TABLE_BITS: u8 = 8;
+const HUFFMAN_TABLE_MASK: u32 = 0xff;
+
 /// An entry in a Huffman table.
 #[derive(Debug, Copy, Clone,Default)]
 pub struct Code {
@@ -25,118 +48,413 @@ pub struct Code {
     value: u16
 }
 
-pub struct Table {
-    codes: [Code;HUFFMAN_MAX_TABLE_SIZE],
+impl Code {
 
-    /// The `codes` field contains repeats, so the real alphabet size needs to be seperately tracked.
-    alphabet_size: u16
+    /// The entry as 3 bytes, for a `Checkpoint`.
+    pub( crate ) fn to_bytes( self ) -> [u8; 3] {
+        let value = self.value.to_le_bytes();
+        [self.bits, value[0], value[1]]
+    }
+
+    /// An entry from the bytes of `to_bytes()`.
+    pub( crate ) fn from_bytes( bytes: [u8; 3] ) -> Code {
+        Code { bits: bytes[0], value: u16::from_le_bytes( [bytes[1], bytes[2]] ) }
+    }
+}
+
+/// A Huffman table, stored in `codes`. The storage must hold `max_table_size()` entries for the
+/// alphabet to fit any table; the decoder keeps its tables in storage from its allocator.
+pub struct Table<C = [Code;HUFFMAN_MAX_TABLE_SIZE]> {
+    codes: C
 }
 
 impl Default for Table  {
     fn default() -> Self {
-        Table { codes: [Code::default();HUFFMAN_MAX_TABLE_SIZE], alphabet_size: 0 }
+        Table { codes: [Code::default();HUFFMAN_MAX_TABLE_SIZE] }
     }
 }
 
-impl Index<u32> for Table {
+impl Clone for Table {
+    fn clone( &self ) -> Self {
+        Table { codes: self.codes }
+    }
+}
+
+/// Returns the maximum possible Huffman table size for an alphabet of `alphabet_size` symbols,
+/// up to 704.
+pub const fn max_table_size( alphabet_size: usize ) -> usize {
+    MAX_TABLE_SIZES[( alphabet_size + 31 ) >> 5]
+}
+
+impl<C> Table<C> {
+
+    /// Makes a table stored in `codes`.
+    pub fn with_codes( codes: C ) -> Table<C> {
+        Table { codes: codes }
+    }
+}
+
+impl<C: AsRef<[Code]>> Index<u32> for Table<C> {
     type Output = Code;
 
     /// Relies on the table having elements repeated. Looks up the first 8 bits,
-    /// which works only because short codes are duplicated such that the unused 
+    /// which works only because short codes are duplicated such that the unused
     /// trailing bits can have any value. Any remaining bits are looked
     /// up with an exact mask.
     #[inline(always)]
     fn index(&self, bits: u32 ) -> &Code {
-        const HUFFMAN_TABLE_BITS: u8 = 8;
-        const HUFFMAN_TABLE_MASK: u32 = 0xff;
-
+        let codes = self.codes.as_ref();
         let mut offs = ( bits & HUFFMAN_TABLE_MASK ) as usize;
-      
-        if self.codes[offs].bits > HUFFMAN_TABLE_BITS {
-            let nbits = self.codes[offs].bits - HUFFMAN_TABLE_BITS;           
-            offs += ( self.codes[offs].value as usize ) + ((bits >> HUFFMAN_TABLE_BITS) & !((0xffffffff) << nbits )) as usize;
+
+        if codes[offs].bits > HUFFMAN_TABLE_BITS {
+            let nbits = codes[offs].bits - HUFFMAN_TABLE_BITS;
+            offs += ( codes[offs].value as usize ) + ((bits >> HUFFMAN_TABLE_BITS) & !((0xffffffff) << nbits )) as usize;
         }
-        
-        &self.codes[offs]
+
+        &codes[offs]
     }
 }
 
-impl Table {
+impl<C: AsRef<[Code]>> Table<C> {
 
     /// Decodes the next Huffman code from a BitReader. Reads 0 - 15 bits.
+    /// Precondition: the accumulator holds at least 15 bits.
     #[inline(always)]
     pub fn read_huffman_symbol<BR:BitReader>( &self, br: &mut BR ) -> u16 {
-        let code = self[br.get_bits_unmasked()];
+        let bits = br.get_bits_unmasked();
+        let code = self[bits];
+        if self.codes.as_ref()[( bits & HUFFMAN_TABLE_MASK ) as usize].bits > HUFFMAN_TABLE_BITS {
+            br.drop_bits( HUFFMAN_TABLE_BITS as u32 );
+        }
         br.drop_bits( code.bits as u32 );
-        
+
         code.value
     }
 
+    /// Same as `read_huffman_symbol()`, but fails with `InsufficientData` if the input ends
+    /// before the end of the code.
+    #[inline(always)]
+    pub fn safe_read_huffman_symbol<BR:BitReader>( &self, br: &mut BR ) -> Result<u16> {
+        let available = br.get_available_bits();
+        if available >= HUFFMAN_MAX_CODE_LENGTH as u32 {
+            return Ok( self.read_huffman_symbol( br ))
+        }
+
+        let bits = br.get_bits_unmasked();
+        let root = self.codes.as_ref()[( bits & HUFFMAN_TABLE_MASK ) as usize];
+        let (code, length) = if root.bits > HUFFMAN_TABLE_BITS {
+            let code = self[bits];
+            (code, HUFFMAN_TABLE_BITS as u32 + code.bits as u32)
+        } else {
+            (root, root.bits as u32)
+        };
+
+        if length > available {
+            return Err( BrotliError::InsufficientData )
+        }
+
+        br.drop_bits( length );
+        Ok( code.value )
+    }
+}
+
+impl<C: AsRef<[Code]>> Table<C> {
+
+    /// Returns `true` if every entry a lookup can reach stays inside the table, and decodes to a
+    /// symbol of an alphabet of `alphabet_size`. Used on tables taken from a `Checkpoint`.
+    pub( crate ) fn is_valid( &self, alphabet_size: usize ) -> bool {
+        let codes = self.codes.as_ref();
+        let root_size = 1 << HUFFMAN_TABLE_BITS;
+        codes.len() >= root_size && codes[..root_size].iter().enumerate().all( |(offs, root)| {
+            if root.bits <= HUFFMAN_TABLE_BITS {
+                return ( root.value as usize ) < alphabet_size
+            }
+
+            // A second level table of `nbits`, after the root table
+            let nbits = root.bits - HUFFMAN_TABLE_BITS;
+            if nbits as usize > HUFFMAN_MAX_CODE_LENGTH - HUFFMAN_TABLE_BITS as usize {
+                return false
+            }
+            let start = offs + root.value as usize;
+            let end = start + ( 1 << nbits );
+            start >= root_size && end <= codes.len() &&
+                codes[start..end].iter().all( |code| code.bits <= nbits && ( code.value as usize ) < alphabet_size )
+        })
+    }
+}
+
+impl<C: AsRef<[Code]> + AsMut<[Code]>> Table<C> {
+
+    /// Returns the key following `key` in bit-reversed order, for codes of length `len`.
     fn get_next_key( key:u32 , len: u32 ) -> u32 {
         let mut step = 1 << (len - 1);
         while ( key & step ) != 0 {
             step >>= 1;
         }
-    
-        (key & (step - 1)) + step
+
+        (key & step.wrapping_sub( 1 )) + step
     }
 
-    /// Copy a value into the destination slice starting at an offset,
-    /// repeated every `repeat` steps. Used to construct tables that
-    /// can consume more than 1 bit at a time.
+    /// Stores `code` at `offs` and every `step` entries after it, up to `end`. Used to
+    /// construct tables that can consume more than 1 bit at a time.
     #[inline(always)]
-    fn replicate( &mut self, offs: usize, repeat: usize ) {
+    fn replicate( &mut self, offs: usize, step: usize, end: usize, code: Code ) {
         // TODO: unstable feature "step_by()" can make this more elegant.
-        let mut i = offs + repeat;
-        let t = self.codes[offs];
-        while i < self.codes.len() {
-            self.codes[i] = t;
-            i += repeat;
+        let codes = self.codes.as_mut();
+        let mut i = offs;
+        while i < end {
+            codes[i] = code;
+            i += step;
         }
-    }   
+    }
+
+    /// Returns the number of bits of the second level table needed for the codes of length
+    /// `len` and longer.
+    fn next_table_bits( count: &[u16], mut len: usize, root_bits: usize ) -> usize {
+        let mut left = 1i32 << (len - root_bits);
+        while len < HUFFMAN_MAX_CODE_LENGTH {
+            left -= count[len] as i32;
+            if left <= 0 {
+                break;
+            }
+            len += 1;
+            left <<= 1;
+        }
+
+        len - root_bits
+    }
 
     /// Because of the way the Brotli tables are encoded, they can be decoded
-    /// from an array of code-lengths only.
+    /// from an array of code-lengths only. The code must be complete, or have a single symbol.
+    ///
+    /// Codes of up to 8 bits are looked up directly. Longer codes use a second level table
+    /// pointed to by the entry of their first 8 bits.
     pub fn build_from_code_lengths( &mut self, lengths: &[u8] ) -> Result<()> {
 
-        // TODO: Check if this is > or >=
         if lengths.len() > HUFFMAN_MAX_CODE_LENGTHS_SIZE {
             return Err( BrotliError::InvalidEncoding)
         }
 
         // Compute histogram of bit-lengths
-        let mut code_length_histogram = [0;HUFFMAN_MAX_CODE_LENGTH+1];
+        let mut count = [0u16;HUFFMAN_MAX_CODE_LENGTH+1];
         for bits in lengths {
             if *bits > HUFFMAN_MAX_CODE_LENGTH as u8 {
                 return Err( BrotliError::InvalidEncoding )
             }
 
-            code_length_histogram[*bits as usize] += 1; 
+            count[*bits as usize] += 1;
         }
 
-        // Find the numerical value of the smallest code for each code length
-        let mut next_code = [0u16;HUFFMAN_MAX_CODE_LENGTH+1];
-        let mut code = 0;
-        for bits in 1 .. HUFFMAN_MAX_CODE_LENGTH+1 {
-            code = (code + code_length_histogram[bits-1]) << 1;
-            next_code[bits as usize] = code;
+        // Sort symbols by length, by symbol order within each length
+        let mut offset = [0usize;HUFFMAN_MAX_CODE_LENGTH+1];
+        for len in 1 .. HUFFMAN_MAX_CODE_LENGTH {
+            offset[len + 1] = offset[len] + count[len] as usize;
         }
 
-        for symbol_value in 0..lengths.len() {
-            let bits = lengths[symbol_value] as usize;
-            let code = next_code[bits] as usize;
-            next_code[bits] += 1;
+        let mut sorted = [0u16;HUFFMAN_MAX_CODE_LENGTHS_SIZE];
+        let mut num_symbols = 0;
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                sorted[offset[len as usize]] = symbol as u16;
+                offset[len as usize] += 1;
+                num_symbols += 1;
+            }
+        }
 
-            // @@@ TODO: This is logically correct, but "not it".
-            // Brotli uses a fancy 8-bit prefix decode 
-            self.codes[code].bits = bits as u8;
-            self.codes[code].value = symbol_value as u16;
-            
-            // Repeat values to allow lookup
-            self.replicate( code, 1 << bits );
+        let root_bits = HUFFMAN_TABLE_BITS as usize;
+        let root_size = 1 << root_bits;
+
+        // Special case code with only one value, which takes no bits
+        if num_symbols <= 1 {
+            let code = Code { bits: 0, value: sorted[0] };
+            self.replicate( 0, 1, root_size, code );
+            return Ok( () )
+        }
+
+        // Fill in the root table
+        let mut key = 0;
+        let mut symbol = 0;
+        let mut step = 2;
+        for (len, count) in count.iter_mut().enumerate().take( root_bits + 1 ).skip( 1 ) {
+            while *count > 0 {
+                let code = Code { bits: len as u8, value: sorted[symbol] };
+                symbol += 1;
+                self.replicate( key as usize, step, root_size, code );
+                key = Self::get_next_key( key, len as u32 );
+                *count -= 1;
+            }
+            step <<= 1;
+        }
+
+        // Fill in the second level tables, and add pointers to the root table
+        let mask = root_size as u32 - 1;
+        let mut low = !0u32;
+        let mut table = 0;
+        let mut table_size = root_size;
+        let mut total_size = root_size;
+        step = 2;
+        for len in root_bits + 1 .. HUFFMAN_MAX_CODE_LENGTH + 1 {
+            while count[len] > 0 {
+                if key & mask != low {
+                    table += table_size;
+                    let table_bits = Self::next_table_bits( &count, len, root_bits );
+                    table_size = 1 << table_bits;
+                    total_size += table_size;
+                    if total_size > self.codes.as_ref().len() {
+                        return Err( BrotliError::InvalidEncoding )
+                    }
+
+                    low = key & mask;
+                    self.codes.as_mut()[low as usize] = Code { bits: (table_bits + root_bits) as u8, value: (table - low as usize) as u16 };
+                }
+
+                let code = Code { bits: (len - root_bits) as u8, value: sorted[symbol] };
+                symbol += 1;
+                self.replicate( table + (key >> root_bits) as usize, step, table + table_size, code );
+                key = Self::get_next_key( key, len as u32 );
+                count[len] -= 1;
+            }
+            step <<= 1;
         }
 
         Ok( () )
     }
-}
 
+    /// Reads a prefix code for an alphabet of `alphabet_size` symbols, and builds the table
+    /// for it. The code lengths are stored in `lengths`, which must hold at least `alphabet_size`
+    /// entries.
+    pub fn read<BR: BitReader>( &mut self, alphabet_size: usize, lengths: &mut [u8], br: &mut BR ) -> Result<()> {
+        let lengths = &mut lengths[..alphabet_size];
+        for length in lengths.iter_mut() {
+            *length = 0;
+        }
+
+        let hskip = br.try_read_bits( 2 )?;
+        if hskip == 1 {
+            Self::read_simple_code_lengths( lengths, br )?;
+        } else {
+            Self::read_complex_code_lengths( hskip as usize, lengths, br )?;
+        }
+
+        self.build_from_code_lengths( lengths )
+    }
+
+    /// Reads the code lengths of a simple prefix code, which has 1 to 4 symbols.
+    fn read_simple_code_lengths<BR: BitReader>( lengths: &mut [u8], br: &mut BR ) -> Result<()> {
+        let alphabet_size = lengths.len();
+        let alphabet_bits = 32 - ( alphabet_size as u32 - 1 ).leading_zeros();
+        let num_symbols = br.try_read_bits( 2 )? as usize + 1;
+
+        let mut symbols = [0usize;4];
+        for i in 0 .. num_symbols {
+            let symbol = br.try_read_bits( alphabet_bits )? as usize;
+            if symbol >= alphabet_size || symbols[..i].contains( &symbol ) {
+                return Err( BrotliError::InvalidEncoding )
+            }
+            symbols[i] = symbol;
+        }
+
+        let code_lengths: &[u8] = match num_symbols {
+            1 => &[1],
+            2 => &[1, 1],
+            3 => &[1, 2, 2],
+            _ => if br.try_read_bits( 1 )? == 0 { &[2, 2, 2, 2] } else { &[1, 2, 3, 3] }
+        };
+
+        for i in 0 .. num_symbols {
+            lengths[symbols[i]] = code_lengths[i];
+        }
+
+        Ok( () )
+    }
+
+    /// Reads the code lengths of a complex prefix code, which are themselves prefix coded.
+    fn read_complex_code_lengths<BR: BitReader>( hskip: usize, lengths: &mut [u8], br: &mut BR ) -> Result<()> {
+        // Static prefix code for the code length code lengths, indexed by 4 bits of input
+        const CODE_LENGTH_PREFIX_LENGTH: [u8;16] = [2, 2, 2, 3, 2, 2, 2, 4, 2, 2, 2, 3, 2, 2, 2, 4];
+        const CODE_LENGTH_PREFIX_VALUE: [u8;16] = [0, 4, 3, 2, 0, 4, 3, 1, 0, 4, 3, 2, 0, 4, 3, 5];
+
+        let mut code_length_code_lengths = [0u8;CODE_LENGTH_CODES];
+        let mut space = 32;
+        let mut num_codes = 0;
+        for &symbol in &CODE_LENGTH_CODE_ORDER[hskip..] {
+            let available = br.get_available_bits();
+            let bits = br.get_bits( 4 ) as usize;
+            let length = CODE_LENGTH_PREFIX_LENGTH[bits] as u32;
+            if length > available {
+                return Err( BrotliError::InsufficientData )
+            }
+            br.drop_bits( length );
+
+            let value = CODE_LENGTH_PREFIX_VALUE[bits];
+            debug_assert!( value <= HUFFMAN_MAX_CODE_LENGTH_CODE_LENGTH );
+            code_length_code_lengths[symbol as usize] = value;
+            if value != 0 {
+                space -= 32 >> value;
+                num_codes += 1;
+                if space <= 0 {
+                    break;
+                }
+            }
+        }
+
+        if num_codes != 1 && space != 0 {
+            return Err( BrotliError::InvalidEncoding )
+        }
+
+        let mut table = <Table>::default();
+        table.build_from_code_lengths( &code_length_code_lengths )?;
+
+        let alphabet_size = lengths.len();
+        let mut symbol = 0;
+        let mut prev_code_len = INITIAL_REPEATED_CODE_LENGTH;
+        let mut repeat = 0;
+        let mut repeat_code_len = 0;
+        let mut space = 32768;
+        while symbol < alphabet_size && space > 0 {
+            let code_len = table.safe_read_huffman_symbol( br )? as u8;
+            if code_len < REPEAT_PREVIOUS_CODE_LENGTH {
+                repeat = 0;
+                lengths[symbol] = code_len;
+                symbol += 1;
+                if code_len != 0 {
+                    prev_code_len = code_len;
+                    space -= 32768 >> code_len;
+                }
+            } else {
+                let extra_bits = if code_len == REPEAT_PREVIOUS_CODE_LENGTH { 2 } else { 3 };
+                let new_len = if code_len == REPEAT_PREVIOUS_CODE_LENGTH { prev_code_len } else { 0 };
+                if repeat_code_len != new_len {
+                    repeat = 0;
+                    repeat_code_len = new_len;
+                }
+
+                let old_repeat = repeat;
+                if repeat > 0 {
+                    repeat -= 2;
+                    repeat <<= extra_bits;
+                }
+                repeat += br.try_read_bits( extra_bits )? as usize + 3;
+
+                let repeat_delta = repeat - old_repeat;
+                if symbol + repeat_delta > alphabet_size {
+                    return Err( BrotliError::InvalidEncoding )
+                }
+
+                for length in &mut lengths[symbol .. symbol + repeat_delta] {
+                    *length = repeat_code_len;
+                }
+                symbol += repeat_delta;
+                if repeat_code_len != 0 {
+                    space -= ( repeat_delta as i32 ) << ( 15 - repeat_code_len );
+                }
+            }
+        }
+
+        if space != 0 {
+            return Err( BrotliError::InvalidEncoding )
+        }
+
+        Ok( () )
+    }
+}
@@ -0,0 +1,212 @@
+//! # Random access
+//!
+//! An `Index` holds resume points spread over a stream, so that decoding can start near any
+//! position in the output, instead of at the start of the stream. `build_index()` decodes the
+//! stream once, taking a decoder `Checkpoint` every so many bytes of output. Each one holds
+//! the position in the input down to the bit, the position in the output, and the sliding
+//! window at that point. `Decoder::seek_to()` then goes on from the point nearest before an
+//! offset, and `IndexedDecoder` reads the stream from a `Read + Seek` source with `Seek`.
+//!
+//! An index is stored in the format below. Numbers are little-endian, and the points are in
+//! the format of `Checkpoint::as_bytes()`.
+//!
+//! | Bytes | Field                                          |
+//! |-------|------------------------------------------------|
+//! | 4     | `BRIX`                                         |
+//! | 1     | Version, 1                                     |
+//! | 8     | Size of the output                             |
+//! | 8     | Number of points                               |
+//! | ...   | For each point, its size in 8 bytes, and it    |
+
+use bitreader::{ BitReader, NoSource, StreamBitReader };
+use checkpoint::Checkpoint;
+use decoder::{ Decoder, Status };
+use std::io::{ self, ErrorKind, Read, Seek, SeekFrom, Write };
+use { BrotliError, Result };
+
+const MAGIC: &[u8; 4] = b"BRIX";
+const VERSION: u8 = 1;
+
+/// The size of the header, which the points follow.
+pub const HEADER_SIZE: usize = 4 + 1 + 8 + 8;
+
+/// The size of the length stored before each point.
+pub const POINT_LENGTH_SIZE: usize = 8;
+
+/// Resume points of a stream, built by `build_index()`. The first one is at the start of the
+/// stream, and they are in the order of their positions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Index {
+    output_size: u64,
+    points: Vec<Checkpoint>
+}
+
+/// Decodes the stream read from `reader`, and returns an index of it with a resume point about
+/// every `every_n_bytes` of output. The decoder can only stop where it needs more input or
+/// its ring buffer is full, so the points can be further apart; they are at most a window apart
+/// as long as `every_n_bytes` is smaller than the window.
+pub fn build_index<R: Read>( reader: R, every_n_bytes: u64 ) -> Result<Index> {
+    let mut decoder = Decoder::new( StreamBitReader::new( reader ));
+    let mut points = vec![decoder.checkpoint()];
+    let mut next = every_n_bytes;
+    let mut output = vec![0; 1 << 16];
+    loop {
+        let status = decoder.decode()?;
+        while decoder.write_output( &mut output ) > 0 {}
+
+        let position = decoder.output_position();
+        match status {
+            Status::Done => return Ok( Index { output_size: position, points: points } ),
+            Status::NeedsMoreInput if !decoder.get_mut().fetch_input()? => return Err( BrotliError::InsufficientData ),
+            _ => {}
+        }
+        if position >= next {
+            points.push( decoder.checkpoint() );
+            next = position + every_n_bytes;
+        }
+    }
+}
+
+impl Index {
+
+    /// The size of the decompressed stream.
+    pub fn output_size( &self ) -> u64 {
+        self.output_size
+    }
+
+    /// The resume points, in order.
+    pub fn points( &self ) -> &[Checkpoint] {
+        &self.points
+    }
+
+    /// The last resume point at or before `offset` in the output.
+    pub fn point_before( &self, offset: u64 ) -> &Checkpoint {
+        let after = self.points.partition_point( |point| point.output_offset() <= offset );
+        &self.points[after - 1]
+    }
+
+    /// Writes the index in its stored format.
+    pub fn write_to<W: Write>( &self, mut writer: W ) -> io::Result<()> {
+        writer.write_all( MAGIC )?;
+        writer.write_all( &[VERSION] )?;
+        writer.write_all( &self.output_size.to_le_bytes() )?;
+        writer.write_all( &( self.points.len() as u64 ).to_le_bytes() )?;
+        for point in &self.points {
+            writer.write_all( &( point.as_bytes().len() as u64 ).to_le_bytes() )?;
+            writer.write_all( point.as_bytes() )?;
+        }
+        Ok( () )
+    }
+
+    /// Reads an index written by `write_to()`. Fails with `BrotliError::InvalidIndex` if it isn't
+    /// one, or a point in it isn't a decoder state that can be resumed.
+    pub fn read_from<R: Read>( mut reader: R ) -> Result<Index> {
+        let mut header = [0; 5];
+        read_exact( &mut reader, &mut header )?;
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err( BrotliError::InvalidIndex )
+        }
+        let output_size = read_u64( &mut reader )?;
+        let count = read_u64( &mut reader )?;
+
+        // Each point is loaded into a decoder, to check its state before it is used to seek
+        let mut scratch = Decoder::new( StreamBitReader::new( NoSource ));
+        let mut points: Vec<Checkpoint> = Vec::new();
+        for _ in 0..count {
+            let len = read_u64( &mut reader )?;
+            let mut bytes = Vec::new();
+            ( &mut reader ).take( len ).read_to_end( &mut bytes )?;
+            if bytes.len() as u64 != len {
+                return Err( BrotliError::InvalidIndex )
+            }
+            let point = Checkpoint::from_bytes( bytes ).map_err( |_| BrotliError::InvalidIndex )?;
+
+            // The first point is at the start, and the others follow in order
+            let in_order = match points.last() {
+                Some( last ) => last.output_offset() <= point.output_offset() && last.bit_offset() <= point.bit_offset(),
+                None => point.output_offset() == 0 && point.bit_offset() == 0
+            };
+            if !in_order || point.output_offset() > output_size {
+                return Err( BrotliError::InvalidIndex )
+            }
+            scratch.restore( &point, StreamBitReader::new( NoSource )).map_err( |_| BrotliError::InvalidIndex )?;
+            points.push( point );
+        }
+        if points.is_empty() {
+            return Err( BrotliError::InvalidIndex )
+        }
+        Ok( Index { output_size: output_size, points: points } )
+    }
+}
+
+/// Fills `buf`, failing with `BrotliError::InvalidIndex` if the index ends first.
+fn read_exact<R: Read>( reader: &mut R, buf: &mut [u8] ) -> Result<()> {
+    reader.read_exact( buf ).map_err( |e| {
+        if e.kind() == ErrorKind::UnexpectedEof { BrotliError::InvalidIndex } else { BrotliError::from( e ) }
+    })
+}
+
+fn read_u64<R: Read>( reader: &mut R ) -> Result<u64> {
+    let mut bytes = [0; 8];
+    read_exact( reader, &mut bytes )?;
+    Ok( u64::from_le_bytes( bytes ))
+}
+
+/// Decodes a stream read from a `Read + Seek` source, with `Seek` over the output, using an
+/// index of the stream. A seek only moves the position; the decoder goes there with
+/// `Decoder::seek_to()` on the next read. Reads past the end of the output return nothing.
+pub struct IndexedDecoder<R: Read + Seek> {
+    decoder: Decoder<StreamBitReader<R>>,
+    index: Index,
+    position: u64
+}
+
+impl<R: Read + Seek> IndexedDecoder<R> {
+
+    /// Creates a decoder of the stream `index` was built from, which `reader` holds at its start.
+    pub fn new( mut reader: R, index: Index ) -> io::Result<IndexedDecoder<R>> {
+        reader.seek( SeekFrom::Start( 0 ))?;
+        Ok( IndexedDecoder { decoder: Decoder::new( StreamBitReader::new( reader )), index: index, position: 0 } )
+    }
+
+    /// Returns the index.
+    pub fn index( &self ) -> &Index {
+        &self.index
+    }
+
+    /// Returns the source.
+    pub fn into_inner( self ) -> R {
+        self.decoder.into_inner().into_inner()
+    }
+}
+
+impl<R: Read + Seek> Read for IndexedDecoder<R> {
+    fn read( &mut self, buf: &mut [u8] ) -> io::Result<usize> {
+        if self.position >= self.index.output_size() {
+            return Ok( 0 )
+        }
+        if self.decoder.output_position() != self.position {
+            self.decoder.seek_to( &self.index, self.position )?;
+        }
+        let count = self.decoder.read( buf )?;
+        self.position += count as u64;
+        Ok( count )
+    }
+}
+
+impl<R: Read + Seek> Seek for IndexedDecoder<R> {
+    fn seek( &mut self, pos: SeekFrom ) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start( offset ) => Some( offset ),
+            SeekFrom::End( offset ) => self.index.output_size().checked_add_signed( offset ),
+            SeekFrom::Current( offset ) => self.position.checked_add_signed( offset )
+        };
+        match position {
+            Some( position ) => {
+                self.position = position;
+                Ok( position )
+            }
+            None => Err( io::Error::new( ErrorKind::InvalidInput, "seek to a negative position" ))
+        }
+    }
+}
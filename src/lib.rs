@@ -11,19 +11,60 @@
 //! WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 //! See the License for the specific language governing permissions and
 //! limitations under the License.
+//!
+//! Without the default `std` feature, the crate builds against `core` and `alloc`. That leaves
+//! the decoder, which is then handed its input with `StreamBitReader::push()` instead of reading
+//! it, and the modules that don't need I/O. With a `SliceBitReader` and a `StaticAlloc`, the
+//! decoder doesn't use the heap at all.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+#![allow(clippy::redundant_field_names)]
+
+#[cfg(feature = "std")]
+extern crate core;
+extern crate alloc;
 
 mod context;            // complete
 pub mod transforms;     // complete & tested
+pub mod allocator;
 pub mod bitreader;      // complete
+pub mod checkpoint;
+#[cfg(feature = "std")]
+pub mod bitwriter;
 mod dictionary;         // complete
-mod prefix;             // complete
+pub mod prefix;         // complete
 mod huffman;
 pub mod decoder;
+#[cfg(feature = "std")]
+pub mod index;
+pub mod disasm;
+pub mod observer;
+#[cfg(feature = "std")]
+pub mod explain;
+#[cfg(feature = "std")]
+pub mod validate;
+pub mod probe;
+#[cfg(feature = "std")]
+pub mod ffi;
+#[cfg(feature = "encoder")]
+pub mod static_dict;
+#[cfg(feature = "encoder")]
+mod entropy_encode;
+#[cfg(feature = "encoder")]
+pub mod encoder;
+#[cfg(feature = "encoder")]
+pub mod asm;
+#[cfg(feature = "encoder")]
+pub mod seekable;
+#[cfg(feature = "encoder")]
+pub mod ffi_encoder;
 
+use core::fmt::{ Formatter, Display };
+use core::result;
+#[cfg(feature = "std")]
 use std::error::Error;
-use std::io::Error as IoError;
-use std::fmt::{ Formatter, Display };
-use std::result;
+#[cfg(feature = "std")]
+use std::io::{ Error as IoError, ErrorKind };
 
 #[derive(Debug)]
 pub enum BrotliError {
@@ -33,32 +74,78 @@ pub enum BrotliError {
     /// Returned if the stream appears to be truncated.
     InsufficientData,
 
+    /// Returned if the allocator of the decoder couldn't provide a buffer.
+    OutOfMemory,
+
+    /// Returned if the window of the stream is larger than the allocator of the decoder supports.
+    WindowTooLarge { window_bits: u32, max_window_bits: u32 },
+
+    /// Returned if a meta-block has more block types, or prefix codes in a context map, than the
+    /// allocator of the decoder supports.
+    TooManyTypes { num_types: usize, max_num_types: usize },
+
+    /// Returned if a decoder checkpoint is not well formed.
+    InvalidCheckpoint,
+
+    /// Returned if a stored index of a stream is not well formed.
+    InvalidIndex,
+
     /// Input/Output error during read
+    #[cfg(feature = "std")]
     Io( IoError )
 }
 
 pub type Result<T> = result::Result<T, BrotliError>;
 
+#[cfg(feature = "std")]
 impl Error for BrotliError {
-    /// A short description of the error.
-    fn description(&self) -> &str {
-        unimplemented!();
-    }
-
     /// The lower level cause of this error, if any.
-    fn cause(&self) -> Option<&Error> { 
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
         if let BrotliError::Io( ref e ) = *self { Some(e) } else { None }
     }
 }
 
 impl Display for BrotliError {
-    fn fmt(&self, _f: &mut Formatter) -> std::fmt::Result { 
-        unimplemented!();
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match *self {
+            BrotliError::InvalidEncoding => f.write_str( "invalid Brotli stream" ),
+            BrotliError::InsufficientData => f.write_str( "truncated Brotli stream" ),
+            BrotliError::OutOfMemory => f.write_str( "out of memory" ),
+            BrotliError::WindowTooLarge { window_bits, max_window_bits } =>
+                write!( f, "window of 2^{} bytes is larger than the decoder's limit of 2^{}", window_bits, max_window_bits ),
+            BrotliError::TooManyTypes { num_types, max_num_types } =>
+                write!( f, "{} block types or prefix codes are more than the decoder's limit of {}", num_types, max_num_types ),
+            BrotliError::InvalidCheckpoint => f.write_str( "invalid decoder checkpoint" ),
+            BrotliError::InvalidIndex => f.write_str( "invalid stream index" ),
+            #[cfg(feature = "std")]
+            BrotliError::Io( ref e ) => Display::fmt( e, f )
+        }
     }
 }
 
+#[cfg(feature = "std")]
 impl From<IoError> for BrotliError {
+    /// Errors of the decoder, wrapped in an `io::Error` by its `Read` implementation, are
+    /// unwrapped again.
     fn from(err: IoError) -> BrotliError {
-        BrotliError::Io( err )
+        if err.get_ref().is_some_and( |inner| inner.is::<BrotliError>() ) {
+            *err.into_inner().unwrap().downcast::<BrotliError>().unwrap()
+        } else {
+            BrotliError::Io( err )
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<BrotliError> for IoError {
+    fn from(err: BrotliError) -> IoError {
+        match err {
+            BrotliError::Io( e ) => e,
+            BrotliError::InsufficientData => IoError::new( ErrorKind::UnexpectedEof, err ),
+            BrotliError::InvalidEncoding => IoError::new( ErrorKind::InvalidData, err ),
+            BrotliError::OutOfMemory => IoError::new( ErrorKind::OutOfMemory, err ),
+            BrotliError::WindowTooLarge { .. } | BrotliError::TooManyTypes { .. } => IoError::new( ErrorKind::Unsupported, err ),
+            BrotliError::InvalidCheckpoint | BrotliError::InvalidIndex => IoError::new( ErrorKind::InvalidData, err )
+        }
     }
 }
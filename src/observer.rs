@@ -0,0 +1,133 @@
+//! # Command observer
+//!
+//! A `CommandObserver` attached to a `Decoder` is told how each part of the output was coded:
+//! as literals, as a copy of earlier output, or as a word of the static dictionary. Together
+//! with the meta-block headers, whose uncompressed meta-blocks are copied to the output as they
+//! are, the events account for every output byte, in order.
+//!
+//! An event is reported once all the input it was read from has been decoded, so it is never
+//! reported twice when the decoder waits for more input. It may be reported before the output
+//! it describes has been read from the decoder. Block switches are reported as they are read,
+//! so one in the middle of a run of literals comes before the run.
+//!
+//! All methods do nothing by default. A decoder without an observer uses `NoObserver`, whose
+//! calls compile to nothing. An observer can also ask for every instruction of the stream, as
+//! `Disassembler` does. The decoder only collects them for observers that do.
+
+use disasm::{ Category, Entry, MetaBlockKind };
+
+/// The header of a meta-block.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MetaBlockHeader {
+    pub is_last: bool,
+
+    /// The number of output bytes, or the size of the payload of a metadata meta-block.
+    pub length: usize,
+    pub kind: MetaBlockKind
+}
+
+/// Receives the commands decoded by a `Decoder`.
+pub trait CommandObserver {
+
+    /// A literal was read from a prefix code word `bits` bits long. Reported for each literal
+    /// of a run, before the run itself.
+    #[inline(always)]
+    fn literal_cost( &mut self, bits: u32 ) {
+        let _ = bits;
+    }
+
+    /// `run` literals were inserted.
+    #[inline(always)]
+    fn literal( &mut self, run: usize ) {
+        let _ = run;
+    }
+
+    /// `length` bytes were copied from `distance` bytes back.
+    #[inline(always)]
+    fn copy( &mut self, length: usize, distance: usize ) {
+        let _ = ( length, distance );
+    }
+
+    /// `length` bytes were written from a word of the static dictionary. `word_id` is the
+    /// position of the word in the dictionary, which holds the words in order of length, and
+    /// `transform_id` is its index in `transforms::TRANSFORMS`.
+    #[inline(always)]
+    fn dictionary( &mut self, length: usize, word_id: usize, transform_id: usize ) {
+        let _ = ( length, word_id, transform_id );
+    }
+
+    /// The block type of a category changed.
+    #[inline(always)]
+    fn block_switch( &mut self, category: Category, block_type: usize ) {
+        let _ = ( category, block_type );
+    }
+
+    /// A meta-block starts.
+    #[inline(always)]
+    fn meta_block( &mut self, header: &MetaBlockHeader ) {
+        let _ = header;
+    }
+
+    /// Returns `true` if the observer is handed every instruction of the stream with
+    /// `instruction()`. Collecting them slows the decoder down, so it is off by default.
+    #[inline(always)]
+    fn lists_instructions( &self ) -> bool {
+        false
+    }
+
+    /// An instruction was read, if `lists_instructions()` returns `true`. Instructions come in
+    /// the order of the disassembler's listing.
+    #[inline(always)]
+    fn instruction( &mut self, entry: Entry ) {
+        let _ = entry;
+    }
+}
+
+/// The observer of a decoder that isn't observed.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NoObserver;
+
+impl CommandObserver for NoObserver {}
+
+impl<T: CommandObserver + ?Sized> CommandObserver for &mut T {
+
+    #[inline(always)]
+    fn literal_cost( &mut self, bits: u32 ) {
+        ( **self ).literal_cost( bits )
+    }
+
+    #[inline(always)]
+    fn literal( &mut self, run: usize ) {
+        ( **self ).literal( run )
+    }
+
+    #[inline(always)]
+    fn copy( &mut self, length: usize, distance: usize ) {
+        ( **self ).copy( length, distance )
+    }
+
+    #[inline(always)]
+    fn dictionary( &mut self, length: usize, word_id: usize, transform_id: usize ) {
+        ( **self ).dictionary( length, word_id, transform_id )
+    }
+
+    #[inline(always)]
+    fn block_switch( &mut self, category: Category, block_type: usize ) {
+        ( **self ).block_switch( category, block_type )
+    }
+
+    #[inline(always)]
+    fn meta_block( &mut self, header: &MetaBlockHeader ) {
+        ( **self ).meta_block( header )
+    }
+
+    #[inline(always)]
+    fn lists_instructions( &self ) -> bool {
+        ( **self ).lists_instructions()
+    }
+
+    #[inline(always)]
+    fn instruction( &mut self, entry: Entry ) {
+        ( **self ).instruction( entry )
+    }
+}
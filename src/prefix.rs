@@ -1,11 +1,11 @@
 // Represents the range of values belonging to a prefix code: [offset, offset + 2^nbits)
 #[derive(Debug, Copy, Clone)]
 pub struct Range {
-    offset: u16,
-    nbits: u8
+    pub offset: u16,
+    pub nbits: u8
 }
 
-pub const PREFIX_CODE_RANGE: [Range;26] = [
+pub static PREFIX_CODE_RANGE: [Range;26] = [
     Range { offset:    1,  nbits:  2}, Range { offset:     5,  nbits: 2},  Range { offset:    9,  nbits: 2},  Range { offset:   13,  nbits: 2},
     Range { offset:   17,  nbits:  3}, Range { offset:    25,  nbits: 3},  Range { offset:   33,  nbits: 3},  Range { offset:   41,  nbits: 3},
     Range { offset:   49,  nbits:  4}, Range { offset:    65,  nbits: 4},  Range { offset:   81,  nbits: 4},  Range { offset:   97,  nbits: 4},
@@ -15,14 +15,47 @@ pub const PREFIX_CODE_RANGE: [Range;26] = [
     Range { offset: 8433,  nbits: 13}, Range { offset: 16625,  nbits: 24}
 ];
 
+/// Finds the prefix code of a block count: `(code, extra bits, extra value)`.
+pub fn block_length_code( length: usize ) -> (usize, u32, u32) {
+    debug_assert!( length >= 1 && length < PREFIX_CODE_RANGE[25].offset as usize + ( 1 << PREFIX_CODE_RANGE[25].nbits ));
+    let code = PREFIX_CODE_RANGE.partition_point( |range| range.offset as usize <= length ) - 1;
+    let range = PREFIX_CODE_RANGE[code];
+    (code, range.nbits as u32, ( length - range.offset as usize ) as u32)
+}
+
+/// The number of distance codes that refer to the last distances.
+pub const NUM_DISTANCE_SHORT_CODES: usize = 16;
+
+/// The last distance that each short distance code refers to, and the offset added to it.
+pub const DISTANCE_SHORT_CODE_INDEX: [usize; NUM_DISTANCE_SHORT_CODES] = [0, 1, 2, 3, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1];
+pub const DISTANCE_SHORT_CODE_OFFSET: [isize; NUM_DISTANCE_SHORT_CODES] = [0, 0, 0, 0, -1, 1, -2, 2, -3, 3, -1, 1, -2, 2, -3, 3];
+
+/// Finds the explicit distance code of `distance`, with the given distance parameters:
+/// `(code, extra bits, extra value)`.
+pub fn distance_code( distance: usize, postfix_bits: u32, num_direct: usize ) -> (usize, u32, u32) {
+    if distance <= num_direct {
+        return (NUM_DISTANCE_SHORT_CODES + distance - 1, 0, 0)
+    }
+
+    let postfix_bits = postfix_bits as usize;
+    let dist = ( 1 << ( postfix_bits + 2 )) + distance - 1 - num_direct;
+    let bucket = dist.ilog2() as usize - 1;
+    let postfix = dist & (( 1 << postfix_bits ) - 1 );
+    let prefix = ( dist >> bucket ) & 1;
+    let offset = ( 2 + prefix ) << bucket;
+    let nbits = bucket - postfix_bits;
+    let code = NUM_DISTANCE_SHORT_CODES + num_direct + (( 2 * ( nbits - 1 ) + prefix ) << postfix_bits ) + postfix;
+    (code, nbits as u32, ( ( dist - offset ) >> postfix_bits ) as u32)
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct CmdLutElement {
-    insert_len_extra_bits: u8,
-    copy_len_extra_bits: u8,
-    distance_code: bool,
-    context: u8,
-    insert_len_offset: u16,
-    copy_len_offset: u16
+    pub insert_len_extra_bits: u8,
+    pub copy_len_extra_bits: u8,
+    pub distance_code: bool,
+    pub context: u8,
+    pub insert_len_offset: u16,
+    pub copy_len_offset: u16
 }
 
 // Convenience macro to reduce the amount of boilerplate for the constant lookup table below
@@ -42,7 +75,7 @@ macro_rules! lut {
     } )
 }
 
-pub const CMD_LUT: [CmdLutElement;704] = [
+pub static CMD_LUT: [CmdLutElement;704] = [
     lut!( 0x00, 0x00, false, 0x00, 0x0000, 0x0002 ),
     lut!( 0x00, 0x00, false, 0x01, 0x0000, 0x0003 ),
     lut!( 0x00, 0x00, false, 0x02, 0x0000, 0x0004 ),
@@ -747,4 +780,118 @@ pub const CMD_LUT: [CmdLutElement;704] = [
     lut!( 0x18, 0x09, true, 0x03, 0x5842, 0x0246 ),
     lut!( 0x18, 0x0a, true, 0x03, 0x5842, 0x0446 ),
     lut!( 0x18, 0x18, true, 0x03, 0x5842, 0x0846 )
-];
\ No newline at end of file
+];
+
+/// Finds the insert length code of `insert_length`, the high bits of an insert-and-copy
+/// command code.
+pub fn insert_length_code( insert_length: usize ) -> usize {
+    if insert_length < 6 {
+        insert_length
+    } else if insert_length < 130 {
+        let nbits = ( insert_length - 2 ).ilog2() as usize - 1;
+        ( nbits << 1 ) + ( ( insert_length - 2 ) >> nbits ) + 2
+    } else if insert_length < 2114 {
+        ( insert_length - 66 ).ilog2() as usize + 10
+    } else if insert_length < 6210 {
+        21
+    } else if insert_length < 22594 {
+        22
+    } else {
+        23
+    }
+}
+
+/// Finds the copy length code of `copy_length`, which is at least 2.
+pub fn copy_length_code( copy_length: usize ) -> usize {
+    if copy_length < 10 {
+        copy_length - 2
+    } else if copy_length < 134 {
+        let nbits = ( copy_length - 6 ).ilog2() as usize - 1;
+        ( nbits << 1 ) + ( ( copy_length - 6 ) >> nbits ) + 4
+    } else if copy_length < 2118 {
+        ( copy_length - 70 ).ilog2() as usize + 12
+    } else {
+        23
+    }
+}
+
+/// Combines the insert and copy length codes into an insert-and-copy command code. The
+/// distance is implied to be the last distance only if `use_last_distance` is set and the
+/// codes are small enough.
+pub fn command_code( insert_length: usize, copy_length: usize, use_last_distance: bool ) -> u16 {
+    let insert_code = insert_length_code( insert_length );
+    let copy_code = copy_length_code( copy_length );
+    let low = ( copy_code & 7 ) | ( ( insert_code & 7 ) << 3 );
+    if use_last_distance && insert_code < 8 && copy_code < 16 {
+        return ( if copy_code < 8 { low } else { low | 64 } ) as u16
+    }
+
+    // Cells of 64 codes, laid out by the high bits of the insert and copy codes
+    let offset = 2 * ( ( copy_code >> 3 ) + 3 * ( insert_code >> 3 ));
+    let offset = ( offset << 5 ) + 0x40 + ( ( 0x520D40 >> offset ) & 0xC0 );
+    ( offset | low ) as u16
+}
+
+#[test]
+fn test_block_length_code() {
+    let last = PREFIX_CODE_RANGE[25].offset as usize + ( 1 << PREFIX_CODE_RANGE[25].nbits ) - 1;
+    for length in 1 ..= last {
+        let (code, nbits, extra) = block_length_code( length );
+        let range = PREFIX_CODE_RANGE[code];
+        assert_eq!( nbits, range.nbits as u32 );
+        assert!( extra >> nbits == 0 );
+        assert_eq!( range.offset as usize + extra as usize, length );
+    }
+}
+
+#[test]
+fn test_command_code() {
+    // Every insert and copy length range of the table, by length code
+    let mut insert_ranges = [(0, 0); 24];
+    let mut copy_ranges = [(0, 0); 24];
+    for lut in CMD_LUT.iter() {
+        let insert = (lut.insert_len_offset as usize, 1usize << lut.insert_len_extra_bits);
+        let copy = (lut.copy_len_offset as usize, 1usize << lut.copy_len_extra_bits);
+        insert_ranges[insert_length_code( insert.0 )] = insert;
+        copy_ranges[copy_length_code( copy.0 )] = copy;
+    }
+
+    // Length codes are consistent with the table for every length in a range, up to the longest,
+    // and the ranges follow each other
+    let mut next = 0;
+    for (code, &(offset, size)) in insert_ranges.iter().enumerate() {
+        assert_eq!( offset, next );
+        for length in offset .. offset + size {
+            assert_eq!( insert_length_code( length ), code );
+        }
+        next = offset + size;
+    }
+    let mut next = 2;
+    for (code, &(offset, size)) in copy_ranges.iter().enumerate() {
+        assert_eq!( offset, next );
+        for length in offset .. offset + size {
+            assert_eq!( copy_length_code( length ), code );
+        }
+        next = offset + size;
+    }
+
+    // Every combination of length codes maps to a command code that decodes to the same lengths
+    let mut seen = [false; 704];
+    for &(insert_offset, insert_size) in insert_ranges.iter() {
+        for &(copy_offset, copy_size) in copy_ranges.iter() {
+            for &use_last_distance in &[false, true] {
+                for &(insert_length, copy_length) in &[(insert_offset, copy_offset), (insert_offset + insert_size - 1, copy_offset + copy_size - 1)] {
+                    let code = command_code( insert_length, copy_length, use_last_distance ) as usize;
+                    let lut = &CMD_LUT[code];
+                    assert!( insert_length >= lut.insert_len_offset as usize && copy_length >= lut.copy_len_offset as usize );
+                    assert!( ( insert_length - lut.insert_len_offset as usize ) >> lut.insert_len_extra_bits == 0 );
+                    assert!( ( copy_length - lut.copy_len_offset as usize ) >> lut.copy_len_extra_bits == 0 );
+                    let implied = use_last_distance && insert_length_code( insert_length ) < 8 && copy_length_code( copy_length ) < 16;
+                    assert_eq!( lut.distance_code, !implied );
+                    seen[code] = true;
+                }
+            }
+        }
+    }
+    assert!( seen.iter().all( |&seen| seen ));
+}
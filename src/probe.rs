@@ -0,0 +1,57 @@
+//! # Stream probe
+//!
+//! Reads only the stream header and the first meta-block header, to tell the window size and
+//! the memory decoding the stream takes before committing to it. Nothing is decoded, so unlike
+//! `validate()` a probe doesn't tell whether the rest of the stream is valid.
+
+use bitreader::{ NoSource, StreamBitReader };
+use decoder::Decoder;
+use disasm::MetaBlockKind;
+use observer::MetaBlockHeader;
+use Result;
+
+/// Input of this many bytes always holds the stream header and the first meta-block header:
+/// at most 7 bits of window size, and 33 bits of meta-block header up to the byte boundary
+/// that precedes the data of an uncompressed or metadata meta-block.
+pub const MIN_PROBE_BYTES: usize = 5;
+
+/// What the start of a stream tells about it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ProbeInfo {
+    /// The base 2 logarithm of the window size.
+    pub window_bits: u32,
+
+    /// The header of the first meta-block. Its `length` is the MLEN of the meta-block.
+    pub first_meta_block: MetaBlockHeader,
+
+    /// The size of the ring buffer the decoder allocates, which holds the window and dominates
+    /// its memory use. After a metadata meta-block, the size is only known not to exceed the
+    /// window size, which is given.
+    pub memory: usize
+}
+
+impl ProbeInfo {
+
+    /// Returns `true` for the trivial empty stream, whose first meta-block is its last.
+    pub fn is_empty( &self ) -> bool {
+        self.first_meta_block.kind == MetaBlockKind::Empty
+    }
+}
+
+/// Reads the start of the stream in `bytes`. Returns `InsufficientData` if they end before the
+/// first meta-block header does, which can't happen given at least `MIN_PROBE_BYTES`, and
+/// `InvalidEncoding` if the headers are invalid.
+pub fn probe( bytes: &[u8] ) -> Result<ProbeInfo> {
+    let mut br = StreamBitReader::new( NoSource );
+    br.push( &bytes[..bytes.len().min( MIN_PROBE_BYTES )] );
+
+    let window_bits = <Decoder<StreamBitReader<NoSource>>>::decode_window_bits( &mut br )?;
+    let header = <Decoder<StreamBitReader<NoSource>>>::read_meta_block_header( &mut br, &mut None )?;
+    let memory = match header.kind {
+        MetaBlockKind::Empty => 0,
+        MetaBlockKind::Metadata => 1 << window_bits,
+        _ => <Decoder<StreamBitReader<NoSource>>>::ring_buffer_size( window_bits, &header, 0 )
+    };
+
+    Ok( ProbeInfo { window_bits: window_bits, first_meta_block: header, memory: memory } )
+}
@@ -0,0 +1,342 @@
+//! # Seekable streams
+//!
+//! A seekable stream is a Brotli stream that can be read from any position without decoding
+//! it from the start, or keeping snapshots of the window as an `Index` does. It is made of
+//! frames, each holding up to a fixed number of bytes of output. A frame is compressed on its
+//! own, like a chunk of `compress_parallel` with `ChunkWindow::Independent`: it never refers to
+//! the output of earlier frames, and its meta-blocks end on a byte boundary, so that decoding
+//! can start at any frame. The first frame starts with the stream header.
+//!
+//! The frames are followed by a seek table, stored in a metadata meta-block, and the empty last
+//! meta-block. A seekable stream is still a single valid Brotli stream, which any decoder can
+//! decode as a whole, skipping the seek table. The payload of the metadata meta-block is, with
+//! little-endian numbers:
+//!
+//! | Bytes | Field                                                      |
+//! |-------|------------------------------------------------------------|
+//! | 16    | For each frame, its compressed size and its output size   |
+//! | 8     | Number of frames                                           |
+//! | 1     | Base 2 logarithm of the window size                        |
+//! | 1     | Version, 1                                                 |
+//! | 4     | `BRSK`                                                     |
+//!
+//! The stream ends right after the payload, with the byte of the empty last meta-block, so the
+//! seek table is found from the end of the stream. A stream always has at least one frame,
+//! which is empty if there is no output.
+
+use bitreader::StreamBitReader;
+use decoder::Decoder;
+use encoder::{ compress_independent, metadata_end, EncoderOptions, MAX_METADATA_LENGTH };
+use std::cmp::{ max, min };
+use std::io::{ self, ErrorKind, Read, Seek, SeekFrom, Write };
+use { BrotliError, Result };
+
+const MAGIC: &[u8; 4] = b"BRSK";
+const VERSION: u8 = 1;
+const FRAME_ENTRY_SIZE: usize = 16;
+const FOOTER_SIZE: usize = 8 + 1 + 1 + 4;
+
+/// The byte of the empty last meta-block that ends a seekable stream.
+const LAST_META_BLOCK: u8 = 0b11;
+
+/// The most frames a seek table holds, as it has to fit in one metadata meta-block.
+const MAX_FRAMES: usize = ( MAX_METADATA_LENGTH - FOOTER_SIZE ) / FRAME_ENTRY_SIZE;
+
+/// A frame of a seekable stream.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Position of the frame in the compressed stream.
+    pub compressed_offset: u64,
+    pub compressed_size: u64,
+
+    /// Position of the output of the frame in the decompressed stream.
+    pub output_offset: u64,
+    pub output_size: u64
+}
+
+/// The frames of a seekable stream, as stored in its seek table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeekTable {
+    window_bits: u32,
+    frames: Vec<Frame>
+}
+
+impl SeekTable {
+
+    /// Reads the seek table at the end of a seekable stream, which `reader` holds at its start.
+    /// Fails with `BrotliError::InvalidIndex` if there isn't one.
+    pub fn read_from<R: Read + Seek>( reader: &mut R ) -> Result<SeekTable> {
+        let len = reader.seek( SeekFrom::End( 0 ))?;
+        if len < ( FOOTER_SIZE + 1 ) as u64 {
+            return Err( BrotliError::InvalidIndex )
+        }
+        let mut footer = [0; FOOTER_SIZE + 1];
+        reader.seek( SeekFrom::End( -( footer.len() as i64 )))?;
+        reader.read_exact( &mut footer )?;
+        if &footer[10..14] != MAGIC || footer[9] != VERSION || footer[14] != LAST_META_BLOCK {
+            return Err( BrotliError::InvalidIndex )
+        }
+        let count = read_u64( &footer[..8] );
+        let window_bits = footer[8] as u32;
+        if count == 0 || count > MAX_FRAMES as u64 || !( 10 ..= 24 ).contains( &window_bits ) {
+            return Err( BrotliError::InvalidIndex )
+        }
+
+        // The payload follows the header of its metadata meta-block, which takes a byte more
+        // than the bytes of its length
+        let payload_size = count as usize * FRAME_ENTRY_SIZE + FOOTER_SIZE;
+        let header_size = ( usize::BITS - ( payload_size - 1 ).leading_zeros() ).div_ceil( 8 ) as usize + 1;
+        let table_offset = match len.checked_sub(( header_size + payload_size + 1 ) as u64 ) {
+            Some( offset ) => offset,
+            None => return Err( BrotliError::InvalidIndex )
+        };
+        let mut entries = vec![0; count as usize * FRAME_ENTRY_SIZE];
+        reader.seek( SeekFrom::Start( table_offset + header_size as u64 ))?;
+        reader.read_exact( &mut entries )?;
+
+        let mut frames = Vec::with_capacity( count as usize );
+        let (mut compressed_offset, mut output_offset) = (0u64, 0u64);
+        for entry in entries.chunks( FRAME_ENTRY_SIZE ) {
+            let frame = Frame {
+                compressed_offset: compressed_offset,
+                compressed_size: read_u64( &entry[..8] ),
+                output_offset: output_offset,
+                output_size: read_u64( &entry[8..] )
+            };
+            compressed_offset = compressed_offset.checked_add( frame.compressed_size ).ok_or( BrotliError::InvalidIndex )?;
+            output_offset = output_offset.checked_add( frame.output_size ).ok_or( BrotliError::InvalidIndex )?;
+            frames.push( frame );
+        }
+        if compressed_offset != table_offset {
+            return Err( BrotliError::InvalidIndex )
+        }
+        Ok( SeekTable { window_bits: window_bits, frames: frames } )
+    }
+
+    /// The base 2 logarithm of the window size of the stream.
+    pub fn window_bits( &self ) -> u32 {
+        self.window_bits
+    }
+
+    /// The frames, in order.
+    pub fn frames( &self ) -> &[Frame] {
+        &self.frames
+    }
+
+    /// The size of the decompressed stream.
+    pub fn output_size( &self ) -> u64 {
+        let last = self.frames[self.frames.len() - 1];
+        last.output_offset + last.output_size
+    }
+
+    /// The frame holding `offset` in the output, or the last one if it is past the end.
+    pub fn frame_at( &self, offset: u64 ) -> &Frame {
+        let after = self.frames.partition_point( |frame| frame.output_offset <= offset );
+        &self.frames[max( after, 1 ) - 1]
+    }
+
+    /// The seek table as stored in the stream.
+    fn to_bytes( &self ) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity( self.frames.len() * FRAME_ENTRY_SIZE + FOOTER_SIZE );
+        for frame in &self.frames {
+            bytes.extend_from_slice( &frame.compressed_size.to_le_bytes() );
+            bytes.extend_from_slice( &frame.output_size.to_le_bytes() );
+        }
+        bytes.extend_from_slice( &( self.frames.len() as u64 ).to_le_bytes() );
+        bytes.push( self.window_bits as u8 );
+        bytes.push( VERSION );
+        bytes.extend_from_slice( MAGIC );
+        bytes
+    }
+}
+
+fn read_u64( bytes: &[u8] ) -> u64 {
+    let mut value = [0; 8];
+    value.copy_from_slice( &bytes[..8] );
+    u64::from_le_bytes( value )
+}
+
+/// Compresses data written to it into a seekable stream, and writes it to the wrapped writer.
+///
+/// Input is buffered until a frame is complete, and each frame is compressed on its own.
+/// `flush()` ends the current frame early, so that the receiver can decode everything written
+/// so far. The stream must be ended with `finish()`, which writes the seek table and returns
+/// the wrapped writer. If it isn't, the stream is ended when the `SeekableWriter` is dropped,
+/// and any errors are ignored.
+pub struct SeekableWriter<W: Write> {
+    options: EncoderOptions,
+    frame_size: usize,
+
+    // The input of the current frame
+    frame: Vec<u8>,
+    table: SeekTable,
+
+    // Only `None` once the stream is finished
+    writer: Option<W>
+}
+
+impl<W: Write> SeekableWriter<W> {
+
+    /// Creates a writer of frames of `frame_size` bytes of output, at least 1.
+    pub fn new( writer: W, options: &EncoderOptions, frame_size: usize ) -> SeekableWriter<W> {
+        let options = options.clamped();
+        SeekableWriter {
+            options: options,
+            frame_size: max( frame_size, 1 ),
+            frame: Vec::new(),
+            table: SeekTable { window_bits: options.window_bits, frames: Vec::new() },
+            writer: Some( writer )
+        }
+    }
+
+    /// Returns a reference to the wrapped writer.
+    pub fn get_ref( &self ) -> &W {
+        self.writer.as_ref().unwrap()
+    }
+
+    /// Returns a mutable reference to the wrapped writer. Writing to it corrupts the stream.
+    pub fn get_mut( &mut self ) -> &mut W {
+        self.writer.as_mut().unwrap()
+    }
+
+    /// The frames written so far.
+    pub fn frames( &self ) -> &[Frame] {
+        self.table.frames()
+    }
+
+    /// Compresses the remaining input, writes the seek table, and returns the wrapped writer.
+    pub fn finish( mut self ) -> io::Result<W> {
+        self.finish_stream()?;
+        Ok( self.writer.take().unwrap() )
+    }
+
+    fn finish_stream( &mut self ) -> io::Result<()> {
+        if !self.frame.is_empty() || self.table.frames.is_empty() {
+            self.write_frame()?;
+        }
+        let end = metadata_end( &self.table.to_bytes() );
+        let writer = self.get_mut();
+        writer.write_all( &end )?;
+        writer.flush()
+    }
+
+    /// Compresses the current frame, and writes it out.
+    fn write_frame( &mut self ) -> io::Result<()> {
+        if self.table.frames.len() == MAX_FRAMES {
+            return Err( io::Error::other( "too many frames for the seek table" ))
+        }
+        let (compressed_offset, output_offset) = match self.table.frames.last() {
+            Some( last ) => (last.compressed_offset + last.compressed_size, last.output_offset + last.output_size),
+            None => (0, 0)
+        };
+        let compressed = compress_independent( &self.frame, output_offset as usize, &self.options );
+        self.get_mut().write_all( &compressed )?;
+        self.table.frames.push( Frame {
+            compressed_offset: compressed_offset,
+            compressed_size: compressed.len() as u64,
+            output_offset: output_offset,
+            output_size: self.frame.len() as u64
+        });
+        self.frame.clear();
+        Ok( () )
+    }
+}
+
+impl<W: Write> Write for SeekableWriter<W> {
+    fn write( &mut self, buf: &[u8] ) -> io::Result<usize> {
+        // A full frame is written first, so that an error doesn't lose any input
+        if self.frame.len() == self.frame_size {
+            self.write_frame()?;
+        }
+        let count = min( buf.len(), self.frame_size - self.frame.len() );
+        self.frame.extend_from_slice( &buf[..count] );
+        Ok( count )
+    }
+
+    fn flush( &mut self ) -> io::Result<()> {
+        if !self.frame.is_empty() {
+            self.write_frame()?;
+        }
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for SeekableWriter<W> {
+    fn drop( &mut self ) {
+        if self.writer.is_some() {
+            let _ = self.finish_stream();
+        }
+    }
+}
+
+/// Decodes a seekable stream read from a `Read + Seek` source, with `Seek` over the output. A
+/// seek only moves the position; on the next read, decoding starts over at the frame holding
+/// it, unless it is further on in the current frame. Reads past the end of the output return
+/// nothing.
+pub struct SeekableReader<R: Read + Seek> {
+    decoder: Decoder<StreamBitReader<R>>,
+    table: SeekTable,
+    position: u64
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+
+    /// Creates a reader of the seekable stream `reader` holds at its start. Fails with
+    /// `ErrorKind::InvalidData` if it has no seek table.
+    pub fn new( mut reader: R ) -> io::Result<SeekableReader<R>> {
+        let table = SeekTable::read_from( &mut reader )?;
+        reader.seek( SeekFrom::Start( 0 ))?;
+        Ok( SeekableReader { decoder: Decoder::new( StreamBitReader::new( reader )), table: table, position: 0 } )
+    }
+
+    /// Returns the seek table.
+    pub fn seek_table( &self ) -> &SeekTable {
+        &self.table
+    }
+
+    /// Returns the source.
+    pub fn into_inner( self ) -> R {
+        self.decoder.into_inner().into_inner()
+    }
+
+    /// Moves the decoder to the position.
+    fn seek_decoder( &mut self ) -> Result<()> {
+        let current = self.decoder.output_position();
+        let frame = *self.table.frame_at( self.position );
+        if self.position < current || current < frame.output_offset {
+            self.decoder.seek_to_frame( frame.compressed_offset, frame.output_offset, self.table.window_bits() )?;
+        }
+        self.decoder.skip_output( self.position - self.decoder.output_position() )
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableReader<R> {
+    fn read( &mut self, buf: &mut [u8] ) -> io::Result<usize> {
+        if self.position >= self.table.output_size() {
+            return Ok( 0 )
+        }
+        if self.decoder.output_position() != self.position {
+            self.seek_decoder()?;
+        }
+        let count = self.decoder.read( buf )?;
+        self.position += count as u64;
+        Ok( count )
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableReader<R> {
+    fn seek( &mut self, pos: SeekFrom ) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start( offset ) => Some( offset ),
+            SeekFrom::End( offset ) => self.table.output_size().checked_add_signed( offset ),
+            SeekFrom::Current( offset ) => self.position.checked_add_signed( offset )
+        };
+        match position {
+            Some( position ) => {
+                self.position = position;
+                Ok( position )
+            }
+            None => Err( io::Error::new( ErrorKind::InvalidInput, "seek to a negative position" ))
+        }
+    }
+}
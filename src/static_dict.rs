@@ -0,0 +1,318 @@
+//! # Static dictionary matching
+//!
+//! A reverse index over the built-in dictionary words and the 121 word transforms, used by the
+//! encoder to replace input with references to transformed dictionary words.
+//!
+//! A transformed word consists of a prefix, a *body* derived from the word, and a suffix. Rather
+//! than indexing every one of the ~1.6 million transformed words, only the distinct bodies are
+//! indexed (the word itself, its uppercased forms and the words with the first few bytes
+//! omitted), keyed by their first four bytes. Prefixes and suffixes are checked at match time.
+//! Bodies produced by the `OmitLast` transforms are prefixes of the word, so they are found
+//! through the entries of the word itself.
+//!
+//! The index is built on first use, and then shared by all encoders.
+
+use std::sync::OnceLock;
+use dictionary::{ self, NDBITS, MIN_WORD_LENGTH, MAX_WORD_LENGTH };
+use transforms::{ Transform, TRANSFORMS, WordTransformType };
+
+/// Bodies shorter than this aren't indexed, because they can't be looked up by a 4 byte key.
+const MIN_BODY_LENGTH: usize = 4;
+
+/// A match between the input and a transformed dictionary word.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DictionaryMatch {
+    /// The number of input bytes covered by the transformed word.
+    pub len: usize,
+
+    /// The length of the dictionary word, which is the copy length used to reference it.
+    pub word_len: usize,
+
+    /// The index of the word among the dictionary words of the same length.
+    pub word_index: usize,
+
+    /// The index of the transform in `TRANSFORMS`.
+    pub transform: usize
+}
+
+impl DictionaryMatch {
+
+    /// The distance that references this word, given the maximum backward distance at the
+    /// position of the match (the smaller of the window size less 16 and the number of bytes
+    /// output so far).
+    pub fn distance( &self, max_distance: usize ) -> usize {
+        max_distance + 1 + ((self.transform << NDBITS[self.word_len]) | self.word_index)
+    }
+
+    /// A rough estimate of the number of bits needed to encode this match as a command with
+    /// a distance code, assuming no postfix bits or direct distance codes. Compare against the
+    /// cost of the literals it replaces.
+    pub fn cost( &self, max_distance: usize ) -> usize {
+        const COMMAND_BITS: usize = 8;
+        const DISTANCE_CODE_BITS: usize = 6;
+
+        let distance = self.distance( max_distance ) + 3;
+        let extra_bits = (0usize.leading_zeros() - distance.leading_zeros()) as usize - 2;
+        COMMAND_BITS + DISTANCE_CODE_BITS + extra_bits
+    }
+}
+
+/// How the body of a transformed word is derived from the dictionary word.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum BodyKind {
+    /// The word as-is, also used for the `Identity` and `OmitLast` transforms.
+    Plain,
+    UppercaseFirst,
+    UppercaseAll,
+    OmitFirst(u8)
+}
+
+impl BodyKind {
+    fn of( transform: WordTransformType ) -> BodyKind {
+        match transform {
+            WordTransformType::Identity | WordTransformType::OmitLast(_) => BodyKind::Plain,
+            WordTransformType::UppercaseFirst => BodyKind::UppercaseFirst,
+            WordTransformType::UppercaseAll => BodyKind::UppercaseAll,
+            WordTransformType::OmitFirst(n) => BodyKind::OmitFirst(n)
+        }
+    }
+
+    /// Writes the body for `word` into `dst`, and returns its length.
+    fn body( self, word: &[u8], dst: &mut [u8; MAX_WORD_LENGTH] ) -> usize {
+        match self {
+            BodyKind::Plain => {
+                dst[..word.len()].copy_from_slice( word );
+                word.len()
+            }
+            BodyKind::UppercaseFirst | BodyKind::UppercaseAll => {
+                dst[..word.len()].copy_from_slice( word );
+                Transform::to_uppercase( &mut dst[..word.len()], self == BodyKind::UppercaseFirst );
+                word.len()
+            }
+            BodyKind::OmitFirst(n) => {
+                let n = ::std::cmp::min( n as usize, word.len() );
+                dst[..word.len() - n].copy_from_slice( &word[n..] );
+                word.len() - n
+            }
+        }
+    }
+}
+
+/// An indexed body of a dictionary word.
+#[derive(Debug, Copy, Clone)]
+struct Entry {
+    kind: BodyKind,
+    key: u32,
+    word_len: u8,
+    word_index: u16
+}
+
+/// The transforms that share a prefix and a body kind.
+struct TransformGroup {
+    prefix: &'static [u8],
+    kind: BodyKind,
+
+    /// `(transform index, bytes omitted from the end of the word, suffix)`
+    transforms: Vec<(usize, usize, &'static [u8])>
+}
+
+/// The number of bits of the hash table over the indexed bodies.
+const HASH_BITS: u32 = 18;
+
+/// Reverse index from input bytes to transformed dictionary words.
+pub struct StaticDictionaryIndex {
+    /// Sorted by the hash of the key, so that the bodies of all kinds that start with the same
+    /// bytes are found with one lookup.
+    entries: Vec<Entry>,
+
+    /// The start of the entries with each hash, followed by the number of entries.
+    buckets: Vec<u32>,
+    groups: Vec<TransformGroup>,
+
+    /// The groups without a prefix, followed by the groups whose prefix starts with each byte.
+    groups_by_first_byte: Vec<Vec<usize>>,
+}
+
+#[inline(always)]
+fn key_of( bytes: &[u8] ) -> u32 {
+    (bytes[0] as u32) | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+#[inline(always)]
+fn hash( key: u32 ) -> usize {
+    (key.wrapping_mul( 0x1E35A7BD ) >> (32 - HASH_BITS)) as usize
+}
+
+impl StaticDictionaryIndex {
+
+    /// Returns the shared index, building it on first use.
+    pub fn get() -> &'static StaticDictionaryIndex {
+        static INDEX: OnceLock<StaticDictionaryIndex> = OnceLock::new();
+        INDEX.get_or_init( StaticDictionaryIndex::build )
+    }
+
+    fn build() -> StaticDictionaryIndex {
+        let mut groups: Vec<TransformGroup> = Vec::new();
+        for (id, t) in TRANSFORMS.iter().enumerate() {
+            let kind = BodyKind::of( t.transform );
+            let omit_last = if let WordTransformType::OmitLast(n) = t.transform { n as usize } else { 0 };
+
+            match groups.iter_mut().find( |g| g.prefix == t.prefix && g.kind == kind ) {
+                Some(g) => g.transforms.push( (id, omit_last, t.suffix) ),
+                None => groups.push( TransformGroup { prefix: t.prefix, kind: kind, transforms: vec![(id, omit_last, t.suffix)] } )
+            }
+        }
+
+        let mut kinds: Vec<BodyKind> = groups.iter().map( |g| g.kind ).collect();
+        kinds.sort();
+        kinds.dedup();
+
+        let mut entries = Vec::new();
+        let mut body = [0u8; MAX_WORD_LENGTH];
+        for (word_len, &index_bits) in NDBITS.iter().enumerate().take( MAX_WORD_LENGTH + 1 ).skip( MIN_WORD_LENGTH ) {
+            for word_index in 0 .. 1 << index_bits {
+                let word = dictionary::word( word_len, word_index ).unwrap();
+                for &kind in &kinds {
+                    let len = kind.body( word, &mut body );
+                    if len < MIN_BODY_LENGTH {
+                        continue;
+                    }
+
+                    entries.push( Entry { kind: kind, key: key_of( &body ), word_len: word_len as u8, word_index: word_index as u16 } );
+                }
+            }
+        }
+
+        entries.sort_by_key( |e| (hash( e.key ), e.kind, e.key) );
+
+        let mut buckets = vec![0u32; (1 << HASH_BITS) + 1];
+        for entry in &entries {
+            buckets[hash( entry.key ) + 1] += 1;
+        }
+        for i in 0 .. 1 << HASH_BITS {
+            buckets[i + 1] += buckets[i];
+        }
+
+        let mut groups_by_first_byte = vec![Vec::new(); 257];
+        for (i, group) in groups.iter().enumerate() {
+            groups_by_first_byte[group.prefix.first().map_or( 0, |&b| b as usize + 1 )].push( i );
+        }
+
+        StaticDictionaryIndex { entries: entries, buckets: buckets, groups: groups, groups_by_first_byte: groups_by_first_byte }
+    }
+
+    /// Returns the entries whose key may be `key`.
+    fn bucket( &self, key: u32 ) -> &[Entry] {
+        let h = hash( key );
+        &self.entries[self.buckets[h] as usize .. self.buckets[h + 1] as usize]
+    }
+
+    /// Finds the transformed dictionary word that covers the most bytes at the start of `data`.
+    /// Of equally long matches, the one with the smallest distance is returned.
+    pub fn find_match( &self, data: &[u8] ) -> Option<DictionaryMatch> {
+        let mut best: Option<DictionaryMatch> = None;
+        let mut body = [0u8; MAX_WORD_LENGTH];
+
+        if data.is_empty() {
+            return None
+        }
+
+        // Groups with prefixes of the same length share the bucket of the bytes after the prefix
+        let mut buckets: [Option<&[Entry]>; 8] = [None; 8];
+        let candidates = self.groups_by_first_byte[0].iter().chain( &self.groups_by_first_byte[data[0] as usize + 1] );
+        for group in candidates.map( |&i| &self.groups[i] ) {
+            if data.len() < group.prefix.len() + MIN_BODY_LENGTH || (!group.prefix.is_empty() && !data.starts_with( group.prefix )) {
+                continue;
+            }
+
+            let rest = &data[group.prefix.len()..];
+            let key = key_of( rest );
+            let bucket = match buckets.get_mut( group.prefix.len() ) {
+                Some( cached ) => *cached.get_or_insert_with( || self.bucket( key )),
+                None => self.bucket( key )
+            };
+
+            for entry in bucket.iter().filter( |e| e.kind == group.kind && e.key == key ) {
+                let word_len = entry.word_len as usize;
+                let word = dictionary::word( word_len, entry.word_index as usize ).unwrap();
+                let body_len = group.kind.body( word, &mut body );
+                let matched = body[..body_len].iter().zip( rest ).take_while( |&(a, b)| a == b ).count();
+
+                for &(transform, omit_last, suffix) in &group.transforms {
+                    if omit_last > body_len || body_len - omit_last < MIN_BODY_LENGTH {
+                        continue;
+                    }
+
+                    let body_len = body_len - omit_last;
+                    if matched < body_len || !rest[body_len..].starts_with( suffix ) {
+                        continue;
+                    }
+
+                    let candidate = DictionaryMatch {
+                        len: group.prefix.len() + body_len + suffix.len(),
+                        word_len: word_len,
+                        word_index: entry.word_index as usize,
+                        transform: transform
+                    };
+
+                    best = match best {
+                        Some(b) if b.len > candidate.len || (b.len == candidate.len && b.distance( 0 ) <= candidate.distance( 0 )) => Some(b),
+                        _ => Some(candidate)
+                    };
+                }
+            }
+        }
+
+        best
+    }
+}
+
+#[test]
+fn test_find_match()
+{
+    let index = StaticDictionaryIndex::get();
+
+    // Every match must expand to exactly the bytes it replaces.
+    let text: &[u8] = b"Once upon a time, the People of the world were given\n\tthe HTML <a href=\"index.html\">";
+    let mut dst = [0u8; 64];
+    let mut found = 0;
+    for pos in 0 .. text.len() {
+        if let Some(m) = index.find_match( &text[pos..] ) {
+            let word = dictionary::word( m.word_len, m.word_index ).unwrap();
+            let len = TRANSFORMS[m.transform].transform( word, &mut dst );
+            assert_eq!( &dst[..len], &text[pos .. pos + m.len] );
+            found += 1;
+        }
+    }
+    assert!( found > 10 );
+
+    // Prefix and suffix around the word.
+    let m = index.find_match( b" the world of the " ).unwrap();
+    assert_eq!( m.len, 18 );
+    assert_eq!( m.transform, 73 );
+
+    assert_eq!( index.find_match( b"\xff\xfe\xfd\xfc\xfb" ), None );
+}
+
+#[test]
+fn test_every_word_is_found()
+{
+    let index = StaticDictionaryIndex::get();
+    let mut dst = [0u8; 64];
+
+    // Each transformed word is found, although possibly through a cheaper transform.
+    for (word_len, &index_bits) in NDBITS.iter().enumerate().take( MAX_WORD_LENGTH + 1 ).skip( MIN_WORD_LENGTH ) {
+        for word_index in (0 .. 1 << index_bits).filter( |i| i % 61 == 0 ) {
+            let word = dictionary::word( word_len, word_index ).unwrap();
+            for transform in TRANSFORMS.iter() {
+                let len = transform.transform( word, &mut dst );
+                if len < MIN_BODY_LENGTH + transform.prefix.len() + transform.suffix.len() {
+                    continue;
+                }
+
+                let m = index.find_match( &dst[..len] ).unwrap();
+                assert_eq!( m.len, len );
+            }
+        }
+    }
+}
@@ -1,7 +1,6 @@
-﻿use std::io::Write;
-use std::slice::IterMut;
-
+﻿
 /// Brotli text transformation functions
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum WordTransformType {
     /// The word is copied to the output buffer as-is
     Identity,
@@ -21,9 +20,9 @@ pub enum WordTransformType {
 
 /// A word transform definition used to generate variants of a source text in the output stream
 pub struct Transform {
-  prefix : &'static [u8],
-  transform : WordTransformType,
-  suffix : &'static [u8]
+  pub prefix : &'static [u8],
+  pub transform : WordTransformType,
+  pub suffix : &'static [u8]
 }
 
 impl Transform {
@@ -48,40 +47,35 @@ impl Transform {
     /// of a byte array.
     pub fn to_uppercase( word: &mut [u8], first_char_only: bool ) {
 
-        let mut ch = word.into_iter();
+        let mut ch = word.iter_mut();
 
-        loop {
-            match ch.next() {
-                Some(p) => {
-                    let c = *p;
-                    if c < 192
-                    {
-                        if c >= 97 && c <= 122 
-                        {
-                            *p ^= 32;
-                        }
-                    }
-                    else if let Some(p1) = ch.next() 
-                    {
-                        if c < 224
-                        {
-                            *p1 ^= 32;
-                        }
-                        else if let Some(p2) = ch.next()
-                        {    
-                            *p2 ^= 5;
-                        }
-                        else
-                        {
-                            break;
-                        }
-                    }
-                    else
-                    {
-                        break;
-                    }
-                },
-                None => break
+        while let Some(p) = ch.next() {
+            let c = *p;
+            if c < 192
+            {
+                if (97..=122).contains( &c )
+                {
+                    *p ^= 32;
+                }
+            }
+            else if let Some(p1) = ch.next() 
+            {
+                if c < 224
+                {
+                    *p1 ^= 32;
+                }
+                else if let Some(p2) = ch.next()
+                {    
+                    *p2 ^= 5;
+                }
+                else
+                {
+                    break;
+                }
+            }
+            else
+            {
+                break;
             }
 
             if first_char_only { break; }
@@ -94,7 +88,10 @@ impl Transform {
         let mut offs = self.prefix.len();
 
         match self.transform {
-            WordTransformType::Identity =>  Transform::copy( src, &mut dst[offs..] ),
+            WordTransformType::Identity => {
+                Transform::copy( src, &mut dst[offs..] );
+                offs += src.len();
+            }
             WordTransformType::UppercaseFirst => {
                 Transform::copy( src, &mut dst[offs..] );
                 Transform::to_uppercase( &mut dst[offs..offs+src.len()], true );
@@ -105,13 +102,16 @@ impl Transform {
                 Transform::to_uppercase( &mut dst[offs..offs+src.len()], false );
                 offs += src.len();
             }
+            // Omitting more bytes than the word has leaves nothing, rather than being an error.
             WordTransformType::OmitFirst( n ) => {
-                Transform::copy( &src[(n as usize)..], &mut dst[offs..] );
-                offs += src.len() - (n as usize);
+                let n = ::core::cmp::min( n as usize, src.len() );
+                Transform::copy( &src[n..], &mut dst[offs..] );
+                offs += src.len() - n;
             }
             WordTransformType::OmitLast( n ) => { 
-                 Transform::copy( &src[..src.len()-(n as usize)], &mut dst[offs..] );
-                offs += src.len() - (n as usize);
+                let n = ::core::cmp::min( n as usize, src.len() );
+                Transform::copy( &src[..src.len()-n], &mut dst[offs..] );
+                offs += src.len() - n;
             }
         }
          
@@ -120,11 +120,11 @@ impl Transform {
     }
 }
 
-#[test()]
+#[test]
 fn test_transform()
 {
     let word = b"hello";
-    let mut dst = &mut [0u8;20];
+    let dst = &mut [0u8;20];
 
     let bytes = TRANSFORMS[4].transform( word, dst );
     assert_eq!( &dst[..bytes], b"Hello " );
@@ -141,7 +141,7 @@ fn test_transform()
 }
 
 /// The Brotli standard defines these 121 standard transforms to use during decompression.
-const TRANSFORMS : [Transform;121] = [
+pub static TRANSFORMS : [Transform;121] = [
 	Transform { prefix: b"",		transform: WordTransformType::Identity,			suffix: b""  },
 	Transform { prefix: b"",		transform: WordTransformType::Identity,		    suffix: b" "  },
 	Transform { prefix: b" ",		transform: WordTransformType::Identity,		    suffix: b" "  },
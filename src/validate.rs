@@ -0,0 +1,95 @@
+//! # Stream validation
+//!
+//! Checks that a Brotli stream is valid without handing its output to anyone. The stream is
+//! decoded in full, so every prefix code, context map, distance and padding bit is checked, and
+//! the output is only measured and checksummed on its way out of the ring buffer. Memory use is
+//! bounded by the window size the stream declares.
+
+use bitreader::{ BitReader, StreamBitReader };
+use decoder::Decoder;
+use std::io::{ ErrorKind, Read };
+use { BrotliError, Result };
+
+/// The number of decoded bytes checksummed at a time.
+const BUFFER_SIZE: usize = 4096;
+
+/// What validating a stream found out about it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+    /// The base 2 logarithm of the window size.
+    pub window_bits: u32,
+
+    /// The length of the decompressed content.
+    pub length: u64,
+
+    /// The CRC-32 of the decompressed content, as used by gzip and zip.
+    pub checksum: u32
+}
+
+/// Decodes the stream read from `reader`, and checks that it is valid and that no input follows
+/// it. Returns `InvalidEncoding` if the stream is corrupt or followed by more input, and
+/// `InsufficientData` if it is truncated.
+pub fn validate<R: Read>( reader: R ) -> Result<StreamInfo> {
+    let mut decoder = Decoder::new( StreamBitReader::new( reader ));
+    let mut buf = [0u8; BUFFER_SIZE];
+    let mut length = 0;
+    let mut crc = Crc32::new();
+    loop {
+        match decoder.read( &mut buf ) {
+            Ok( 0 ) => break,
+            Ok( count ) => {
+                length += count as u64;
+                crc.update( &buf[..count] );
+            }
+            Err( ref e ) if e.kind() == ErrorKind::Interrupted => {}
+            Err( e ) => return Err( BrotliError::from( e ))
+        }
+    }
+
+    let window_bits = decoder.window_bits();
+    let mut br = decoder.into_inner();
+    if br.get_remaining_bytes() > 0 || br.fetch_input()? {
+        return Err( BrotliError::InvalidEncoding )
+    }
+
+    Ok( StreamInfo { window_bits: window_bits, length: length, checksum: crc.finish() } )
+}
+
+/// CRC-32 with the polynomial of ISO 3309, computed a byte at a time.
+struct Crc32 {
+    table: [u32; 256],
+    crc: u32
+}
+
+impl Crc32 {
+
+    fn new() -> Crc32 {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut c = i as u32;
+            for _ in 0 .. 8 {
+                c = if c & 1 != 0 { 0xedb8_8320 ^ ( c >> 1 ) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+        Crc32 { table: table, crc: 0xffff_ffff }
+    }
+
+    fn update( &mut self, bytes: &[u8] ) {
+        for &byte in bytes {
+            self.crc = self.table[(( self.crc ^ byte as u32 ) & 0xff ) as usize] ^ ( self.crc >> 8 );
+        }
+    }
+
+    fn finish( &self ) -> u32 {
+        !self.crc
+    }
+}
+
+#[test]
+fn test_crc32() {
+    let mut crc = Crc32::new();
+    assert_eq!( crc.finish(), 0 );
+    crc.update( b"123456789" );
+    assert_eq!( crc.finish(), 0xcbf4_3926 );
+}
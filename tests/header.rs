@@ -0,0 +1,11 @@
+//! Checks that the checked-in encoder header matches the one generated from `src/ffi_encoder.rs`
+//! by the build script. Runs with `cargo test --features header --test header`.
+
+#![cfg(feature = "header")]
+
+#[test]
+fn test_encoder_header() {
+    let generated = include_str!( concat!( env!( "OUT_DIR" ), "/encode.h" ));
+    let checked_in = include_str!( "../c/include/brotli/encode.h" );
+    assert!( generated == checked_in, "c/include/brotli/encode.h is out of date, copy it from {}/encode.h", env!( "OUT_DIR" ));
+}
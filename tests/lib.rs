@@ -1,4 +1,1399 @@
-﻿extern crate brotli;
+#![cfg(feature = "std")]
+#![allow(clippy::redundant_field_names)]
 
-const x_bin = include_bin!( ".\\testdata\\10x10y");
-const x_cmp = include_bin!( ".\\testdata\\10x10y.compressed");
\ No newline at end of file
+extern crate brotli;
+
+use brotli::BrotliError;
+use brotli::allocator::{ BrotliAlloc, Code };
+#[cfg(feature = "encoder")]
+use brotli::asm::assemble;
+use brotli::bitreader::{ NoSource, StreamBitReader };
+use brotli::checkpoint::{ self, Checkpoint };
+#[cfg(feature = "encoder")]
+use brotli::index::{ self, build_index, Index, IndexedDecoder };
+#[cfg(feature = "encoder")]
+use brotli::seekable::{ SeekTable, SeekableReader, SeekableWriter };
+use brotli::decoder::{ DecompressorWriter, Decoder, Failure, Status };
+use brotli::disasm::{ Category, Disassembler, Distance, Entry, Instruction, MetaBlockKind };
+use brotli::explain::{ explain, Coding };
+use brotli::probe::{ probe, MIN_PROBE_BYTES };
+use brotli::validate::{ validate, StreamInfo };
+use brotli::observer::{ CommandObserver, MetaBlockHeader, NoObserver };
+#[cfg(feature = "encoder")]
+use brotli::encoder::{ compress_parallel, ChunkWindow, CompressorReader, CompressorWriter, EncoderOptions, Mode, ParallelOptions };
+#[cfg(feature = "encoder")]
+use std::cell::RefCell;
+#[cfg(feature = "encoder")]
+use std::cmp::{ max, min };
+use std::fs::File;
+use std::io::{ BufRead, ErrorKind, Read, Write };
+#[cfg(feature = "encoder")]
+use std::io::{ Cursor, Seek, SeekFrom };
+use std::mem;
+#[cfg(feature = "encoder")]
+use std::rc::Rc;
+#[cfg(feature = "encoder")]
+use std::sync::{ Arc, Mutex };
+
+fn decompress( compressed: &[u8] ) -> std::io::Result<Vec<u8>> {
+    let mut decoder = Decoder::new( StreamBitReader::new( compressed ));
+    let mut output = Vec::new();
+    decoder.read_to_end( &mut output )?;
+    Ok( output )
+}
+
+fn read_testdata( name: &str ) -> Vec<u8> {
+    let mut data = Vec::new();
+    File::open( format!( "tests/testdata/{}", name )).unwrap().read_to_end( &mut data ).unwrap();
+    data
+}
+
+#[cfg(feature = "encoder")]
+/// Reads a text fixture with the CRLF line endings it was compressed with, whichever line
+/// endings it was checked out with.
+fn read_crlf_testdata( name: &str ) -> Vec<u8> {
+    let mut data = Vec::new();
+    for &b in read_testdata( name ).iter().filter( |&&b| b != b'\r' ) {
+        if b == b'\n' {
+            data.push( b'\r' );
+        }
+        data.push( b );
+    }
+    data
+}
+
+fn check_testdata( name: &str, compressed: &str ) {
+    let expected = read_testdata( name );
+    let output = decompress( &read_testdata( compressed )).unwrap();
+    assert!( output == expected, "{} does not decompress to {}", compressed, name );
+}
+
+#[test]
+fn test_decompress_testdata() {
+    for name in &["10x10y", "64x", "asyoulik.txt", "backward65536", "compressed_file",
+                  "compressed_repeated", "empty", "mapsdatazrh", "monkey",
+                  "quickfox", "quickfox_repeated", "random_org_10k.bin", "ukkonooa", "x", "xyzzy", "zeros"] {
+        check_testdata( name, &format!( "{}.compressed", name ));
+    }
+}
+
+#[test]
+fn test_decompress_text_testdata() {
+    // These were compressed with CRLF line endings, but are checked out with LF line endings.
+    for name in &["alice29.txt", "lcet10.txt", "plrabn12.txt"] {
+        let expected = read_testdata( name );
+        let mut output = decompress( &read_testdata( &format!( "{}.compressed", name ))).unwrap();
+        output.retain( |&b| b != b'\r' );
+        assert!( output == expected, "{}.compressed does not decompress to {}", name, name );
+    }
+}
+
+#[test]
+fn test_decompress_variants() {
+    for i in 0 .. 19 {
+        check_testdata( "empty", &format!( "empty.compressed.{:02}", i ));
+    }
+    for i in 0 .. 4 {
+        check_testdata( "x", &format!( "x.compressed.{:02}", i ));
+    }
+}
+
+#[test]
+fn test_truncated() {
+    let compressed = read_testdata( "alice29.txt.compressed" );
+    let err = decompress( &compressed[..compressed.len() / 2] ).unwrap_err();
+    assert_eq!( err.kind(), std::io::ErrorKind::UnexpectedEof );
+}
+
+#[test]
+fn test_failure() {
+    let failure = |compressed: &[u8]| {
+        let mut decoder = Decoder::new( StreamBitReader::new( compressed ));
+        assert!( decoder.read_to_end( &mut Vec::new() ).is_err() );
+        decoder.failure()
+    };
+    assert_eq!( failure( &[0x1c] ), Some( Failure::Reserved ));
+    assert_eq!( failure( &[0x04, 0x00, 0x00, 0x00] ), Some( Failure::ExuberantNibble ));
+
+    // Running out of input isn't a failure of the stream
+    let compressed = read_testdata( "alice29.txt.compressed" );
+    let mut decoder = Decoder::new( StreamBitReader::new( &compressed[..compressed.len() / 2] ));
+    assert!( decoder.read_to_end( &mut Vec::new() ).is_err() );
+    assert_eq!( decoder.failure(), None );
+    let mut decoder = Decoder::new( StreamBitReader::new( &compressed[..] ));
+    decoder.read_to_end( &mut Vec::new() ).unwrap();
+    assert_eq!( decoder.failure(), None );
+}
+
+/// A reader that hands out one byte at a time.
+struct OneByte<'a>( &'a [u8] );
+
+impl<'a> Read for OneByte<'a> {
+    fn read( &mut self, buf: &mut [u8] ) -> std::io::Result<usize> {
+        if self.0.is_empty() || buf.is_empty() {
+            return Ok( 0 )
+        }
+        buf[0] = self.0[0];
+        self.0 = &self.0[1..];
+        Ok( 1 )
+    }
+}
+
+#[test]
+fn test_one_byte_reads() {
+    // Input arrives a byte at a time, and output is read a byte at a time.
+    let compressed = read_testdata( "ukkonooa.compressed" );
+    let mut decoder = Decoder::new( StreamBitReader::new( OneByte( &compressed )));
+    let mut output = Vec::new();
+    let mut byte = [0u8];
+    while decoder.read( &mut byte ).unwrap() == 1 {
+        output.push( byte[0] );
+    }
+    assert_eq!( output, read_testdata( "ukkonooa" ));
+}
+
+/// Decodes by pushing `chunk_size` bytes of input at a time, the way a decoder without `std`
+/// is driven. Returns the output and whether the end of the stream was reached.
+fn decompress_pushed( compressed: &[u8], chunk_size: usize ) -> (Vec<u8>, bool) {
+    let mut decoder = Decoder::new( StreamBitReader::new( NoSource ));
+    let mut chunks = compressed.chunks( chunk_size );
+    let mut output = Vec::new();
+    let mut buf = [0u8; 100];
+    loop {
+        let status = decoder.decode().unwrap();
+        loop {
+            let count = decoder.write_output( &mut buf );
+            if count == 0 {
+                break
+            }
+            output.extend_from_slice( &buf[..count] );
+        }
+        match status {
+            Status::Done => return (output, true),
+            Status::NeedsMoreOutput => {}
+            Status::NeedsMoreInput => match chunks.next() {
+                Some( chunk ) => decoder.get_mut().push( chunk ),
+                None => return (output, false)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_push_input() {
+    for name in &["10x10y", "asyoulik.txt", "empty", "mapsdatazrh", "random_org_10k.bin", "zeros"] {
+        let compressed = read_testdata( &format!( "{}.compressed", name ));
+        for &chunk_size in &[1, 7, 4096] {
+            assert_eq!( decompress_pushed( &compressed, chunk_size ), (read_testdata( name ), true), "{}", name );
+        }
+    }
+
+    // The decoder keeps waiting for the rest of a truncated stream
+    let compressed = read_testdata( "asyoulik.txt.compressed" );
+    let (output, finished) = decompress_pushed( &compressed[..compressed.len() / 2], 4096 );
+    assert!( !finished );
+    assert!( read_testdata( "asyoulik.txt" ).starts_with( &output ));
+}
+
+#[test]
+fn test_buf_read() {
+    let expected = read_testdata( "asyoulik.txt" );
+    let mut decoder = Decoder::new( StreamBitReader::new( File::open( "tests/testdata/asyoulik.txt.compressed" ).unwrap() ));
+    let mut output = Vec::new();
+    let mut lines = 0;
+    while decoder.read_until( b'\n', &mut output ).unwrap() > 0 {
+        lines += 1;
+    }
+    assert!( output == expected );
+    assert_eq!( lines, expected.iter().filter( |&&b| b == b'\n' ).count() );
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_buf_read_wrapped() {
+    // The output wraps around the end of a 64 KiB ring buffer
+    let expected = read_testdata( "asyoulik.txt" );
+    let options = EncoderOptions { window_bits: 16, ..EncoderOptions::default() };
+    let compressed = compress( &expected, &options );
+    let mut decoder = Decoder::new( StreamBitReader::new( &compressed[..] ));
+    let mut output = Vec::new();
+    let mut wrapped = false;
+    loop {
+        let available = decoder.fill_buf().unwrap().len();
+        if available == 0 {
+            break;
+        }
+        assert!( available <= 1 << 16 );
+        wrapped |= available < decoder.pending_output();
+
+        let count = available.min( 1000 );
+        output.extend_from_slice( &decoder.fill_buf().unwrap()[..count] );
+        decoder.consume( count );
+    }
+    assert!( wrapped );
+    assert!( output == expected );
+
+    // A truncated stream
+    let mut decoder = Decoder::new( StreamBitReader::new( &compressed[..compressed.len() / 2] ));
+    let mut output = Vec::new();
+    while decoder.read_until( b'\n', &mut output ).map_err( |e| e.kind() ) != Err( ErrorKind::UnexpectedEof ) {}
+    assert!( expected.starts_with( &output ));
+}
+
+/// Fails every other write, having written nothing.
+struct FlakyWriter {
+    output: Vec<u8>,
+    fail: bool
+}
+
+impl Write for FlakyWriter {
+    fn write( &mut self, buf: &[u8] ) -> std::io::Result<usize> {
+        self.fail = !self.fail;
+        if self.fail {
+            return Err( std::io::Error::other( "flaky" ))
+        }
+        self.output.write( buf )
+    }
+
+    fn flush( &mut self ) -> std::io::Result<()> {
+        Ok( () )
+    }
+}
+
+#[test]
+fn test_decompressor_writer() {
+    // Chunks of one byte split every prefix code table
+    for &(name, compressed) in &[("asyoulik.txt", "asyoulik.txt.compressed"), ("10x10y", "10x10y.compressed"),
+                                 ("mapsdatazrh", "mapsdatazrh.compressed"), ("empty", "empty.compressed")] {
+        let expected = read_testdata( name );
+        let compressed = read_testdata( compressed );
+        for &chunk_size in &[1, 7, 4096] {
+            let mut writer = DecompressorWriter::new( Vec::new() );
+            for chunk in compressed.chunks( chunk_size ) {
+                writer.write_all( chunk ).unwrap();
+            }
+            assert!( writer.close().unwrap() == expected );
+        }
+    }
+
+    // Input is kept when the wrapped writer fails
+    let expected = read_testdata( "asyoulik.txt" );
+    let compressed = read_testdata( "asyoulik.txt.compressed" );
+    let mut writer = DecompressorWriter::new( FlakyWriter { output: Vec::new(), fail: false });
+    for chunk in compressed.chunks( 100 ) {
+        while writer.write( chunk ).is_err() {}
+    }
+    while writer.flush().is_err() {}
+    assert!( writer.get_ref().output == expected );
+
+    // A truncated stream
+    let mut writer = DecompressorWriter::new( Vec::new() );
+    writer.write_all( &compressed[..compressed.len() / 2] ).unwrap();
+    assert!( expected.starts_with( writer.get_ref() ));
+    assert_eq!( writer.close().unwrap_err().kind(), ErrorKind::UnexpectedEof );
+
+    // Data after the end of the stream
+    let mut writer = DecompressorWriter::new( Vec::new() );
+    assert_eq!( writer.write_all( &[&compressed[..], b"x"].concat() ).unwrap_err().kind(), ErrorKind::InvalidData );
+    let mut writer = DecompressorWriter::new( Vec::new() );
+    writer.write_all( &compressed ).unwrap();
+    assert_eq!( writer.write_all( b"x" ).unwrap_err().kind(), ErrorKind::InvalidData );
+    assert!( writer.close().unwrap() == expected );
+
+    // An invalid stream
+    let mut writer = DecompressorWriter::new( Vec::new() );
+    assert_eq!( writer.write_all( &[0xff; 16] ).unwrap_err().kind(), ErrorKind::InvalidData );
+}
+
+/// Hands out buffers from fixed pools, which are never reused.
+struct Pool<'a> {
+    bytes: &'a mut [u8],
+    codes: &'a mut [Code],
+    allocations: usize
+}
+
+impl<'a> BrotliAlloc for Pool<'a> {
+    type Bytes = &'a mut [u8];
+    type Codes = &'a mut [Code];
+
+    fn alloc_u8( &mut self, buf: &mut &'a mut [u8], len: usize ) -> brotli::Result<()> {
+        if len > self.bytes.len() {
+            return Err( BrotliError::OutOfMemory )
+        }
+        let (new, rest) = mem::take( &mut self.bytes ).split_at_mut( len );
+        *buf = new;
+        self.bytes = rest;
+        self.allocations += 1;
+        Ok( () )
+    }
+
+    fn alloc_codes( &mut self, buf: &mut &'a mut [Code], len: usize ) -> brotli::Result<()> {
+        if len > self.codes.len() {
+            return Err( BrotliError::OutOfMemory )
+        }
+        let (new, rest) = mem::take( &mut self.codes ).split_at_mut( len );
+        *buf = new;
+        self.codes = rest;
+        self.allocations += 1;
+        Ok( () )
+    }
+}
+
+#[test]
+fn test_allocator() {
+    let mut bytes = vec![0xaa; 1 << 23];
+    let mut codes = vec![Code::default(); 1 << 19];
+    for name in &["10x10y", "asyoulik.txt", "backward65536", "mapsdatazrh", "x", "zeros"] {
+        let pool = Pool { bytes: &mut bytes, codes: &mut codes, allocations: 0 };
+        let mut decoder = Decoder::with_allocator( StreamBitReader::new( File::open( format!( "tests/testdata/{}.compressed", name )).unwrap() ), NoObserver, pool );
+        let mut output = Vec::new();
+        decoder.read_to_end( &mut output ).unwrap();
+        assert!( output == read_testdata( name ), "{} doesn't match", name );
+        assert!( decoder.allocator().allocations > 0 );
+    }
+
+    // Running out of memory is an error, and not retried
+    let compressed = read_testdata( "mapsdatazrh.compressed" );
+    for &(num_bytes, num_codes) in &[(1 << 10, 1 << 19), (1 << 23, 1 << 10)] {
+        let pool = Pool { bytes: &mut bytes[..num_bytes], codes: &mut codes[..num_codes], allocations: 0 };
+        let mut decoder = Decoder::with_allocator( StreamBitReader::new( &compressed[..] ), NoObserver, pool );
+        let error = decoder.read_to_end( &mut Vec::new() ).unwrap_err();
+        assert_eq!( error.kind(), ErrorKind::OutOfMemory );
+        assert!( matches!( decoder.decode(), Err( BrotliError::InvalidEncoding )));
+    }
+}
+
+#[test]
+fn test_reset() {
+    let names = ["10x10y", "asyoulik.txt", "quickfox", "x", "empty", "mapsdatazrh", "zeros"];
+    let streams: Vec<Vec<u8>> = names.iter().map( |name| read_testdata( &format!( "{}.compressed", name ))).collect();
+    let mut bytes = vec![0xaa; 1 << 23];
+    let mut codes = vec![Code::default(); 1 << 19];
+    let pool = Pool { bytes: &mut bytes, codes: &mut codes, allocations: 0 };
+    let mut decoder = Decoder::with_allocator( StreamBitReader::new( &streams[0][..] ), NoObserver, pool );
+
+    // Once the buffers have grown for all the streams, decoding them again allocates nothing
+    let mut allocations = 0;
+    for round in 0..2 {
+        for (name, compressed) in names.iter().zip( &streams ) {
+            decoder.reset( StreamBitReader::new( compressed ));
+            let mut output = Vec::new();
+            decoder.read_to_end( &mut output ).unwrap();
+            assert!( output == read_testdata( name ), "{} doesn't match", name );
+            assert!( decoder.is_finished() );
+        }
+        if round == 0 {
+            allocations = decoder.allocator().allocations;
+        }
+    }
+    assert_eq!( decoder.allocator().allocations, allocations );
+
+    // A decoder that failed, or was left mid-stream, can be reset
+    decoder.reset( StreamBitReader::new( &[0xff; 16] ));
+    assert!( decoder.read_to_end( &mut Vec::new() ).is_err() );
+    decoder.reset( StreamBitReader::new( &streams[1][..streams[1].len() / 2] ));
+    assert_eq!( decoder.read_to_end( &mut Vec::new() ).unwrap_err().kind(), ErrorKind::UnexpectedEof );
+    decoder.reset( StreamBitReader::new( &streams[1] ));
+    let mut output = Vec::new();
+    decoder.read_to_end( &mut output ).unwrap();
+    assert!( output == read_testdata( "asyoulik.txt" ));
+}
+
+/// Counts the meta-blocks.
+#[cfg(feature = "encoder")]
+#[derive(Default)]
+struct MetaBlockCounter( usize );
+
+#[cfg(feature = "encoder")]
+impl CommandObserver for MetaBlockCounter {
+    fn meta_block( &mut self, _header: &MetaBlockHeader ) {
+        self.0 += 1;
+    }
+}
+
+/// Resumes decoding `compressed` from `checkpoint`, and checks the rest of the output.
+#[cfg(feature = "encoder")]
+fn check_resume( checkpoint: &Checkpoint, compressed: &[u8], expected: &[u8] ) {
+    let checkpoint = Checkpoint::from_bytes( checkpoint.as_bytes().to_vec() ).unwrap();
+    let mut decoder = Decoder::resume( &checkpoint, StreamBitReader::new( &compressed[checkpoint.input_offset() as usize ..] )).unwrap();
+    assert!( decoder.checkpoint() == checkpoint );
+    let mut output = Vec::new();
+    decoder.read_to_end( &mut output ).unwrap();
+    assert!( output[..] == expected[checkpoint.output_offset() as usize ..] );
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_checkpoint() {
+    // The fixtures, and streams of the encoder with many meta-blocks
+    let mut streams = Vec::new();
+    for name in &["mapsdatazrh", "asyoulik.txt", "compressed_repeated"] {
+        streams.push( (read_testdata( name ), read_testdata( &format!( "{}.compressed", name ))) );
+    }
+    for name in &["alice29.txt", "lcet10.txt", "plrabn12.txt"] {
+        streams.push( (read_crlf_testdata( name ), read_testdata( &format!( "{}.compressed", name ))) );
+    }
+    for name in &["mapsdatazrh", "asyoulik.txt"] {
+        let data = read_testdata( name );
+        let compressed = compress( &data, &EncoderOptions { quality: 5, window_bits: 16, block_bits: 16, ..EncoderOptions::default() } );
+        streams.push( (data, compressed) );
+    }
+
+    let mut most_meta_blocks = 0;
+    for (expected, compressed) in &streams {
+        check_resume( &Decoder::new( StreamBitReader::new( NoSource )).checkpoint(), compressed, expected );
+
+        // Input is handed over a byte at a time, and a checkpoint taken in each meta-block as soon
+        // as its header has been read, and every 4 KiB of input, before reading the output
+        let mut decoder = Decoder::with_observer( StreamBitReader::new( NoSource ), MetaBlockCounter::default() );
+        let mut output = Vec::new();
+        let mut buf = [0; 4096];
+        let mut meta_blocks = 0;
+        for (position, &byte) in compressed.iter().enumerate() {
+            decoder.get_mut().push( &[byte] );
+            loop {
+                let status = decoder.decode().unwrap();
+                if decoder.observer().0 > meta_blocks || position % 4096 == 0 {
+                    meta_blocks = decoder.observer().0;
+                    let checkpoint = decoder.checkpoint();
+                    assert_eq!( checkpoint.output_offset(), output.len() as u64 );
+                    check_resume( &checkpoint, compressed, expected );
+                }
+                let count = decoder.write_output( &mut buf );
+                output.extend_from_slice( &buf[..count] );
+                if status == Status::NeedsMoreInput || ( status == Status::Done && decoder.pending_output() == 0 ) {
+                    break;
+                }
+            }
+        }
+        assert!( output == *expected );
+        most_meta_blocks = max( most_meta_blocks, meta_blocks );
+        check_resume( &decoder.checkpoint(), compressed, expected );
+    }
+    assert!( most_meta_blocks > 3 );
+
+    // A decoder is restored with its buffers reused, and checkpoints are checked
+    let expected = read_testdata( "asyoulik.txt" );
+    let compressed = read_testdata( "asyoulik.txt.compressed" );
+    let mut decoder = Decoder::new( StreamBitReader::new( &compressed[..] ));
+    let mut output = vec![0; 10000];
+    decoder.read_exact( &mut output ).unwrap();
+    let checkpoint = decoder.checkpoint();
+    decoder.restore( &checkpoint, StreamBitReader::new( &compressed[checkpoint.input_offset() as usize ..] )).unwrap();
+    output.truncate( checkpoint.output_offset() as usize );
+    decoder.read_to_end( &mut output ).unwrap();
+    assert!( output == expected );
+
+    let bytes = checkpoint.into_bytes();
+    for invalid in &[&bytes[..3], &b"BRCK\x02"[..], &[b'X'; 100][..]] {
+        assert!( matches!( Checkpoint::from_bytes( invalid.to_vec() ), Err( BrotliError::InvalidCheckpoint )));
+    }
+    let truncated = Checkpoint::from_bytes( bytes[.. bytes.len() - 1].to_vec() ).unwrap();
+    let error = decoder.restore( &truncated, StreamBitReader::new( &compressed[..] )).unwrap_err();
+    assert_eq!( error.to_string(), "invalid decoder checkpoint" );
+    assert!( matches!( decoder.decode(), Err( BrotliError::InvalidEncoding )));
+}
+
+#[test]
+fn test_checkpoint_corrupted() {
+    // Checkpoints taken all through a stream, with their bytes changed one at a time, are either
+    // rejected or decode something, without a panic
+    let compressed = read_testdata( "monkey.compressed" );
+    let mut decoder = Decoder::new( StreamBitReader::new( NoSource ));
+    let mut buf = [0; 256];
+    let mut rejected = 0;
+    for (position, &byte) in compressed.iter().enumerate() {
+        decoder.get_mut().push( &[byte] );
+        while decoder.decode().unwrap() == Status::NeedsMoreOutput {
+            decoder.write_output( &mut buf );
+        }
+        if position % 100 != 0 {
+            continue;
+        }
+
+        let bytes = decoder.checkpoint().into_bytes();
+        // Every byte of the state before the ring buffer, and some of the rest, to keep it quick
+        for index in ( checkpoint::HEADER_SIZE .. bytes.len() ).filter( |&index| index < 128 || index % 23 == 0 ) {
+            for &mask in &[0x01, 0xff] {
+                let mut corrupted = bytes.clone();
+                corrupted[index] ^= mask;
+                let checkpoint = Checkpoint::from_bytes( corrupted ).unwrap();
+                let input = &compressed[checkpoint.input_offset() as usize ..];
+                match Decoder::resume( &checkpoint, StreamBitReader::new( input )) {
+                    Ok( mut resumed ) => { let _ = resumed.read_to_end( &mut Vec::new() ); }
+                    Err( BrotliError::InvalidCheckpoint ) | Err( BrotliError::TooManyTypes { .. } ) => rejected += 1,
+                    Err( error ) => panic!( "{}", error )
+                }
+            }
+        }
+
+        // A window too large for the format
+        let mut corrupted = bytes.clone();
+        corrupted[checkpoint::WINDOW_BITS_OFFSET] = 25;
+        let checkpoint = Checkpoint::from_bytes( corrupted ).unwrap();
+        assert!( matches!( Decoder::resume( &checkpoint, StreamBitReader::new( &compressed[..] )), Err( BrotliError::InvalidCheckpoint )));
+    }
+    assert!( rejected > 0 );
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_index() {
+    let expected = read_testdata( "mapsdatazrh" );
+    let compressed = compress( &expected, &EncoderOptions { quality: 5, window_bits: 16, block_bits: 16, ..EncoderOptions::default() } );
+    let index = build_index( &compressed[..], 20000 ).unwrap();
+    assert_eq!( index.output_size(), expected.len() as u64 );
+    assert!( index.points().len() > 5 );
+    assert_eq!( index.point_before( 0 ).output_offset(), 0 );
+    for pair in index.points().windows( 2 ) {
+        assert!( pair[1].output_offset() >= pair[0].output_offset() + 20000 );
+        assert!( pair[1].output_offset() - pair[0].output_offset() <= 20000 + ( 1 << 16 ));
+    }
+
+    // The stored index reads back the same
+    let mut stored = Vec::new();
+    index.write_to( &mut stored ).unwrap();
+    assert!( stored.starts_with( b"BRIX\x01" ));
+    let index = Index::read_from( &stored[..] ).unwrap();
+    for invalid in &[&stored[..4], &stored[.. stored.len() - 1], &b"BRIX\x01\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0"[..]] {
+        assert!( matches!( Index::read_from( *invalid ), Err( BrotliError::InvalidIndex )));
+    }
+
+    // A point whose state doesn't load, with a window too large for the format
+    let mut corrupted = stored.clone();
+    let second = index::HEADER_SIZE + index::POINT_LENGTH_SIZE + index.points()[0].as_bytes().len() + index::POINT_LENGTH_SIZE;
+    corrupted[second + checkpoint::WINDOW_BITS_OFFSET] = 25;
+    assert!( matches!( Index::read_from( &corrupted[..] ), Err( BrotliError::InvalidIndex )));
+
+    // Reads after seeks anywhere, forwards and backwards
+    let size = expected.len() as u64;
+    let mut decoder = IndexedDecoder::new( Cursor::new( &compressed ), index.clone() ).unwrap();
+    for &offset in &[size / 2, 0, 1, size / 3, size / 3 + 100, 12345, 70000, size - 1, size, size + 10] {
+        assert_eq!( decoder.seek( SeekFrom::Start( offset )).unwrap(), offset );
+        let mut output = [0; 1000];
+        let count = decoder.read( &mut output ).unwrap();
+        let start = min( offset, size ) as usize;
+        let end = min( start + 1000, expected.len() );
+        assert!( output[..count] == expected[start .. start + count] );
+        assert!( count > 0 || start == end );
+    }
+    assert_eq!( decoder.seek( SeekFrom::End( -100 )).unwrap(), size - 100 );
+    assert_eq!( decoder.seek( SeekFrom::Current( -100 )).unwrap(), size - 200 );
+    let mut output = Vec::new();
+    decoder.read_to_end( &mut output ).unwrap();
+    assert!( output[..] == expected[expected.len() - 200 ..] );
+    assert_eq!( decoder.seek( SeekFrom::Current( -( size as i64 ) - 1 )).unwrap_err().kind(), ErrorKind::InvalidInput );
+
+    // Seeking a decoder directly
+    let mut decoder = Decoder::new( StreamBitReader::new( Cursor::new( &compressed )));
+    decoder.seek_to( &index, 100000 ).unwrap();
+    assert_eq!( decoder.output_position(), 100000 );
+    let mut output = Vec::new();
+    decoder.read_to_end( &mut output ).unwrap();
+    assert!( output[..] == expected[100000 ..] );
+    let error = decoder.seek_to( &index, size + 1 ).unwrap_err();
+    assert!( matches!( error, BrotliError::InsufficientData ));
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_seekable() {
+    let expected = read_testdata( "mapsdatazrh" );
+    let size = expected.len() as u64;
+    let options = EncoderOptions { quality: 5, window_bits: 16, ..EncoderOptions::default() };
+    let mut writer = SeekableWriter::new( Vec::new(), &options, 50000 );
+    for chunk in expected.chunks( 7000 ) {
+        writer.write_all( chunk ).unwrap();
+    }
+    let compressed = writer.finish().unwrap();
+
+    // Plain decoders decode it as a whole
+    assert!( decompress( &compressed ).unwrap() == expected );
+
+    let table = SeekTable::read_from( &mut Cursor::new( &compressed )).unwrap();
+    assert_eq!( table.window_bits(), 16 );
+    assert_eq!( table.output_size(), size );
+    assert_eq!( table.frames().len(), expected.len().div_ceil( 50000 ));
+    for (index, frame) in table.frames().iter().enumerate() {
+        assert_eq!( frame.output_offset, index as u64 * 50000 );
+        assert_eq!( frame.output_size, min( 50000, size - frame.output_offset ));
+    }
+
+    // Every frame decodes on its own
+    let mut reader = SeekableReader::new( Cursor::new( &compressed )).unwrap();
+    for frame in table.frames().iter().rev() {
+        reader.seek( SeekFrom::Start( frame.output_offset )).unwrap();
+        let mut output = vec![0; frame.output_size as usize];
+        reader.read_exact( &mut output ).unwrap();
+        assert!( output[..] == expected[frame.output_offset as usize ..][.. output.len()] );
+    }
+
+    // Reads after seeks anywhere, forwards and backwards
+    for &offset in &[size / 2, 0, 1, 49999, 50000, 50001, size / 3, size / 3 + 100, size - 1, size, size + 10] {
+        assert_eq!( reader.seek( SeekFrom::Start( offset )).unwrap(), offset );
+        let mut output = [0; 1000];
+        let count = reader.read( &mut output ).unwrap();
+        let start = min( offset, size ) as usize;
+        assert!( output[..count] == expected[start .. start + count] );
+        assert!( count > 0 || start == expected.len() );
+    }
+    assert_eq!( reader.seek( SeekFrom::End( -60000 )).unwrap(), size - 60000 );
+    let mut output = Vec::new();
+    reader.read_to_end( &mut output ).unwrap();
+    assert!( output[..] == expected[expected.len() - 60000 ..] );
+
+    // Flushing ends a frame early, and an empty stream has one empty frame
+    let mut writer = SeekableWriter::new( Vec::new(), &options, 50000 );
+    writer.write_all( &expected[..100] ).unwrap();
+    writer.flush().unwrap();
+    writer.write_all( &expected[100..200] ).unwrap();
+    assert_eq!( writer.frames().len(), 1 );
+    let compressed = writer.finish().unwrap();
+    let table = SeekTable::read_from( &mut Cursor::new( &compressed )).unwrap();
+    assert_eq!( table.frames().iter().map( |frame| frame.output_size ).collect::<Vec<_>>(), [100, 100] );
+    assert!( decompress( &compressed ).unwrap() == expected[..200] );
+
+    let compressed = SeekableWriter::new( Vec::new(), &options, 50000 ).finish().unwrap();
+    let mut reader = SeekableReader::new( Cursor::new( &compressed )).unwrap();
+    assert_eq!( reader.seek_table().frames().len(), 1 );
+    assert_eq!( reader.read( &mut [0; 10] ).unwrap(), 0 );
+    assert!( decompress( &compressed ).unwrap().is_empty() );
+
+    // Other streams have no seek table
+    for name in &["mapsdatazrh.compressed", "empty.compressed"] {
+        let error = SeekTable::read_from( &mut Cursor::new( read_testdata( name ))).unwrap_err();
+        assert!( matches!( error, BrotliError::InvalidIndex ));
+    }
+}
+
+#[cfg(feature = "encoder")]
+fn compress( data: &[u8], options: &EncoderOptions ) -> Vec<u8> {
+    let mut writer = CompressorWriter::new( Vec::new(), options );
+    writer.write_all( data ).unwrap();
+    writer.finish().unwrap()
+}
+
+#[cfg(feature = "encoder")]
+fn check_round_trip( data: &[u8], options: &EncoderOptions ) {
+    let compressed = compress( data, options );
+    let output = decompress( &compressed ).unwrap();
+    assert!( output == data, "round trip failed with {:?}", options );
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_compress_round_trip() {
+    for name in &["alice29.txt", "compressed_file", "mapsdatazrh", "random_org_10k.bin", "x", "empty", "zeros", "backward65536"] {
+        let data = read_testdata( name );
+        for &quality in &[0, 5, 9] {
+            check_round_trip( &data, &EncoderOptions { quality: quality, .. EncoderOptions::default() } );
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_compress_options() {
+    let data = read_testdata( "asyoulik.txt" );
+    for &window_bits in &[10, 16, 17, 18, 24] {
+        check_round_trip( &data, &EncoderOptions { window_bits: window_bits, .. EncoderOptions::default() } );
+    }
+    for &mode in &[Mode::Generic, Mode::Text, Mode::Font] {
+        check_round_trip( &data, &EncoderOptions { mode: mode, block_bits: 16, .. EncoderOptions::default() } );
+    }
+
+    let compressed = compress( &data, &EncoderOptions::default() );
+    assert!( compressed.len() < data.len() / 2 );
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_compress_flush() {
+    let mut writer = CompressorWriter::new( Vec::new(), &EncoderOptions::default() );
+    writer.write_all( b"Hello, " ).unwrap();
+    writer.flush().unwrap();
+
+    // The flushed stream isn't finished, but all of its data can be decoded
+    let mut decoder = Decoder::new( StreamBitReader::new( &writer.get_ref()[..] ));
+    let mut output = [0u8; 7];
+    decoder.read_exact( &mut output ).unwrap();
+    assert_eq!( &output, b"Hello, " );
+
+    writer.write_all( b"world!" ).unwrap();
+    let compressed = writer.finish().unwrap();
+    assert_eq!( decompress( &compressed ).unwrap(), b"Hello, world!" );
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_compress_drop() {
+    let mut compressed = Vec::new();
+    {
+        let mut writer = CompressorWriter::new( &mut compressed, &EncoderOptions::default() );
+        writer.write_all( b"ukko nooa" ).unwrap();
+    }
+    assert_eq!( decompress( &compressed ).unwrap(), b"ukko nooa" );
+}
+
+/// Writes at most 100 bytes at a time, and fails the third and the sixth write.
+#[cfg(feature = "encoder")]
+struct ShortWriter {
+    output: Vec<u8>,
+    writes: usize
+}
+
+#[cfg(feature = "encoder")]
+impl Write for ShortWriter {
+    fn write( &mut self, buf: &[u8] ) -> std::io::Result<usize> {
+        self.writes += 1;
+        if self.writes == 3 || self.writes == 6 {
+            return Err( std::io::Error::other( "short" ))
+        }
+        self.output.write( &buf[.. min( buf.len(), 100 )] )
+    }
+
+    fn flush( &mut self ) -> std::io::Result<()> {
+        Ok( () )
+    }
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_compress_short_writes() {
+    // Output the wrapped writer took before failing isn't written again
+    let data = read_testdata( "asyoulik.txt" );
+    let mut writer = CompressorWriter::new( ShortWriter { output: Vec::new(), writes: 0 }, &EncoderOptions { block_bits: 16, .. EncoderOptions::default() } );
+    for chunk in data.chunks( 1000 ) {
+        let mut chunk = chunk;
+        while !chunk.is_empty() {
+            if let Ok( count ) = writer.write( chunk ) {
+                chunk = &chunk[count..];
+            }
+        }
+    }
+    let compressed = writer.finish().unwrap().output;
+    assert!( decompress( &compressed ).unwrap() == data );
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_compress_reader() {
+    let data = read_testdata( "asyoulik.txt" );
+    let options = EncoderOptions { block_bits: 16, .. EncoderOptions::default() };
+    let mut compressed = Vec::new();
+    CompressorReader::new( &data[..], &options ).read_to_end( &mut compressed ).unwrap();
+    assert_eq!( decompress( &compressed ).unwrap(), data );
+
+    // The output doesn't depend on how the input and output are split up
+    let mut reader = CompressorReader::new( OneByte( &data[..] ), &options );
+    let mut output = Vec::new();
+    let mut chunk = [0u8; 7];
+    loop {
+        let count = reader.read( &mut chunk ).unwrap();
+        if count == 0 {
+            break;
+        }
+        output.extend_from_slice( &chunk[..count] );
+    }
+    assert_eq!( output, compressed );
+
+    let mut compressed = Vec::new();
+    CompressorReader::new( &b""[..], &options ).read_to_end( &mut compressed ).unwrap();
+    assert_eq!( decompress( &compressed ).unwrap(), b"" );
+}
+
+/// The sending end of a connection.
+#[cfg(feature = "encoder")]
+struct PipeWriter( Rc<RefCell<Vec<u8>>> );
+
+#[cfg(feature = "encoder")]
+impl Write for PipeWriter {
+    fn write( &mut self, buf: &[u8] ) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice( buf );
+        Ok( buf.len() )
+    }
+
+    fn flush( &mut self ) -> std::io::Result<()> {
+        Ok( () )
+    }
+}
+
+/// The receiving end of a connection, which hands out the bytes sent so far, and then fails
+/// with `WouldBlock` until more are sent.
+#[cfg(feature = "encoder")]
+struct PipeReader( Rc<RefCell<Vec<u8>>> );
+
+#[cfg(feature = "encoder")]
+impl Read for PipeReader {
+    fn read( &mut self, buf: &mut [u8] ) -> std::io::Result<usize> {
+        let mut data = self.0.borrow_mut();
+        if data.is_empty() {
+            return Err( std::io::Error::new( ErrorKind::WouldBlock, "no data sent" ))
+        }
+
+        let count = std::cmp::min( buf.len(), data.len() );
+        buf[..count].copy_from_slice( &data[..count] );
+        data.drain( ..count );
+        Ok( count )
+    }
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_flush_sync_points() {
+    let text = read_testdata( "alice29.txt" );
+    let messages: Vec<&[u8]> = vec![b"data: hello\n\n", b"data: hello again\n\n", b"", &text[..100000], b"x", b"data: bye\n\n"];
+
+    let pipe = Rc::new( RefCell::new( Vec::new() ));
+    let options = EncoderOptions { block_bits: 16, .. EncoderOptions::default() };
+    let mut writer = CompressorWriter::new( PipeWriter( pipe.clone() ), &options );
+    let mut decoder = Decoder::new( StreamBitReader::new( PipeReader( pipe.clone() )));
+    for message in &messages {
+        writer.write_all( message ).unwrap();
+        writer.flush().unwrap();
+
+        // Everything flushed comes out, and then the decoder waits for more input
+        let mut output = vec![0u8; message.len()];
+        decoder.read_exact( &mut output ).unwrap();
+        assert!( &output[..] == *message );
+        assert_eq!( decoder.read( &mut [0u8; 1] ).unwrap_err().kind(), ErrorKind::WouldBlock );
+
+        // Flushing again writes nothing
+        writer.flush().unwrap();
+        assert!( pipe.borrow().is_empty() );
+    }
+
+    writer.finish().unwrap();
+    let mut output = Vec::new();
+    decoder.read_to_end( &mut output ).unwrap();
+    assert!( output.is_empty() );
+}
+
+#[test]
+fn test_sync_point_padding() {
+    // Window bits, an empty metadata meta-block padded to a byte boundary, and an empty last
+    // meta-block. The padding bit at the top of the first byte must be zero.
+    assert_eq!( decompress( &[0x0c, 0x03] ).unwrap(), b"" );
+    assert_eq!( decompress( &[0x8c, 0x03] ).unwrap_err().kind(), ErrorKind::InvalidData );
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_compress_parallel() {
+    let mut data = Vec::new();
+    for name in &["alice29.txt", "random_org_10k.bin", "backward65536", "x"] {
+        data.extend( read_testdata( name ));
+    }
+
+    for &window in &[ChunkWindow::Independent, ChunkWindow::PreviousChunk] {
+        for options in &[EncoderOptions { quality: 9, window_bits: 18, .. EncoderOptions::default() },
+                         EncoderOptions { quality: 0, window_bits: 16, .. EncoderOptions::default() },
+                         EncoderOptions { quality: 5, window_bits: 22, mode: Mode::Font, .. EncoderOptions::default() }] {
+            let parallel = ParallelOptions { threads: 1, chunk_bits: 16, window: window };
+            let compressed = compress_parallel( &data, options, &parallel );
+            assert!( decompress( &compressed ).unwrap() == data, "round trip failed with {:?} {:?}", window, options );
+
+            // The output doesn't depend on the number of threads
+            for &threads in &[3, 8] {
+                let parallel = ParallelOptions { threads: threads, .. parallel };
+                assert!( compress_parallel( &data, options, &parallel ) == compressed );
+            }
+        }
+    }
+
+    for name in &["empty", "x"] {
+        let data = read_testdata( name );
+        let compressed = compress_parallel( &data, &EncoderOptions::default(), &ParallelOptions::default() );
+        assert_eq!( decompress( &compressed ).unwrap(), data );
+    }
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_compress_parallel_previous_chunk() {
+    // Every chunk repeats the previous one, so only the first compresses poorly on its own
+    let chunk = &read_testdata( "random_org_10k.bin" )[..8192];
+    let data = chunk.repeat( 32 );
+    let compress = |window| compress_parallel( &data, &EncoderOptions::default(), &ParallelOptions { threads: 4, chunk_bits: 16, window: window } );
+    let independent = compress( ChunkWindow::Independent );
+    let previous_chunk = compress( ChunkWindow::PreviousChunk );
+    assert_eq!( decompress( &previous_chunk ).unwrap(), data );
+    assert!( previous_chunk.len() + 8192 < independent.len() );
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_metadata() {
+    let text = read_testdata( "alice29.txt" );
+    let large: Vec<u8> = ( 0 .. ( 1 << 24 ) + 1 ).map( |i| i as u8 ).collect();
+    let payloads: Vec<&[u8]> = vec![b"", b"a", b"ab", &large[..256], &large[..257], &large[..65537], &large];
+
+    let mut writer = CompressorWriter::new( Vec::new(), &EncoderOptions::default() );
+    for payload in &payloads {
+        writer.write_all( &text[..1000] ).unwrap();
+        writer.write_metadata( payload ).unwrap();
+    }
+    let compressed = writer.finish().unwrap();
+
+    // Metadata is skipped by default
+    let expected = text[..1000].repeat( payloads.len() );
+    assert!( decompress( &compressed ).unwrap() == expected );
+
+    let received = Arc::new( Mutex::new( Vec::new() ));
+    let mut decoder = Decoder::new( StreamBitReader::new( &compressed[..] ));
+    let sink = received.clone();
+    decoder.set_metadata_callback( move |payload| sink.lock().unwrap().push( payload.to_vec() ));
+    let mut output = Vec::new();
+    decoder.read_to_end( &mut output ).unwrap();
+    assert!( output == expected );
+
+    // The longest payload is split in two
+    let received = received.lock().unwrap();
+    let mut expected_payloads: Vec<&[u8]> = payloads[..payloads.len() - 1].to_vec();
+    expected_payloads.extend_from_slice( &[&large[..1 << 24], &large[1 << 24..]] );
+    assert_eq!( received.len(), expected_payloads.len() );
+    for (payload, expected) in received.iter().zip( expected_payloads ) {
+        assert!( &payload[..] == expected );
+    }
+}
+
+#[test]
+fn test_disassemble() {
+    let entries: Vec<Entry> = Disassembler::new( &read_testdata( "x.compressed" )[..] ).map( |entry| entry.unwrap() ).collect();
+    assert_eq!( entries, vec![
+        Entry { bit_offset: 0, instruction: Instruction::WindowBits( 22 ) },
+        Entry { bit_offset: 4, instruction: Instruction::MetaBlockHeader { is_last: false, length: 1, kind: MetaBlockKind::Uncompressed } },
+        Entry { bit_offset: 24, instruction: Instruction::Uncompressed( b"X".to_vec() ) },
+        Entry { bit_offset: 32, instruction: Instruction::MetaBlockHeader { is_last: true, length: 0, kind: MetaBlockKind::Empty } }
+    ]);
+    assert_eq!( entries[1].to_string(), "         4  meta_block uncompressed length=1 last=0" );
+
+    // Replaying the commands reproduces the output
+    for name in &["asyoulik.txt", "mapsdatazrh", "random_org_10k.bin", "ukkonooa", "backward65536", "quickfox_repeated"] {
+        let data = read_testdata( name );
+        let compressed = read_testdata( &format!( "{}.compressed", name ));
+        let mut output = Vec::new();
+        let mut meta_block_end = 0;
+        let mut num_block_switches = 0;
+        for entry in Disassembler::new( &compressed[..] ) {
+            match entry.unwrap().instruction {
+                Instruction::MetaBlockHeader { length, .. } => {
+                    assert_eq!( output.len(), meta_block_end );
+                    meta_block_end += length;
+                }
+                Instruction::Uncompressed( bytes ) => output.extend( bytes ),
+                Instruction::Command { literals, copy_length, distance, .. } => {
+                    output.extend( literals );
+                    match distance {
+                        Some( Distance::Last { distance, .. } ) | Some( Distance::Explicit { distance, .. } ) => {
+                            for _ in 0 .. copy_length {
+                                let byte = output[output.len() - distance];
+                                output.push( byte );
+                            }
+                        }
+                        Some( Distance::Dictionary { length, .. } ) => {
+                            let start = output.len();
+                            output.extend_from_slice( &data[start .. start + length] );
+                        }
+                        None => {}
+                    }
+                }
+                Instruction::BlockSwitch { category, .. } => {
+                    assert!( category != Category::Literal || !output.is_empty() );
+                    num_block_switches += 1;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!( output.len(), meta_block_end );
+        assert!( output == data, "{} doesn't match", name );
+        assert!( *name != "asyoulik.txt" || num_block_switches > 0 );
+    }
+
+    // A decoder's observer that lists instructions gets the same ones, as the output is read
+    struct Lister( Vec<Entry> );
+    impl CommandObserver for Lister {
+        fn lists_instructions( &self ) -> bool {
+            true
+        }
+
+        fn instruction( &mut self, entry: Entry ) {
+            self.0.push( entry );
+        }
+    }
+    let compressed = read_testdata( "asyoulik.txt.compressed" );
+    let mut decoder = Decoder::with_observer( StreamBitReader::new( &compressed[..] ), Lister( Vec::new() ));
+    decoder.read_to_end( &mut Vec::new() ).unwrap();
+    let entries: Vec<Entry> = Disassembler::new( &compressed[..] ).map( |entry| entry.unwrap() ).collect();
+    assert!( decoder.observer().0 == entries );
+
+    // Errors end the listing
+    let compressed = read_testdata( "alice29.txt.compressed" );
+    let result: std::io::Result<Vec<Entry>> = Disassembler::new( &compressed[..1000] ).collect();
+    assert!( result.is_err() );
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Event {
+    Literal( usize ),
+    Copy( usize, usize ),
+    Dictionary( usize, usize ),
+    BlockSwitch( Category, usize ),
+    MetaBlock( MetaBlockHeader )
+}
+
+#[derive(Default)]
+struct Recorder( Vec<Event> );
+
+impl CommandObserver for Recorder {
+    fn literal( &mut self, run: usize ) {
+        self.0.push( Event::Literal( run ));
+    }
+
+    fn copy( &mut self, length: usize, distance: usize ) {
+        self.0.push( Event::Copy( length, distance ));
+    }
+
+    fn dictionary( &mut self, length: usize, word_id: usize, transform_id: usize ) {
+        assert!( word_id < 13504 );
+        self.0.push( Event::Dictionary( length, transform_id ));
+    }
+
+    fn block_switch( &mut self, category: Category, block_type: usize ) {
+        self.0.push( Event::BlockSwitch( category, block_type ));
+    }
+
+    fn meta_block( &mut self, header: &MetaBlockHeader ) {
+        self.0.push( Event::MetaBlock( *header ));
+    }
+}
+
+#[test]
+fn test_command_observer() {
+    for name in &["asyoulik.txt", "alice29.txt", "x", "quickfox_repeated", "empty"] {
+        let compressed = read_testdata( &format!( "{}.compressed", name ));
+        let mut expected = Vec::new();
+        for entry in Disassembler::new( &compressed[..] ) {
+            match entry.unwrap().instruction {
+                Instruction::MetaBlockHeader { is_last, length, kind } =>
+                    expected.push( Event::MetaBlock( MetaBlockHeader { is_last: is_last, length: length, kind: kind } )),
+                Instruction::BlockSwitch { category, block_type, .. } => expected.push( Event::BlockSwitch( category, block_type )),
+                Instruction::Command { literals, copy_length, distance, .. } => {
+                    if !literals.is_empty() {
+                        expected.push( Event::Literal( literals.len() ));
+                    }
+                    match distance {
+                        Some( Distance::Last { distance, .. } ) | Some( Distance::Explicit { distance, .. } ) =>
+                            expected.push( Event::Copy( copy_length, distance )),
+                        Some( Distance::Dictionary { transform, length, .. } ) => expected.push( Event::Dictionary( length, transform )),
+                        None => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Input arrives a byte at a time, so decoding often stops in the middle of a command
+        let mut recorder = Recorder::default();
+        let mut output = Vec::new();
+        Decoder::with_observer( StreamBitReader::new( OneByte( &compressed )), &mut recorder ).read_to_end( &mut output ).unwrap();
+        // The listing holds a command back until its distance, and the block switches read before
+        let (switches, commands): (Vec<&Event>, Vec<&Event>) = recorder.0.iter().partition( |event| matches!( **event, Event::BlockSwitch( .. )) );
+        let (expected_switches, expected_commands): (Vec<&Event>, Vec<&Event>) = expected.iter().partition( |event| matches!( **event, Event::BlockSwitch( .. )) );
+        assert!( switches == expected_switches, "{} block switches don't match the listing", name );
+        assert!( commands == expected_commands, "{} commands don't match the listing", name );
+
+        let mut length = 0;
+        for event in &recorder.0 {
+            match *event {
+                Event::Literal( run ) => length += run,
+                Event::Copy( copy_length, _ ) | Event::Dictionary( copy_length, _ ) => length += copy_length,
+                Event::MetaBlock( MetaBlockHeader { kind: MetaBlockKind::Uncompressed, length: uncompressed, .. } ) => length += uncompressed,
+                _ => {}
+            }
+        }
+        assert_eq!( length, output.len() );
+    }
+
+    let compressed = read_testdata( "alice29.txt.compressed" );
+    let mut decoder = Decoder::with_observer( StreamBitReader::new( &compressed[..] ), Recorder::default() );
+    decoder.read_to_end( &mut Vec::new() ).unwrap();
+    assert!( decoder.observer().0.iter().any( |event| matches!( *event, Event::Dictionary( .. )) ));
+}
+
+#[test]
+fn test_explain() {
+    for name in &["alice29.txt", "x", "empty", "random_org_10k.bin"] {
+        let compressed = read_testdata( &format!( "{}.compressed", name ));
+        let explanation = explain( &compressed[..] ).unwrap();
+        assert!( explanation.data == decompress( &compressed ).unwrap() );
+        assert_eq!( explanation.bits.len(), explanation.data.len() );
+
+        let mut end = 0;
+        let mut total_bits = 0;
+        for region in &explanation.regions {
+            assert_eq!( region.start, end );
+            let bits: u64 = explanation.bits[region.start .. region.start + region.length].iter().map( |&bits| bits as u64 ).sum();
+            assert_eq!( region.bits, bits );
+            end += region.length;
+            total_bits += bits;
+        }
+        assert_eq!( end, explanation.data.len() );
+        assert!( total_bits <= compressed.len() as u64 * 8 );
+
+        let mut html = Vec::new();
+        explanation.write_html( &mut html, name ).unwrap();
+        let html = String::from_utf8( html ).unwrap();
+        assert!( html.starts_with( "<!DOCTYPE html>" ) && html.ends_with( "</html>\n" ));
+    }
+
+    let explanation = explain( &read_testdata( "x.compressed" )[..] ).unwrap();
+    assert_eq!( explanation.regions.len(), 1 );
+    assert_eq!( explanation.regions[0].coding, Coding::Uncompressed );
+
+    let explanation = explain( &read_testdata( "alice29.txt.compressed" )[..] ).unwrap();
+    assert!( explanation.regions.iter().any( |region| matches!( region.coding, Coding::Dictionary { .. } )));
+    let costliest = explanation.costliest_regions( 5 );
+    assert_eq!( costliest.len(), 5 );
+    assert!( costliest.windows( 2 ).all( |pair| pair[0].bits >= pair[1].bits ));
+    assert!( explanation.regions.iter().all( |region| region.bits <= costliest[0].bits ));
+
+    let mut summary = Vec::new();
+    explanation.write_summary( &mut summary, 5 ).unwrap();
+    let summary = String::from_utf8( summary ).unwrap();
+    assert!( summary.starts_with( "152089 bytes" ));
+    assert!( summary.contains( "\"ALICE'S ADVENTURES IN WONDERLAND\"" ));
+
+    // Errors aren't explained away
+    assert!( explain( &read_testdata( "alice29.txt.compressed" )[..1000] ).is_err() );
+}
+
+#[test]
+fn test_validate() {
+    for &(name, checksum) in &[("asyoulik.txt", 0x015e_5966), ("x", 0xb7b2_364b), ("empty", 0), ("random_org_10k.bin", 0x114e_ad99)] {
+        let compressed = read_testdata( &format!( "{}.compressed", name ));
+        let window_bits = match Disassembler::new( &compressed[..] ).next().unwrap().unwrap().instruction {
+            Instruction::WindowBits( window_bits ) => window_bits,
+            _ => unreachable!()
+        };
+        let info = validate( OneByte( &compressed )).unwrap();
+        assert_eq!( info, StreamInfo { window_bits: window_bits, length: read_testdata( name ).len() as u64, checksum: checksum } );
+    }
+
+    let compressed = read_testdata( "alice29.txt.compressed" );
+    match validate( &compressed[..compressed.len() - 1] ) {
+        Err( BrotliError::InsufficientData ) => {}
+        result => panic!( "truncated stream: {:?}", result )
+    }
+
+    let mut trailing = compressed.clone();
+    trailing.push( 0 );
+    match validate( &trailing[..] ) {
+        Err( BrotliError::InvalidEncoding ) => {}
+        result => panic!( "trailing input: {:?}", result )
+    }
+
+    // Nonzero padding after the last meta-block
+    let mut padded = read_testdata( "x.compressed.01" );
+    *padded.last_mut().unwrap() |= 0x80;
+    match validate( &padded[..] ) {
+        Err( BrotliError::InvalidEncoding ) => {}
+        result => panic!( "bad padding: {:?}", result )
+    }
+
+    // Every corruption of a byte is either caught or changes the checksum
+    let expected = validate( &compressed[..] ).unwrap();
+    for i in ( 0 .. compressed.len() ).step_by( 97 ) {
+        let mut corrupt = compressed.clone();
+        corrupt[i] ^= 0x10;
+        if let Ok( info ) = validate( &corrupt[..] ) {
+            assert!( info != expected, "corrupting byte {} went unnoticed", i );
+        }
+    }
+}
+
+#[test]
+fn test_probe() {
+    let mut names: Vec<String> = ["asyoulik.txt", "x", "empty", "random_org_10k.bin", "zeros", "ukkonooa", "64x"].iter()
+        .map( |name| format!( "{}.compressed", name )).collect();
+    names.extend(( 0 .. 19 ).map( |i| format!( "empty.compressed.{:02}", i )));
+    names.extend(( 0 .. 4 ).map( |i| format!( "x.compressed.{:02}", i )));
+    for name in &names {
+        let compressed = read_testdata( name );
+        let mut entries = Disassembler::new( &compressed[..] ).map( |entry| entry.unwrap().instruction );
+        let window_bits = match entries.next() {
+            Some( Instruction::WindowBits( window_bits )) => window_bits,
+            _ => unreachable!()
+        };
+        let header = match entries.next() {
+            Some( Instruction::MetaBlockHeader { is_last, length, kind } ) => MetaBlockHeader { is_last: is_last, length: length, kind: kind },
+            _ => unreachable!()
+        };
+
+        let info = probe( &compressed ).unwrap();
+        assert_eq!( info.window_bits, window_bits, "{}", name );
+        assert_eq!( info.first_meta_block, header, "{}", name );
+        assert_eq!( info.is_empty(), header.kind == MetaBlockKind::Empty );
+        match header.kind {
+            MetaBlockKind::Empty => assert_eq!( info.memory, 0 ),
+            MetaBlockKind::Metadata => assert_eq!( info.memory, 1 << window_bits ),
+            _ => assert!( info.memory >= header.length.min( 1 << window_bits ) && info.memory <= ( 1 << window_bits ).max( 64 ))
+        }
+
+        // Shorter input either holds the headers or is reported as too short
+        for length in 0 .. compressed.len().min( MIN_PROBE_BYTES ) {
+            match probe( &compressed[..length] ) {
+                Ok( short ) => assert_eq!( short, info ),
+                Err( BrotliError::InsufficientData ) => {}
+                Err( e ) => panic!( "{} bytes of {}: {}", length, name, e )
+            }
+        }
+    }
+
+    // The longest headers: a 7 bit window size, and 6 nibbles or 3 bytes of length
+    let uncompressed = [0x21, 0xfe, 0xff, 0xff, 0x07];
+    let metadata = [0xa1, 0xf6, 0xff, 0xff, 0x3f];
+    for &(bytes, kind, is_last) in &[(uncompressed, MetaBlockKind::Uncompressed, false), (metadata, MetaBlockKind::Metadata, true)] {
+        let info = probe( &bytes ).unwrap();
+        assert_eq!( info.window_bits, 10 );
+        assert_eq!( info.first_meta_block, MetaBlockHeader { is_last: is_last, length: 1 << 24, kind: kind } );
+        assert_eq!( info.memory, 1 << 10 );
+        assert!( matches!( probe( &bytes[..MIN_PROBE_BYTES - 1] ), Err( BrotliError::InsufficientData )));
+    }
+
+    // Reserved window size
+    assert!( matches!( probe( &[0x11, 0, 0, 0, 0] ), Err( BrotliError::InvalidEncoding )));
+}
+
+#[cfg(feature = "encoder")]
+fn disassemble( compressed: &[u8] ) -> String {
+    Disassembler::new( compressed ).map( |entry| entry.unwrap().to_string() + "\n" ).collect()
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_assemble() {
+    let x = "
+        window_bits 16
+        meta_block uncompressed length=1 last=0
+        data \"X\"            # aligned to a byte boundary
+        meta_block empty last=1";
+    assert_eq!( assemble( x ).unwrap(), read_testdata( "x.compressed.00" ));
+
+    let x = "
+        window_bits 10
+        meta_block compressed length=1 last=1
+        block_types literal 1
+        block_types command 1
+        block_types distance 1
+        distance_parameters postfix_bits=0 direct_codes=0 context_modes=[0]
+        context_map literal trees=1
+        context_map distance trees=1
+        prefix_code literal 0 0x58:1
+        prefix_code command 0 8:1
+        prefix_code distance 0 0:1
+        command 8 insert=1 \"X\" copy=2";
+    assert_eq!( assemble( x ).unwrap(), read_testdata( "x.compressed.03" ));
+
+    let metadata = "window_bits 17\nmeta_block metadata length=1 last=1\nmetadata \"\\x58\"";
+    assert_eq!( assemble( metadata ).unwrap(), read_testdata( "empty.compressed.16" ));
+
+    // Two literal block types, with a prefix code each, chosen through a run length coded
+    // context map
+    let source = "
+        window_bits 16
+        meta_block compressed length=12 last=1
+        block_types literal 2 length=3
+        prefix_code literal_block_type 0 0:1 1:1
+        prefix_code literal_block_length 0 0:1 1:1
+        block_types command 1
+        block_types distance 1
+        distance_parameters postfix_bits=0 direct_codes=0 context_modes=[0, 0]
+        context_map literal trees=2 [".to_string() + &[" 0"; 64].join( "," ) + "," + &[" 1"; 64].join( "," ) + "] rle=5 imtf=1
+        prefix_code literal_context_map 0 0:2 5:1 6:2
+        context_map distance trees=1
+        prefix_code literal 0 97:1 98:1
+        prefix_code literal 1 120:1
+        prefix_code command 0 166:1
+        prefix_code distance 0 16:1
+        block_switch literal type=1 length=1
+        command insert=4 \"abax\" copy=8 distance 1";
+    let compressed = assemble( &source ).unwrap();
+    assert_eq!( decompress( &compressed ).unwrap(), b"abaxxxxxxxxx" );
+    assert_eq!( assemble( &disassemble( &compressed )).unwrap(), compressed );
+
+    // Streams of the encoder are reproduced exactly
+    let data = read_testdata( "asyoulik.txt" );
+    for options in &[EncoderOptions::default(),
+                     EncoderOptions { quality: 0, window_bits: 10, .. EncoderOptions::default() },
+                     EncoderOptions { quality: 11, mode: Mode::Text, block_bits: 16, .. EncoderOptions::default() }] {
+        let compressed = compress( &data, options );
+        assert!( assemble( &disassemble( &compressed )).unwrap() == compressed, "{:?} isn't reproduced", options );
+    }
+    let mut writer = CompressorWriter::new( Vec::new(), &EncoderOptions::default() );
+    writer.write_all( &data[.. 1000] ).unwrap();
+    writer.flush().unwrap();
+    writer.write_metadata( b"metadata" ).unwrap();
+    writer.write_all( &data[1000 ..] ).unwrap();
+    let compressed = writer.finish().unwrap();
+    assert_eq!( assemble( &disassemble( &compressed )).unwrap(), compressed );
+
+    // Streams of other encoders decode the same, even where they aren't reproduced exactly
+    for name in &["alice29.txt", "mapsdatazrh", "monkey", "quickfox_repeated", "ukkonooa", "zeros"] {
+        let compressed = read_testdata( &format!( "{}.compressed", name ));
+        let assembled = assemble( &disassemble( &compressed )).unwrap();
+        assert!( decompress( &assembled ).unwrap() == decompress( &compressed ).unwrap(), "{} doesn't match", name );
+    }
+}
+
+#[test]
+#[cfg(feature = "encoder")]
+fn test_assemble_invalid() {
+    // Nonzero padding before the data of an uncompressed meta-block
+    let bad_padding = "window_bits 16\nmeta_block uncompressed length=1 last=0\nalign 1\ndata \"X\"\nmeta_block empty last=1";
+    assert_eq!( decompress( &assemble( &bad_padding.replace( "align 1", "align 0" )).unwrap() ).unwrap(), b"X" );
+    assert_eq!( decompress( &assemble( bad_padding ).unwrap() ).unwrap_err().kind(), ErrorKind::InvalidData );
+
+    // An over-subscribed literal code
+    let code = "
+        window_bits 16
+        meta_block compressed length=1 last=1
+        block_types literal 1
+        block_types command 1
+        block_types distance 1
+        distance_parameters postfix_bits=0 direct_codes=0 context_modes=[0]
+        context_map literal trees=1
+        context_map distance trees=1
+        prefix_code literal 0 88:1 89:2 90:2
+        prefix_code command 0 8:1
+        prefix_code distance 0 0:1
+        command 8 insert=1 \"X\" copy=2";
+    assert_eq!( decompress( &assemble( code ).unwrap() ).unwrap(), b"X" );
+    let over_subscribed = code.replace( "88:1 89:2 90:2", "88:1 89:2 90:1" );
+    assert_eq!( decompress( &assemble( &over_subscribed ).unwrap() ).unwrap_err().kind(), ErrorKind::InvalidData );
+
+    // A distance beyond the window and the static dictionary
+    let distance = "
+        window_bits 16
+        meta_block compressed length=8 last=1
+        block_types literal 1
+        block_types command 1
+        block_types distance 1
+        distance_parameters postfix_bits=0 direct_codes=0 context_modes=[0]
+        context_map literal trees=1
+        context_map distance trees=1
+        prefix_code literal 0 88:1
+        prefix_code command 0 162:1
+        prefix_code distance 0 17:1 61:1
+        command insert=4 \"XXXX\" copy=4 distance 4";
+    assert_eq!( decompress( &assemble( distance ).unwrap() ).unwrap(), b"XXXXXXXX" );
+    let out_of_window = distance.replace( "distance 4", "distance 30000000" );
+    assert_eq!( decompress( &assemble( &out_of_window ).unwrap() ).unwrap_err().kind(), ErrorKind::InvalidData );
+
+    // Errors in the source are reported with their line
+    let error = assemble( "window_bits 16\nmeta_block compressed length=1 last=1\nblock_types literal 0" ).unwrap_err();
+    assert_eq!( error.line, 3 );
+    assert_eq!( error.to_string(), "line 3: invalid number of block types 0" );
+    assert_eq!( assemble( "command insert=1 \"X\" copy=2" ).unwrap_err().message, "missing prefix_code command 0" );
+    assert_eq!( assemble( &code.replace( "command 8 insert=1 \"X\" copy=2", "command 9 insert=1 \"X\" copy=3" )).unwrap_err().message, "symbol 9 isn't in prefix_code command 0" );
+}
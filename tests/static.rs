@@ -0,0 +1,182 @@
+//! Decodes with a `StaticAlloc`, counting the heap allocations made meanwhile. The streams are
+//! made by the encoder.
+
+#![cfg(feature = "encoder")]
+#![allow(clippy::redundant_field_names)]
+
+extern crate brotli;
+
+use brotli::BrotliError;
+use brotli::allocator::{ BrotliAlloc, StaticAlloc, Types16, Types64, Types256, Window10, Window12, Window14, Window15, Window16 };
+use brotli::asm::assemble;
+use brotli::bitreader::SliceBitReader;
+use brotli::decoder::{ Decoder, Status };
+use brotli::encoder::{ CompressorWriter, EncoderOptions };
+use brotli::observer::NoObserver;
+use std::alloc::{ GlobalAlloc, Layout, System };
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{ Read, Write };
+use std::mem;
+use std::thread;
+
+/// Counts the allocations of threads that are `COUNTING`.
+struct CountingAlloc;
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new( false ) };
+    static ALLOCATIONS: Cell<usize> = const { Cell::new( 0 ) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc( &self, layout: Layout ) -> *mut u8 {
+        if COUNTING.with( |counting| counting.get() ) {
+            ALLOCATIONS.with( |count| count.set( count.get() + 1 ));
+        }
+        System.alloc( layout )
+    }
+
+    unsafe fn dealloc( &self, ptr: *mut u8, layout: Layout ) {
+        System.dealloc( ptr, layout )
+    }
+
+    unsafe fn realloc( &self, ptr: *mut u8, layout: Layout, new_size: usize ) -> *mut u8 {
+        if COUNTING.with( |counting| counting.get() ) {
+            ALLOCATIONS.with( |count| count.set( count.get() + 1 ));
+        }
+        System.realloc( ptr, layout, new_size )
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+fn read_testdata( name: &str ) -> Vec<u8> {
+    let mut data = Vec::new();
+    File::open( format!( "tests/testdata/{}", name )).unwrap().read_to_end( &mut data ).unwrap();
+    data
+}
+
+fn compress( data: &[u8], window_bits: u32 ) -> Vec<u8> {
+    let options = EncoderOptions { quality: 5, window_bits: window_bits, ..EncoderOptions::default() };
+    let mut writer = CompressorWriter::new( Vec::new(), &options );
+    writer.write_all( data ).unwrap();
+    writer.finish().unwrap()
+}
+
+/// Decodes `compressed`, checking the output against `expected` as it goes. Returns the result,
+/// and the number of heap allocations made.
+fn decode_static<A: BrotliAlloc + Default>( compressed: &[u8], expected: &[u8] ) -> (Result<(), BrotliError>, usize) {
+    COUNTING.with( |counting| counting.set( true ));
+    ALLOCATIONS.with( |count| count.set( 0 ));
+    let result = {
+        let mut decoder = Decoder::with_allocator( SliceBitReader::new( compressed ), NoObserver, A::default() );
+        let mut output = [0u8; 1000];
+        let mut position = 0;
+        loop {
+            match decoder.decode() {
+                Ok( status ) => {
+                    let count = decoder.write_output( &mut output );
+                    assert!( output[..count] == expected[position .. position + count] );
+                    position += count;
+                    match status {
+                        Status::Done if decoder.pending_output() == 0 => break Ok( () ),
+                        Status::NeedsMoreInput => break Err( BrotliError::InsufficientData ),
+                        _ => {}
+                    }
+                }
+                Err( e ) => break Err( e )
+            }
+        }.map( |()| assert_eq!( position, expected.len() ))
+    };
+    COUNTING.with( |counting| counting.set( false ));
+    (result, ALLOCATIONS.with( |count| count.get() ))
+}
+
+/// Runs `f` on a thread with room on the stack for a few static decoders of up to 64 types.
+fn with_large_stack<F: FnOnce() + Send + 'static>( f: F ) {
+    thread::Builder::new().stack_size( 8 << 20 ).spawn( f ).unwrap().join().unwrap();
+}
+
+#[test]
+fn test_static_decoder() {
+    with_large_stack( || {
+        let data = read_testdata( "asyoulik.txt" );
+        for &window_bits in &[10, 12, 16] {
+            let compressed = compress( &data, window_bits );
+            let (result, allocations) = match window_bits {
+                10 => decode_static::<StaticAlloc<Window10, Types16>>( &compressed, &data ),
+                12 => decode_static::<StaticAlloc<Window12, Types16>>( &compressed, &data ),
+                _ => decode_static::<StaticAlloc<Window16, Types16>>( &compressed, &data )
+            };
+            result.unwrap();
+            assert_eq!( allocations, 0 );
+        }
+
+        // Streams of other encoders
+        for &(name, compressed) in &[("empty", "empty.compressed.02"), ("random_org_10k.bin", "random_org_10k.bin.compressed"),
+                                     ("x", "x.compressed.01"), ("x", "x.compressed.03")] {
+            let (result, allocations) = decode_static::<StaticAlloc<Window16, Types16>>( &read_testdata( compressed ), &read_testdata( name ));
+            result.unwrap();
+            assert_eq!( allocations, 0 );
+        }
+
+        // A truncated stream
+        let compressed = compress( &data, 16 );
+        let (result, _) = decode_static::<StaticAlloc<Window16, Types16>>( &compressed[..compressed.len() / 2], &data );
+        assert!( matches!( result, Err( BrotliError::InsufficientData )));
+    });
+}
+
+#[test]
+fn test_static_decoder_window_too_large() {
+    with_large_stack( || {
+        let data = read_testdata( "asyoulik.txt" );
+        for &(window_bits, max_window_bits) in &[(11, 10), (17, 16), (22, 16)] {
+            let compressed = compress( &data, window_bits );
+            let (result, _) = if max_window_bits == 10 {
+                decode_static::<StaticAlloc<Window10, Types16>>( &compressed, &data )
+            } else {
+                decode_static::<StaticAlloc<Window16, Types64>>( &compressed, &data )
+            };
+            let error = result.unwrap_err();
+            assert_eq!( error.to_string(), format!( "window of 2^{} bytes is larger than the decoder's limit of 2^{}", window_bits, max_window_bits ));
+            assert!( matches!( error, BrotliError::WindowTooLarge { window_bits: w, max_window_bits: m } if w == window_bits && m == max_window_bits ));
+        }
+    });
+}
+
+#[test]
+fn test_static_decoder_too_many_types() {
+    with_large_stack( || {
+        let compressed = assemble( "
+            window_bits 16
+            meta_block compressed length=1 last=1
+            block_types literal 17 length=1
+            prefix_code literal_block_type 0 0:1 1:1
+            prefix_code literal_block_length 0 0:1 1:1" ).unwrap();
+        let (result, _) = decode_static::<StaticAlloc<Window16, Types16>>( &compressed, b"" );
+        let error = result.unwrap_err();
+        assert_eq!( error.to_string(), "17 block types or prefix codes are more than the decoder's limit of 16" );
+        assert!( matches!( error, BrotliError::TooManyTypes { num_types: 17, max_num_types: 16 } ));
+
+        // With room for them, the truncated stream is read on
+        let (result, _) = decode_static::<StaticAlloc<Window16, Types64>>( &compressed, b"" );
+        assert!( !matches!( result, Err( BrotliError::TooManyTypes { .. } )));
+    });
+}
+
+#[test]
+#[cfg(target_pointer_width = "64")]
+fn test_static_decoder_size() {
+    // As documented on StaticAlloc
+    assert_eq!( mem::size_of::<Decoder<SliceBitReader, NoObserver, StaticAlloc<Window10, Types16>>>(), 184_808 );
+    assert_eq!( mem::size_of::<Decoder<SliceBitReader, NoObserver, StaticAlloc<Window14, Types16>>>(), 215_400 );
+    assert_eq!( mem::size_of::<Decoder<SliceBitReader, NoObserver, StaticAlloc<Window16, Types16>>>(), 313_704 );
+    assert_eq!( mem::size_of::<Decoder<SliceBitReader, NoObserver, StaticAlloc<Window10, Types64>>>(), 696_296 );
+    assert_eq!( mem::size_of::<Decoder<SliceBitReader, NoObserver, StaticAlloc<Window16, Types64>>>(), 818_664 );
+    assert_eq!( mem::size_of::<Decoder<SliceBitReader, NoObserver, StaticAlloc<Window10>>>(), 2_742_248 );
+    assert_eq!( mem::size_of::<Decoder<SliceBitReader, NoObserver, StaticAlloc<Window14, Types256>>>(), 2_742_248 );
+    assert_eq!( mem::size_of::<Decoder<SliceBitReader, NoObserver, StaticAlloc<Window15, Types256>>>(), 2_772_968 );
+    assert_eq!( mem::size_of::<Decoder<SliceBitReader, NoObserver, StaticAlloc<Window16, Types256>>>(), 2_838_504 );
+}